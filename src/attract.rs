@@ -0,0 +1,280 @@
+use bevy::prelude::*;
+
+use crate::{
+    joyride::{
+        change_screen, GameScreen, JoyrideAction, JoyrideInput, RaceRestarted, RebindState,
+        TIME_STEP,
+    },
+    player::{Player, PLAYER_MAX_NORMAL_SPEED},
+    racer::Racer,
+    replay::{InputRecording, InputReplay},
+    road::{is_offroad, RoadDynamic, RoadStatic},
+};
+
+pub struct Systems {
+    pub update_attract_mode: SystemSet,
+    pub drive_attract_player: SystemSet,
+}
+
+impl Systems {
+    pub fn new() -> Self {
+        Self {
+            update_attract_mode: SystemSet::new().with_system(update_attract_mode.system()),
+            drive_attract_player: SystemSet::new().with_system(drive_attract_player.system()),
+        }
+    }
+}
+
+// The demo that plays behind the menu
+const DEMO_PATH: &str = "assets/demo_input.ron";
+
+// The AI keeps a little below the player's top speed, and eases off further into sharp curves
+const ATTRACT_CRUISE_SPEED: f32 = PLAYER_MAX_NORMAL_SPEED * 0.8;
+const ATTRACT_CURVE_SLOWDOWN: f32 = 0.4;
+
+// How far ahead (in seconds of travel) the AI reads curvature, to make up for the turn buffer
+const ATTRACT_LOOKAHEAD_SECS: f32 = 0.5;
+
+// How strongly the AI steers back towards the center of the road, per unit of X offset
+const ATTRACT_CENTERING_STRENGTH: f32 = 2.0;
+
+// The AI doesn't bother steering when it's this close to its desired turn rate
+const ATTRACT_STEER_DEADZONE: f32 = 20.0;
+
+pub struct AttractConfig {
+    // How long the menu waits without any input before a demo starts behind it. A race the
+    // player has started is theirs, however long they leave it
+    pub idle_timeout_secs: f32,
+
+    // How long the AI's demo runs before it starts over from the beginning of the track. The
+    // recorded demo just runs until the recording does
+    pub demo_length_secs: f32,
+}
+
+impl Default for AttractConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout_secs: 30.0,
+            demo_length_secs: 60.0,
+        }
+    }
+}
+
+// While active, the AI's decisions stand in for the player's input in update_input
+#[derive(Default)]
+pub struct AttractMode {
+    is_active: bool,
+    idle_secs: f32,
+    demo_secs: f32,
+    steer_left: bool,
+    steer_right: bool,
+    accel: bool,
+}
+
+impl AttractMode {
+    pub fn is_active(&self) -> bool {
+        self.is_active
+    }
+
+    pub fn is_pressed(&self, action: JoyrideAction) -> bool {
+        match action {
+            JoyrideAction::Left => self.steer_left,
+            JoyrideAction::Right => self.steer_right,
+            JoyrideAction::Accel => self.accel,
+            _ => false,
+        }
+    }
+
+    fn exit(&mut self) {
+        *self = Self::default();
+    }
+}
+
+// Runs after update_input, which still reads the player's own keyboard and gamepads while a demo
+// is driving. The recorded demo plays if there is one, otherwise the AI takes the bike
+fn update_attract_mode(
+    input: Res<JoyrideInput>,
+    rebind_state: Res<RebindState>,
+    config: Res<AttractConfig>,
+    mut attract: ResMut<AttractMode>,
+    mut replay: ResMut<InputReplay>,
+    mut screen: ResMut<State<GameScreen>>,
+    mut restarted_events: EventWriter<RaceRestarted>,
+) {
+    // Rebinding is waiting on the player to press something, so they can't be away during it
+    let is_player_present = input.any.is_pressed() || rebind_state.is_active();
+
+    match *screen.current() {
+        GameScreen::Menu => {}
+        GameScreen::Racing | GameScreen::Results => {
+            attract.exit();
+            return;
+        }
+
+        // Either the player coming back or the recording running out goes back to the menu
+        // with the race reset
+        GameScreen::Demo => {
+            let is_recording_over = !attract.is_active && !replay.is_playing_back();
+            if is_player_present || is_recording_over {
+                attract.exit();
+                *replay = InputReplay::Off;
+                restarted_events.send(RaceRestarted);
+                change_screen(&mut screen, GameScreen::Menu);
+                return;
+            }
+
+            // The AI's demo loops by starting the race over, the same as the player restarting
+            // it. Its length is about how long the player has been away, so it's timed in real
+            // time, whatever the time scale is doing to the race
+            if attract.is_active {
+                attract.demo_secs += TIME_STEP;
+                if attract.demo_secs >= config.demo_length_secs {
+                    attract.demo_secs = 0.0;
+                    restarted_events.send(RaceRestarted);
+                }
+            }
+            return;
+        }
+    }
+
+    if is_player_present {
+        attract.idle_secs = 0.0;
+        return;
+    }
+
+    attract.idle_secs += TIME_STEP;
+    if attract.idle_secs < config.idle_timeout_secs {
+        return;
+    }
+
+    attract.idle_secs = 0.0;
+    match InputRecording::load(DEMO_PATH) {
+        Some(recording) => *replay = InputReplay::playback(recording),
+        None => {
+            attract.is_active = true;
+            attract.demo_secs = 0.0;
+        }
+    }
+    restarted_events.send(RaceRestarted);
+    change_screen(&mut screen, GameScreen::Demo);
+}
+
+fn drive_attract_player(
+    mut attract: ResMut<AttractMode>,
+    players: Query<&Racer, With<Player>>,
+    road_static: Res<RoadStatic>,
+    road_dyn: Res<RoadDynamic>,
+) {
+    if !attract.is_active {
+        return;
+    }
+
+    let racer = match players.iter().next() {
+        Some(racer) => racer,
+        None => return,
+    };
+
+    // Counter the pull of the upcoming curve, plus whatever it takes to get back to the center
+    let lookahead_z = racer.speed * ATTRACT_LOOKAHEAD_SECS;
+    let desired_turn_rate = road_dyn.get_road_x_pull(lookahead_z, racer.speed)
+        + (road_dyn.x_offset * ATTRACT_CENTERING_STRENGTH);
+
+    attract.steer_left = desired_turn_rate < racer.turn_rate - ATTRACT_STEER_DEADZONE;
+    attract.steer_right = desired_turn_rate > racer.turn_rate + ATTRACT_STEER_DEADZONE;
+
+    let curvature = f32::min(road_dyn.get_seg_curvature(lookahead_z).abs(), 1.0);
+    let target_speed = ATTRACT_CRUISE_SPEED * (1.0 - (curvature * ATTRACT_CURVE_SLOWDOWN));
+    attract.accel = racer.speed < target_speed && !is_offroad(&road_static, &road_dyn);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::Events, ecs::schedule::Stage};
+
+    use super::*;
+    use crate::joyride::JoyrideInputState;
+
+    fn attract_world(screen: GameScreen) -> (World, SystemStage) {
+        let mut world = World::default();
+        world.insert_resource(JoyrideInput::default());
+        world.insert_resource(RebindState::default());
+        world.insert_resource(AttractConfig {
+            idle_timeout_secs: TIME_STEP * 2.5,
+            demo_length_secs: 60.0,
+        });
+        world.insert_resource(AttractMode::default());
+        world.insert_resource(InputReplay::Off);
+        world.insert_resource(State::new(screen));
+        world.insert_resource(Events::<RaceRestarted>::default());
+        (world, SystemStage::single(update_attract_mode.system()))
+    }
+
+    fn num_restarts(world: &World) -> usize {
+        let events = world.get_resource::<Events<RaceRestarted>>().unwrap();
+        events.get_reader().iter(events).count()
+    }
+
+    // Nothing pressed, long past the timeout, but the race is the player's to leave
+    #[test]
+    fn a_race_is_never_idled_out() {
+        let (mut world, mut stage) = attract_world(GameScreen::Racing);
+        for _ in 0..10 {
+            stage.run(&mut world);
+        }
+
+        assert!(!world.get_resource::<AttractMode>().unwrap().is_active());
+        assert_eq!(num_restarts(&world), 0);
+    }
+
+    #[test]
+    fn ai_demo_loops_until_the_player_returns() {
+        let (mut world, mut stage) = attract_world(GameScreen::Demo);
+        world.get_resource_mut::<AttractMode>().unwrap().is_active = true;
+        world
+            .get_resource_mut::<AttractConfig>()
+            .unwrap()
+            .demo_length_secs = TIME_STEP * 2.5;
+
+        for _ in 0..3 {
+            stage.run(&mut world);
+        }
+        assert!(world.get_resource::<AttractMode>().unwrap().is_active());
+        assert_eq!(num_restarts(&world), 1);
+
+        // Whatever they press, the demo hands straight back
+        world.get_resource_mut::<JoyrideInput>().unwrap().any = JoyrideInputState::JustPressed;
+        stage.run(&mut world);
+        assert!(!world.get_resource::<AttractMode>().unwrap().is_active());
+        assert_eq!(num_restarts(&world), 2);
+    }
+
+    // Sitting on the menu waiting to bind a key isn't idling, but once it's done, the idle timer
+    // starts from scratch and the recorded demo plays as normal
+    #[test]
+    fn menu_demo_waits_for_rebinding() {
+        let (mut world, mut stage) = attract_world(GameScreen::Menu);
+        world
+            .get_resource_mut::<RebindState>()
+            .unwrap()
+            .start(&[JoyrideAction::Accel]);
+
+        for _ in 0..10 {
+            stage.run(&mut world);
+        }
+        assert!(!world
+            .get_resource::<InputReplay>()
+            .unwrap()
+            .is_playing_back());
+        assert_eq!(num_restarts(&world), 0);
+
+        world.insert_resource(RebindState::default());
+        for _ in 0..3 {
+            stage.run(&mut world);
+        }
+        assert!(world
+            .get_resource::<InputReplay>()
+            .unwrap()
+            .is_playing_back());
+        assert_eq!(num_restarts(&world), 1);
+    }
+}
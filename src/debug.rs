@@ -1,7 +1,16 @@
-use bevy::{ecs::system::EntityCommands, prelude::*, utils::Instant};
+use std::collections::VecDeque;
+
+use bevy::{
+    ecs::system::EntityCommands,
+    prelude::*,
+    render::camera::{Camera, OrthographicProjection},
+    utils::Instant,
+};
+use easy_cast::*;
 
 use crate::{
-    joyride::{JoyrideInput, JoyrideInputState},
+    joyride::{JoyrideInput, JoyrideInputState, FIELD_HEIGHT, FIELD_WIDTH, TIME_STEP},
+    road_object::CollisionAction,
     util::LocalVisible,
 };
 
@@ -14,72 +23,245 @@ impl Systems {
     pub fn new() -> Self {
         Self {
             startup_debug: SystemSet::new().with_system(startup_debug.system()),
-            update_debug_vis: SystemSet::new().with_system(update_debug_vis.system()),
+            update_debug_vis: SystemSet::new()
+                .with_system(update_debug_vis.system())
+                .with_system(debug_free_camera.system())
+                .with_system(record_frame_time.system()),
         }
     }
 }
 
+// How fast the debug camera pans and zooms, in field pixels and zoom levels per second
+const FREE_CAMERA_PAN_SPEED: f32 = 160.0;
+const FREE_CAMERA_ZOOM_SPEED: f32 = 1.0;
+const FREE_CAMERA_MIN_SCALE: f32 = 0.1;
+const FREE_CAMERA_MAX_SCALE: f32 = 10.0;
+
 struct DebugCollision {}
 
+struct DebugGameplay {}
+
 pub struct DebugAssets {
-    solid_color_mat: Handle<ColorMaterial>,
+    collision_mat: Handle<ColorMaterial>,
+    crash_collision_mat: Handle<ColorMaterial>,
+    slide_collision_mat: Handle<ColorMaterial>,
+    gameplay_mat: Handle<ColorMaterial>,
+}
+
+impl DebugAssets {
+    // Obstacle colliders are colored by what they do to the player, so dangerous ones stand out
+    fn get_collision_mat(&self, action: Option<CollisionAction>) -> &Handle<ColorMaterial> {
+        match action {
+            Some(CollisionAction::CrashPlayer) => &self.crash_collision_mat,
+            Some(CollisionAction::SlidePlayer) | Some(CollisionAction::SlipPlayer) => {
+                &self.slide_collision_mat
+            }
+            Some(CollisionAction::BouncePlayer) | Some(CollisionAction::CollectPickup) | None => {
+                &self.collision_mat
+            }
+        }
+    }
 }
 
 pub struct DebugConfig {
     pub debug_collision: bool,
     pub debug_road_seg_boundaries: bool,
     pub debug_gameplay: bool,
+    pub debug_framerate: bool,
+    pub debug_perf: bool,
+
+    // Each debug category gets its own color, so overlapping visualizations stay readable
+    pub collision_color: Color,
+    pub crash_collision_color: Color,
+    pub slide_collision_color: Color,
+    pub road_seg_boundary_color: Color,
+    pub gameplay_color: Color,
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self {
+            debug_collision: false,
+            debug_road_seg_boundaries: false,
+            debug_gameplay: false,
+            debug_framerate: false,
+            debug_perf: false,
+            collision_color: Color::rgba(1.0, 1.0, 1.0, 0.8),
+            crash_collision_color: Color::rgba(1.0, 0.0, 0.0, 0.8),
+            slide_collision_color: Color::rgba(1.0, 0.5, 0.0, 0.8),
+            road_seg_boundary_color: Color::rgba(0.0, 1.0, 0.0, 1.0),
+            gameplay_color: Color::rgba(0.0, 0.5, 1.0, 0.8),
+        }
+    }
+}
+
+impl DebugConfig {
+    // Turns all the debug visualizations off if any are on, otherwise turns them all on
+    fn toggle_all_vis(&mut self) {
+        let is_any_on =
+            self.debug_collision || self.debug_road_seg_boundaries || self.debug_gameplay;
+        self.debug_collision = !is_any_on;
+        self.debug_road_seg_boundaries = !is_any_on;
+        self.debug_gameplay = !is_any_on;
+    }
+
+    // Packs a color into the RGBA u32 layout used by the road's draw buffer
+    pub fn color_to_rgba_u32(color: Color) -> u32 {
+        let [r, g, b, a] = color.as_rgba_f32();
+        let to_u8 = |c: f32| u32::conv_nearest(f32::clamp(c, 0.0, 1.0) * 255.0);
+        (to_u8(r) << 24) | (to_u8(g) << 16) | (to_u8(b) << 8) | to_u8(a)
+    }
 }
 
 fn startup_debug(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
+    let debug_config = DebugConfig::default();
+
     commands.insert_resource(DebugAssets {
-        solid_color_mat: materials.add(ColorMaterial {
-            color: Color::Rgba {
-                red: 1.0,
-                green: 0.0,
-                blue: 0.0,
-                alpha: 0.8,
-            },
-            texture: None,
-        }),
-    });
-    commands.insert_resource(DebugConfig {
-        debug_collision: false,
-        debug_road_seg_boundaries: false,
-        debug_gameplay: false,
+        collision_mat: materials.add(debug_config.collision_color.into()),
+        crash_collision_mat: materials.add(debug_config.crash_collision_color.into()),
+        slide_collision_mat: materials.add(debug_config.slide_collision_color.into()),
+        gameplay_mat: materials.add(debug_config.gameplay_color.into()),
     });
+    commands.insert_resource(debug_config);
 }
 
 fn update_debug_vis(
-    coll_query: Query<(&mut LocalVisible, With<DebugCollision>)>,
+    coll_query: Query<&mut LocalVisible, (With<DebugCollision>, Without<DebugGameplay>)>,
+    gameplay_query: Query<&mut LocalVisible, (With<DebugGameplay>, Without<DebugCollision>)>,
     mut debug_cfg: ResMut<DebugConfig>,
     input: Res<JoyrideInput>,
 ) {
     if input.debug == JoyrideInputState::JustPressed {
+        debug_cfg.toggle_all_vis();
+    }
+    if input.debug_collision == JoyrideInputState::JustPressed {
         debug_cfg.debug_collision = !debug_cfg.debug_collision;
     }
+    if input.debug_road_seg_boundaries == JoyrideInputState::JustPressed {
+        debug_cfg.debug_road_seg_boundaries = !debug_cfg.debug_road_seg_boundaries;
+    }
+    if input.debug_gameplay == JoyrideInputState::JustPressed {
+        debug_cfg.debug_gameplay = !debug_cfg.debug_gameplay;
+    }
+    if input.debug_framerate == JoyrideInputState::JustPressed {
+        debug_cfg.debug_framerate = !debug_cfg.debug_framerate;
+    }
+    if input.debug_perf == JoyrideInputState::JustPressed {
+        debug_cfg.debug_perf = !debug_cfg.debug_perf;
+    }
 
-    coll_query.for_each_mut(|(mut local_vis, _)| {
+    coll_query.for_each_mut(|mut local_vis| {
         if local_vis.is_visible != debug_cfg.debug_collision {
             local_vis.is_visible = debug_cfg.debug_collision;
         }
     });
+    gameplay_query.for_each_mut(|mut local_vis| {
+        if local_vis.is_visible != debug_cfg.debug_gameplay {
+            local_vis.is_visible = debug_cfg.debug_gameplay;
+        }
+    });
+}
+
+// Only the camera moves, so the road and everything else still render for the fixed framing.
+// Holds the camera's original placement while free, and restores it exactly once turned off
+fn debug_free_camera(
+    debug_cfg: Res<DebugConfig>,
+    input: Res<JoyrideInput>,
+    mut cameras: Query<(&mut Transform, &mut OrthographicProjection), With<Camera>>,
+    mut original: Local<Option<(Transform, OrthographicProjection)>>,
+) {
+    for (mut xform, mut projection) in cameras.iter_mut() {
+        if !debug_cfg.debug_gameplay {
+            if let Some((orig_xform, orig_projection)) = original.take() {
+                *xform = orig_xform;
+                *projection = orig_projection;
+            }
+            continue;
+        }
+
+        if original.is_none() {
+            *original = Some((xform.clone(), projection.clone()));
+        }
+
+        let axis = |neg: &JoyrideInputState, pos: &JoyrideInputState| {
+            f32::conv(u8::from(pos.is_pressed())) - f32::conv(u8::from(neg.is_pressed()))
+        };
+
+        // Pan faster when zoomed out, so it covers the same screen distance
+        let pan = Vec2::new(
+            axis(&input.debug_camera_left, &input.debug_camera_right),
+            axis(&input.debug_camera_down, &input.debug_camera_up),
+        );
+        if pan != Vec2::ZERO {
+            let pan = pan * FREE_CAMERA_PAN_SPEED * projection.scale * TIME_STEP;
+            xform.translation += pan.extend(0.0);
+        }
+
+        // The projection scales away from the bottom left corner, so keep the center in place
+        let zoom = axis(&input.debug_camera_zoom_in, &input.debug_camera_zoom_out);
+        if zoom != 0.0 {
+            let old_scale = projection.scale;
+            let new_scale = f32::clamp(
+                old_scale * (1.0 + zoom * FREE_CAMERA_ZOOM_SPEED * TIME_STEP),
+                FREE_CAMERA_MIN_SCALE,
+                FREE_CAMERA_MAX_SCALE,
+            );
+            let half_field = Vec2::new(f32::conv(FIELD_WIDTH), f32::conv(FIELD_HEIGHT)) * 0.5;
+            xform.translation += (half_field * (old_scale - new_scale)).extend(0.0);
+            projection.scale = new_scale;
+        }
+    }
 }
 
+// Pass the collision action of the object the collider belongs to, or None for the player's own
 pub fn spawn_collision_debug_box(
     commands: &mut Commands,
     assets: &DebugAssets,
+    action: Option<CollisionAction>,
     offset: Vec2,
     size: Vec2,
 ) -> Entity {
-    spawn_debug_box(commands, assets, offset, size)
+    spawn_debug_box(commands, assets.get_collision_mat(action), offset, size)
         .insert(DebugCollision {})
         .id()
 }
 
+// Reconfigures a box from spawn_collision_debug_box, for when its owner is reused
+pub fn set_collision_debug_box(
+    commands: &mut Commands,
+    assets: &DebugAssets,
+    debug_box: Entity,
+    action: Option<CollisionAction>,
+    offset: Vec2,
+    size: Vec2,
+) {
+    commands
+        .entity(debug_box)
+        .insert(Sprite {
+            size,
+            ..Default::default()
+        })
+        .insert(assets.get_collision_mat(action).clone())
+        .insert(Transform::from_translation(Vec3::new(
+            offset.x, offset.y, 0.0,
+        )));
+}
+
+// For markers showing gameplay zones that aren't colliders, toggled separately from those
+pub fn spawn_gameplay_debug_box(
+    commands: &mut Commands,
+    assets: &DebugAssets,
+    offset: Vec2,
+    size: Vec2,
+) -> Entity {
+    spawn_debug_box(commands, &assets.gameplay_mat, offset, size)
+        .insert(DebugGameplay {})
+        .id()
+}
+
 fn spawn_debug_box<'a, 'b>(
     commands: &'b mut Commands<'a>,
-    assets: &DebugAssets,
+    material: &Handle<ColorMaterial>,
     offset: Vec2,
     size: Vec2,
 ) -> EntityCommands<'a, 'b> {
@@ -88,7 +270,7 @@ fn spawn_debug_box<'a, 'b>(
             size,
             ..Default::default()
         },
-        material: assets.solid_color_mat.clone(),
+        material: material.clone(),
         transform: Transform::from_translation(Vec3::new(offset.x, offset.y, 0.0)),
         ..Default::default()
     });
@@ -97,16 +279,49 @@ fn spawn_debug_box<'a, 'b>(
     ent_cmd
 }
 
+// How many of the most recent frames the average frame time covers
+const FRAME_TIME_HISTORY_LEN: usize = 30;
+
 pub struct LoopSectionTimer {
     start_time: Instant,
+
+    // The most recent frame durations in seconds, oldest first
+    frame_secs: VecDeque<f64>,
+    last_frame_time: Option<Instant>,
 }
 
 impl LoopSectionTimer {
     pub fn new() -> Self {
         Self {
             start_time: Instant::now(),
+            frame_secs: VecDeque::with_capacity(FRAME_TIME_HISTORY_LEN),
+            last_frame_time: None,
         }
     }
+
+    // Call once per frame. The first call only starts timing
+    pub fn end_frame(&mut self) {
+        let now = Instant::now();
+        if let Some(last_frame_time) = self.last_frame_time {
+            if self.frame_secs.len() >= FRAME_TIME_HISTORY_LEN {
+                self.frame_secs.pop_front();
+            }
+            self.frame_secs
+                .push_back(now.duration_since(last_frame_time).as_secs_f64());
+        }
+        self.last_frame_time = Some(now);
+    }
+
+    pub fn get_average_frame_secs(&self) -> f64 {
+        if self.frame_secs.is_empty() {
+            return 0.0;
+        }
+        self.frame_secs.iter().sum::<f64>() / f64::conv(self.frame_secs.len())
+    }
+}
+
+fn record_frame_time(mut loop_section_timer: ResMut<LoopSectionTimer>) {
+    loop_section_timer.end_frame();
 }
 
 #[allow(dead_code)]
@@ -120,3 +335,75 @@ pub fn loop_section_timer_end(loop_section_timer: Res<LoopSectionTimer>) {
     let secs = total_time.as_secs_f64();
     println!("{}", secs);
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::schedule::Stage;
+
+    use super::*;
+
+    fn run_debug_vis(world: &mut World, input: JoyrideInput) {
+        world.insert_resource(input);
+        SystemStage::single(update_debug_vis.system()).run(world);
+    }
+
+    #[test]
+    fn debug_key_toggles_all_vis_once_per_press() {
+        let mut world = World::default();
+        world.insert_resource(DebugConfig::default());
+
+        let pressed = |state| JoyrideInput {
+            debug: state,
+            ..Default::default()
+        };
+        run_debug_vis(&mut world, pressed(JoyrideInputState::JustPressed));
+        run_debug_vis(&mut world, pressed(JoyrideInputState::Pressed));
+        run_debug_vis(&mut world, pressed(JoyrideInputState::Pressed));
+
+        let debug_cfg = world.get_resource::<DebugConfig>().unwrap();
+        assert!(debug_cfg.debug_collision);
+        assert!(debug_cfg.debug_road_seg_boundaries);
+        assert!(debug_cfg.debug_gameplay);
+
+        // With any of them on, the next press turns them all off
+        world
+            .get_resource_mut::<DebugConfig>()
+            .unwrap()
+            .debug_road_seg_boundaries = false;
+        run_debug_vis(&mut world, pressed(JoyrideInputState::JustReleased));
+        run_debug_vis(&mut world, pressed(JoyrideInputState::JustPressed));
+
+        let debug_cfg = world.get_resource::<DebugConfig>().unwrap();
+        assert!(!debug_cfg.debug_collision);
+        assert!(!debug_cfg.debug_gameplay);
+    }
+
+    #[test]
+    fn seg_boundary_key_toggles_boundaries() {
+        let mut world = World::default();
+        world.insert_resource(DebugConfig::default());
+        assert!(
+            !world
+                .get_resource::<DebugConfig>()
+                .unwrap()
+                .debug_road_seg_boundaries,
+            "Segment boundaries are shown by default"
+        );
+
+        let pressed = |state| JoyrideInput {
+            debug_road_seg_boundaries: state,
+            ..Default::default()
+        };
+        run_debug_vis(&mut world, pressed(JoyrideInputState::JustPressed));
+        run_debug_vis(&mut world, pressed(JoyrideInputState::Pressed));
+
+        let debug_cfg = world.get_resource::<DebugConfig>().unwrap();
+        assert!(debug_cfg.debug_road_seg_boundaries);
+        assert!(!debug_cfg.debug_collision);
+
+        run_debug_vis(&mut world, pressed(JoyrideInputState::JustReleased));
+        run_debug_vis(&mut world, pressed(JoyrideInputState::JustPressed));
+        let debug_cfg = world.get_resource::<DebugConfig>().unwrap();
+        assert!(!debug_cfg.debug_road_seg_boundaries);
+    }
+}
@@ -1,9 +1,41 @@
-use bevy::{ecs::schedule::ShouldRun, prelude::*};
+use bevy::{
+    app::{Events, ManualEventReader},
+    ecs::schedule::ShouldRun,
+    input::{keyboard::KeyboardInput, ElementState},
+    prelude::*,
+    window::WindowCloseRequested,
+};
+use easy_cast::*;
 
 pub struct FixedFramerate {
     pub fixed_step: f64,
     pub max_runs_per_step: Option<u32>,
     pub drop_time_after_max_runs: bool,
+
+    // Toggles the Paused resource, if it exists
+    pub pause_key: Option<KeyCode>,
+}
+
+// While paused, no steps run at all, and the time spent paused is never caught up on
+#[derive(Default)]
+pub struct Paused {
+    pub is_paused: bool,
+}
+
+// How often the measured rates in FixedFramerateStats are refreshed
+const STATS_INTERVAL_SECS: f64 = 0.25;
+
+// Measured rates of the fixed-framerate run criteria. Only written if the resource exists
+#[derive(Default)]
+pub struct FixedFramerateStats {
+    // Simulation steps actually run per real second
+    pub steps_per_second: f64,
+
+    // Times the app polled the run criteria per real second, whether a step ran or not
+    pub updates_per_second: f64,
+
+    // Steps whose time was dropped after hitting the run cap, rather than run late
+    pub skipped_steps: u64,
 }
 
 struct FixedFramerateState {
@@ -11,6 +43,15 @@ struct FixedFramerateState {
     accum_seconds: f64,
     num_updates: u32,
     framerate: FixedFramerate,
+
+    stats_elapsed_secs: f64,
+    stats_num_steps: u32,
+    stats_num_updates: u32,
+
+    // Nothing runs while paused, including Bevy's input systems, so raw events are read here
+    keyboard_reader: ManualEventReader<KeyboardInput>,
+    close_reader: ManualEventReader<WindowCloseRequested>,
+    is_pause_key_down: bool,
 }
 
 impl FixedFramerateState {
@@ -20,6 +61,12 @@ impl FixedFramerateState {
             accum_seconds: 0.0,
             num_updates: 0,
             framerate,
+            stats_elapsed_secs: 0.0,
+            stats_num_steps: 0,
+            stats_num_updates: 0,
+            keyboard_reader: ManualEventReader::default(),
+            close_reader: ManualEventReader::default(),
+            is_pause_key_down: false,
         }
     }
 }
@@ -29,12 +76,62 @@ pub fn create_fixed_framerate_run_criteria(
     fixed_framerate: FixedFramerate,
 ) -> impl System<In = (), Out = ShouldRun> {
     let mut state = FixedFramerateState::new(fixed_framerate);
-    let system_fn = move || {
+    let system_fn = move |mut stats: Option<ResMut<FixedFramerateStats>>,
+                          paused: Option<ResMut<Paused>>,
+                          keyboard_events: Res<Events<KeyboardInput>>,
+                          close_events: Res<Events<WindowCloseRequested>>| {
         let cur_time = bevy::utils::Instant::now();
         let elapsed_secs = cur_time.duration_since(state.last_time).as_secs_f64();
+        state.last_time = cur_time;
+
+        state.stats_elapsed_secs += elapsed_secs;
+        if state.stats_elapsed_secs >= STATS_INTERVAL_SECS {
+            if let Some(stats) = stats.as_mut() {
+                stats.steps_per_second =
+                    f64::from(state.stats_num_steps) / state.stats_elapsed_secs;
+                stats.updates_per_second =
+                    f64::from(state.stats_num_updates) / state.stats_elapsed_secs;
+            }
+            state.stats_elapsed_secs = 0.0;
+            state.stats_num_steps = 0;
+            state.stats_num_updates = 0;
+        }
+
+        // Held keys repeat their press events, so only the first press of each hold counts
+        let mut pause_toggled = false;
+        for event in state.keyboard_reader.iter(&keyboard_events) {
+            if state.framerate.pause_key.is_none() || event.key_code != state.framerate.pause_key {
+                continue;
+            }
+
+            let is_pressed = event.state == ElementState::Pressed;
+            if is_pressed && !state.is_pause_key_down {
+                pause_toggled = !pause_toggled;
+            }
+            state.is_pause_key_down = is_pressed;
+        }
+
+        // Closing the window has to unpause, or the systems that act on it would never run
+        let close_requested = state.close_reader.iter(&close_events).next().is_some();
+
+        if let Some(mut paused) = paused {
+            if pause_toggled {
+                paused.is_paused = !paused.is_paused;
+            }
+            if close_requested {
+                paused.is_paused = false;
+            }
+
+            // Paused time is never accumulated, and last_time is already refreshed,
+            // so unpausing picks up exactly where the simulation left off
+            if paused.is_paused {
+                state.num_updates = 0;
+                state.stats_num_updates += 1;
+                return ShouldRun::No;
+            }
+        }
 
         state.accum_seconds += elapsed_secs;
-        state.last_time = cur_time;
 
         let hit_run_cap = if let Some(run_cap) = state.framerate.max_runs_per_step {
             state.num_updates >= run_cap
@@ -45,16 +142,89 @@ pub fn create_fixed_framerate_run_criteria(
         let step_accumulated = state.accum_seconds >= state.framerate.fixed_step;
         if !step_accumulated || hit_run_cap {
             if step_accumulated && state.framerate.drop_time_after_max_runs {
+                if let Some(stats) = stats.as_mut() {
+                    stats.skipped_steps +=
+                        u64::conv_floor(state.accum_seconds / state.framerate.fixed_step);
+                }
                 state.accum_seconds = 0.0;
             }
             state.num_updates = 0;
+            state.stats_num_updates += 1;
             return ShouldRun::No;
         }
 
         state.accum_seconds -= state.framerate.fixed_step;
         state.num_updates += 1;
+        state.stats_num_steps += 1;
         ShouldRun::YesAndCheckAgain
     };
 
     system_fn.system()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+
+    const TEST_STEP_SECS: f64 = 0.01;
+
+    fn tap_pause_key(world: &mut World) {
+        let mut events = world.get_resource_mut::<Events<KeyboardInput>>().unwrap();
+        for &state in [ElementState::Pressed, ElementState::Released].iter() {
+            events.send(KeyboardInput {
+                scan_code: 0,
+                key_code: Some(KeyCode::P),
+                state,
+            });
+        }
+    }
+
+    // Polls the run criteria like the scheduler does, returning how many steps ran
+    fn run_steps(world: &mut World, criteria: &mut impl System<In = (), Out = ShouldRun>) -> u32 {
+        let mut num_steps = 0;
+        while criteria.run((), world) == ShouldRun::YesAndCheckAgain {
+            num_steps += 1;
+        }
+        num_steps
+    }
+
+    fn is_paused(world: &World) -> bool {
+        world.get_resource::<Paused>().unwrap().is_paused
+    }
+
+    // Pauses for several frames' worth of steps, and checks none of them run, either while
+    // paused or as a burst to catch up once unpaused
+    #[test]
+    fn paused_steps_are_never_caught_up() {
+        let mut world = World::default();
+        world.insert_resource(Paused::default());
+        world.insert_resource(Events::<KeyboardInput>::default());
+        world.insert_resource(Events::<WindowCloseRequested>::default());
+
+        let mut criteria = create_fixed_framerate_run_criteria(FixedFramerate {
+            fixed_step: TEST_STEP_SECS,
+            max_runs_per_step: None,
+            drop_time_after_max_runs: false,
+            pause_key: Some(KeyCode::P),
+        });
+        criteria.initialize(&mut world);
+
+        // Unpaused, time slept is made up in steps. One short, in case of rounding
+        thread::sleep(Duration::from_secs_f64(TEST_STEP_SECS * 5.0));
+        assert!(run_steps(&mut world, &mut criteria) >= 4);
+
+        tap_pause_key(&mut world);
+        for _ in 0..5 {
+            thread::sleep(Duration::from_secs_f64(TEST_STEP_SECS * 2.0));
+            assert_eq!(run_steps(&mut world, &mut criteria), 0);
+            assert!(is_paused(&world));
+        }
+
+        // Only what was left over from before the pause can make up a step
+        tap_pause_key(&mut world);
+        assert!(run_steps(&mut world, &mut criteria) <= 1);
+        assert!(!is_paused(&world));
+    }
+}
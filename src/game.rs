@@ -1,4 +1,8 @@
-use crate::{debug, joyride, player, racer, rival, road, road_object, skybox, text};
+use crate::{
+    attract, debug, high_score, joyride, menu, player, racer, replay, results, rival, road,
+    road_object, road_shader, session, sfx, skybox, snapshot, telemetry, text, time_of_day, track,
+    weather,
+};
 use bevy::prelude::*;
 
 #[derive(StageLabel, PartialEq, Eq, Clone, Copy, Hash, Debug)]
@@ -19,13 +23,21 @@ enum GameStageLabels {
 
 #[derive(SystemLabel, PartialEq, Eq, Clone, Copy, Hash, Debug)]
 enum GameSystemLabels {
+    UpdateRebinding,
     UpdateInput,
+    UpdateAttractMode,
+    RestartRace,
+    ResetRace,
+    ResetRoadObjects,
     UpdatePlayerDriving,
     UpdatePlayerRoadPosition,
+    ExtendTrack,
     UpdateRoad,
     UpdateRivals,
     UpdateRoadObjects,
     UpdateOverlayState,
+    CompleteFrame,
+    RecordTelemetry,
 }
 
 struct StageBuilder<'a, S: StageLabel + Clone> {
@@ -53,6 +65,19 @@ impl<'a, S: StageLabel + Clone> StageBuilder<'a, S> {
         }
     }
 
+    // Like add_systems_after, but the systems are paused whenever the race isn't running
+    pub fn add_racing_systems_after(
+        &mut self,
+        after: Option<GameSystemLabels>,
+        sets: Vec<SystemSet>,
+    ) {
+        let gated = sets
+            .into_iter()
+            .map(|set| set.with_run_criteria(joyride::run_if_racing.system()))
+            .collect();
+        self.add_systems_after(after, gated);
+    }
+
     pub fn add_startup_systems_after(
         &mut self,
         after: Option<StartupSystemLabels>,
@@ -78,12 +103,66 @@ pub fn setup_game(app: &mut AppBuilder) {
     let joyride_systems = joyride::Systems::new();
     let player_systems = player::Systems::new();
     let road_systems = road::Systems::new();
+    let road_shader_systems = road_shader::Systems::new();
     let skybox_systems = skybox::Systems::new();
     let text_systems = text::Systems::new();
+    let results_systems = results::Systems::new();
     let rival_systems = rival::Systems::new();
     let racer_systems = racer::Systems::new();
     let road_object_systems = road_object::Systems::new();
     let debug_systems = debug::Systems::new();
+    let track_systems = track::Systems::new();
+    let attract_systems = attract::Systems::new();
+    let telemetry_systems = telemetry::Systems::new();
+    let menu_systems = menu::Systems::new();
+    let session_systems = session::Systems::new();
+    let high_score_systems = high_score::Systems::new();
+    let sfx_systems = sfx::Systems::new();
+    let snapshot_systems = snapshot::Systems::new();
+    let replay_systems = replay::Systems::new();
+
+    track::TrackFile::from_args().insert_resources(app);
+
+    app.init_resource::<road::EndlessMode>()
+        .init_resource::<road::ProjectionConfig>()
+        .init_resource::<road::RoadRenderMode>()
+        .init_resource::<road::RoadTheme>()
+        .init_resource::<road::TrackConfig>()
+        .init_resource::<road::SkidMarks>()
+        .init_resource::<rival::RivalConfig>()
+        .init_resource::<rival::RacePosition>()
+        .init_resource::<rival::Slipstream>()
+        .init_resource::<time_of_day::TimeOfDay>()
+        .init_resource::<weather::Weather>()
+        .init_resource::<road::CheckpointConfig>()
+        .init_resource::<player::PlayerStartConfig>()
+        .init_resource::<player::OverRevConfig>()
+        .init_resource::<player::PlayerTuning>()
+        .init_resource::<text::SpeedTextConfig>()
+        .init_resource::<text::CurvePreviewConfig>()
+        .init_resource::<track::TrackGeneratorConfig>()
+        .init_resource::<track::DifficultyCurve>()
+        .init_resource::<attract::AttractConfig>()
+        .init_resource::<attract::AttractMode>()
+        .init_resource::<replay::InputReplay>()
+        .init_resource::<joyride::FrameCounter>()
+        .init_resource::<joyride::Difficulty>()
+        .init_resource::<joyride::RaceState>()
+        .init_resource::<joyride::TimeScale>()
+        .init_resource::<joyride::Score>()
+        .init_resource::<high_score::HighScoreConfig>()
+        .add_asset::<road_shader::RoadMaterial>()
+        .add_event::<joyride::FrameComplete>()
+        .add_event::<joyride::RaceRestarted>()
+        .add_event::<road::AmbienceEvent>()
+        .add_event::<road::CheckpointReached>()
+        .add_event::<road_object::PickupCollected>()
+        .add_event::<player::PlayerCrashed>()
+        .add_event::<player::PlayerSlid>()
+        .insert_resource(joyride::GameRng::from_args())
+        .insert_resource(session::SessionConfig::from_args())
+        .insert_resource(telemetry::TelemetryConfig::from_args())
+        .add_state(joyride::GameScreen::Menu);
 
     app.add_startup_stage_before(
         StartupStage::Startup,
@@ -106,9 +185,15 @@ pub fn setup_game(app: &mut AppBuilder) {
             road_systems
                 .startup_road
                 .label(StartupSystemLabels::StartupRoad),
+            road_shader_systems.startup_road_shader,
             rival_systems.startup_rivals,
             text_systems.startup_text,
+            results_systems.startup_results,
             skybox_systems.startup_skybox,
+            telemetry_systems.startup_telemetry,
+            menu_systems.startup_menu,
+            high_score_systems.startup_high_scores,
+            sfx_systems.startup_sfx,
         ],
     );
 
@@ -124,10 +209,16 @@ pub fn setup_game(app: &mut AppBuilder) {
     // TODO: Enforce that systems are labeled and added in game loop order sequence
     let mut builder = StageBuilder::new(CoreStage::Update, app);
 
-    builder.add_systems_after(None, vec![road_systems.test_curve_road]);
-
+    // Keys pressed while rebinding don't do anything else, so rebinding has to see them first
     builder.add_systems_after(
         None,
+        vec![joyride_systems
+            .update_rebinding
+            .label(GameSystemLabels::UpdateRebinding)],
+    );
+
+    builder.add_systems_after(
+        Some(GameSystemLabels::UpdateRebinding),
         vec![joyride_systems
             .update_input
             .label(GameSystemLabels::UpdateInput)],
@@ -135,14 +226,82 @@ pub fn setup_game(app: &mut AppBuilder) {
 
     builder.add_systems_after(
         Some(GameSystemLabels::UpdateInput),
+        vec![menu_systems.update_menu, results_systems.update_results],
+    );
+    builder.add_systems_after(
+        Some(GameSystemLabels::UpdateRebinding),
+        vec![session_systems
+            .request_quit
+            .before(GameSystemLabels::UpdateAttractMode)],
+    );
+    builder.add_racing_systems_after(None, vec![road_systems.test_curve_road]);
+
+    // Also watches the menu, which plays its own demo when left idle
+    builder.add_systems_after(
+        Some(GameSystemLabels::UpdateInput),
+        vec![attract_systems
+            .update_attract_mode
+            .label(GameSystemLabels::UpdateAttractMode)],
+    );
+
+    builder.add_racing_systems_after(
+        Some(GameSystemLabels::UpdateAttractMode),
+        vec![
+            joyride_systems
+                .restart_race
+                .label(GameSystemLabels::RestartRace),
+            replay_systems
+                .update_input_replay
+                .label(GameSystemLabels::RestartRace),
+        ],
+    );
+
+    // Resets also run off the racing screen, since dismissing the results restarts the race
+    builder.add_systems_after(
+        Some(GameSystemLabels::RestartRace),
+        vec![
+            joyride_systems
+                .reset_race
+                .label(GameSystemLabels::ResetRace),
+            replay_systems
+                .start_input_replay
+                .label(GameSystemLabels::ResetRace),
+            player_systems
+                .reset_player
+                .label(GameSystemLabels::ResetRace),
+            road_systems.reset_road.label(GameSystemLabels::ResetRace),
+            rival_systems
+                .reset_rivals
+                .label(GameSystemLabels::ResetRace),
+        ],
+    );
+
+    // Refilling the road with objects goes by where the road was reset to
+    builder.add_systems_after(
+        Some(GameSystemLabels::ResetRace),
+        vec![road_object_systems
+            .reset_road_objects
+            .label(GameSystemLabels::ResetRoadObjects)],
+    );
+
+    builder.add_racing_systems_after(
+        Some(GameSystemLabels::ResetRoadObjects),
+        vec![snapshot_systems
+            .update_snapshots
+            .before(GameSystemLabels::UpdatePlayerDriving)],
+    );
+
+    builder.add_racing_systems_after(
+        Some(GameSystemLabels::ResetRoadObjects),
         vec![player_systems
             .update_player_driving
             .label(GameSystemLabels::UpdatePlayerDriving)],
     );
 
-    builder.add_systems_after(
+    builder.add_racing_systems_after(
         Some(GameSystemLabels::UpdatePlayerDriving),
         vec![
+            joyride_systems.update_race_timer,
             text_systems.update_texts,
             player_systems
                 .update_player_road_position
@@ -150,19 +309,29 @@ pub fn setup_game(app: &mut AppBuilder) {
         ],
     );
 
-    builder.add_systems_after(
+    builder.add_racing_systems_after(
         Some(GameSystemLabels::UpdatePlayerRoadPosition),
+        vec![
+            track_systems
+                .extend_track
+                .label(GameSystemLabels::ExtendTrack),
+            attract_systems.drive_attract_player,
+        ],
+    );
+
+    builder.add_racing_systems_after(
+        Some(GameSystemLabels::ExtendTrack),
         vec![road_systems.update_road.label(GameSystemLabels::UpdateRoad)],
     );
 
-    builder.add_systems_after(
+    builder.add_racing_systems_after(
         Some(GameSystemLabels::UpdateRoad),
         vec![rival_systems
             .update_rivals
             .label(GameSystemLabels::UpdateRivals)],
     );
 
-    builder.add_systems_after(
+    builder.add_racing_systems_after(
         Some(GameSystemLabels::UpdateRivals),
         vec![road_object_systems
             .manage_road_objects
@@ -196,6 +365,41 @@ pub fn setup_game(app: &mut AppBuilder) {
             road_object_systems.update_road_object_visuals,
             road_systems.draw_road,
             debug_systems.update_debug_vis,
+            sfx_systems.play_sfx,
+            sfx_systems.play_ambience,
+        ],
+    );
+
+    let mut last_builder = StageBuilder::new(CoreStage::Last, app);
+    last_builder.add_racing_systems_after(
+        None,
+        vec![joyride_systems
+            .complete_frame
+            .label(GameSystemLabels::CompleteFrame)],
+    );
+    last_builder.add_racing_systems_after(
+        Some(GameSystemLabels::CompleteFrame),
+        vec![
+            telemetry_systems
+                .record_telemetry
+                .label(GameSystemLabels::RecordTelemetry),
+            high_score_systems.update_high_scores,
+            joyride_systems.show_results,
         ],
     );
+
+    // Not gated on racing, since the game can be quit from the menu too
+    last_builder.add_systems_after(
+        Some(GameSystemLabels::CompleteFrame),
+        vec![
+            session_systems.save_on_quit,
+            high_score_systems.save_high_scores_on_quit,
+        ],
+    );
+
+    // Also ungated, but the last rows recorded have to make it into the flush
+    last_builder.add_systems_after(
+        Some(GameSystemLabels::RecordTelemetry),
+        vec![telemetry_systems.flush_telemetry_on_quit],
+    );
 }
@@ -0,0 +1,203 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bevy::{
+    asset::AssetPlugin,
+    input::InputPlugin,
+    prelude::*,
+    render::{pipeline::PipelineDescriptor, render_graph::RenderGraph},
+    transform::TransformPlugin,
+};
+use bevy_kira_audio::{Audio, AudioSource};
+
+use crate::{
+    debug::LoopSectionTimer,
+    fixed_framerate::{FixedFramerateStats, Paused},
+    game,
+    high_score::HighScoreConfig,
+    joyride::{GameScreen, RaceState},
+    player::Player,
+    racer::Racer,
+    replay::{InputReplay, RecordedInput},
+    rival::Rival,
+    road::{RoadDynamic, RoadRenderMode, RoadSegment},
+    util,
+};
+
+// How many straight segments a straight track is laid with, which is far more than any test drives
+const STRAIGHT_NUM_SEGS: usize = 64;
+
+// The countdown shouldn't take anywhere near this long. It only guards against it never ending
+const MAX_COUNTDOWN_TICKS: u32 = 1000;
+
+// Numbers each headless app's high score file, so tests running side by side don't share one
+static NEXT_APP_IDX: AtomicUsize = AtomicUsize::new(0);
+
+// The gameplay systems, without a window or any rendering, for driving the game from code.
+// Nothing is drawn, but the systems that set up sprites still need somewhere to put them, so the
+// asset stores they use are registered empty. Textures and sounds just fail to load.
+// There's no fixed framerate run criteria, so each update is exactly one tick
+pub fn build_headless_app() -> App {
+    let mut app_builder = App::build();
+
+    app_builder
+        .insert_resource(LoopSectionTimer::new())
+        .insert_resource(FixedFramerateStats::default())
+        .insert_resource(Paused::default())
+        .insert_resource(RoadRenderMode::Cpu)
+        .insert_resource(InputReplay::Scripted(RecordedInput::default()))
+        .init_resource::<util::TextureLoader>()
+        .add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin)
+        .add_plugin(TransformPlugin)
+        .add_plugin(InputPlugin)
+        .add_asset::<Texture>()
+        .add_asset::<TextureAtlas>()
+        .add_asset::<ColorMaterial>()
+        .add_asset::<Mesh>()
+        .add_asset::<Shader>()
+        .add_asset::<PipelineDescriptor>()
+        .add_asset::<AudioSource>()
+        .init_resource::<RenderGraph>()
+        .init_resource::<Audio>()
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            util::propagate_visibility_system.system(),
+        );
+
+    game::setup_game(&mut app_builder);
+
+    // Finishing a race saves the high scores, which shouldn't land in the working directory.
+    // Each app starts with none, whatever an earlier run left behind
+    let app_idx = NEXT_APP_IDX.fetch_add(1, Ordering::Relaxed);
+    let high_scores_file = format!("joyride_headless_{}_{}.ron", std::process::id(), app_idx);
+    let path = std::env::temp_dir().join(high_scores_file);
+    let path = path.to_str().unwrap().to_string();
+    let _ = std::fs::remove_file(&path);
+    app_builder.insert_resource(HighScoreConfig { path });
+
+    // Straight into the race, skipping the menu
+    app_builder.insert_resource(State::new(GameScreen::Racing));
+
+    app_builder.app
+}
+
+// Advances the game by one tick, with the given actions held for it
+pub fn step(app: &mut App, input: RecordedInput) {
+    *app.world.get_resource_mut::<InputReplay>().unwrap() = InputReplay::Scripted(input);
+    app.update();
+}
+
+// Runs startup, swaps the track for a long straight, and waits out the countdown, leaving the
+// player stopped at the start with control of the bike
+pub fn start_race_on_straight(app: &mut App) {
+    // The first update runs startup, which lays down the track the straight replaces
+    step(app, RecordedInput::default());
+    let straight = RoadSegment {
+        curve: 0.0,
+        hill: 0.0,
+        spawn_object_type: None,
+        ambient_tint: None,
+        audio_cue: None,
+        checkpoint: false,
+        spawn_table: None,
+    };
+    app.world
+        .get_resource_mut::<RoadDynamic>()
+        .unwrap()
+        .replace_segs(vec![straight; STRAIGHT_NUM_SEGS]);
+
+    let mut countdown_ticks = 0;
+    while *app.world.get_resource::<RaceState>().unwrap() == RaceState::Countdown {
+        assert!(
+            countdown_ticks < MAX_COUNTDOWN_TICKS,
+            "The countdown never finished"
+        );
+        step(app, RecordedInput::default());
+        countdown_ticks += 1;
+    }
+}
+
+// Takes every rival off the road, for checks that need the player to drive undisturbed. Rivals
+// keep spawning as the player drives, so this needs doing every step
+pub fn clear_rivals(app: &mut App) {
+    let rivals: Vec<Entity> = app
+        .world
+        .query_filtered::<Entity, With<Rival>>()
+        .iter(&app.world)
+        .collect();
+    for rival in rivals {
+        despawn_recursive(&mut app.world, rival);
+    }
+}
+
+fn despawn_recursive(world: &mut World, ent: Entity) {
+    let children: Vec<Entity> = world
+        .get::<Children>(ent)
+        .map_or_else(Vec::new, |children| children.iter().copied().collect());
+    for child in children {
+        despawn_recursive(world, child);
+    }
+    world.despawn(ent);
+}
+
+// The one player's components, for checking on or setting up
+pub fn get_player_mut(app: &mut App) -> (Mut<'_, Player>, Mut<'_, Racer>) {
+    let mut players = app.world.query::<(&mut Player, &mut Racer)>();
+    players
+        .iter_mut(&mut app.world)
+        .next()
+        .expect("There's no player")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::PlayerTuning;
+
+    // How long accel is held for once the countdown is over
+    const ACCEL_TICKS: u32 = 600;
+
+    // Holds accel down a straight road, and checks the bike gets up to speed and actually goes
+    // somewhere
+    #[test]
+    fn held_accel_gets_up_to_speed() {
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+
+        let start_distance = app
+            .world
+            .get_resource::<RoadDynamic>()
+            .unwrap()
+            .get_distance_traveled();
+
+        let accel = RecordedInput {
+            accel: true,
+            ..Default::default()
+        };
+        for _ in 0..ACCEL_TICKS {
+            step(&mut app, accel);
+        }
+
+        let speed = get_player_mut(&mut app).1.speed;
+        let distance = app
+            .world
+            .get_resource::<RoadDynamic>()
+            .unwrap()
+            .get_distance_traveled()
+            - start_distance;
+
+        // A little short of the cap, as acceleration tails off approaching it
+        let max_normal_speed = app
+            .world
+            .get_resource::<PlayerTuning>()
+            .unwrap()
+            .max_normal_speed;
+        assert!(
+            speed >= max_normal_speed * 0.95,
+            "Speed {} never got near {}",
+            speed,
+            max_normal_speed
+        );
+        assert!(distance > 0.0, "The player didn't go anywhere");
+    }
+}
@@ -0,0 +1,300 @@
+use bevy::{app::AppExit, prelude::*};
+
+use crate::{
+    joyride::{GameRng, GameScreen, JoyrideGame, RaceState},
+    road::RoadDynamic,
+};
+
+pub struct Systems {
+    pub startup_high_scores: SystemSet,
+    pub update_high_scores: SystemSet,
+    pub save_high_scores_on_quit: SystemSet,
+}
+
+impl Systems {
+    pub fn new() -> Self {
+        Self {
+            startup_high_scores: SystemSet::new().with_system(startup_high_scores.system()),
+            update_high_scores: SystemSet::new().with_system(update_high_scores.system()),
+            save_high_scores_on_quit: SystemSet::new()
+                .with_system(save_high_scores_on_quit.system()),
+        }
+    }
+}
+
+pub struct HighScoreConfig {
+    pub path: String,
+}
+
+impl Default for HighScoreConfig {
+    fn default() -> Self {
+        Self {
+            path: "high_scores.ron".to_string(),
+        }
+    }
+}
+
+// The seed is kept alongside the score, so that the record run can be played again
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct ScoreRecord {
+    pub value: f32,
+    pub seed: u64,
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct HighScores {
+    // The furthest distance down the track reached in a single run
+    pub best_distance: Option<ScoreRecord>,
+
+    // The fastest time to the final checkpoint, in seconds
+    pub best_time: Option<ScoreRecord>,
+
+    // Whether there are new records that haven't been written to disk yet
+    #[serde(skip)]
+    is_dirty: bool,
+}
+
+impl HighScores {
+    // A missing file just means no records have been set yet
+    fn load(path: &str) -> Self {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(_) => return Self::default(),
+        };
+
+        match ron::de::from_reader(file) {
+            Ok(high_scores) => high_scores,
+            Err(e) => {
+                warn!(
+                    "Failed to parse high scores from {}, starting fresh: {}",
+                    path, e
+                );
+                Self::default()
+            }
+        }
+    }
+
+    fn save(&mut self, path: &str) {
+        let result = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())
+            .map_err(|e| e.to_string())
+            .and_then(|text| std::fs::write(path, text).map_err(|e| e.to_string()));
+
+        match result {
+            Ok(_) => self.is_dirty = false,
+            Err(e) => warn!("Failed to save high scores to {}: {}", path, e),
+        }
+    }
+
+    fn submit_distance(&mut self, distance: f32, seed: u64) {
+        // Quitting before going anywhere isn't worth recording
+        let is_record = distance > 0.0
+            && self
+                .best_distance
+                .map_or(true, |record| distance > record.value);
+        if is_record {
+            self.best_distance = Some(ScoreRecord {
+                value: distance,
+                seed,
+            });
+            self.is_dirty = true;
+        }
+    }
+
+    fn submit_time(&mut self, secs: f32, seed: u64) {
+        let is_record = self.best_time.map_or(true, |record| secs < record.value);
+        if is_record {
+            self.best_time = Some(ScoreRecord { value: secs, seed });
+            self.is_dirty = true;
+        }
+    }
+}
+
+fn startup_high_scores(mut commands: Commands, config: Res<HighScoreConfig>) {
+    commands.insert_resource(HighScores::load(&config.path));
+}
+
+// A run ends at the final checkpoint or finish line, or when the race timer runs out
+fn update_high_scores(
+    config: Res<HighScoreConfig>,
+    mut high_scores: ResMut<HighScores>,
+    race_state: Res<RaceState>,
+    game: Res<JoyrideGame>,
+    game_rng: Res<GameRng>,
+    road_dyn: Res<RoadDynamic>,
+    screen: Res<State<GameScreen>>,
+) {
+    // The demo isn't the player's run, so it doesn't count
+    if !race_state.is_changed()
+        || *race_state != RaceState::Finished
+        || *screen.current() == GameScreen::Demo
+    {
+        return;
+    }
+
+    let seed = game_rng.seed();
+    if let Some(completion_secs) = game.completion_secs {
+        high_scores.submit_time(completion_secs, seed);
+    }
+
+    high_scores.submit_distance(road_dyn.get_distance_traveled(), seed);
+    if high_scores.is_dirty {
+        high_scores.save(&config.path);
+    }
+}
+
+// A run that's quit partway through still counts for distance
+fn save_high_scores_on_quit(
+    config: Res<HighScoreConfig>,
+    mut high_scores: ResMut<HighScores>,
+    mut exit_events: EventReader<AppExit>,
+    game_rng: Res<GameRng>,
+    road_dyn: Res<RoadDynamic>,
+    screen: Res<State<GameScreen>>,
+) {
+    if exit_events.iter().next().is_none() || *screen.current() == GameScreen::Demo {
+        return;
+    }
+
+    high_scores.submit_distance(road_dyn.get_distance_traveled(), game_rng.seed());
+    if high_scores.is_dirty {
+        high_scores.save(&config.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::app::Events;
+
+    use super::*;
+    use crate::{
+        headless::{build_headless_app, clear_rivals, start_race_on_straight, step},
+        joyride::{change_screen, RaceRestarted},
+        replay::RecordedInput,
+        road::CheckpointReached,
+    };
+
+    // Drives for the given number of ticks, then crosses the final checkpoint. Returns how long
+    // the race says it took
+    fn finish_race(app: &mut App, ticks: u32) -> f32 {
+        let accel = RecordedInput {
+            accel: true,
+            ..Default::default()
+        };
+        for _ in 0..ticks {
+            clear_rivals(app);
+            step(app, accel);
+        }
+
+        app.world
+            .get_resource_mut::<Events<CheckpointReached>>()
+            .unwrap()
+            .send(CheckpointReached { is_final: true });
+        step(app, accel);
+
+        assert_eq!(
+            *app.world.get_resource::<RaceState>().unwrap(),
+            RaceState::Finished
+        );
+        app.world
+            .get_resource::<JoyrideGame>()
+            .unwrap()
+            .completion_secs
+            .expect("The race finished without a completion time")
+    }
+
+    #[test]
+    fn high_scores_round_trip_through_a_file() {
+        let mut high_scores = HighScores::default();
+        high_scores.submit_distance(1234.5, 42);
+        high_scores.submit_time(61.25, 7);
+        assert!(high_scores.is_dirty);
+
+        let path = std::env::temp_dir().join("joyride_test_high_scores.ron");
+        let path = path.to_str().unwrap();
+        high_scores.save(path);
+        assert!(!high_scores.is_dirty);
+
+        let loaded = HighScores::load(path);
+        std::fs::remove_file(path).unwrap();
+
+        let best_distance = loaded.best_distance.unwrap();
+        assert_eq!(best_distance.value, 1234.5);
+        assert_eq!(best_distance.seed, 42);
+        let best_time = loaded.best_time.unwrap();
+        assert_eq!(best_time.value, 61.25);
+        assert_eq!(best_time.seed, 7);
+    }
+
+    #[test]
+    fn missing_high_scores_start_empty() {
+        let high_scores = HighScores::load("no_such_high_scores.ron");
+        assert!(high_scores.best_distance.is_none());
+        assert!(high_scores.best_time.is_none());
+    }
+
+    #[test]
+    fn only_better_scores_are_records() {
+        let mut high_scores = HighScores::default();
+        high_scores.submit_time(60.0, 1);
+        high_scores.submit_time(70.0, 2);
+        high_scores.submit_distance(500.0, 1);
+        high_scores.submit_distance(400.0, 2);
+
+        assert_eq!(high_scores.best_time.unwrap().seed, 1);
+        assert_eq!(high_scores.best_distance.unwrap().seed, 1);
+    }
+
+    // The second race is quicker, so it should be the record, and with its own time rather than
+    // one that carries on counting from the first race
+    #[test]
+    fn back_to_back_races_record_their_own_time() {
+        let path = std::env::temp_dir().join("joyride_test_race_high_scores.ron");
+        let path = path.to_str().unwrap().to_string();
+        let _ = std::fs::remove_file(&path);
+
+        let mut app = build_headless_app();
+        app.world
+            .insert_resource(HighScoreConfig { path: path.clone() });
+
+        start_race_on_straight(&mut app);
+        let first_secs = finish_race(&mut app, 300);
+        assert_eq!(
+            app.world
+                .get_resource::<HighScores>()
+                .unwrap()
+                .best_time
+                .unwrap()
+                .value,
+            first_secs
+        );
+
+        // Let the results come up, then go straight back into another race
+        step(&mut app, RecordedInput::default());
+        app.world
+            .get_resource_mut::<Events<RaceRestarted>>()
+            .unwrap()
+            .send(RaceRestarted);
+        change_screen(
+            &mut app.world.get_resource_mut::<State<GameScreen>>().unwrap(),
+            GameScreen::Racing,
+        );
+        start_race_on_straight(&mut app);
+        let second_secs = finish_race(&mut app, 100);
+        assert!(second_secs < first_secs);
+
+        let best_time = app
+            .world
+            .get_resource::<HighScores>()
+            .unwrap()
+            .best_time
+            .unwrap()
+            .value;
+        assert_eq!(best_time, second_secs);
+        assert_eq!(
+            HighScores::load(&path).best_time.unwrap().value,
+            second_secs
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
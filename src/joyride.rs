@@ -1,4 +1,16 @@
-use bevy::prelude::*;
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::{
+    ecs::schedule::{ShouldRun, StateError},
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use easy_cast::*;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    attract::AttractMode, replay::InputReplay, road::CheckpointReached, util::timer_serde,
+};
 
 pub const FIELD_WIDTH: u32 = 320;
 pub const FIELD_HEIGHT: u32 = 240;
@@ -6,8 +18,275 @@ pub const FIELD_HEIGHT: u32 = 240;
 // We lock the framerate, since this is a retro-style game, after all
 pub const TIME_STEP: f32 = 1.0 / 30.0;
 
+// Scales how much game time passes each fixed step, for slow motion. The scheduler's rate is
+// unaffected, so a scale of 0.5 runs everything at half speed just as smoothly
+pub struct TimeScale(pub f32);
+
+impl Default for TimeScale {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+// The game time that passes this step
+pub fn scaled_step(time_scale: &TimeScale) -> f32 {
+    TIME_STEP * time_scale.0
+}
+
+// How long the countdown before the race runs, in whole seconds
+pub const COUNTDOWN_SECS: u32 = 3;
+
+// Picked from the menu before the race. Only the world scales with it, the player handles the
+// same on every difficulty
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty::Normal
+    }
+}
+
+pub struct DifficultySettings {
+    // Multiplies how fast rivals drive
+    pub rival_speed: f32,
+
+    // Multiplies the weight of obstacles in a spawn table, against the other entries. Below 1,
+    // it's also the chance of a segment's fixed obstacle spawning at all
+    pub obstacle_weight: f32,
+
+    // How long the race timer starts with, before any checkpoints extend it
+    pub race_secs: f32,
+
+    // The most rivals speed up when the player is on their tail, or slow down when they've
+    // pulled far ahead, as a fraction of their speed
+    pub rubber_band: f32,
+}
+
+const EASY_SETTINGS: DifficultySettings = DifficultySettings {
+    rival_speed: 0.8,
+    obstacle_weight: 0.6,
+    race_secs: 120.0,
+    rubber_band: 0.15,
+};
+const NORMAL_SETTINGS: DifficultySettings = DifficultySettings {
+    rival_speed: 1.0,
+    obstacle_weight: 1.0,
+    race_secs: 100.0,
+    rubber_band: 0.25,
+};
+const HARD_SETTINGS: DifficultySettings = DifficultySettings {
+    rival_speed: 1.25,
+    obstacle_weight: 1.5,
+    race_secs: 85.0,
+    rubber_band: 0.35,
+};
+
+impl Difficulty {
+    pub fn settings(self) -> &'static DifficultySettings {
+        match self {
+            Difficulty::Easy => &EASY_SETTINGS,
+            Difficulty::Normal => &NORMAL_SETTINGS,
+            Difficulty::Hard => &HARD_SETTINGS,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "EASY",
+            Difficulty::Normal => "NORMAL",
+            Difficulty::Hard => "HARD",
+        }
+    }
+
+    pub fn easier(self) -> Self {
+        match self {
+            Difficulty::Easy | Difficulty::Normal => Difficulty::Easy,
+            Difficulty::Hard => Difficulty::Normal,
+        }
+    }
+
+    pub fn harder(self) -> Self {
+        match self {
+            Difficulty::Easy => Difficulty::Normal,
+            Difficulty::Normal | Difficulty::Hard => Difficulty::Hard,
+        }
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct JoyrideGame {
+    // Counts down while racing. Checkpoints extend it
+    #[serde(with = "timer_serde")]
     pub remaining_time: Timer,
+
+    // Counts down before the race starts
+    #[serde(with = "timer_serde")]
+    pub countdown: Timer,
+
+    // How long the race took, if it was completed rather than running out of time
+    pub completion_secs: Option<f32>,
+}
+
+impl JoyrideGame {
+    pub fn new(difficulty: Difficulty) -> Self {
+        Self {
+            remaining_time: Timer::from_seconds(difficulty.settings().race_secs, false),
+            countdown: Timer::from_seconds(COUNTDOWN_SECS as f32, false),
+            completion_secs: None,
+        }
+    }
+}
+
+// Sent when the player restarts the race, leaves the results for the menu, or picks a new
+// difficulty. Each module puts its own part of the race back to how it started, without
+// respawning anything that lasts the whole session
+pub struct RaceRestarted;
+
+// Points earned for every unit of Z the player travels
+const SCORE_POINTS_PER_Z: f32 = 10.0;
+
+// Points earned over the race, from distance, near misses and pickups
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct Score {
+    pub points: u32,
+
+    // Distance points accrue fractionally, so this holds whatever hasn't made a whole point yet
+    distance_remainder: f32,
+}
+
+impl Score {
+    pub fn add_distance(&mut self, advance_z: f32) {
+        let earned = self.distance_remainder + (advance_z * SCORE_POINTS_PER_Z);
+        let whole_points = earned.floor();
+        self.points += u32::conv_trunc(whole_points);
+        self.distance_remainder = earned - whole_points;
+    }
+}
+
+// The player only has control while Racing. The race finishes when the timer runs out,
+// or at the final checkpoint or finish line
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+pub enum RaceState {
+    Countdown,
+    Racing,
+    Finished,
+}
+
+impl Default for RaceState {
+    fn default() -> Self {
+        Self::Countdown
+    }
+}
+
+// The game starts at the menu, and only simulates the race once it's been started.
+// Once the race is finished, the results stay up until a key returns to the menu.
+// Left idle, the menu plays a demo race behind it
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum GameScreen {
+    Menu,
+    Racing,
+    Results,
+    Demo,
+}
+
+impl GameScreen {
+    // Whether the race is being simulated, either for the player or as the demo
+    pub fn is_race_running(self) -> bool {
+        self == GameScreen::Racing || self == GameScreen::Demo
+    }
+}
+
+// Several systems can ask for a screen change in the same frame, such as a restart and the race
+// finishing. The first change asked for wins, and asking for the screen that's already up does
+// nothing
+pub fn change_screen(screen: &mut State<GameScreen>, next: GameScreen) {
+    match screen.set(next) {
+        Ok(()) | Err(StateError::AlreadyInState) | Err(StateError::StateAlreadyQueued) => {}
+        Err(err) => panic!("Couldn't change screen: {:?}", err),
+    }
+}
+
+pub fn run_if_racing(screen: Res<State<GameScreen>>) -> ShouldRun {
+    if screen.current().is_race_running() {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+// Counts simulation steps. The schedule only runs on fixed steps, so this never advances on
+// render-only frames, and a given frame index always refers to the same point in the simulation
+#[derive(Default)]
+pub struct FrameCounter {
+    frame_index: u64,
+    sim_time: Duration,
+}
+
+impl FrameCounter {
+    pub fn frame_index(&self) -> u64 {
+        self.frame_index
+    }
+}
+
+// Sent at the very end of each simulation step, for tooling that needs to key off of frames
+pub struct FrameComplete {
+    pub frame_index: u64,
+    pub sim_time: Duration,
+}
+
+// Each consumer of seeded randomness draws from its own stream. See GameRng::make_stream
+#[derive(Clone, Copy)]
+pub enum RngStream {
+    Track = 1,
+    RoadObjects = 2,
+    SpawnTables = 3,
+}
+
+// The source of all seeded randomness, so that a run can be reproduced from its seed
+pub struct GameRng {
+    seed: u64,
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        Self::new(rand::random())
+    }
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    // Plays a specific seed if one was given with `--seed <number>`, otherwise a random one
+    pub fn from_args() -> Self {
+        let args: Vec<String> = std::env::args().collect();
+        let seed_arg = args
+            .windows(2)
+            .find(|pair| pair[0] == "--seed")
+            .and_then(|pair| pair[1].parse().ok());
+
+        match seed_arg {
+            Some(seed) => Self::new(seed),
+            None => Self::default(),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Creates an independent random stream for one consumer, so that how much randomness
+    // one consumer uses doesn't perturb the results of another
+    pub fn make_stream(&self, stream: RngStream) -> StdRng {
+        let stream = stream as u64;
+        StdRng::seed_from_u64(self.seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
 }
 
 #[derive(PartialEq, Eq)]
@@ -39,28 +318,260 @@ pub struct JoyrideInput {
     pub accel: JoyrideInputState,
     pub brake: JoyrideInputState,
     pub turbo: JoyrideInputState,
+    pub restart: JoyrideInputState,
     pub debug: JoyrideInputState,
+    pub debug_collision: JoyrideInputState,
+    pub debug_road_seg_boundaries: JoyrideInputState,
+    pub debug_gameplay: JoyrideInputState,
+    pub debug_framerate: JoyrideInputState,
+    pub debug_perf: JoyrideInputState,
+    pub debug_save_snapshot: JoyrideInputState,
+    pub debug_load_snapshot: JoyrideInputState,
+    pub debug_record_input: JoyrideInputState,
+    pub debug_play_recording: JoyrideInputState,
+    pub debug_camera_left: JoyrideInputState,
+    pub debug_camera_right: JoyrideInputState,
+    pub debug_camera_up: JoyrideInputState,
+    pub debug_camera_down: JoyrideInputState,
+    pub debug_camera_zoom_in: JoyrideInputState,
+    pub debug_camera_zoom_out: JoyrideInputState,
+
+    // Anything at all on the player's own keyboard or gamepads, bound to an action or not.
+    // Demos drive the actions above while they play, but never this, so it's how the player is
+    // noticed coming back. Rebinding doesn't hold it back either
+    pub any: JoyrideInputState,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum JoyrideAction {
+    Left,
+    Right,
+    Up,
+    Down,
+    Accel,
+    Brake,
+    Turbo,
+    Restart,
+    Debug,
+    DebugCollision,
+    DebugRoadSegBoundaries,
+    DebugGameplay,
+    DebugFramerate,
+    DebugPerf,
+    DebugSaveSnapshot,
+    DebugLoadSnapshot,
+    DebugRecordInput,
+    DebugPlayRecording,
+    DebugCameraLeft,
+    DebugCameraRight,
+    DebugCameraUp,
+    DebugCameraDown,
+    DebugCameraZoomIn,
+    DebugCameraZoomOut,
+}
+
+// Pause is read by the fixed framerate run criteria, outside the bindings, so it's kept off every
+// action, and rebinding passes over it the same as Escape
+pub const PAUSE_KEY: KeyCode = KeyCode::P;
+
+pub struct InputBindings {
+    bindings: HashMap<JoyrideAction, KeyCode>,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        let bindings = [
+            (JoyrideAction::Left, KeyCode::Left),
+            (JoyrideAction::Right, KeyCode::Right),
+            (JoyrideAction::Up, KeyCode::Up),
+            (JoyrideAction::Down, KeyCode::Down),
+            (JoyrideAction::Accel, KeyCode::Z),
+            (JoyrideAction::Brake, KeyCode::X),
+            (JoyrideAction::Turbo, KeyCode::C),
+            (JoyrideAction::Restart, KeyCode::R),
+            (JoyrideAction::Debug, KeyCode::Grave),
+            (JoyrideAction::DebugCollision, KeyCode::F1),
+            (JoyrideAction::DebugRoadSegBoundaries, KeyCode::F2),
+            (JoyrideAction::DebugGameplay, KeyCode::F3),
+            (JoyrideAction::DebugFramerate, KeyCode::F4),
+            (JoyrideAction::DebugPerf, KeyCode::F5),
+            (JoyrideAction::DebugSaveSnapshot, KeyCode::F6),
+            (JoyrideAction::DebugLoadSnapshot, KeyCode::F7),
+            (JoyrideAction::DebugRecordInput, KeyCode::F8),
+            (JoyrideAction::DebugPlayRecording, KeyCode::F9),
+            (JoyrideAction::DebugCameraLeft, KeyCode::J),
+            (JoyrideAction::DebugCameraRight, KeyCode::L),
+            (JoyrideAction::DebugCameraUp, KeyCode::I),
+            (JoyrideAction::DebugCameraDown, KeyCode::K),
+            (JoyrideAction::DebugCameraZoomIn, KeyCode::U),
+            (JoyrideAction::DebugCameraZoomOut, KeyCode::O),
+        ];
+
+        Self {
+            bindings: bindings.iter().cloned().collect(),
+        }
+    }
+}
+
+impl InputBindings {
+    pub fn is_pressed(&self, action: JoyrideAction, input: &Input<KeyCode>) -> bool {
+        self.bindings
+            .get(&action)
+            .map_or(false, |key| input.pressed(*key))
+    }
+
+    // Binding a key that's already used by another action swaps the two actions' keys
+    pub fn rebind(&mut self, action: JoyrideAction, key: KeyCode) {
+        let old_key = self.bindings.insert(action, key);
+        let conflicting_action = self
+            .bindings
+            .iter()
+            .find(|(other_action, other_key)| **other_action != action && **other_key == key)
+            .map(|(other_action, _)| *other_action);
+
+        if let Some(conflicting_action) = conflicting_action {
+            match old_key {
+                Some(old_key) => self.bindings.insert(conflicting_action, old_key),
+                None => self.bindings.remove(&conflicting_action),
+            };
+        }
+    }
+}
+
+// How far the left stick has to be pushed before it counts as a press
+const GAMEPAD_STICK_DEADZONE: f32 = 0.5;
+
+// Gamepads are tracked from connection events, so that one being unplugged mid-race just stops
+// contributing input instead of leaving stale button states behind
+#[derive(Default)]
+pub struct ConnectedGamepads {
+    gamepads: HashSet<Gamepad>,
+}
+
+impl ConnectedGamepads {
+    fn is_pressed(
+        &self,
+        action: JoyrideAction,
+        buttons: &Input<GamepadButton>,
+        axes: &Axis<GamepadAxis>,
+    ) -> bool {
+        self.gamepads
+            .iter()
+            .any(|gamepad| is_gamepad_pressed(*gamepad, action, buttons, axes))
+    }
+
+    // Any button at all, or the stick pushed any way
+    fn is_any_pressed(&self, buttons: &Input<GamepadButton>, axes: &Axis<GamepadAxis>) -> bool {
+        let directions = [
+            JoyrideAction::Left,
+            JoyrideAction::Right,
+            JoyrideAction::Up,
+            JoyrideAction::Down,
+        ];
+        buttons
+            .get_pressed()
+            .any(|button| self.gamepads.contains(&button.0))
+            || directions
+                .iter()
+                .any(|action| self.is_pressed(*action, buttons, axes))
+    }
+}
+
+fn is_gamepad_pressed(
+    gamepad: Gamepad,
+    action: JoyrideAction,
+    buttons: &Input<GamepadButton>,
+    axes: &Axis<GamepadAxis>,
+) -> bool {
+    let button = |button_type| buttons.pressed(GamepadButton(gamepad, button_type));
+
+    // An axis with no value yet reads as centered
+    let axis = |axis_type| axes.get(GamepadAxis(gamepad, axis_type)).unwrap_or(0.0);
+    let stick_x = axis(GamepadAxisType::LeftStickX);
+    let stick_y = axis(GamepadAxisType::LeftStickY);
+
+    match action {
+        JoyrideAction::Left => {
+            button(GamepadButtonType::DPadLeft) || stick_x < -GAMEPAD_STICK_DEADZONE
+        }
+        JoyrideAction::Right => {
+            button(GamepadButtonType::DPadRight) || stick_x > GAMEPAD_STICK_DEADZONE
+        }
+        JoyrideAction::Up => button(GamepadButtonType::DPadUp) || stick_y > GAMEPAD_STICK_DEADZONE,
+        JoyrideAction::Down => {
+            button(GamepadButtonType::DPadDown) || stick_y < -GAMEPAD_STICK_DEADZONE
+        }
+        JoyrideAction::Accel => button(GamepadButtonType::South),
+        JoyrideAction::Brake => button(GamepadButtonType::West),
+        JoyrideAction::Turbo => button(GamepadButtonType::East),
+        JoyrideAction::Restart => button(GamepadButtonType::Select),
+
+        // Debug functions are keyboard-only
+        _ => false,
+    }
+}
+
+// While active, each key pressed is bound to the next action waiting for one, and gameplay
+// input is ignored
+#[derive(Default)]
+pub struct RebindState {
+    pending: VecDeque<JoyrideAction>,
+
+    // Set for the step the last key was bound, or rebinding was backed out of, so that the key
+    // doesn't also act on anything else
+    just_finished: bool,
+}
+
+impl RebindState {
+    // The actions are bound in order, one for each key pressed
+    pub fn start(&mut self, actions: &[JoyrideAction]) {
+        self.pending = actions.iter().cloned().collect();
+    }
+
+    pub fn current_action(&self) -> Option<JoyrideAction> {
+        self.pending.front().cloned()
+    }
+
+    // Whether keys pressed this step belong to rebinding rather than anything else
+    pub fn is_active(&self) -> bool {
+        !self.pending.is_empty() || self.just_finished
+    }
 }
 
 pub struct Systems {
     pub startup_joyride: SystemSet,
     pub update_input: SystemSet,
+    pub update_rebinding: SystemSet,
+    pub restart_race: SystemSet,
+    pub reset_race: SystemSet,
+    pub update_race_timer: SystemSet,
+    pub complete_frame: SystemSet,
+    pub show_results: SystemSet,
 }
 
 impl Systems {
     pub fn new() -> Self {
         Self {
             startup_joyride: SystemSet::new().with_system(startup_joyride.system()),
-            update_input: SystemSet::new().with_system(update_input.system()),
+            update_input: SystemSet::new()
+                .with_system(update_gamepads.system().label("update_gamepads"))
+                .with_system(update_input.system().after("update_gamepads")),
+            update_rebinding: SystemSet::new().with_system(update_rebinding.system()),
+            restart_race: SystemSet::new().with_system(restart_race.system()),
+            reset_race: SystemSet::new().with_system(reset_race.system()),
+            update_race_timer: SystemSet::new().with_system(update_race_timer.system()),
+            complete_frame: SystemSet::new().with_system(complete_frame.system()),
+            show_results: SystemSet::new().with_system(show_results.system()),
         }
     }
 }
 
-fn startup_joyride(mut commands: Commands) {
-    commands.insert_resource(JoyrideGame {
-        remaining_time: Timer::from_seconds(100.0, false),
-    });
+fn startup_joyride(mut commands: Commands, difficulty: Res<Difficulty>) {
+    commands.insert_resource(JoyrideGame::new(*difficulty));
     commands.insert_resource(JoyrideInput::default());
+    commands.insert_resource(InputBindings::default());
+    commands.insert_resource(RebindState::default());
+    commands.insert_resource(ConnectedGamepads::default());
 
     let mut camera = OrthographicCameraBundle::new_2d();
     camera.orthographic_projection.scaling_mode = bevy::render::camera::ScalingMode::None;
@@ -71,15 +582,232 @@ fn startup_joyride(mut commands: Commands) {
     commands.spawn_bundle(camera);
 }
 
-fn update_input(input: Res<Input<KeyCode>>, mut input_state: ResMut<JoyrideInput>) {
-    update_input_state(&mut input_state.left, input.pressed(KeyCode::Left));
-    update_input_state(&mut input_state.right, input.pressed(KeyCode::Right));
-    update_input_state(&mut input_state.up, input.pressed(KeyCode::Up));
-    update_input_state(&mut input_state.down, input.pressed(KeyCode::Down));
-    update_input_state(&mut input_state.accel, input.pressed(KeyCode::Z));
-    update_input_state(&mut input_state.brake, input.pressed(KeyCode::X));
-    update_input_state(&mut input_state.turbo, input.pressed(KeyCode::C));
-    update_input_state(&mut input_state.debug, input.pressed(KeyCode::P));
+fn update_gamepads(
+    mut connected: ResMut<ConnectedGamepads>,
+    mut gamepad_events: EventReader<GamepadEvent>,
+) {
+    for GamepadEvent(gamepad, event_type) in gamepad_events.iter() {
+        match event_type {
+            GamepadEventType::Connected => {
+                connected.gamepads.insert(*gamepad);
+            }
+            GamepadEventType::Disconnected => {
+                connected.gamepads.remove(gamepad);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Runs on every screen, as the menus and attract mode go by it too
+fn update_input(
+    input: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    gamepads: Res<ConnectedGamepads>,
+    bindings: Res<InputBindings>,
+    rebind_state: Res<RebindState>,
+    attract: Res<AttractMode>,
+    replay: Res<InputReplay>,
+    mut input_state: ResMut<JoyrideInput>,
+) {
+    // Nothing counts as pressed while rebinding, so the key being bound doesn't also drive the bike
+    let is_rebinding = rebind_state.is_active();
+    let playback_input = replay.playback_input();
+    let pressed = |action| {
+        if let Some(playback_input) = playback_input {
+            playback_input.is_pressed(action)
+        } else if attract.is_active() {
+            attract.is_pressed(action)
+        } else {
+            // The keyboard and any gamepads can be used interchangeably
+            !is_rebinding
+                && (bindings.is_pressed(action, &input)
+                    || gamepads.is_pressed(action, &gamepad_buttons, &gamepad_axes))
+        }
+    };
+
+    let is_any_pressed = input.get_pressed().next().is_some()
+        || gamepads.is_any_pressed(&gamepad_buttons, &gamepad_axes);
+    update_input_state(&mut input_state.any, is_any_pressed);
+
+    update_input_state(&mut input_state.left, pressed(JoyrideAction::Left));
+    update_input_state(&mut input_state.right, pressed(JoyrideAction::Right));
+    update_input_state(&mut input_state.up, pressed(JoyrideAction::Up));
+    update_input_state(&mut input_state.down, pressed(JoyrideAction::Down));
+    update_input_state(&mut input_state.accel, pressed(JoyrideAction::Accel));
+    update_input_state(&mut input_state.brake, pressed(JoyrideAction::Brake));
+    update_input_state(&mut input_state.turbo, pressed(JoyrideAction::Turbo));
+    update_input_state(&mut input_state.restart, pressed(JoyrideAction::Restart));
+    update_input_state(&mut input_state.debug, pressed(JoyrideAction::Debug));
+    update_input_state(
+        &mut input_state.debug_collision,
+        pressed(JoyrideAction::DebugCollision),
+    );
+    update_input_state(
+        &mut input_state.debug_road_seg_boundaries,
+        pressed(JoyrideAction::DebugRoadSegBoundaries),
+    );
+    update_input_state(
+        &mut input_state.debug_gameplay,
+        pressed(JoyrideAction::DebugGameplay),
+    );
+    update_input_state(
+        &mut input_state.debug_framerate,
+        pressed(JoyrideAction::DebugFramerate),
+    );
+    update_input_state(
+        &mut input_state.debug_perf,
+        pressed(JoyrideAction::DebugPerf),
+    );
+    update_input_state(
+        &mut input_state.debug_save_snapshot,
+        pressed(JoyrideAction::DebugSaveSnapshot),
+    );
+    update_input_state(
+        &mut input_state.debug_load_snapshot,
+        pressed(JoyrideAction::DebugLoadSnapshot),
+    );
+    update_input_state(
+        &mut input_state.debug_record_input,
+        pressed(JoyrideAction::DebugRecordInput),
+    );
+    update_input_state(
+        &mut input_state.debug_play_recording,
+        pressed(JoyrideAction::DebugPlayRecording),
+    );
+    update_input_state(
+        &mut input_state.debug_camera_left,
+        pressed(JoyrideAction::DebugCameraLeft),
+    );
+    update_input_state(
+        &mut input_state.debug_camera_right,
+        pressed(JoyrideAction::DebugCameraRight),
+    );
+    update_input_state(
+        &mut input_state.debug_camera_up,
+        pressed(JoyrideAction::DebugCameraUp),
+    );
+    update_input_state(
+        &mut input_state.debug_camera_down,
+        pressed(JoyrideAction::DebugCameraDown),
+    );
+    update_input_state(
+        &mut input_state.debug_camera_zoom_in,
+        pressed(JoyrideAction::DebugCameraZoomIn),
+    );
+    update_input_state(
+        &mut input_state.debug_camera_zoom_out,
+        pressed(JoyrideAction::DebugCameraZoomOut),
+    );
+}
+
+fn update_rebinding(
+    input: Res<Input<KeyCode>>,
+    mut rebind_state: ResMut<RebindState>,
+    mut bindings: ResMut<InputBindings>,
+) {
+    if rebind_state.just_finished {
+        rebind_state.just_finished = false;
+    }
+
+    let action = match rebind_state.current_action() {
+        Some(action) => action,
+        None => return, // Not rebinding
+    };
+
+    if let Some(key) = input.get_just_pressed().find(|key| **key != PAUSE_KEY) {
+        // Escape backs out, leaving any actions not bound yet as they were
+        if *key == KeyCode::Escape {
+            rebind_state.pending.clear();
+        } else {
+            bindings.rebind(action, *key);
+            rebind_state.pending.pop_front();
+        }
+        rebind_state.just_finished = rebind_state.pending.is_empty();
+    }
+}
+
+// Restarting mid-race starts it over straight away, but once the race is over, it's back to the
+// menu the same as dismissing the results
+fn restart_race(
+    input: Res<JoyrideInput>,
+    race_state: Res<RaceState>,
+    mut screen: ResMut<State<GameScreen>>,
+    mut restarted_events: EventWriter<RaceRestarted>,
+) {
+    if input.restart != JoyrideInputState::JustPressed {
+        return;
+    }
+
+    restarted_events.send(RaceRestarted);
+    if *race_state == RaceState::Finished && *screen.current() == GameScreen::Racing {
+        change_screen(&mut screen, GameScreen::Menu);
+    }
+}
+
+fn reset_race(
+    mut restarted_events: EventReader<RaceRestarted>,
+    mut game: ResMut<JoyrideGame>,
+    mut race_state: ResMut<RaceState>,
+    mut score: ResMut<Score>,
+    difficulty: Res<Difficulty>,
+) {
+    if restarted_events.iter().next().is_none() {
+        return;
+    }
+
+    *game = JoyrideGame::new(*difficulty);
+    *race_state = RaceState::default();
+    *score = Score::default();
+}
+
+fn update_race_timer(
+    time_scale: Res<TimeScale>,
+    mut game: ResMut<JoyrideGame>,
+    mut race_state: ResMut<RaceState>,
+    mut checkpoint_events: EventReader<CheckpointReached>,
+) {
+    let step = Duration::from_secs_f32(scaled_step(&time_scale));
+    match *race_state {
+        RaceState::Countdown => {
+            if game.countdown.tick(step).just_finished() {
+                *race_state = RaceState::Racing;
+            }
+        }
+        RaceState::Racing => {
+            let is_final_checkpoint = checkpoint_events.iter().any(|event| event.is_final);
+            if is_final_checkpoint {
+                game.completion_secs = Some(game.remaining_time.elapsed_secs());
+            }
+            if game.remaining_time.tick(step).finished() || is_final_checkpoint {
+                *race_state = RaceState::Finished;
+            }
+        }
+        RaceState::Finished => {}
+    }
+}
+
+// Runs at the end of the frame, so that everything else gets to see the race finish first.
+// The state driver only runs in the update stage, so the screen changes next frame
+// The demo just runs on past the finish, until it's out of input
+fn show_results(race_state: Res<RaceState>, mut screen: ResMut<State<GameScreen>>) {
+    if *race_state == RaceState::Finished && *screen.current() == GameScreen::Racing {
+        change_screen(&mut screen, GameScreen::Results);
+    }
+}
+
+fn complete_frame(
+    mut frame_counter: ResMut<FrameCounter>,
+    mut frame_complete: EventWriter<FrameComplete>,
+) {
+    frame_complete.send(FrameComplete {
+        frame_index: frame_counter.frame_index,
+        sim_time: frame_counter.sim_time,
+    });
+
+    frame_counter.frame_index += 1;
+    frame_counter.sim_time += Duration::from_secs_f32(TIME_STEP);
 }
 
 fn update_input_state(input_state: &mut JoyrideInputState, press_state: bool) {
@@ -103,3 +831,259 @@ fn update_input_state(input_state: &mut JoyrideInputState, press_state: bool) {
 
     *input_state = new_state;
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::schedule::Stage;
+
+    use super::*;
+    use crate::road_object::{PickupKind, RoadObjectType, SpawnTable};
+
+    fn press_key(world: &mut World, stage: &mut SystemStage, key: KeyCode) {
+        let mut input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+        *input = Input::default();
+        input.press(key);
+        stage.run(world);
+        world
+            .get_resource_mut::<Input<KeyCode>>()
+            .unwrap()
+            .release(key);
+    }
+
+    #[test]
+    fn first_screen_change_in_a_frame_wins() {
+        let mut world = World::default();
+        world.insert_resource(State::new(GameScreen::Menu));
+        let mut stage = SystemStage::parallel().with_system_set(State::<GameScreen>::get_driver());
+
+        {
+            let mut screen = world.get_resource_mut::<State<GameScreen>>().unwrap();
+            change_screen(&mut screen, GameScreen::Menu);
+            change_screen(&mut screen, GameScreen::Racing);
+            change_screen(&mut screen, GameScreen::Results);
+        }
+        stage.run(&mut world);
+
+        let screen = world.get_resource::<State<GameScreen>>().unwrap();
+        assert_eq!(*screen.current(), GameScreen::Racing);
+    }
+
+    fn rebinding_world() -> (World, SystemStage) {
+        let mut world = World::default();
+        world.insert_resource(Input::<KeyCode>::default());
+        world.insert_resource(InputBindings::default());
+        world.insert_resource(RebindState::default());
+        (world, SystemStage::single(update_rebinding.system()))
+    }
+
+    #[test]
+    fn rebinding_binds_actions_in_order() {
+        let (mut world, mut stage) = rebinding_world();
+        world
+            .get_resource_mut::<RebindState>()
+            .unwrap()
+            .start(&[JoyrideAction::Accel, JoyrideAction::Brake]);
+
+        press_key(&mut world, &mut stage, KeyCode::A);
+        assert_eq!(
+            world
+                .get_resource::<RebindState>()
+                .unwrap()
+                .current_action(),
+            Some(JoyrideAction::Brake)
+        );
+        press_key(&mut world, &mut stage, KeyCode::S);
+
+        let bindings = world.get_resource::<InputBindings>().unwrap();
+        assert_eq!(bindings.bindings[&JoyrideAction::Accel], KeyCode::A);
+        assert_eq!(bindings.bindings[&JoyrideAction::Brake], KeyCode::S);
+
+        // The last key still belongs to rebinding for the step it was pressed, but not after
+        assert!(world.get_resource::<RebindState>().unwrap().is_active());
+        stage.run(&mut world);
+        assert!(!world.get_resource::<RebindState>().unwrap().is_active());
+    }
+
+    #[test]
+    fn escape_backs_out_of_rebinding() {
+        let (mut world, mut stage) = rebinding_world();
+        world
+            .get_resource_mut::<RebindState>()
+            .unwrap()
+            .start(&[JoyrideAction::Accel, JoyrideAction::Brake]);
+
+        press_key(&mut world, &mut stage, KeyCode::A);
+        press_key(&mut world, &mut stage, KeyCode::Escape);
+
+        let rebind_state = world.get_resource::<RebindState>().unwrap();
+        assert_eq!(rebind_state.current_action(), None);
+        let bindings = world.get_resource::<InputBindings>().unwrap();
+        assert_eq!(bindings.bindings[&JoyrideAction::Accel], KeyCode::A);
+        assert_eq!(bindings.bindings[&JoyrideAction::Brake], KeyCode::X);
+    }
+
+    #[test]
+    fn pause_key_is_never_bound() {
+        let (mut world, mut stage) = rebinding_world();
+        assert!(world
+            .get_resource::<InputBindings>()
+            .unwrap()
+            .bindings
+            .values()
+            .all(|key| *key != PAUSE_KEY));
+
+        world
+            .get_resource_mut::<RebindState>()
+            .unwrap()
+            .start(&[JoyrideAction::Accel]);
+        press_key(&mut world, &mut stage, PAUSE_KEY);
+
+        assert_eq!(
+            world
+                .get_resource::<RebindState>()
+                .unwrap()
+                .current_action(),
+            Some(JoyrideAction::Accel)
+        );
+        assert_eq!(
+            world.get_resource::<InputBindings>().unwrap().bindings[&JoyrideAction::Accel],
+            KeyCode::Z
+        );
+    }
+
+    #[test]
+    fn rebinding_a_used_key_swaps_it() {
+        let mut bindings = InputBindings::default();
+        bindings.rebind(JoyrideAction::Accel, KeyCode::X);
+        assert_eq!(bindings.bindings[&JoyrideAction::Accel], KeyCode::X);
+        assert_eq!(bindings.bindings[&JoyrideAction::Brake], KeyCode::Z);
+    }
+
+    fn input_world() -> (World, SystemStage) {
+        let mut world = World::default();
+        world.insert_resource(Input::<KeyCode>::default());
+        world.insert_resource(Input::<GamepadButton>::default());
+        world.insert_resource(Axis::<GamepadAxis>::default());
+        world.insert_resource(ConnectedGamepads::default());
+        world.insert_resource(InputBindings::default());
+        world.insert_resource(RebindState::default());
+        world.insert_resource(AttractMode::default());
+        world.insert_resource(InputReplay::Off);
+        world.insert_resource(JoyrideInput::default());
+        (world, SystemStage::single(update_input.system()))
+    }
+
+    #[test]
+    fn input_is_read_through_custom_bindings() {
+        let (mut world, mut stage) = input_world();
+        {
+            let mut bindings = world.get_resource_mut::<InputBindings>().unwrap();
+            bindings.rebind(JoyrideAction::Accel, KeyCode::Space);
+            bindings.rebind(JoyrideAction::Left, KeyCode::A);
+        }
+
+        let mut keys = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+        keys.press(KeyCode::Space);
+        keys.press(KeyCode::Z);
+        stage.run(&mut world);
+
+        let input = world.get_resource::<JoyrideInput>().unwrap();
+        assert!(input.accel == JoyrideInputState::JustPressed);
+
+        // Z isn't bound to anything any more, and Left is only pressed through its new key
+        assert!(input.left == JoyrideInputState::Released);
+        assert!(input.brake == JoyrideInputState::Released);
+    }
+
+    // A gamepad's buttons reach the game like keys do, whether they drive an action or only
+    // show the player is there
+    #[test]
+    fn gamepad_buttons_count_as_input() {
+        let (mut world, mut stage) = input_world();
+        let gamepad = Gamepad(0);
+        world
+            .get_resource_mut::<ConnectedGamepads>()
+            .unwrap()
+            .gamepads
+            .insert(gamepad);
+
+        let mut buttons = world.get_resource_mut::<Input<GamepadButton>>().unwrap();
+        buttons.press(GamepadButton(gamepad, GamepadButtonType::Start));
+        stage.run(&mut world);
+
+        let input = world.get_resource::<JoyrideInput>().unwrap();
+        assert!(input.any == JoyrideInputState::JustPressed);
+        assert!(input.accel == JoyrideInputState::Released);
+
+        let mut buttons = world.get_resource_mut::<Input<GamepadButton>>().unwrap();
+        buttons.press(GamepadButton(gamepad, GamepadButtonType::South));
+        stage.run(&mut world);
+
+        let input = world.get_resource::<JoyrideInput>().unwrap();
+        assert!(input.any == JoyrideInputState::Pressed);
+        assert!(input.accel == JoyrideInputState::JustPressed);
+    }
+
+    #[test]
+    fn held_debug_key_is_just_pressed_once() {
+        let (mut world, mut stage) = input_world();
+        let mut states = Vec::new();
+        for is_held in [true, true, true, false, false].iter() {
+            let mut keys = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+            *keys = Input::default();
+            if *is_held {
+                keys.press(KeyCode::Grave);
+            }
+            stage.run(&mut world);
+
+            let debug = &world.get_resource::<JoyrideInput>().unwrap().debug;
+            states.push(*debug == JoyrideInputState::JustPressed);
+        }
+        assert_eq!(states, vec![true, false, false, false, false]);
+    }
+
+    // How many of a run of rolls come up as the obstacle, from a table with it evenly against a
+    // pickup. The same seed every time, so only the difficulty differs
+    fn count_obstacle_rolls(difficulty: Difficulty) -> u32 {
+        let table = SpawnTable {
+            entries: vec![
+                (1.0, Some(RoadObjectType::OilSlick)),
+                (1.0, Some(RoadObjectType::Pickup(PickupKind::Points))),
+            ],
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let obstacle_weight = difficulty.settings().obstacle_weight;
+        let mut count = 0;
+        for _ in 0..1000 {
+            if let Some(RoadObjectType::OilSlick) = table.roll(&mut rng, obstacle_weight) {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn harder_difficulties_have_faster_rivals_and_more_obstacles() {
+        let difficulties = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+        for pair in difficulties.windows(2) {
+            let (easier, harder) = (pair[0], pair[1]);
+            assert!(
+                easier.settings().rival_speed < harder.settings().rival_speed,
+                "{:?} rivals aren't slower than {:?} rivals",
+                easier,
+                harder
+            );
+            assert!(
+                count_obstacle_rolls(easier) < count_obstacle_rolls(harder),
+                "{:?} doesn't roll fewer obstacles than {:?}",
+                easier,
+                harder
+            );
+        }
+
+        // Normal is the baseline the rest are scaled from
+        let normal = Difficulty::Normal.settings();
+        assert_eq!(normal.rival_speed, 1.0);
+        assert_eq!(normal.obstacle_weight, 1.0);
+    }
+}
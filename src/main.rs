@@ -2,30 +2,48 @@ use bevy::prelude::*;
 use bevy::render::RenderSystem;
 use debug::LoopSectionTimer;
 use easy_cast::*;
-use fixed_framerate::FixedFramerate;
+use fixed_framerate::{FixedFramerate, FixedFramerateStats, Paused};
+use settings::VideoSettings;
 
 #[cfg(target_arch = "wasm32")]
 use bevy_webgl2;
 
-use crate::joyride::TIME_STEP;
+use crate::joyride::{PAUSE_KEY, TIME_STEP};
 
 const WINDOW_WIDTH: f32 = 1280.0;
 const WINDOW_HEIGHT: f32 = 960.0;
 
+mod attract;
 mod debug;
 mod fixed_framerate;
 mod game;
+#[cfg(test)]
+mod headless;
+mod high_score;
 mod joyride;
+mod menu;
 mod player;
 mod racer;
+mod replay;
+mod results;
 mod rival;
 mod road;
 mod road_object;
+mod road_shader;
+mod session;
+mod settings;
+mod sfx;
 mod skybox;
+mod snapshot;
+mod telemetry;
 mod text;
+mod time_of_day;
+mod track;
 mod util;
+mod weather;
 
 fn main() {
+    let video_settings = VideoSettings::load();
     let mut app_builder = App::build();
 
     app_builder
@@ -33,13 +51,19 @@ fn main() {
             title: "Joyride".to_string(),
             width: WINDOW_WIDTH,
             height: WINDOW_HEIGHT,
-            vsync: true,
+            vsync: video_settings.vsync,
             resizable: false,
             ..Default::default()
         })
+        .insert_resource(video_settings.clone())
         .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
         .insert_resource(LoopSectionTimer::new())
+        .insert_resource(FixedFramerateStats::default())
+        .insert_resource(Paused::default())
+        .init_resource::<util::TextureLoader>()
         .add_plugins(DefaultPlugins)
+        .add_plugin(bevy_kira_audio::AudioPlugin)
+        .add_system(util::substitute_missing_textures.system())
         .add_system_to_stage(
             CoreStage::PostUpdate,
             util::propagate_visibility_system
@@ -54,13 +78,15 @@ fn main() {
         fixed_framerate::create_fixed_framerate_run_criteria(FixedFramerate {
             fixed_step: TIME_STEP.cast(),
 
-            // We don't need to bother trying to catch up if we fall behind
-            drop_time_after_max_runs: true,
+            // By default we don't bother trying to catch up if we fall behind
+            drop_time_after_max_runs: video_settings.drop_time_after_max_runs,
 
             // If we don't cap at one run for the top-level scheduler, event readers that are
             // part of the app runner will sometimes fail to receive events (notably,
             // the AppExit event reader of the Winit runner)
-            max_runs_per_step: Some(1),
+            max_runs_per_step: video_settings.max_runs_per_step,
+
+            pause_key: Some(PAUSE_KEY),
         })
         .system(),
     );
@@ -0,0 +1,321 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use easy_cast::*;
+
+use crate::{
+    joyride::{
+        change_screen, Difficulty, GameScreen, JoyrideAction, JoyrideInput, JoyrideInputState,
+        RaceRestarted, RebindState, FIELD_HEIGHT, FIELD_WIDTH, TIME_STEP,
+    },
+    text::{spawn_text, SMALL_FONT_SPRITE_DESC, SMALL_FONT_WIDTH},
+    util::{spawn_empty_parent, LocalVisible, SpriteGridDesc, TextureLoader},
+};
+
+pub struct Systems {
+    pub startup_menu: SystemSet,
+    pub update_menu: SystemSet,
+}
+
+impl Systems {
+    pub fn new() -> Self {
+        Self {
+            startup_menu: SystemSet::new().with_system(startup_menu.system()),
+            update_menu: SystemSet::new().with_system(update_menu.system()),
+        }
+    }
+}
+
+// Draws over everything else, including the HUD
+const MENU_Z: f32 = 900.0;
+
+const TITLE_SPRITE_DESC: SpriteGridDesc = SpriteGridDesc {
+    tile_size: 128,
+    rows: 2,
+    columns: 1,
+};
+const TITLE_SPRITE_IDX: u32 = 0;
+const PRESS_START_SPRITE_IDX: u32 = 1;
+
+const PRESS_START_BLINK_SECS: f32 = 0.5;
+
+const DIFFICULTIES: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+
+// Starts rebinding the driving controls, one after another in this order
+const REBIND_KEY: KeyCode = KeyCode::Tab;
+const REBIND_ACTIONS: [(JoyrideAction, &str); 8] = [
+    (JoyrideAction::Left, "LEFT"),
+    (JoyrideAction::Right, "RIGHT"),
+    (JoyrideAction::Up, "UP"),
+    (JoyrideAction::Down, "DOWN"),
+    (JoyrideAction::Accel, "ACCEL"),
+    (JoyrideAction::Brake, "BRAKE"),
+    (JoyrideAction::Turbo, "TURBO"),
+    (JoyrideAction::Restart, "RESTART"),
+];
+
+struct Menu {
+    press_start_ent: Entity,
+    blink_timer: Timer,
+
+    // Text for each difficulty, of which only the selected one is shown
+    difficulty_ents: Vec<(Difficulty, Entity)>,
+
+    // While rebinding, the prompt for the action being bound takes the difficulty's place
+    rebind_prompt_ents: Vec<(JoyrideAction, Entity)>,
+    rebind_hint_ent: Entity,
+}
+
+// Text centered horizontally on x = 0, under a parent that can be hidden
+fn spawn_centered_text(
+    commands: &mut Commands,
+    font_atlas: &Handle<TextureAtlas>,
+    string: &str,
+    y: f32,
+    is_visible: bool,
+) -> Entity {
+    let x = -SMALL_FONT_WIDTH * f32::conv(string.len() - 1) * 0.5;
+    let text_ents = spawn_text(commands, font_atlas, string, Vec3::ZERO);
+
+    spawn_empty_parent(commands, Vec3::new(x, y, 0.0))
+        .insert(LocalVisible { is_visible })
+        .push_children(&text_ents)
+        .id()
+}
+
+fn startup_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut texture_loader: ResMut<TextureLoader>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    selected_difficulty: Res<Difficulty>,
+) {
+    let title_tex = texture_loader.load(&asset_server, "textures/title_atlas.png");
+    let title_atlas = texture_atlases.add(TITLE_SPRITE_DESC.make_atlas(title_tex));
+    let font_tex = texture_loader.load(&asset_server, "textures/small_font_atlas.png");
+    let font_atlas = texture_atlases.add(SMALL_FONT_SPRITE_DESC.make_atlas(font_tex));
+
+    let center_x = f32::conv(FIELD_WIDTH) * 0.5;
+    let field_height = f32::conv(FIELD_HEIGHT);
+
+    let title_ent = commands
+        .spawn_bundle(SpriteSheetBundle {
+            texture_atlas: title_atlas.clone(),
+            sprite: TextureAtlasSprite::new(TITLE_SPRITE_IDX),
+            transform: Transform::from_translation(Vec3::new(0.0, field_height * 0.2, 0.0)),
+            ..Default::default()
+        })
+        .id();
+
+    let press_start_ent = commands
+        .spawn_bundle(SpriteSheetBundle {
+            texture_atlas: title_atlas,
+            sprite: TextureAtlasSprite::new(PRESS_START_SPRITE_IDX),
+            transform: Transform::from_translation(Vec3::new(0.0, -field_height * 0.2, 0.0)),
+            ..Default::default()
+        })
+        .insert(LocalVisible::default())
+        .id();
+
+    let difficulty_ents: Vec<(Difficulty, Entity)> = DIFFICULTIES
+        .iter()
+        .map(|&difficulty| {
+            let ent = spawn_centered_text(
+                &mut commands,
+                &font_atlas,
+                &format!("DIFFICULTY {}", difficulty.name()),
+                -field_height * 0.4,
+                difficulty == *selected_difficulty,
+            );
+            (difficulty, ent)
+        })
+        .collect();
+
+    let rebind_prompt_ents: Vec<(JoyrideAction, Entity)> = REBIND_ACTIONS
+        .iter()
+        .map(|&(action, name)| {
+            let ent = spawn_centered_text(
+                &mut commands,
+                &font_atlas,
+                &format!("PRESS KEY FOR {}", name),
+                -field_height * 0.4,
+                false,
+            );
+            (action, ent)
+        })
+        .collect();
+
+    let rebind_hint_ent = spawn_centered_text(
+        &mut commands,
+        &font_atlas,
+        "TAB TO SET KEYS",
+        -field_height * 0.46,
+        true,
+    );
+
+    let mut children = vec![title_ent, press_start_ent, rebind_hint_ent];
+    children.extend(difficulty_ents.iter().map(|(_, ent)| *ent));
+    children.extend(rebind_prompt_ents.iter().map(|(_, ent)| *ent));
+
+    spawn_empty_parent(
+        &mut commands,
+        Vec3::new(center_x, field_height * 0.5, MENU_Z),
+    )
+    .insert(Menu {
+        press_start_ent,
+        blink_timer: Timer::from_seconds(PRESS_START_BLINK_SECS, true),
+        difficulty_ents,
+        rebind_prompt_ents,
+        rebind_hint_ent,
+    })
+    .push_children(&children);
+}
+
+// Left and right pick the difficulty, and anything else on the keyboard or a gamepad starts the
+// race. Rebinding is for keys, so it's started from the keyboard alone
+fn update_menu(
+    input: Res<JoyrideInput>,
+    keys: Res<Input<KeyCode>>,
+    mut screen: ResMut<State<GameScreen>>,
+    mut menus: Query<(&mut Menu, &mut LocalVisible)>,
+    mut child_visible_query: Query<&mut LocalVisible, Without<Menu>>,
+    mut difficulty: ResMut<Difficulty>,
+    mut rebind_state: ResMut<RebindState>,
+    mut restarted_events: EventWriter<RaceRestarted>,
+) {
+    let is_in_menu = *screen.current() == GameScreen::Menu;
+
+    // The demo plays behind the menu, but it's the demo that watches for keys then
+    let is_menu_shown = is_in_menu || *screen.current() == GameScreen::Demo;
+
+    // Keys pressed while rebinding are only for rebinding
+    let is_taking_keys = is_in_menu && !rebind_state.is_active();
+
+    let is_rebind_started = is_taking_keys && keys.just_pressed(REBIND_KEY);
+    if is_rebind_started {
+        let actions: Vec<JoyrideAction> =
+            REBIND_ACTIONS.iter().map(|(action, _)| *action).collect();
+        rebind_state.start(&actions);
+    }
+
+    let picked_difficulty = if !is_taking_keys {
+        None
+    } else if input.left == JoyrideInputState::JustPressed {
+        Some(difficulty.easier())
+    } else if input.right == JoyrideInputState::JustPressed {
+        Some(difficulty.harder())
+    } else {
+        None
+    };
+
+    // The race is set up again, so what's already on the road matches the new difficulty
+    if let Some(picked_difficulty) = picked_difficulty {
+        if picked_difficulty != *difficulty {
+            *difficulty = picked_difficulty;
+            restarted_events.send(RaceRestarted);
+        }
+    }
+
+    for (mut menu, mut visible) in menus.iter_mut() {
+        if visible.is_visible != is_menu_shown {
+            visible.is_visible = is_menu_shown;
+        }
+        if !is_menu_shown {
+            continue;
+        }
+
+        // Slow motion is for moments in the race, so the menu keeps blinking in real time
+        if menu
+            .blink_timer
+            .tick(Duration::from_secs_f32(TIME_STEP))
+            .just_finished()
+        {
+            if let Ok(mut press_start_visible) = child_visible_query.get_mut(menu.press_start_ent) {
+                press_start_visible.is_visible = !press_start_visible.is_visible;
+            }
+        }
+
+        if difficulty.is_changed() || rebind_state.is_changed() {
+            let rebind_action = rebind_state.current_action();
+            let mut set_visible = |ent, is_visible| {
+                if let Ok(mut visible) = child_visible_query.get_mut(ent) {
+                    visible.is_visible = is_visible;
+                }
+            };
+
+            for (ent_difficulty, ent) in menu.difficulty_ents.iter() {
+                set_visible(
+                    *ent,
+                    rebind_action.is_none() && *ent_difficulty == *difficulty,
+                );
+            }
+            for (prompt_action, ent) in menu.rebind_prompt_ents.iter() {
+                set_visible(*ent, rebind_action == Some(*prompt_action));
+            }
+            set_visible(menu.rebind_hint_ent, rebind_action.is_none());
+        }
+    }
+
+    let is_start_pressed = is_taking_keys
+        && !is_rebind_started
+        && picked_difficulty.is_none()
+        && input.any == JoyrideInputState::JustPressed;
+    if is_start_pressed {
+        change_screen(&mut screen, GameScreen::Racing);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::Events, ecs::schedule::Stage};
+
+    use super::*;
+
+    fn run_menu(world: &mut World, input: JoyrideInput) {
+        world.insert_resource(input);
+        SystemStage::single(update_menu.system()).run(world);
+        SystemStage::parallel()
+            .with_system_set(State::<GameScreen>::get_driver())
+            .run(world);
+    }
+
+    // The menu only sees JoyrideInput, so a gamepad's d-pad and buttons work it the same as keys
+    #[test]
+    fn menu_is_driven_by_merged_input() {
+        let mut world = World::default();
+        world.insert_resource(Input::<KeyCode>::default());
+        world.insert_resource(Difficulty::Normal);
+        world.insert_resource(RebindState::default());
+        world.insert_resource(State::new(GameScreen::Menu));
+        world.insert_resource(Events::<RaceRestarted>::default());
+
+        run_menu(
+            &mut world,
+            JoyrideInput {
+                right: JoyrideInputState::JustPressed,
+                any: JoyrideInputState::JustPressed,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            *world.get_resource::<Difficulty>().unwrap(),
+            Difficulty::Hard
+        );
+        assert_eq!(
+            *world.get_resource::<State<GameScreen>>().unwrap().current(),
+            GameScreen::Menu
+        );
+
+        run_menu(
+            &mut world,
+            JoyrideInput {
+                any: JoyrideInputState::JustPressed,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            *world.get_resource::<State<GameScreen>>().unwrap().current(),
+            GameScreen::Racing
+        );
+    }
+}
@@ -1,36 +1,71 @@
-use std::time::Duration;
+use std::{collections::VecDeque, time::Duration};
 
 use bevy::prelude::*;
 use easy_cast::*;
 
 use crate::{
-    debug::{spawn_collision_debug_box, DebugAssets},
-    joyride::{JoyrideInput, JoyrideInputState, FIELD_WIDTH, TIME_STEP},
+    debug::{spawn_collision_debug_box, spawn_gameplay_debug_box, DebugAssets},
+    joyride::{
+        scaled_step, JoyrideInput, JoyrideInputState, RaceRestarted, RaceState, Score, TimeScale,
+        FIELD_WIDTH, TIME_STEP,
+    },
     racer::{
         get_turning_sprite_desc, make_racer, OverlayOffsets, Racer, RacerAssets, RacerOverlay,
         RacerSpriteParams, Tire, MAX_TURN_RATE, RACER_MAX_SPEED,
     },
-    road::{is_offroad, RoadDynamic, RoadStatic},
-    road_object::{PLAYER_COLLISION_WIDTH, ROAD_OBJ_BASE_Z},
-    util::{LocalVisible, SpriteGridDesc},
+    rival::Slipstream,
+    road::{
+        get_offroad_terrain, is_offroad, CheckpointReached, RoadDynamic, RoadStatic, SkidMarks,
+        Terrain, TrackConfig,
+    },
+    road_object::{NEAR_MISS_MAX_GAP, PLAYER_COLLISION_WIDTH, ROAD_OBJ_BASE_Z},
+    time_of_day::TimeOfDay,
+    util::{opt_timer_serde, timer_serde, LocalVisible, SpriteGridDesc, TextureLoader},
+    weather::Weather,
 };
 
 #[derive(Clone, Copy)]
 struct PlayerFrameTurn {
+    // When the input was made, on the player's turn clock
+    time: Duration,
     left: bool,
     right: bool,
 }
 
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 struct PlayerSlide {
     direction: PlayerSlideDirection,
+    #[serde(with = "timer_serde")]
     timer: Timer,
 }
 
+// A light shove sideways. Unlike a slide, the player keeps control throughout
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct PlayerKnockback {
+    direction: PlayerSlideDirection,
+    #[serde(with = "timer_serde")]
+    timer: Timer,
+}
+
+impl PlayerKnockback {
+    // How fast the player is being pushed along the road's X offset, easing off over time
+    fn get_x_offset_speed(&self) -> f32 {
+        let speed = PLAYER_BOUNCE_STRENGTH * self.timer.percent_left();
+        match self.direction {
+            PlayerSlideDirection::Left => speed,
+            PlayerSlideDirection::Right => -speed,
+        }
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 struct PlayerCrash {
+    #[serde(with = "opt_timer_serde")]
     sprite_cycle_timer: Option<Timer>,
     sprite_cycle_idx: u32,
 
     resetting: bool,
+    #[serde(with = "timer_serde")]
     pre_reset_timer: Timer,
 }
 
@@ -46,46 +81,266 @@ impl PlayerCrash {
     }
 }
 
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 enum PlayerControlLoss {
     Slide(PlayerSlide),
     Crash(PlayerCrash),
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum PlayerSlideDirection {
     Left,
     Right,
 }
 
-const TURN_BUFFER_SIZE: usize = 3;
+// Sent when the player starts crashing. Hitting things mid-crash doesn't send another
+pub struct PlayerCrashed;
+
+// Sent when the player starts sliding, including when a new slide interrupts an old one
+pub struct PlayerSlid {
+    pub direction: PlayerSlideDirection,
+}
+
+// How long steering input is held back before it takes effect, for a weightier feel
+const TURN_BUFFER_DELAY: Duration = Duration::from_millis(100);
+
+// Step times don't divide evenly into milliseconds, so inputs are let through slightly early
+// rather than a whole step late
+const TURN_BUFFER_TOLERANCE: Duration = Duration::from_millis(1);
 
 const OFFROAD_SHAKE_OFFSETS: [(f32, f32); 4] = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
 
+pub struct PlayerStartConfig {
+    pub initial_speed: f32,
+
+    // Where the player starts relative to the center of the road. This only affects the start,
+    // resetting after a crash always brings the player back to the center
+    pub initial_x_offset: f32,
+}
+
+impl Default for PlayerStartConfig {
+    fn default() -> Self {
+        Self {
+            initial_speed: 0.0,
+            initial_x_offset: 0.0,
+        }
+    }
+}
+
+// How fast the bike can go, and how hard it gets there. Defaults to the stock bike, and can be
+// changed at any time, such as for upgrades
+pub struct PlayerTuning {
+    pub max_normal_speed: f32,
+
+    // Only reachable on turbo, which kicks in at max_normal_speed
+    pub max_turbo_speed: f32,
+
+    // Acceleration eases from max_accel at a standstill down to min_accel at max_normal_speed
+    pub min_accel: f32,
+    pub max_accel: f32,
+    pub turbo_accel: f32,
+}
+
+impl Default for PlayerTuning {
+    fn default() -> Self {
+        Self {
+            max_normal_speed: PLAYER_MAX_NORMAL_SPEED,
+            max_turbo_speed: PLAYER_MAX_TURBO_SPEED,
+            min_accel: PLAYER_SPEED_MIN_ACCEL,
+            max_accel: PLAYER_SPEED_MAX_ACCEL,
+            turbo_accel: PLAYER_SPEED_TURBO_ACCEL,
+        }
+    }
+}
+
+// Holding the throttle at top speed for too long over-revs the engine, briefly cutting power
+pub struct OverRevConfig {
+    pub enabled: bool,
+
+    // Holding the throttle at or above this speed counts towards an over-rev
+    pub top_band_speed: f32,
+
+    // How long the player can stay in the top band before over-revving,
+    // and how long before that the warning is shown
+    pub max_top_band_secs: f32,
+    pub warning_secs: f32,
+
+    // How hard, and for how long, power is cut when the engine over-revs
+    pub penalty_drag: f32,
+    pub penalty_secs: f32,
+}
+
+impl Default for OverRevConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            top_band_speed: PLAYER_MAX_NORMAL_SPEED * 0.95,
+            max_top_band_secs: 4.0,
+            warning_secs: 1.5,
+            penalty_drag: 4.0,
+            penalty_secs: 0.75,
+        }
+    }
+}
+
+// The mutable simulation state of Player. The overlay entities it drives are left out, along
+// with buffered steering and the offroad shake, which start over when restored
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct PlayerState {
+    control_loss: Option<PlayerControlLoss>,
+    knockback: Option<PlayerKnockback>,
+    airborne_y: f32,
+    #[serde(with = "opt_timer_serde")]
+    airborne_timer: Option<Timer>,
+    top_band_secs: f32,
+    over_rev_penalty_secs: f32,
+    is_over_rev_warning: bool,
+    turbo_fuel: f32,
+    health: u8,
+    #[serde(with = "timer_serde")]
+    health_regen_timer: Timer,
+    #[serde(default, with = "opt_timer_serde")]
+    wheelie_timer: Option<Timer>,
+    #[serde(default, with = "opt_timer_serde")]
+    invuln_timer: Option<Timer>,
+}
+
+// Lives on the player's racer entity, so that more than one player can exist at once
 pub struct Player {
-    turn_buffer: [PlayerFrameTurn; TURN_BUFFER_SIZE],
+    turn_buffer: VecDeque<PlayerFrameTurn>,
+    turn_clock: Duration,
 
     offroad_shake_index: usize,
     offroad_shake_timer: Timer,
 
     control_loss: Option<PlayerControlLoss>,
+    knockback: Option<PlayerKnockback>,
+
+    // How high off the ground the player is. Zero unless jumping
+    airborne_y: f32,
+
+    // Runs for the length of a jump off a hill crest
+    airborne_timer: Option<Timer>,
+
+    // Runs while the front wheel is up, which can end early. See update_player_wheelie
+    wheelie_timer: Option<Timer>,
+
+    // Whether the throttle was open last step, so a wheelie only pops as it's opened
+    was_accelerating: bool,
+
+    // Runs for a short while after recovering from a crash, when nothing can crash the player
+    invuln_timer: Option<Timer>,
 
-    racer_ent: Entity,
+    top_band_secs: f32,
+    over_rev_penalty_secs: f32,
+    is_over_rev_warning: bool,
+
+    turbo_fuel: f32,
+
+    // Whether turbo was actually pushing the bike on the last step, rather than just held
+    is_turboing: bool,
+
+    // Light collisions wear this down, and the player only crashes once it runs out
+    health: u8,
+    health_regen_timer: Timer,
 
     brake_light_ent: Entity,
+    headlight_ent: Entity,
     sand_blast_ent: Entity,
+    rain_spray_ent: Entity,
     smoke_ent: Entity,
     turbo_flare_ent: Entity,
 }
 
 impl Player {
-    pub fn get_racer_ent(&self) -> Entity {
-        self.racer_ent
+    fn new(
+        brake_light_ent: Entity,
+        headlight_ent: Entity,
+        sand_blast_ent: Entity,
+        rain_spray_ent: Entity,
+        smoke_ent: Entity,
+        turbo_flare_ent: Entity,
+    ) -> Self {
+        Self {
+            turn_buffer: VecDeque::new(),
+            turn_clock: Duration::default(),
+            offroad_shake_timer: Timer::from_seconds(1.0 / 15.0, true),
+            offroad_shake_index: 0,
+            control_loss: None,
+            knockback: None,
+            airborne_y: 0.0,
+            airborne_timer: None,
+            wheelie_timer: None,
+            was_accelerating: false,
+            invuln_timer: None,
+            top_band_secs: 0.0,
+            over_rev_penalty_secs: 0.0,
+            is_over_rev_warning: false,
+            turbo_fuel: 1.0,
+            is_turboing: false,
+            health: PLAYER_MAX_HEALTH,
+            health_regen_timer: Timer::from_seconds(PLAYER_HEALTH_REGEN_SECS, true),
+            brake_light_ent,
+            headlight_ent,
+            sand_blast_ent,
+            rain_spray_ent,
+            smoke_ent,
+            turbo_flare_ent,
+        }
+    }
+
+    // Back to how the player starts a race, keeping the overlays already spawned for it
+    fn reset(&mut self) {
+        *self = Self::new(
+            self.brake_light_ent,
+            self.headlight_ent,
+            self.sand_blast_ent,
+            self.rain_spray_ent,
+            self.smoke_ent,
+            self.turbo_flare_ent,
+        );
     }
 
-    pub fn crash(&mut self) {
+    pub fn snapshot(&self) -> PlayerState {
+        PlayerState {
+            control_loss: self.control_loss.clone(),
+            knockback: self.knockback.clone(),
+            airborne_y: self.airborne_y,
+            airborne_timer: self.airborne_timer.clone(),
+            top_band_secs: self.top_band_secs,
+            over_rev_penalty_secs: self.over_rev_penalty_secs,
+            is_over_rev_warning: self.is_over_rev_warning,
+            turbo_fuel: self.turbo_fuel,
+            health: self.health,
+            health_regen_timer: self.health_regen_timer.clone(),
+            wheelie_timer: self.wheelie_timer.clone(),
+            invuln_timer: self.invuln_timer.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, state: &PlayerState) {
+        self.reset();
+
+        let state = state.clone();
+        self.control_loss = state.control_loss;
+        self.knockback = state.knockback;
+        self.airborne_y = state.airborne_y;
+        self.airborne_timer = state.airborne_timer;
+        self.top_band_secs = state.top_band_secs;
+        self.over_rev_penalty_secs = state.over_rev_penalty_secs;
+        self.is_over_rev_warning = state.is_over_rev_warning;
+        self.turbo_fuel = state.turbo_fuel;
+        self.health = state.health;
+        self.health_regen_timer = state.health_regen_timer;
+        self.wheelie_timer = state.wheelie_timer;
+        self.invuln_timer = state.invuln_timer;
+    }
+
+    // Returns whether the player started crashing, so callers know to send PlayerCrashed
+    pub fn crash(&mut self) -> bool {
         match self.control_loss {
             // Don't override an existing crash, it will reset sprite cycles and stuff
-            Some(PlayerControlLoss::Crash(_)) => return,
+            Some(PlayerControlLoss::Crash(_)) => false,
             _ => {
                 self.control_loss = Some(PlayerControlLoss::Crash(PlayerCrash {
                     resetting: false,
@@ -93,35 +348,124 @@ impl Player {
                     sprite_cycle_idx: 0,
                     sprite_cycle_timer: None,
                 }));
+                true
             }
         }
     }
 
-    pub fn slide(&mut self, direction: PlayerSlideDirection) {
+    // Returns whether the damage crashed the player
+    pub fn damage(&mut self) -> bool {
+        if self.is_crashing() {
+            return false;
+        }
+
+        self.health = self.health.saturating_sub(1);
+        self.health_regen_timer.reset();
+        self.health == 0 && self.crash()
+    }
+
+    fn heal(&mut self) {
+        self.health = u8::min(self.health + 1, PLAYER_MAX_HEALTH);
+    }
+
+    // Returns whether the player started sliding, so callers know to send PlayerSlid
+    pub fn slide(&mut self, direction: PlayerSlideDirection) -> bool {
         match self.control_loss {
             // Slides do not override a crash
-            Some(PlayerControlLoss::Crash(_)) => return,
+            Some(PlayerControlLoss::Crash(_)) => false,
             _ => {
                 self.control_loss = Some(PlayerControlLoss::Slide(PlayerSlide {
                     direction,
                     timer: Timer::from_seconds(PLAYER_SLIDE_DURATION, false),
                 }));
+                true
             }
         }
     }
 
-    fn is_crashing(&self) -> bool {
+    pub fn bounce(&mut self, direction: PlayerSlideDirection) {
+        // Bounces are the lightest collision, and don't interrupt losing control
+        if self.control_loss.is_some() {
+            return;
+        }
+
+        self.knockback = Some(PlayerKnockback {
+            direction,
+            timer: Timer::from_seconds(PLAYER_BOUNCE_DURATION, false),
+        });
+    }
+
+    pub fn get_airborne_y(&self) -> f32 {
+        self.airborne_y
+    }
+
+    pub fn is_airborne(&self) -> bool {
+        self.airborne_timer.is_some()
+    }
+
+    pub fn get_turbo_fuel(&self) -> f32 {
+        self.turbo_fuel
+    }
+
+    pub fn is_turboing(&self) -> bool {
+        self.is_turboing
+    }
+
+    // Read by the HUD's health pips
+    pub fn get_health(&self) -> u8 {
+        self.health
+    }
+
+    pub fn is_over_rev_warning(&self) -> bool {
+        self.is_over_rev_warning
+    }
+
+    pub fn is_crashing(&self) -> bool {
         match &self.control_loss {
             Some(PlayerControlLoss::Crash(_)) => true,
             _ => false,
         }
     }
 
+    pub fn is_invulnerable(&self) -> bool {
+        self.invuln_timer.is_some()
+    }
+
+    pub fn is_wheelieing(&self) -> bool {
+        self.wheelie_timer.is_some()
+    }
+
+    pub fn is_sliding(&self) -> bool {
+        match &self.control_loss {
+            Some(PlayerControlLoss::Slide(_)) => true,
+            _ => false,
+        }
+    }
+
     fn reset_turn_buffer(&mut self) {
-        for b in self.turn_buffer.as_mut() {
-            b.left = false;
-            b.right = false;
+        self.turn_buffer.clear();
+    }
+
+    // Records this step's input, and returns the newest input that's old enough to act on.
+    // Inputs older than that one can never be acted on again, so they're dropped
+    fn buffer_turn(&mut self, step: Duration, left: bool, right: bool) -> Option<PlayerFrameTurn> {
+        self.turn_clock += step;
+        self.turn_buffer.push_back(PlayerFrameTurn {
+            time: self.turn_clock,
+            left,
+            right,
+        });
+
+        let turn_clock = self.turn_clock;
+        let is_due = |turn: &PlayerFrameTurn| {
+            turn_clock - turn.time + TURN_BUFFER_TOLERANCE >= TURN_BUFFER_DELAY
+        };
+
+        while self.turn_buffer.len() > 1 && is_due(&self.turn_buffer[1]) {
+            self.turn_buffer.pop_front();
         }
+
+        self.turn_buffer.front().copied().filter(is_due)
     }
 }
 
@@ -140,6 +484,21 @@ fn make_brake_light_overlay() -> RacerOverlay {
     )
 }
 
+// The beam leans into turns, on top of the lean already drawn into the sprite
+const HEADLIGHT_OFFSETS: [OverlayOffsets; 1] =
+    [OverlayOffsets([(0, 36), (3, 36), (6, 35), (9, 34)])];
+fn make_headlight_overlay() -> RacerOverlay {
+    RacerOverlay::new(
+        1,
+        1,
+        1,
+        true,
+        true,
+        &HEADLIGHT_SPRITE_DESC,
+        &HEADLIGHT_OFFSETS,
+    )
+}
+
 const SAND_BLAST_OFFSETS: [OverlayOffsets; 1] = [OverlayOffsets([
     (0, -16),
     (-8, -16),
@@ -190,33 +549,97 @@ const PLAYER_SPEED_MIN_ACCEL: f32 = 0.4;
 const PLAYER_SPEED_MAX_ACCEL: f32 = 3.0;
 const PLAYER_SPEED_TURBO_ACCEL: f32 = 0.75;
 
+// In a rival's slipstream, the bike can pull this far past its normal top speed, and gets this
+// much extra acceleration while it does
+const PLAYER_DRAFT_SPEED_BONUS: f32 = 1.0;
+const PLAYER_DRAFT_ACCEL_BONUS: f32 = 0.5;
+
+// Turbo fuel runs from 0 to 1. It drains while turbo is pushing the bike past its normal top
+// speed, and recharges whenever the turbo button is let go
+const PLAYER_TURBO_FUEL_DRAIN: f32 = 0.2;
+const PLAYER_TURBO_FUEL_RECHARGE: f32 = 0.05;
+
+// Health is counted in whole pips. One comes back after going this long without being hit,
+// and another at every checkpoint
+pub const PLAYER_MAX_HEALTH: u8 = 3;
+const PLAYER_HEALTH_REGEN_SECS: f32 = 15.0;
+
 const PLAYER_COAST_DRAG: f32 = 0.75;
 const PLAYER_BRAKE_DRAG: f32 = 3.6;
 const PLAYER_OFFROAD_DRAG: f32 = 1.8;
+const PLAYER_GRASS_DRAG: f32 = 1.2;
+const PLAYER_GRAVEL_DRAG: f32 = 2.4;
 const PLAYER_CRASH_DRAG: f32 = 3.0;
 
 const PLAYER_TURN_ACCEL: f32 = 1200.0;
 const PLAYER_TURN_FALLOFF: f32 = 1800.0;
 
 const PLAYER_CRASH_RESET_SPEED: f32 = 300.0;
+
+// After a crash, the player can't crash again for a moment, and flashes meanwhile
+const PLAYER_INVULN_SECS: f32 = 2.0;
+const PLAYER_INVULN_FLASH_SECS: f32 = 2.0 / 30.0;
 const PLAYER_SLIDE_DURATION: f32 = 2.0 / 3.0;
 const PLAYER_SLIDE_STRENGTH: f32 = 300.0;
 
+const PLAYER_BOUNCE_DURATION: f32 = 0.3;
+const PLAYER_BOUNCE_STRENGTH: f32 = 400.0;
+const PLAYER_BOUNCE_DRAG: f32 = 2.0;
+
+// Cresting a hill fast enough launches the player. Crests are measured as how much the hill
+// value rises from one segment to the next, since uphills are negative, then scaled by speed
+const PLAYER_JUMP_CREST_THRESHOLD: f32 = 0.1;
+const PLAYER_JUMP_SECS: f32 = 0.6;
+const PLAYER_JUMP_HEIGHT: f32 = 32.0;
+
+// Opening the throttle below this speed pops a wheelie, which comes down once the bike passes
+// the end speed, the time runs out, or the throttle is let go. Steering is weaker meanwhile
+const PLAYER_WHEELIE_START_SPEED: f32 = 2.5;
+const PLAYER_WHEELIE_END_SPEED: f32 = 5.0;
+const PLAYER_WHEELIE_SECS: f32 = 1.0;
+const PLAYER_WHEELIE_TURN_SCALAR: f32 = 0.5;
+
+// Landing while steering at least this hard slides the bike out
+const PLAYER_LANDING_SLIDE_TURN_RATE: f32 = MAX_TURN_RATE * 0.75;
+
+// Rain makes the pavement draggier, and slides take longer to recover from
+const RAIN_PAVEMENT_DRAG: f32 = 0.3;
+const RAIN_SLIDE_RECOVERY_SCALAR: f32 = 1.5;
+
+// In the rain, steering hard against a curve pulling this strongly breaks traction
+const RAIN_CURVE_SLIDE_PULL: f32 = 280.0;
+const RAIN_CURVE_SLIDE_TURN_RATE: f32 = MAX_TURN_RATE * 0.75;
+
+// Spray is only kicked up once the bike is actually moving
+const RAIN_SPRAY_MIN_SPEED: f32 = 2.0;
+
 const BRAKE_LIGHT_OFFSET_Z: f32 = 0.1;
+
+// The beam is cast ahead of the bike, so it's drawn behind it
+const HEADLIGHT_OFFSET_Z: f32 = -0.1;
 const TURBO_FLARE_OFFSET_Z: f32 = 0.15;
 const SAND_BLAST_OFFSET_Z: f32 = 0.2;
+const RAIN_SPRAY_OFFSET_Z: f32 = 0.19;
 const SMOKE_OFFSET_Z: f32 = 0.2;
 
+// Row 0 is the bike up close, one column per turn level. Rows 1 and 2 run through the farther
+// LOD levels in order, row 3 is the crash cycle, and row 4 is the wheelie, laid out like row 0
 const PLAYER_SPRITE_DESC: SpriteGridDesc = SpriteGridDesc {
     tile_size: 64,
-    rows: 4,
+    rows: 5,
     columns: 4,
 };
+const PLAYER_WHEELIE_SPRITE_ROW: u32 = 4;
 const BRAKE_LIGHT_SPRITE_DESC: SpriteGridDesc = SpriteGridDesc {
     tile_size: 16,
     rows: 1,
     columns: 4,
 };
+const HEADLIGHT_SPRITE_DESC: SpriteGridDesc = SpriteGridDesc {
+    tile_size: 64,
+    rows: 1,
+    columns: 4,
+};
 const SAND_BLAST_SPRITE_DESC: SpriteGridDesc = SpriteGridDesc {
     tile_size: 32,
     rows: 1,
@@ -237,6 +660,7 @@ const PLAYER_NOT_INIT: &str = "Player was not initialized";
 
 pub struct Systems {
     pub startup_player: SystemSet,
+    pub reset_player: SystemSet,
     pub update_player_driving: SystemSet,
     pub update_player_road_position: SystemSet,
     pub update_player_visuals: SystemSet,
@@ -246,17 +670,22 @@ impl Systems {
     pub fn new() -> Self {
         Self {
             startup_player: SystemSet::new().with_system(startup_player.system()),
+            reset_player: SystemSet::new().with_system(reset_player.system()),
             update_player_driving: SystemSet::new()
                 .with_system(update_player_turning.system())
-                .with_system(update_player_speed.system())
+                .with_system(update_player_speed.system().label("update_player_speed"))
+                .with_system(update_player_wheelie.system().after("update_player_speed"))
                 .with_system(update_player_crash.system())
+                .with_system(update_player_health.system())
                 .with_system(test_modify_player.system()),
             update_player_road_position: SystemSet::new()
-                .with_system(update_player_road_position.system()),
+                .with_system(update_player_road_position.system())
+                .with_system(lay_skid_marks.system()),
             update_player_visuals: SystemSet::new()
                 .with_system(update_player_shake.system())
                 .with_system(update_player_bike_sprites.system())
                 .with_system(update_brake_lights.system())
+                .with_system(update_headlight.system())
                 .with_system(update_sand_blasts.system())
                 .with_system(update_turbo_flare.system())
                 .with_system(update_smoke.system()),
@@ -269,25 +698,34 @@ fn startup_player(
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     racer_assets: Res<RacerAssets>,
     asset_server: Res<AssetServer>,
+    mut texture_loader: ResMut<TextureLoader>,
     debug_assets: Res<DebugAssets>,
+    start_config: Res<PlayerStartConfig>,
+    tuning: Res<PlayerTuning>,
 ) {
-    let bike_tex = asset_server.load("textures/player_atlas.png");
+    let bike_tex = texture_loader.load(&asset_server, "textures/player_atlas.png");
     let bike_atlas = PLAYER_SPRITE_DESC.make_atlas(bike_tex);
-    let brake_light_tex = asset_server.load("textures/brake_light_atlas.png");
+    let brake_light_tex = texture_loader.load(&asset_server, "textures/brake_light_atlas.png");
     let brake_light_atlas = BRAKE_LIGHT_SPRITE_DESC.make_atlas(brake_light_tex);
-    let sand_blast_tex = asset_server.load("textures/sand_blast_atlas.png");
-    let sand_blast_atlas = SAND_BLAST_SPRITE_DESC.make_atlas(sand_blast_tex);
-    let turbo_flare_tex = asset_server.load("textures/turbo_flare_atlas.png");
+    let headlight_tex = texture_loader.load(&asset_server, "textures/headlight_atlas.png");
+    let headlight_atlas = HEADLIGHT_SPRITE_DESC.make_atlas(headlight_tex);
+    let sand_blast_tex = texture_loader.load(&asset_server, "textures/sand_blast_atlas.png");
+    let sand_blast_atlas = texture_atlases.add(SAND_BLAST_SPRITE_DESC.make_atlas(sand_blast_tex));
+    let turbo_flare_tex = texture_loader.load(&asset_server, "textures/turbo_flare_atlas.png");
     let turbo_flare_atlas = TURBO_FLARE_SPRITE_DESC.make_atlas(turbo_flare_tex);
-    let smoke_tex = asset_server.load("textures/smoke_atlas.png");
+    let smoke_tex = texture_loader.load(&asset_server, "textures/smoke_atlas.png");
     let smoke_atlas = SMOKE_SPRITE_DESC.make_atlas(smoke_tex);
 
     let racer_ent = make_racer(
         &mut commands,
         &racer_assets,
         texture_atlases.add(bike_atlas),
-        0.0,
-        Vec3::new(0.0, 0.0, ROAD_OBJ_BASE_Z - 0.5),
+        f32::clamp(start_config.initial_speed, 0.0, tuning.max_turbo_speed),
+        Vec3::new(
+            f32::conv(FIELD_WIDTH) * 0.5,
+            f32::conv(PLAYER_SPRITE_DESC.tile_size) * 0.5,
+            ROAD_OBJ_BASE_Z - 0.5,
+        ),
     );
 
     let brake_light_xform = Transform::from_translation(Vec3::new(0.0, 0.0, BRAKE_LIGHT_OFFSET_Z));
@@ -301,9 +739,23 @@ fn startup_player(
         .insert(LocalVisible::default())
         .id();
 
+    // Hidden until it gets dark
+    let headlight_xform = Transform::from_translation(Vec3::new(0.0, 0.0, HEADLIGHT_OFFSET_Z));
+    let mut headlight_overlay = make_headlight_overlay();
+    headlight_overlay.is_visible = false;
+    let headlight_ent = commands
+        .spawn_bundle(SpriteSheetBundle {
+            texture_atlas: texture_atlases.add(headlight_atlas),
+            transform: headlight_xform,
+            ..Default::default()
+        })
+        .insert(headlight_overlay)
+        .insert(LocalVisible::default())
+        .id();
+
     let sand_blast_ent = commands
         .spawn_bundle(SpriteSheetBundle {
-            texture_atlas: texture_atlases.add(sand_blast_atlas),
+            texture_atlas: sand_blast_atlas.clone(),
             transform: Transform::from_translation(Vec3::new(0.0, 0.0, SAND_BLAST_OFFSET_Z)),
             ..Default::default()
         })
@@ -312,6 +764,22 @@ fn startup_player(
         .insert(LocalVisible::default())
         .id();
 
+    // Rain spray is the sand blast, washed out to look like water
+    let rain_spray_ent = commands
+        .spawn_bundle(SpriteSheetBundle {
+            texture_atlas: sand_blast_atlas,
+            sprite: TextureAtlasSprite {
+                color: Color::rgba(0.75, 0.85, 1.0, 0.6),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(Vec3::new(0.0, 0.0, RAIN_SPRAY_OFFSET_Z)),
+            ..Default::default()
+        })
+        .insert(Timer::from_seconds(0.1, true))
+        .insert(make_sand_blast_overlay())
+        .insert(LocalVisible::default())
+        .id();
+
     let smoke_ent = commands
         .spawn_bundle(SpriteSheetBundle {
             texture_atlas: texture_atlases.add(smoke_atlas),
@@ -337,393 +805,776 @@ fn startup_player(
     let debug_box = spawn_collision_debug_box(
         &mut commands,
         &debug_assets,
+        None,
         Vec2::new(0.0, -f32::conv(PLAYER_SPRITE_DESC.tile_size) * 0.5),
         Vec2::new(PLAYER_COLLISION_WIDTH, 1.0),
     );
 
+    // How close an obstacle has to pass to count as a near miss, drawn just under the collider
+    let near_miss_box = spawn_gameplay_debug_box(
+        &mut commands,
+        &debug_assets,
+        Vec2::new(0.0, -f32::conv(PLAYER_SPRITE_DESC.tile_size) * 0.5 - 2.0),
+        Vec2::new(PLAYER_COLLISION_WIDTH + (NEAR_MISS_MAX_GAP * 2.0), 1.0),
+    );
+
     commands.entity(racer_ent).push_children(&[
         brake_light_ent,
+        headlight_ent,
         sand_blast_ent,
+        rain_spray_ent,
         smoke_ent,
         turbo_flare_ent,
         debug_box,
+        near_miss_box,
     ]);
 
-    commands.insert_resource(Player {
-        turn_buffer: [PlayerFrameTurn {
-            left: false,
-            right: false,
-        }; TURN_BUFFER_SIZE],
-        offroad_shake_timer: Timer::from_seconds(1.0 / 15.0, true),
-        offroad_shake_index: 0,
-        control_loss: None,
-        racer_ent,
+    commands.entity(racer_ent).insert(Player::new(
         brake_light_ent,
+        headlight_ent,
         sand_blast_ent,
+        rain_spray_ent,
         smoke_ent,
         turbo_flare_ent,
-    })
+    ));
+}
+
+// The player is stopped dead, rather than put back to the configured starting speed
+fn reset_player(
+    mut restarted_events: EventReader<RaceRestarted>,
+    mut players: Query<(&mut Player, &mut Racer)>,
+) {
+    if restarted_events.iter().next().is_none() {
+        return;
+    }
+
+    for (mut player, mut racer) in players.iter_mut() {
+        player.reset();
+        racer.speed = 0.0;
+        racer.turn_rate = 0.0;
+    }
 }
 
 fn update_player_turning(
-    mut player: ResMut<Player>,
+    time_scale: Res<TimeScale>,
     input: Res<JoyrideInput>,
-    mut racers: Query<&mut Racer>,
+    race_state: Res<RaceState>,
+    weather: Res<Weather>,
+    mut players: Query<(&mut Player, &mut Racer)>,
+    road_static: Res<RoadStatic>,
+    road_dyn: Res<RoadDynamic>,
+    mut slid_events: EventWriter<PlayerSlid>,
 ) {
-    let mut racer = racers.get_mut(player.racer_ent).expect(PLAYER_NOT_INIT);
-
-    // TODO: This buffering algorithm will change turn mechanics based on framerate. Use a time-based buffer instead
-    let next_turn = player.turn_buffer[0];
-    player.turn_buffer.copy_within(1.., 0);
-    player.turn_buffer[TURN_BUFFER_SIZE - 1] = PlayerFrameTurn {
-        left: input.left.is_pressed(),
-        right: input.right.is_pressed(),
-    };
+    let step = scaled_step(&time_scale);
 
-    let turn_accel = PLAYER_TURN_ACCEL * TIME_STEP;
-    let turn_falloff = PLAYER_TURN_FALLOFF * TIME_STEP;
+    for (mut player, mut racer) in players.iter_mut() {
+        // Steering held during the countdown shouldn't carry over into the race,
+        // and none is taken once it's over
+        if *race_state != RaceState::Racing {
+            player.reset_turn_buffer();
+            continue;
+        }
 
-    // Increase steering to the left if the button is held, otherwise undo any left steering
-    if next_turn.left {
-        racer.turn_rate = f32::max(-MAX_TURN_RATE, racer.turn_rate - turn_accel);
-    } else if racer.turn_rate < 0.0 {
-        racer.turn_rate = f32::min(0.0, racer.turn_rate + turn_falloff)
-    }
+        // The buffer is time-based, so steering feels the same regardless of the step size
+        let next_turn = player.buffer_turn(
+            Duration::from_secs_f32(step),
+            input.left.is_pressed(),
+            input.right.is_pressed(),
+        );
+        let (turn_left, turn_right) = next_turn.map_or((false, false), |t| (t.left, t.right));
+
+        // Steering is locked in while airborne, and only the rear wheel steers in a wheelie
+        let (turn_accel, turn_falloff) = if player.is_airborne() {
+            (0.0, 0.0)
+        } else if player.is_wheelieing() {
+            (
+                PLAYER_TURN_ACCEL * PLAYER_WHEELIE_TURN_SCALAR * step,
+                PLAYER_TURN_FALLOFF * step,
+            )
+        } else {
+            (PLAYER_TURN_ACCEL * step, PLAYER_TURN_FALLOFF * step)
+        };
+
+        // Increase steering to the left if the button is held, otherwise undo any left steering
+        if turn_left {
+            racer.turn_rate = f32::max(-MAX_TURN_RATE, racer.turn_rate - turn_accel);
+        } else if racer.turn_rate < 0.0 {
+            racer.turn_rate = f32::min(0.0, racer.turn_rate + turn_falloff)
+        }
 
-    // Same for the right
-    if next_turn.right {
-        racer.turn_rate = f32::min(MAX_TURN_RATE, racer.turn_rate + turn_accel);
-    } else if racer.turn_rate > 0.0 {
-        racer.turn_rate = f32::max(0.0, racer.turn_rate - turn_falloff);
+        // Same for the right
+        if turn_right {
+            racer.turn_rate = f32::min(MAX_TURN_RATE, racer.turn_rate + turn_accel);
+        } else if racer.turn_rate > 0.0 {
+            racer.turn_rate = f32::max(0.0, racer.turn_rate - turn_falloff);
+        }
+
+        // Fighting a sharp curve too hard on wet pavement slides the bike out of the turn
+        let can_lose_grip = player.control_loss.is_none() && player.airborne_y <= 0.0;
+        if weather.is_raining() && can_lose_grip && !is_offroad(&road_static, &road_dyn) {
+            let pull = road_dyn.get_road_x_pull(0.0, racer.speed);
+            let is_fighting_pull = racer.turn_rate.abs() >= RAIN_CURVE_SLIDE_TURN_RATE
+                && racer.turn_rate.signum() == pull.signum();
+            if pull.abs() >= RAIN_CURVE_SLIDE_PULL && is_fighting_pull {
+                let direction = if pull > 0.0 {
+                    PlayerSlideDirection::Left
+                } else {
+                    PlayerSlideDirection::Right
+                };
+                if player.slide(direction) {
+                    slid_events.send(PlayerSlid { direction });
+                }
+            }
+        }
+
+        let slide_tick = if weather.is_raining() {
+            step / RAIN_SLIDE_RECOVERY_SCALAR
+        } else {
+            step
+        };
+
+        match player.control_loss.as_mut() {
+            Some(PlayerControlLoss::Slide(slide)) => {
+                racer.turn_rate = if slide.direction == PlayerSlideDirection::Left {
+                    PLAYER_SLIDE_STRENGTH
+                } else {
+                    -PLAYER_SLIDE_STRENGTH
+                };
+
+                if slide
+                    .timer
+                    .tick(Duration::from_secs_f32(slide_tick))
+                    .just_finished()
+                {
+                    player.control_loss = None;
+                    racer.turn_rate = 0.0;
+                    player.reset_turn_buffer();
+                }
+            }
+            Some(PlayerControlLoss::Crash(_)) => {
+                racer.turn_rate = 0.0;
+            }
+            _ => {}
+        };
     }
+}
 
-    match player.control_loss.as_mut() {
-        Some(PlayerControlLoss::Slide(slide)) => {
-            racer.turn_rate = if slide.direction == PlayerSlideDirection::Left {
-                PLAYER_SLIDE_STRENGTH
+fn update_player_speed(
+    time_scale: Res<TimeScale>,
+    input: Res<JoyrideInput>,
+    race_state: Res<RaceState>,
+    over_rev: Res<OverRevConfig>,
+    tuning: Res<PlayerTuning>,
+    weather: Res<Weather>,
+    slipstream: Res<Slipstream>,
+    mut players: Query<(&mut Player, &mut Racer)>,
+    road_static: Res<RoadStatic>,
+    road_dyn: Res<RoadDynamic>,
+    track_config: Res<TrackConfig>,
+) {
+    let step = scaled_step(&time_scale);
+
+    for (mut player, mut racer) in players.iter_mut() {
+        if *race_state == RaceState::Countdown {
+            racer.speed = 0.0;
+            continue;
+        }
+
+        let mut speed_change = 0.0;
+
+        // Once the race is over, the bike just coasts
+        let has_control = *race_state == RaceState::Racing;
+        let is_braking = has_control && input.brake.is_pressed();
+        let is_accelerating = has_control && input.accel.is_pressed();
+        let is_turboing = has_control
+            && input.turbo.is_pressed()
+            && racer.speed >= tuning.max_normal_speed
+            && player.turbo_fuel > 0.0;
+        let is_crashing = player.is_crashing();
+        let mut is_burning_fuel = false;
+
+        let is_drafting = has_control && slipstream.is_drafting;
+        let (max_normal_speed, draft_accel) = if is_drafting {
+            (
+                tuning.max_normal_speed + PLAYER_DRAFT_SPEED_BONUS,
+                PLAYER_DRAFT_ACCEL_BONUS,
+            )
+        } else {
+            (tuning.max_normal_speed, 0.0)
+        };
+
+        if player.control_loss.is_some() {
+            speed_change -= if is_crashing {
+                PLAYER_CRASH_DRAG
             } else {
-                -PLAYER_SLIDE_STRENGTH
+                PLAYER_COAST_DRAG
             };
+        } else if is_braking {
+            speed_change -= PLAYER_BRAKE_DRAG;
+        } else if is_turboing {
+            speed_change += tuning.turbo_accel;
+            is_burning_fuel = true;
+        } else if racer.speed > max_normal_speed {
+            let to_normal_cap = (racer.speed - max_normal_speed) / step;
+            speed_change -= f32::min(PLAYER_COAST_DRAG * 2.0, to_normal_cap);
+        } else if is_accelerating {
+            let accel_scale = f32::max(1.0 - (racer.speed / max_normal_speed), 0.0);
+            let accel = tuning.min_accel
+                + ((tuning.max_accel - tuning.min_accel) * accel_scale)
+                + draft_accel;
+
+            let accel_cap = f32::max((max_normal_speed - racer.speed) / step, 0.0);
+            speed_change += f32::min(accel, accel_cap);
+        } else {
+            speed_change -= PLAYER_COAST_DRAG;
+        }
 
-            if slide
+        if let Some(knockback) = player.knockback.as_mut() {
+            speed_change -= PLAYER_BOUNCE_DRAG;
+            if knockback
                 .timer
-                .tick(Duration::from_secs_f32(TIME_STEP))
-                .just_finished()
+                .tick(Duration::from_secs_f32(step))
+                .finished()
             {
-                player.control_loss = None;
-                racer.turn_rate = 0.0;
-                player.reset_turn_buffer();
+                player.knockback = None;
             }
         }
-        Some(PlayerControlLoss::Crash(_)) => {
-            racer.turn_rate = 0.0;
+
+        player.is_turboing = is_burning_fuel;
+        if is_burning_fuel {
+            player.turbo_fuel = f32::max(player.turbo_fuel - PLAYER_TURBO_FUEL_DRAIN * step, 0.0);
+        } else if !input.turbo.is_pressed() {
+            player.turbo_fuel =
+                f32::min(player.turbo_fuel + PLAYER_TURBO_FUEL_RECHARGE * step, 1.0);
         }
-        _ => {}
-    };
+
+        if let Some(terrain) = get_offroad_terrain(&road_static, &road_dyn, &track_config) {
+            speed_change -= match terrain {
+                Terrain::Sand => PLAYER_OFFROAD_DRAG,
+                Terrain::Grass => PLAYER_GRASS_DRAG,
+                Terrain::Gravel => PLAYER_GRAVEL_DRAG,
+            };
+        } else if weather.is_raining() {
+            speed_change -= RAIN_PAVEMENT_DRAG;
+        }
+
+        if over_rev.enabled {
+            let is_throttling = is_accelerating || input.turbo.is_pressed();
+            let is_in_top_band =
+                is_throttling && !is_crashing && racer.speed >= over_rev.top_band_speed;
+            update_over_rev(&mut player, &over_rev, is_in_top_band, step);
+
+            if player.over_rev_penalty_secs > 0.0 {
+                speed_change -= over_rev.penalty_drag;
+            }
+        }
+
+        // Tuning can raise the normal cap past the turbo cap, and turbo never slows the bike
+        racer.speed = f32::clamp(
+            racer.speed + (speed_change * step),
+            if is_crashing { 0.0 } else { PLAYER_MIN_SPEED },
+            f32::max(tuning.max_turbo_speed, max_normal_speed),
+        );
+    }
 }
 
-fn update_player_speed(
+// Runs after update_player_speed, so a launch is judged on the speed the bike set off at
+fn update_player_wheelie(
+    time_scale: Res<TimeScale>,
     input: Res<JoyrideInput>,
-    player: Res<Player>,
-    mut racers: Query<&mut Racer>,
-    road_static: Res<RoadStatic>,
-    road_dyn: Res<RoadDynamic>,
+    race_state: Res<RaceState>,
+    mut players: Query<(&mut Player, &Racer)>,
 ) {
-    let mut racer = racers.get_mut(player.racer_ent).expect(PLAYER_NOT_INIT);
-    let mut speed_change = 0.0;
+    let step = scaled_step(&time_scale);
 
-    let is_braking = input.brake.is_pressed();
-    let is_accelerating = input.accel.is_pressed();
-    let is_turboing = input.turbo.is_pressed() && racer.speed >= PLAYER_MAX_NORMAL_SPEED;
-    let is_crashing = player.is_crashing();
+    for (mut player, racer) in players.iter_mut() {
+        let is_accelerating = *race_state == RaceState::Racing && input.accel.is_pressed();
+        let is_launching = is_accelerating && !player.was_accelerating;
+        player.was_accelerating = is_accelerating;
 
-    if player.control_loss.is_some() {
-        speed_change -= if is_crashing {
-            PLAYER_CRASH_DRAG
-        } else {
-            PLAYER_COAST_DRAG
+        let can_wheelie = player.control_loss.is_none() && !player.is_airborne();
+        if is_launching && can_wheelie && racer.speed < PLAYER_WHEELIE_START_SPEED {
+            player.wheelie_timer = Some(Timer::from_seconds(PLAYER_WHEELIE_SECS, false));
+        }
+
+        let is_over = match player.wheelie_timer.as_mut() {
+            Some(timer) => {
+                timer.tick(Duration::from_secs_f32(step)).finished()
+                    || !is_accelerating
+                    || !can_wheelie
+                    || racer.speed >= PLAYER_WHEELIE_END_SPEED
+            }
+            None => false,
         };
-    } else if is_braking {
-        speed_change -= PLAYER_BRAKE_DRAG;
-    } else if is_turboing {
-        speed_change += PLAYER_SPEED_TURBO_ACCEL;
-    } else if racer.speed > PLAYER_MAX_NORMAL_SPEED {
-        let to_normal_cap = (racer.speed - PLAYER_MAX_NORMAL_SPEED) / TIME_STEP;
-        speed_change -= f32::min(PLAYER_COAST_DRAG * 2.0, to_normal_cap);
-    } else if is_accelerating {
-        let accel_scale = f32::max(1.0 - (racer.speed / PLAYER_MAX_NORMAL_SPEED), 0.0);
-        let accel = PLAYER_SPEED_MIN_ACCEL
-            + ((PLAYER_SPEED_MAX_ACCEL - PLAYER_SPEED_MIN_ACCEL) * accel_scale);
-
-        let accel_cap = f32::max((PLAYER_MAX_NORMAL_SPEED - racer.speed) / TIME_STEP, 0.0);
-        speed_change += f32::min(accel, accel_cap);
-    } else {
-        speed_change -= PLAYER_COAST_DRAG;
+        if is_over {
+            player.wheelie_timer = None;
+        }
     }
+}
+
+fn update_over_rev(player: &mut Player, config: &OverRevConfig, is_in_top_band: bool, step: f32) {
+    player.over_rev_penalty_secs = f32::max(player.over_rev_penalty_secs - step, 0.0);
 
-    let is_offroad = is_offroad(&road_static, &road_dyn);
-    if is_offroad {
-        speed_change -= PLAYER_OFFROAD_DRAG;
+    // Backing off the throttle (or dropping out of the top band) starts the count over
+    if is_in_top_band {
+        player.top_band_secs += step;
+        if player.top_band_secs >= config.max_top_band_secs {
+            player.top_band_secs = 0.0;
+            player.over_rev_penalty_secs = config.penalty_secs;
+        }
+    } else {
+        player.top_band_secs = 0.0;
     }
 
-    racer.speed = f32::clamp(
-        racer.speed + (speed_change * TIME_STEP),
-        if is_crashing { 0.0 } else { PLAYER_MIN_SPEED },
-        PLAYER_MAX_TURBO_SPEED,
-    );
+    player.is_over_rev_warning = player.over_rev_penalty_secs > 0.0
+        || player.top_band_secs >= config.max_top_band_secs - config.warning_secs;
 }
 
+// TODO: Players share the one road for now, so this only makes sense with a single player
 fn update_player_road_position(
-    player: Res<Player>,
-    racers: Query<&Racer>,
+    time_scale: Res<TimeScale>,
+    mut players: Query<(&mut Player, &Racer)>,
     mut road_dyn: ResMut<RoadDynamic>,
+    road_static: Res<RoadStatic>,
+    track_config: Res<TrackConfig>,
+    mut slid_events: EventWriter<PlayerSlid>,
+    mut score: ResMut<Score>,
 ) {
-    let racer = racers.get(player.racer_ent).expect(PLAYER_NOT_INIT);
-    road_dyn.advance_z(racer.speed * TIME_STEP);
+    let step = scaled_step(&time_scale);
 
-    let is_sliding = match &player.control_loss {
-        Some(PlayerControlLoss::Slide(_)) => true,
-        _ => false,
-    };
+    for (mut player, racer) in players.iter_mut() {
+        let last_point = road_dyn.query_road_point(0.0);
+        road_dyn.advance_z(racer.speed * step);
+        score.add_distance(racer.speed * step);
 
-    let turn_rate = if is_sliding {
-        -racer.turn_rate
-    } else {
-        racer.turn_rate
-    };
-    let mut road_x = road_dyn.x_offset;
-    road_x -= turn_rate * TIME_STEP;
+        update_player_airborne(&mut player, racer, step, &mut slid_events);
+
+        let point = road_dyn.query_road_point(0.0);
+        let can_jump = !player.is_airborne() && player.control_loss.is_none();
+        if can_jump && point.seg_idx != last_point.seg_idx {
+            let crest = point.seg.hill - last_point.seg.hill;
+            if crest * racer.speed >= PLAYER_JUMP_CREST_THRESHOLD {
+                player.airborne_timer = Some(Timer::from_seconds(PLAYER_JUMP_SECS, false));
+            }
+        }
+
+        let is_sliding = match &player.control_loss {
+            Some(PlayerControlLoss::Slide(_)) => true,
+            _ => false,
+        };
 
-    // Apply the road's curvature against the player
-    road_x += road_dyn.get_road_x_pull(0.0, racer.speed) * TIME_STEP;
-    road_dyn.x_offset = f32::clamp(road_x, -500.0, 500.0);
+        let turn_rate = if is_sliding {
+            -racer.turn_rate
+        } else {
+            racer.turn_rate
+        };
+        let mut road_x = road_dyn.x_offset;
+        road_x -= turn_rate * step;
+
+        if let Some(knockback) = &player.knockback {
+            road_x += knockback.get_x_offset_speed() * step;
+        }
+
+        // Apply the road's curvature against the player
+        road_x += road_dyn.get_road_x_pull(0.0, racer.speed) * step;
+
+        // The road's X offset is the negative of the player's position, scaled to the screen
+        // bottom. Going past a barrier is reflected back from it, and knocks the player away
+        if let Some(barriers) = &track_config.barriers {
+            let scale = road_static.scale_map()[0];
+            let (min_offset, max_offset) = (-barriers.right * scale, -barriers.left * scale);
+            if road_x < min_offset {
+                road_x = (2.0 * min_offset) - road_x;
+                player.bounce(PlayerSlideDirection::Left);
+            } else if road_x > max_offset {
+                road_x = (2.0 * max_offset) - road_x;
+                player.bounce(PlayerSlideDirection::Right);
+            }
+        }
+
+        road_dyn.x_offset = f32::clamp(road_x, -500.0, 500.0);
+    }
 }
 
-fn update_player_shake(
-    mut player: ResMut<Player>,
-    mut xforms: Query<&mut Transform>,
+fn lay_skid_marks(
+    players: Query<&Player>,
     road_static: Res<RoadStatic>,
     road_dyn: Res<RoadDynamic>,
+    mut skid_marks: ResMut<SkidMarks>,
 ) {
-    let mut xform = xforms.get_mut(player.racer_ent).expect(PLAYER_NOT_INIT);
-
-    let xform_offset = if is_offroad(&road_static, &road_dyn) && !player.is_crashing() {
-        player
-            .offroad_shake_timer
-            .tick(Duration::from_secs_f32(TIME_STEP));
-        if player.offroad_shake_timer.just_finished() {
-            player.offroad_shake_index =
-                (player.offroad_shake_index + 1) % OFFROAD_SHAKE_OFFSETS.len();
+    let player_x = -road_dyn.x_offset / road_static.scale_map()[0];
+    for player in players.iter() {
+        let is_sliding = matches!(player.control_loss, Some(PlayerControlLoss::Slide(_)));
+        if is_sliding && !player.is_airborne() {
+            skid_marks.lay(&road_static, &road_dyn, player_x);
         }
+    }
+}
 
-        let offset = OFFROAD_SHAKE_OFFSETS[player.offroad_shake_index];
-        (offset.0, offset.1)
-    } else {
-        (0.0, 0.0)
+// Follows a parabola over the jump. Landing while turning hard slides the bike out
+// in the direction it was turning
+fn update_player_airborne(
+    player: &mut Player,
+    racer: &Racer,
+    step: f32,
+    slid_events: &mut EventWriter<PlayerSlid>,
+) {
+    let timer = match player.airborne_timer.as_mut() {
+        Some(timer) => timer,
+        None => return,
     };
 
-    xform.translation.x = (f32::conv(FIELD_WIDTH) * 0.5) + xform_offset.0;
-    xform.translation.y = (f32::conv(PLAYER_SPRITE_DESC.tile_size) * 0.5) + xform_offset.1;
+    timer.tick(Duration::from_secs_f32(step));
+    if !timer.finished() {
+        let t = timer.percent();
+        player.airborne_y = PLAYER_JUMP_HEIGHT * 4.0 * t * (1.0 - t);
+        return;
+    }
+
+    player.airborne_timer = None;
+    player.airborne_y = 0.0;
+
+    if racer.turn_rate.abs() >= PLAYER_LANDING_SLIDE_TURN_RATE {
+        let direction = if racer.turn_rate > 0.0 {
+            PlayerSlideDirection::Right
+        } else {
+            PlayerSlideDirection::Left
+        };
+        if player.slide(direction) {
+            slid_events.send(PlayerSlid { direction });
+        }
+    }
+}
+
+fn update_player_shake(
+    time_scale: Res<TimeScale>,
+    mut players: Query<(&mut Player, &mut Transform)>,
+    road_static: Res<RoadStatic>,
+    road_dyn: Res<RoadDynamic>,
+) {
+    let step = scaled_step(&time_scale);
+
+    for (mut player, mut xform) in players.iter_mut() {
+        let is_grounded = !player.is_crashing() && !player.is_airborne();
+        let xform_offset = if is_offroad(&road_static, &road_dyn) && is_grounded {
+            player
+                .offroad_shake_timer
+                .tick(Duration::from_secs_f32(step));
+            if player.offroad_shake_timer.just_finished() {
+                player.offroad_shake_index =
+                    (player.offroad_shake_index + 1) % OFFROAD_SHAKE_OFFSETS.len();
+            }
+
+            let offset = OFFROAD_SHAKE_OFFSETS[player.offroad_shake_index];
+            (offset.0, offset.1)
+        } else {
+            (0.0, 0.0)
+        };
+
+        xform.translation.x = (f32::conv(FIELD_WIDTH) * 0.5) + xform_offset.0;
+        xform.translation.y =
+            (f32::conv(PLAYER_SPRITE_DESC.tile_size) * 0.5) + xform_offset.1 + player.airborne_y;
+    }
 }
 
 fn update_player_bike_sprites(
-    player: Res<Player>,
-    mut racer_query: Query<(&mut TextureAtlasSprite, &Racer)>,
+    mut players: Query<(&Player, &mut TextureAtlasSprite, &Racer)>,
     mut tire_query: Query<(&mut RacerOverlay, With<Tire>)>,
 ) {
-    let (mut sprite, racer) = racer_query
-        .get_mut(player.racer_ent)
-        .expect(PLAYER_NOT_INIT);
-
-    let mut tire_visible = true;
-
-    match player.control_loss.as_ref() {
-        Some(PlayerControlLoss::Crash(crash)) => {
-            tire_visible = false;
-            sprite.index = PLAYER_SPRITE_DESC.get_sprite_index(crash.sprite_cycle_idx, 3);
-            sprite.flip_x = false;
-        }
-        _ => {
-            // The player's sprite sheet is laid out differently than other racers, missing a lot
-            if racer.lod_level == 0 {
-                let RacerSpriteParams {
-                    turn_idx: sprite_x,
-                    flip_x,
-                } = get_turning_sprite_desc(racer.turn_rate);
-
-                let sprite_y = 0;
-                sprite.index = PLAYER_SPRITE_DESC.get_sprite_index(sprite_x, sprite_y);
-                sprite.flip_x = flip_x;
-            } else {
-                let sprite_x = racer.lod_level.cast();
-                let sprite_y = 1;
-                sprite.index = PLAYER_SPRITE_DESC.get_sprite_index(sprite_x, sprite_y);
+    for (player, mut sprite, racer) in players.iter_mut() {
+        let mut tire_visible = true;
+
+        match player.control_loss.as_ref() {
+            Some(PlayerControlLoss::Crash(crash)) => {
+                tire_visible = false;
+                sprite.index = PLAYER_SPRITE_DESC.get_sprite_index(crash.sprite_cycle_idx, 3);
                 sprite.flip_x = false;
             }
-        }
-    };
+            _ => {
+                // The player's sprite sheet is laid out differently than other racers, missing a lot
+                if racer.lod_level == 0 {
+                    let RacerSpriteParams {
+                        turn_idx: sprite_x,
+                        flip_x,
+                    } = get_turning_sprite_desc(racer.turn_rate);
+
+                    let sprite_y = if player.is_wheelieing() {
+                        PLAYER_WHEELIE_SPRITE_ROW
+                    } else {
+                        0
+                    };
+                    sprite.index = PLAYER_SPRITE_DESC.get_sprite_index(sprite_x, sprite_y);
+                    sprite.flip_x = flip_x;
+                } else {
+                    let sprite_x = racer.lod_level.cast();
+                    let sprite_y = 1;
+                    sprite.index = PLAYER_SPRITE_DESC.get_sprite_index(sprite_x, sprite_y);
+                    sprite.flip_x = false;
+                }
+            }
+        };
 
-    if let Ok((mut tire_overlay, _)) = tire_query.get_mut(racer.tire_ent) {
-        tire_overlay.is_visible = tire_visible;
+        if let Ok((mut tire_overlay, _)) = tire_query.get_mut(racer.tire_ent) {
+            tire_overlay.is_visible = tire_visible;
+        }
     }
 }
 
 fn update_brake_lights(
-    player: Res<Player>,
+    players: Query<&Player>,
     input: Res<JoyrideInput>,
     mut query: Query<&mut RacerOverlay>,
 ) {
-    let mut overlay = query
-        .get_mut(player.brake_light_ent)
-        .expect(PLAYER_NOT_INIT);
+    for player in players.iter() {
+        let mut overlay = query
+            .get_mut(player.brake_light_ent)
+            .expect(PLAYER_NOT_INIT);
 
-    overlay.is_visible = !player.is_crashing() && input.brake.is_pressed();
+        overlay.is_visible = !player.is_crashing() && input.brake.is_pressed();
+    }
+}
+
+fn update_headlight(
+    players: Query<&Player>,
+    time_of_day: Res<TimeOfDay>,
+    mut query: Query<&mut RacerOverlay>,
+) {
+    for player in players.iter() {
+        let mut overlay = query.get_mut(player.headlight_ent).expect(PLAYER_NOT_INIT);
+        overlay.is_visible = !player.is_crashing() && time_of_day.is_dark();
+    }
 }
 
+// Handles both the sand blasted up offroad, and the spray kicked up on wet pavement
 fn update_sand_blasts(
-    player: Res<Player>,
+    time_scale: Res<TimeScale>,
+    players: Query<(&Player, &Racer)>,
+    weather: Res<Weather>,
     road_static: Res<RoadStatic>,
     road_dyn: Res<RoadDynamic>,
+    track_config: Res<TrackConfig>,
     mut query: Query<(&mut Timer, &mut RacerOverlay)>,
+    mut sprites: Query<&mut TextureAtlasSprite>,
 ) {
-    let (mut timer, mut overlay) = query.get_mut(player.sand_blast_ent).expect(PLAYER_NOT_INIT);
+    let step = scaled_step(&time_scale);
 
-    let is_offroad = is_offroad(&road_static, &road_dyn);
-    if is_offroad {
-        timer.tick(Duration::from_secs_f32(TIME_STEP));
-        if timer.just_finished() {
-            overlay.sprite_cycle_pos =
-                (overlay.sprite_cycle_pos + 1) % overlay.get_sprite_cycle_length()
+    let terrain = get_offroad_terrain(&road_static, &road_dyn, &track_config);
+    let is_offroad = terrain.is_some();
+
+    // The blast sprites are drawn as sand, and tinted for other terrain
+    let blast_color = match terrain {
+        Some(Terrain::Grass) => Color::rgb(0.5, 0.9, 0.3),
+        Some(Terrain::Gravel) => Color::rgb(0.65, 0.65, 0.65),
+        Some(Terrain::Sand) | None => Color::WHITE,
+    };
+
+    for (player, racer) in players.iter() {
+        if let Ok(mut sprite) = sprites.get_mut(player.sand_blast_ent) {
+            if is_offroad && sprite.color != blast_color {
+                sprite.color = blast_color;
+            }
         }
-    }
 
-    overlay.is_visible = !player.is_crashing() && is_offroad;
+        let is_spraying = weather.is_raining() && racer.speed >= RAIN_SPRAY_MIN_SPEED;
+        let blasts = [
+            (player.sand_blast_ent, is_offroad),
+            (player.rain_spray_ent, is_spraying && !is_offroad),
+        ];
+
+        for (ent, is_active) in blasts.iter() {
+            let (mut timer, mut overlay) = query.get_mut(*ent).expect(PLAYER_NOT_INIT);
+            if *is_active {
+                timer.tick(Duration::from_secs_f32(step));
+                if timer.just_finished() {
+                    overlay.sprite_cycle_pos =
+                        (overlay.sprite_cycle_pos + 1) % overlay.get_sprite_cycle_length()
+                }
+            }
+
+            overlay.is_visible = !player.is_crashing() && *is_active;
+        }
+    }
 }
 
 fn update_smoke(
-    player: Res<Player>,
+    time_scale: Res<TimeScale>,
+    players: Query<&Player>,
     road_static: Res<RoadStatic>,
     road_dyn: Res<RoadDynamic>,
     mut overlay_query: Query<(&mut Timer, &mut RacerOverlay)>,
 ) {
-    let (mut timer, mut overlay) = overlay_query
-        .get_mut(player.smoke_ent)
-        .expect(PLAYER_NOT_INIT);
+    let step = scaled_step(&time_scale);
 
-    let is_sliding = match &player.control_loss {
-        Some(PlayerControlLoss::Slide(_)) => true,
-        _ => false,
-    };
+    for player in players.iter() {
+        let (mut timer, mut overlay) = overlay_query
+            .get_mut(player.smoke_ent)
+            .expect(PLAYER_NOT_INIT);
 
-    let is_active = is_sliding && !is_offroad(&road_static, &road_dyn);
-    if is_active {
-        timer.tick(Duration::from_secs_f32(TIME_STEP));
-        if timer.just_finished() {
-            overlay.sprite_cycle_pos =
-                (overlay.sprite_cycle_pos + 1) % overlay.get_sprite_cycle_length()
+        let is_sliding = match &player.control_loss {
+            Some(PlayerControlLoss::Slide(_)) => true,
+            _ => false,
+        };
+
+        let is_active = is_sliding && !is_offroad(&road_static, &road_dyn);
+        if is_active {
+            timer.tick(Duration::from_secs_f32(step));
+            if timer.just_finished() {
+                overlay.sprite_cycle_pos =
+                    (overlay.sprite_cycle_pos + 1) % overlay.get_sprite_cycle_length()
+            }
         }
-    }
 
-    overlay.is_visible = is_active;
+        overlay.is_visible = is_active;
+    }
 }
 
 fn update_turbo_flare(
-    player: Res<Player>,
+    time_scale: Res<TimeScale>,
+    players: Query<(&Player, &Racer)>,
     input: Res<JoyrideInput>,
+    tuning: Res<PlayerTuning>,
     road_static: Res<RoadStatic>,
     road_dyn: Res<RoadDynamic>,
     mut overlay_query: Query<(&mut Timer, &mut RacerOverlay)>,
-    racer_query: Query<&Racer>,
 ) {
-    let (mut timer, mut overlay) = overlay_query
-        .get_mut(player.turbo_flare_ent)
-        .expect(PLAYER_NOT_INIT);
-    let racer = racer_query.get(player.racer_ent).expect(PLAYER_NOT_INIT);
-
-    if is_offroad(&road_static, &road_dyn)
-        || !input.turbo.is_pressed()
-        || racer.speed <= PLAYER_MAX_NORMAL_SPEED
-        || player.is_crashing()
-    {
-        overlay.is_visible = false;
-        return;
+    let step = scaled_step(&time_scale);
+
+    for (player, racer) in players.iter() {
+        let (mut timer, mut overlay) = overlay_query
+            .get_mut(player.turbo_flare_ent)
+            .expect(PLAYER_NOT_INIT);
+
+        if is_offroad(&road_static, &road_dyn)
+            || !input.turbo.is_pressed()
+            || player.turbo_fuel <= 0.0
+            || racer.speed <= tuning.max_normal_speed
+            || player.is_crashing()
+        {
+            overlay.is_visible = false;
+            continue;
+        }
+
+        timer.tick(Duration::from_secs_f32(step));
+        if timer.just_finished() {
+            overlay.is_visible = !overlay.is_visible;
+            overlay.sprite_cycle_pos =
+                (overlay.sprite_cycle_pos + 1) % overlay.get_sprite_cycle_length()
+        }
     }
+}
 
-    timer.tick(Duration::from_secs_f32(TIME_STEP));
-    if timer.just_finished() {
-        overlay.is_visible = !overlay.is_visible;
-        overlay.sprite_cycle_pos =
-            (overlay.sprite_cycle_pos + 1) % overlay.get_sprite_cycle_length()
+fn update_player_health(
+    time_scale: Res<TimeScale>,
+    mut players: Query<&mut Player>,
+    mut checkpoint_events: EventReader<CheckpointReached>,
+) {
+    let step = scaled_step(&time_scale);
+
+    let num_checkpoints = checkpoint_events.iter().count();
+    for mut player in players.iter_mut() {
+        // Crashing refills health once the reset is done
+        if player.is_crashing() {
+            continue;
+        }
+
+        for _ in 0..num_checkpoints {
+            player.heal();
+        }
+
+        if player.health >= PLAYER_MAX_HEALTH {
+            player.health_regen_timer.reset();
+        } else if player
+            .health_regen_timer
+            .tick(Duration::from_secs_f32(step))
+            .just_finished()
+        {
+            player.heal();
+        }
     }
 }
 
 fn update_player_crash(
-    mut player: ResMut<Player>,
-    mut racer_query: Query<(&mut Racer, &mut LocalVisible)>,
+    time_scale: Res<TimeScale>,
+    mut players: Query<(&mut Player, &mut Racer, &mut LocalVisible)>,
     mut road_dyn: ResMut<RoadDynamic>,
 ) {
-    let player: &mut Player = &mut player;
+    let step = scaled_step(&time_scale);
 
-    let crash = match player.control_loss.as_mut() {
-        Some(PlayerControlLoss::Crash(crash)) => crash,
-        _ => return,
-    };
+    for (mut player, mut racer, mut visible) in players.iter_mut() {
+        let player: &mut Player = &mut player;
+        let tick_duration = Duration::from_secs_f32(step);
 
-    let (mut racer, mut visible) = racer_query
-        .get_mut(player.racer_ent)
-        .expect(PLAYER_NOT_INIT);
-    let tick_duration = Duration::from_secs_f32(TIME_STEP);
+        let crash = match player.control_loss.as_mut() {
+            Some(PlayerControlLoss::Crash(crash)) => crash,
+            _ => {
+                let mut is_visible = true;
+                if let Some(timer) = player.invuln_timer.as_mut() {
+                    if timer.tick(tick_duration).finished() {
+                        player.invuln_timer = None;
+                    } else {
+                        let flash_idx =
+                            u32::conv_floor(timer.elapsed_secs() / PLAYER_INVULN_FLASH_SECS);
+                        is_visible = flash_idx % 2 == 0;
+                    }
+                }
+
+                if visible.is_visible != is_visible {
+                    visible.is_visible = is_visible;
+                }
+                continue;
+            }
+        };
 
-    if crash.resetting {
-        let remaining = road_dyn.x_offset / TIME_STEP;
-        let mut is_visible = false;
+        if crash.resetting {
+            let remaining = road_dyn.x_offset / step;
+            let mut is_visible = false;
 
-        if remaining <= PLAYER_CRASH_RESET_SPEED {
-            road_dyn.x_offset = 0.0;
-            player.control_loss = None;
-            racer.speed = PLAYER_MIN_SPEED;
-            is_visible = true;
-            player.reset_turn_buffer();
-        } else {
-            road_dyn.x_offset -= PLAYER_CRASH_RESET_SPEED * TIME_STEP;
-        }
+            if remaining <= PLAYER_CRASH_RESET_SPEED {
+                road_dyn.x_offset = 0.0;
+                player.control_loss = None;
+                player.health = PLAYER_MAX_HEALTH;
+                player.invuln_timer = Some(Timer::from_seconds(PLAYER_INVULN_SECS, false));
+                racer.speed = PLAYER_MIN_SPEED;
+                is_visible = true;
+                player.reset_turn_buffer();
+            } else {
+                road_dyn.x_offset -= PLAYER_CRASH_RESET_SPEED * step;
+            }
 
-        if visible.is_visible != is_visible {
-            visible.is_visible = is_visible;
-        }
-    } else {
-        if racer.speed <= 0.0 {
-            crash.sprite_cycle_idx = 2;
-            crash.pre_reset_timer.tick(tick_duration);
-            if crash.pre_reset_timer.just_finished() {
-                crash.resetting = true;
+            if visible.is_visible != is_visible {
+                visible.is_visible = is_visible;
             }
         } else {
-            //let timer: &mut Timer =
-            let next_cycle_time =
-                Duration::from_secs_f32(PlayerCrash::next_sprite_cycle_time(racer.speed));
-            let cycle_timer = crash
-                .sprite_cycle_timer
-                .get_or_insert(Timer::new(next_cycle_time, false));
-
-            cycle_timer.tick(tick_duration);
-            if cycle_timer.just_finished() {
-                crash.sprite_cycle_idx = (crash.sprite_cycle_idx + 1) % 4;
-                cycle_timer.set_duration(next_cycle_time);
-                cycle_timer.reset();
+            if racer.speed <= 0.0 {
+                crash.sprite_cycle_idx = 2;
+                crash.pre_reset_timer.tick(tick_duration);
+                if crash.pre_reset_timer.just_finished() {
+                    crash.resetting = true;
+                }
+            } else {
+                //let timer: &mut Timer =
+                let next_cycle_time =
+                    Duration::from_secs_f32(PlayerCrash::next_sprite_cycle_time(racer.speed));
+                let cycle_timer = crash
+                    .sprite_cycle_timer
+                    .get_or_insert(Timer::new(next_cycle_time, false));
+
+                cycle_timer.tick(tick_duration);
+                if cycle_timer.just_finished() {
+                    crash.sprite_cycle_idx = (crash.sprite_cycle_idx + 1) % 4;
+                    cycle_timer.set_duration(next_cycle_time);
+                    cycle_timer.reset();
+                }
             }
         }
     }
 }
 
-fn test_modify_player(
-    input: Res<JoyrideInput>,
-    mut player: ResMut<Player>,
-    mut racer_query: Query<&mut Racer>,
-) {
-    let mut racer = racer_query
-        .get_mut(player.racer_ent)
-        .expect(PLAYER_NOT_INIT);
+fn test_modify_player(input: Res<JoyrideInput>, mut players: Query<(&mut Player, &mut Racer)>) {
+    let (mut player, mut racer) = match players.iter_mut().next() {
+        Some(player) => player,
+        None => return,
+    };
 
     //if input.debug == JoyrideInputState::JustPressed {
     // player.control_loss = Some(PlayerControlLoss::Slide(PlayerSlide {
@@ -740,3 +1591,335 @@ fn test_modify_player(
     //     racer.turn_rate = f32::min(racer.turn_rate + MAX_TURN_RATE / 4.0, MAX_TURN_RATE);
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::app::Events;
+
+    use super::*;
+    use crate::{
+        headless::{
+            build_headless_app, clear_rivals, get_player_mut, start_race_on_straight, step,
+        },
+        replay::RecordedInput,
+        road::RoadBounds,
+        road_object::{Collider, CollisionAction, RoadObject},
+        track::TrackFile,
+    };
+
+    fn test_player() -> Player {
+        Player::new(
+            Entity::new(0),
+            Entity::new(1),
+            Entity::new(2),
+            Entity::new(3),
+            Entity::new(4),
+            Entity::new(5),
+        )
+    }
+
+    // Steers left, then right with a little overlap, sampling the turns acted on every
+    // sample_every steps
+    fn buffered_turns(step_secs: f64, sample_every: u32) -> Vec<Option<(bool, bool)>> {
+        let mut player = test_player();
+        let step = Duration::from_secs_f64(step_secs);
+
+        let mut samples = Vec::new();
+        for i in 1..=(30 * sample_every) {
+            let time = step_secs * f64::conv(i);
+            let left = time < 0.21;
+            let right = time > 0.155 && time < 0.31;
+            let turn = player.buffer_turn(step, left, right);
+            if i % sample_every == 0 {
+                samples.push(turn.map(|turn| (turn.left, turn.right)));
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn crash_only_begins_once_health_runs_out() {
+        let mut player = test_player();
+
+        for hit in 1..PLAYER_MAX_HEALTH {
+            assert!(!player.damage(), "Hit {} crashed the player", hit);
+            assert!(!player.is_crashing());
+            assert_eq!(player.get_health(), PLAYER_MAX_HEALTH - hit);
+        }
+
+        assert!(player.damage(), "The last hit didn't crash the player");
+        assert!(player.is_crashing());
+        assert_eq!(player.get_health(), 0);
+    }
+
+    #[test]
+    fn turn_buffer_delay_is_independent_of_step() {
+        let at_30fps = buffered_turns(1.0 / 30.0, 1);
+        let at_60fps = buffered_turns(1.0 / 60.0, 2);
+        assert_eq!(at_30fps, at_60fps);
+
+        // Nothing is acted on until the delay has passed, and then the first input comes through
+        assert_eq!(at_30fps[2], None);
+        assert_eq!(at_30fps[3], Some((true, false)));
+    }
+
+    #[test]
+    fn turbo_cuts_out_once_fuel_runs_dry() {
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+
+        let max_normal_speed = app
+            .world
+            .get_resource::<PlayerTuning>()
+            .unwrap()
+            .max_normal_speed;
+        get_player_mut(&mut app).1.speed = max_normal_speed;
+
+        let turbo = RecordedInput {
+            accel: true,
+            turbo: true,
+            ..Default::default()
+        };
+
+        // Fuel lasts a fraction of this at the drain rate
+        let mut fuel_out_speed = None;
+        for _ in 0..300 {
+            clear_rivals(&mut app);
+            step(&mut app, turbo);
+            let (player, racer) = get_player_mut(&mut app);
+
+            match fuel_out_speed {
+                None if player.get_turbo_fuel() == 0.0 => fuel_out_speed = Some(racer.speed),
+                None => assert!(racer.speed > max_normal_speed, "Turbo should boost speed"),
+                Some(last_speed) => {
+                    assert!(
+                        racer.speed <= last_speed,
+                        "Turbo kept boosting without fuel"
+                    );
+                    fuel_out_speed = Some(racer.speed);
+                }
+            }
+        }
+
+        assert!(fuel_out_speed.is_some(), "Fuel never ran out");
+        assert_eq!(get_player_mut(&mut app).0.get_turbo_fuel(), 0.0);
+    }
+
+    #[test]
+    fn raised_max_speed_lifts_the_cap() {
+        let mut app = build_headless_app();
+        let raised_max_speed = PLAYER_MAX_NORMAL_SPEED * 1.5;
+        app.world.insert_resource(PlayerTuning {
+            max_normal_speed: raised_max_speed,
+            ..Default::default()
+        });
+        start_race_on_straight(&mut app);
+
+        let accel = RecordedInput {
+            accel: true,
+            ..Default::default()
+        };
+        for _ in 0..450 {
+            clear_rivals(&mut app);
+            step(&mut app, accel);
+        }
+
+        // Past even the stock turbo cap, without touching turbo
+        let speed = get_player_mut(&mut app).1.speed;
+        assert!(
+            speed > PLAYER_MAX_TURBO_SPEED,
+            "Speed {} never passed the stock cap",
+            speed
+        );
+        assert!(
+            speed <= raised_max_speed,
+            "Speed {} passed the raised cap",
+            speed
+        );
+    }
+
+    #[test]
+    fn driving_into_a_barrier_is_reflected() {
+        let mut app = build_headless_app();
+        let barriers = RoadBounds {
+            left: -150.0,
+            right: 150.0,
+        };
+        app.world.insert_resource(TrackConfig {
+            barriers: Some(barriers),
+            ..Default::default()
+        });
+        start_race_on_straight(&mut app);
+
+        // Start right up against the right barrier, then keep steering into it
+        let scale = app.world.get_resource::<RoadStatic>().unwrap().scale_map()[0];
+        let min_offset = -barriers.right * scale;
+        app.world
+            .get_resource_mut::<RoadDynamic>()
+            .unwrap()
+            .x_offset = min_offset + 1.0;
+
+        let steer_right = RecordedInput {
+            accel: true,
+            right: true,
+            ..Default::default()
+        };
+        let mut was_bounced = false;
+        for _ in 0..60 {
+            clear_rivals(&mut app);
+            step(&mut app, steer_right);
+
+            let x_offset = app.world.get_resource::<RoadDynamic>().unwrap().x_offset;
+            assert!(
+                x_offset >= min_offset,
+                "The player went through the barrier to {}",
+                x_offset
+            );
+            was_bounced |= get_player_mut(&mut app).0.knockback.is_some();
+        }
+        assert!(was_bounced, "The barrier never knocked the player back");
+    }
+
+    fn player_sprite_row(app: &mut App) -> u32 {
+        let mut players = app.world.query::<(&Player, &TextureAtlasSprite)>();
+        let (_, sprite) = players.iter(&app.world).next().unwrap();
+        sprite.index / PLAYER_SPRITE_DESC.columns
+    }
+
+    // Opening the throttle from a standstill brings the front wheel up, and letting it go or
+    // holding it long enough brings it back down, with the sprite following along
+    #[test]
+    fn standstill_launch_pops_a_wheelie() {
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+
+        let accel = RecordedInput {
+            accel: true,
+            ..Default::default()
+        };
+        let is_wheelieing = |app: &mut App| get_player_mut(app).0.is_wheelieing();
+
+        clear_rivals(&mut app);
+        step(&mut app, accel);
+        assert!(is_wheelieing(&mut app), "Launching didn't pop a wheelie");
+        assert_eq!(player_sprite_row(&mut app), PLAYER_WHEELIE_SPRITE_ROW);
+
+        clear_rivals(&mut app);
+        step(&mut app, RecordedInput::default());
+        assert!(
+            !is_wheelieing(&mut app),
+            "Letting go didn't end the wheelie"
+        );
+        assert_eq!(player_sprite_row(&mut app), 0);
+
+        // Still slow enough to pop another, which comes down on its own
+        clear_rivals(&mut app);
+        step(&mut app, accel);
+        assert!(is_wheelieing(&mut app), "Relaunching didn't pop a wheelie");
+        for _ in 0..u32::conv_ceil(PLAYER_WHEELIE_SECS / TIME_STEP) {
+            clear_rivals(&mut app);
+            step(&mut app, accel);
+        }
+        assert!(!is_wheelieing(&mut app), "The wheelie never came down");
+        assert_eq!(player_sprite_row(&mut app), 0);
+    }
+
+    // Drops something the player can't help but crash into right under them, and reports
+    // whether it crashed them
+    fn hit_crash_obstacle(app: &mut App) -> bool {
+        let player_x = -app.world.get_resource::<RoadDynamic>().unwrap().x_offset;
+        app.world.spawn().insert(RoadObject {
+            x_pos: player_x,
+            z_pos: -1.0,
+            collider1: Some(Collider {
+                left: -20.0,
+                right: 20.0,
+                height: 50.0,
+            }),
+            collider2: None,
+            collision_action: CollisionAction::CrashPlayer,
+            ambient_tint: None,
+        });
+        clear_rivals(app);
+        step(app, RecordedInput::default());
+
+        let crashed_events = app.world.get_resource::<Events<PlayerCrashed>>().unwrap();
+        crashed_events.get_reader().iter(crashed_events).count() > 0
+    }
+
+    #[test]
+    fn crashes_are_ignored_while_invulnerable() {
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+        {
+            let (mut player, _) = get_player_mut(&mut app);
+            player.health = 1;
+            player.invuln_timer = Some(Timer::from_seconds(PLAYER_INVULN_SECS, false));
+        }
+
+        assert!(!hit_crash_obstacle(&mut app));
+        let (player, _) = get_player_mut(&mut app);
+        assert!(!player.is_crashing());
+        assert_eq!(player.get_health(), 1);
+
+        // The same hit once it's worn off is enough to crash
+        get_player_mut(&mut app).0.invuln_timer = None;
+        assert!(hit_crash_obstacle(&mut app));
+        assert!(get_player_mut(&mut app).0.is_crashing());
+    }
+
+    // How long a slide started on a straight lasts, in ticks
+    fn slide_ticks(weather: Weather) -> u32 {
+        let mut app = build_headless_app();
+        app.world.insert_resource(weather);
+        start_race_on_straight(&mut app);
+        get_player_mut(&mut app).0.slide(PlayerSlideDirection::Left);
+
+        let mut ticks = 0;
+        while get_player_mut(&mut app).0.is_sliding() {
+            assert!(ticks < 1000, "The slide never ended");
+            clear_rivals(&mut app);
+            step(&mut app, RecordedInput::default());
+            ticks += 1;
+        }
+        ticks
+    }
+
+    #[test]
+    fn slides_last_longer_in_rain() {
+        assert!(slide_ticks(Weather::Rain) > slide_ticks(Weather::Clear));
+    }
+
+    // Drives the night track, and checks the headlight stays on the whole way
+    #[test]
+    fn headlight_is_on_for_the_night_track() {
+        let night_track = TrackFile::load("assets/tracks/night.ron");
+        let mut app = build_headless_app();
+        app.world
+            .get_resource_mut::<TrackConfig>()
+            .unwrap()
+            .time_of_day = night_track.time_of_day.unwrap();
+        start_race_on_straight(&mut app);
+        assert!(app.world.get_resource::<TimeOfDay>().unwrap().is_dark());
+
+        let accel = RecordedInput {
+            accel: true,
+            ..Default::default()
+        };
+        for _ in 0..120 {
+            clear_rivals(&mut app);
+            step(&mut app, accel);
+
+            let headlight_ent = get_player_mut(&mut app).0.headlight_ent;
+            let headlight = app.world.get::<RacerOverlay>(headlight_ent).unwrap();
+            assert!(headlight.is_visible, "The headlight went out at night");
+        }
+
+        let distance = app
+            .world
+            .get_resource::<RoadDynamic>()
+            .unwrap()
+            .get_distance_traveled();
+        assert!(distance > 0.0, "The player didn't go anywhere");
+    }
+}
@@ -4,8 +4,8 @@ use bevy::prelude::*;
 use easy_cast::*;
 
 use crate::{
-    joyride::TIME_STEP,
-    util::{LocalVisible, SpriteGridDesc},
+    joyride::{scaled_step, TimeScale},
+    util::{LocalVisible, SpriteGridDesc, TextureLoader},
 };
 
 pub struct OverlayOffsets(pub [(i32, i32); NUM_TURN_LEVELS]);
@@ -57,6 +57,24 @@ const TIRE_SPRITE_DESC: SpriteGridDesc = SpriteGridDesc {
     columns: 4,
 };
 
+// What an overlay follows, read from the overlay's parent. Racers fill this in from their
+// Racer component, but anything that wants turning-aware overlays can provide one
+pub struct OverlaySource {
+    pub turn_rate: f32,
+    pub lod_level: u8,
+    pub visible: bool,
+}
+
+impl Default for OverlaySource {
+    fn default() -> Self {
+        Self {
+            turn_rate: 0.0,
+            lod_level: 0,
+            visible: true,
+        }
+    }
+}
+
 pub struct RacerOverlay {
     pub offset_cycle_pos: u8,
     pub sprite_cycle_pos: u8,
@@ -149,7 +167,16 @@ impl Systems {
             startup_racer: SystemSet::new().with_system(startup_racer.system()),
             update_racers: SystemSet::new()
                 .with_system(update_tires.system())
-                .with_system(update_racer_overlays.system()),
+                .with_system(
+                    update_racer_overlay_sources
+                        .system()
+                        .label("update_overlay_sources"),
+                )
+                .with_system(
+                    update_racer_overlays
+                        .system()
+                        .after("update_overlay_sources"),
+                ),
         }
     }
 }
@@ -158,8 +185,9 @@ fn startup_racer(
     mut commands: Commands,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     asset_server: Res<AssetServer>,
+    mut texture_loader: ResMut<TextureLoader>,
 ) {
-    let tire_tex = asset_server.load("textures/tire_atlas.png");
+    let tire_tex = texture_loader.load(&asset_server, "textures/tire_atlas.png");
     let tire_atlas = TIRE_SPRITE_DESC.make_atlas(tire_tex);
 
     commands.insert_resource(RacerAssets {
@@ -199,6 +227,7 @@ pub fn make_racer(
             speed,
             tire_ent,
         })
+        .insert(OverlaySource::default())
         .insert(LocalVisible::default())
         .push_children(&[tire_ent])
         .id();
@@ -207,13 +236,16 @@ pub fn make_racer(
 }
 
 fn update_tires(
+    time_scale: Res<TimeScale>,
     mut overlay_query: Query<(&mut RacerOverlay, &mut Timer, &Parent), With<Tire>>,
     racer_query: Query<&Racer>,
 ) {
+    let step = scaled_step(&time_scale);
+
     for (mut overlay, mut timer, parent) in overlay_query.iter_mut() {
         let speed = racer_query.get(parent.0).map_or(0.0, |r| r.speed);
 
-        timer.tick(Duration::from_secs_f32(TIME_STEP));
+        timer.tick(Duration::from_secs_f32(step));
         if timer.finished() {
             overlay.offset_cycle_pos = (overlay.offset_cycle_pos + 1) % overlay.offset_cycle_length;
 
@@ -224,6 +256,13 @@ fn update_tires(
     }
 }
 
+fn update_racer_overlay_sources(mut racer_query: Query<(&Racer, &mut OverlaySource)>) {
+    for (racer, mut source) in racer_query.iter_mut() {
+        source.turn_rate = racer.turn_rate;
+        source.lod_level = racer.lod_level;
+    }
+}
+
 fn update_racer_overlays(
     mut overlay_query: Query<(
         &RacerOverlay,
@@ -232,12 +271,13 @@ fn update_racer_overlays(
         &mut Transform,
         &Parent,
     )>,
-    racer_query: Query<&Racer>,
+    source_query: Query<&OverlaySource>,
 ) {
+    let default_source = OverlaySource::default();
     for (overlay, mut visible, mut sprite, mut xform, parent) in overlay_query.iter_mut() {
-        let (turn_rate, lod_level) = racer_query
-            .get(parent.0)
-            .map_or((0.0, 0), |r| (r.turn_rate, r.lod_level));
+        let source = source_query.get(parent.0).unwrap_or(&default_source);
+        let turn_rate = source.turn_rate;
+        let lod_level = source.lod_level;
 
         if lod_level >= overlay.num_lod_levels {
             if visible.is_visible {
@@ -245,8 +285,10 @@ fn update_racer_overlays(
             }
             continue;
         }
-        if visible.is_visible != overlay.is_visible {
-            visible.is_visible = overlay.is_visible;
+
+        let is_visible = source.visible && overlay.is_visible;
+        if visible.is_visible != is_visible {
+            visible.is_visible = is_visible;
         }
 
         let RacerSpriteParams { turn_idx, flip_x } = get_turning_sprite_desc(turn_rate);
@@ -298,3 +340,61 @@ pub fn get_turning_sprite_desc(turn_rate: f32) -> RacerSpriteParams {
 fn get_tire_cycle_seconds(speed: f32) -> f32 {
     f32::clamp((RACER_MAX_SPEED / speed) / 16.0, 0.02, 0.5)
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::schedule::Stage;
+
+    use super::*;
+
+    // Long enough that a cycle is well clear of ending on a step boundary at either time scale
+    const TEST_CYCLE_SECS: f32 = 0.49;
+
+    // How many times a racer's tires cycle over the given number of steps
+    fn count_tire_cycles(time_scale: f32, steps: u32) -> u32 {
+        let mut world = World::default();
+        world.insert_resource(TimeScale(time_scale));
+
+        let speed = RACER_MAX_SPEED / (TEST_CYCLE_SECS * 16.0);
+        let tire_ent = world
+            .spawn()
+            .insert(Timer::from_seconds(get_tire_cycle_seconds(speed), false))
+            .insert(make_tire_overlay())
+            .insert(Tire {})
+            .id();
+        let racer_ent = world
+            .spawn()
+            .insert(Racer {
+                lod_level: 0,
+                turn_rate: 0.0,
+                speed,
+                tire_ent,
+            })
+            .id();
+        world.entity_mut(tire_ent).insert(Parent(racer_ent));
+
+        let mut stage = SystemStage::single(update_tires.system());
+        let mut cycles = 0;
+        let mut last_pos = 0;
+        for _ in 0..steps {
+            stage.run(&mut world);
+            let pos = world
+                .get::<RacerOverlay>(tire_ent)
+                .unwrap()
+                .offset_cycle_pos;
+            if pos != last_pos {
+                cycles += 1;
+                last_pos = pos;
+            }
+        }
+        cycles
+    }
+
+    #[test]
+    fn tire_cycle_slows_with_time_scale() {
+        let full_speed = count_tire_cycles(1.0, 300);
+        assert!(full_speed > 0);
+        assert_eq!(count_tire_cycles(0.5, 300) * 2, full_speed);
+        assert_eq!(count_tire_cycles(0.5, 600), full_speed);
+    }
+}
@@ -0,0 +1,369 @@
+use bevy::prelude::*;
+
+use crate::joyride::{JoyrideAction, JoyrideInput, JoyrideInputState, RaceRestarted};
+
+pub struct Systems {
+    pub update_input_replay: SystemSet,
+    pub start_input_replay: SystemSet,
+}
+
+impl Systems {
+    pub fn new() -> Self {
+        Self {
+            update_input_replay: SystemSet::new().with_system(update_input_replay.system()),
+            start_input_replay: SystemSet::new().with_system(start_input_replay.system()),
+        }
+    }
+}
+
+// Where a recording is written when it's stopped, and read back from for debug playback
+const RECORDING_PATH: &str = "input_recording.ron";
+
+// Which gameplay actions were held on one frame. Debug actions aren't recorded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct RecordedInput {
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    pub down: bool,
+    pub accel: bool,
+    pub brake: bool,
+    pub turbo: bool,
+}
+
+impl RecordedInput {
+    fn from_input(input: &JoyrideInput) -> Self {
+        Self {
+            left: input.left.is_pressed(),
+            right: input.right.is_pressed(),
+            up: input.up.is_pressed(),
+            down: input.down.is_pressed(),
+            accel: input.accel.is_pressed(),
+            brake: input.brake.is_pressed(),
+            turbo: input.turbo.is_pressed(),
+        }
+    }
+
+    pub fn is_pressed(&self, action: JoyrideAction) -> bool {
+        match action {
+            JoyrideAction::Left => self.left,
+            JoyrideAction::Right => self.right,
+            JoyrideAction::Up => self.up,
+            JoyrideAction::Down => self.down,
+            JoyrideAction::Accel => self.accel,
+            JoyrideAction::Brake => self.brake,
+            JoyrideAction::Turbo => self.turbo,
+            _ => false,
+        }
+    }
+}
+
+// Consecutive frames with the same input are stored as one run, which keeps recordings small
+// enough to write by hand
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct RecordedRun {
+    frames: u32,
+    input: RecordedInput,
+}
+
+// Input for every frame of a race, starting from the first frame after it was restarted
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct InputRecording {
+    runs: Vec<RecordedRun>,
+}
+
+impl InputRecording {
+    fn push(&mut self, input: RecordedInput) {
+        match self.runs.last_mut() {
+            Some(run) if run.input == input => run.frames += 1,
+            _ => self.runs.push(RecordedRun { frames: 1, input }),
+        }
+    }
+
+    pub fn load(path: &str) -> Option<Self> {
+        let result = std::fs::File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| ron::de::from_reader(file).map_err(|e| e.to_string()));
+
+        match result {
+            Ok(recording) => Some(recording),
+            Err(e) => {
+                warn!("Failed to load input recording from {}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    fn save(&self, path: &str) {
+        let result = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())
+            .map_err(|e| e.to_string())
+            .and_then(|text| std::fs::write(path, text).map_err(|e| e.to_string()));
+
+        match result {
+            Ok(_) => info!("Saved input recording to {}", path),
+            Err(e) => warn!("Failed to save input recording to {}: {}", path, e),
+        }
+    }
+}
+
+// Recording and playback are never active together, as playback stands in for the very input
+// that would be recorded. While playing back, the recording drives JoyrideInput in update_input.
+// Neither starts until the race has been restarted, so both count frames from the same place.
+// Scripted input is set from outside the game each frame, for tests driving it without a window
+pub enum InputReplay {
+    Off,
+    Recording {
+        recording: InputRecording,
+        is_started: bool,
+    },
+    Playback {
+        recording: InputRecording,
+        run_idx: usize,
+        run_frame: u32,
+        is_started: bool,
+    },
+    #[cfg(test)]
+    Scripted(RecordedInput),
+}
+
+impl Default for InputReplay {
+    fn default() -> Self {
+        InputReplay::Off
+    }
+}
+
+impl InputReplay {
+    fn recording() -> Self {
+        InputReplay::Recording {
+            recording: InputRecording::default(),
+            is_started: false,
+        }
+    }
+
+    pub fn playback(recording: InputRecording) -> Self {
+        InputReplay::Playback {
+            recording,
+            run_idx: 0,
+            run_frame: 0,
+            is_started: false,
+        }
+    }
+
+    pub fn is_playing_back(&self) -> bool {
+        matches!(self, InputReplay::Playback { .. })
+    }
+
+    // The recorded input for the current frame, once playback has started
+    pub fn playback_input(&self) -> Option<RecordedInput> {
+        match self {
+            InputReplay::Playback {
+                recording,
+                run_idx,
+                is_started: true,
+                ..
+            } => recording.runs.get(*run_idx).map(|run| run.input),
+            #[cfg(test)]
+            InputReplay::Scripted(input) => Some(*input),
+            _ => None,
+        }
+    }
+
+    // Moves playback on to the next frame, and stops it once the recording runs out
+    fn advance_playback(&mut self) {
+        let is_finished = match self {
+            InputReplay::Playback {
+                recording,
+                run_idx,
+                run_frame,
+                is_started: true,
+            } => {
+                *run_frame += 1;
+                while let Some(run) = recording.runs.get(*run_idx) {
+                    if *run_frame < run.frames {
+                        break;
+                    }
+                    *run_idx += 1;
+                    *run_frame = 0;
+                }
+                *run_idx >= recording.runs.len()
+            }
+            _ => false,
+        };
+
+        if is_finished {
+            *self = InputReplay::Off;
+        }
+    }
+}
+
+// Runs right after update_input, so each frame is recorded exactly as the game sees it
+fn update_input_replay(
+    input: Res<JoyrideInput>,
+    mut replay: ResMut<InputReplay>,
+    mut restarted_events: EventWriter<RaceRestarted>,
+) {
+    let replay: &mut InputReplay = &mut replay;
+
+    if input.debug_record_input == JoyrideInputState::JustPressed {
+        match replay {
+            InputReplay::Off => {
+                *replay = InputReplay::recording();
+                restarted_events.send(RaceRestarted);
+            }
+            InputReplay::Recording { recording, .. } => {
+                recording.save(RECORDING_PATH);
+                *replay = InputReplay::Off;
+            }
+            InputReplay::Playback { .. } => {}
+            #[cfg(test)]
+            InputReplay::Scripted(_) => {}
+        }
+        return;
+    }
+
+    if input.debug_play_recording == JoyrideInputState::JustPressed {
+        if let InputReplay::Off = replay {
+            if let Some(recording) = InputRecording::load(RECORDING_PATH) {
+                *replay = InputReplay::playback(recording);
+                restarted_events.send(RaceRestarted);
+            }
+        }
+        return;
+    }
+
+    match replay {
+        InputReplay::Recording {
+            recording,
+            is_started: true,
+        } => recording.push(RecordedInput::from_input(&input)),
+        InputReplay::Playback { .. } => replay.advance_playback(),
+        _ => {}
+    }
+}
+
+// Restarting the race partway through a recording starts the recording over too
+fn start_input_replay(
+    mut restarted_events: EventReader<RaceRestarted>,
+    mut replay: ResMut<InputReplay>,
+) {
+    if restarted_events.iter().next().is_none() {
+        return;
+    }
+
+    match &mut *replay {
+        InputReplay::Recording {
+            recording,
+            is_started,
+        } => {
+            *recording = InputRecording::default();
+            *is_started = true;
+        }
+        InputReplay::Playback {
+            run_idx,
+            run_frame,
+            is_started,
+            ..
+        } => {
+            *run_idx = 0;
+            *run_frame = 0;
+            *is_started = true;
+        }
+        InputReplay::Off => {}
+        #[cfg(test)]
+        InputReplay::Scripted(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::app::Events;
+
+    use super::*;
+    use crate::{
+        headless::{build_headless_app, clear_rivals},
+        joyride::GameRng,
+        road::RoadDynamic,
+    };
+
+    // Both runs need the same road objects, so they need the same seed
+    const TEST_SEED: u64 = 1234;
+
+    // Comfortably past the countdown, so there's some driving to compare
+    const NUM_FRAMES: usize = 540;
+
+    const DRIVING_KEYS: [KeyCode; 4] = [KeyCode::Z, KeyCode::X, KeyCode::Left, KeyCode::Right];
+
+    // Accel the whole way, with some weaving and a dab of the brakes once the race is on. Nothing
+    // is held on the frame the race restarts, which isn't recorded
+    fn held_keys(frame: usize) -> Vec<KeyCode> {
+        let mut keys = Vec::new();
+        if frame > 0 {
+            keys.push(KeyCode::Z);
+        }
+        if (240..300).contains(&frame) {
+            keys.push(KeyCode::Left);
+        }
+        if (330..420).contains(&frame) {
+            keys.push(KeyCode::Right);
+        }
+        if (450..470).contains(&frame) {
+            keys.push(KeyCode::X);
+        }
+        keys
+    }
+
+    // Restarts the race with the given replay, then runs it with the keys held on each frame.
+    // Returns the X offset after each frame, and the replay as it was left
+    fn run_race(
+        replay: InputReplay,
+        held_keys: impl Fn(usize) -> Vec<KeyCode>,
+    ) -> (Vec<f32>, InputReplay) {
+        let mut app = build_headless_app();
+        app.world.insert_resource(GameRng::new(TEST_SEED));
+        app.update();
+
+        app.world.insert_resource(replay);
+        app.world
+            .get_resource_mut::<Events<RaceRestarted>>()
+            .unwrap()
+            .send(RaceRestarted);
+
+        let mut x_offsets = Vec::new();
+        for frame in 0..NUM_FRAMES {
+            let held = held_keys(frame);
+            let mut keys = app.world.get_resource_mut::<Input<KeyCode>>().unwrap();
+            for key in DRIVING_KEYS.iter() {
+                if held.contains(key) {
+                    keys.press(*key);
+                } else {
+                    keys.release(*key);
+                }
+            }
+
+            clear_rivals(&mut app);
+            app.update();
+            x_offsets.push(app.world.get_resource::<RoadDynamic>().unwrap().x_offset);
+        }
+
+        let replay = app.world.remove_resource::<InputReplay>().unwrap();
+        (x_offsets, replay)
+    }
+
+    #[test]
+    fn played_back_recording_retraces_the_recorded_run() {
+        let (recorded_x_offsets, replay) = run_race(InputReplay::recording(), held_keys);
+        let recording = match replay {
+            InputReplay::Recording { recording, .. } => recording,
+            _ => panic!("The recording was stopped"),
+        };
+        assert!(
+            recorded_x_offsets.iter().any(|x_offset| *x_offset != 0.0),
+            "The recorded run never moved sideways"
+        );
+
+        // Playback stands in for the keyboard, so nothing needs holding the second time
+        let (played_x_offsets, _) = run_race(InputReplay::playback(recording), |_| Vec::new());
+        assert_eq!(recorded_x_offsets, played_x_offsets);
+    }
+}
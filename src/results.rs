@@ -0,0 +1,129 @@
+use bevy::prelude::*;
+use easy_cast::*;
+
+use crate::{
+    joyride::{
+        change_screen, GameScreen, JoyrideGame, JoyrideInput, JoyrideInputState, RaceRestarted,
+        Score, FIELD_HEIGHT, FIELD_WIDTH,
+    },
+    rival::RacePosition,
+    road::RoadDynamic,
+    text::{spawn_text, DISTANCE_TO_METERS, SMALL_FONT_SPRITE_DESC, SMALL_FONT_WIDTH},
+    util::{spawn_empty_parent, TextureLoader},
+};
+
+pub struct Systems {
+    pub startup_results: SystemSet,
+    pub update_results: SystemSet,
+}
+
+impl Systems {
+    pub fn new() -> Self {
+        Self {
+            startup_results: SystemSet::new().with_system(startup_results.system()),
+            update_results: SystemSet::new().with_system(update_results.system()),
+        }
+    }
+}
+
+// Draws over the HUD, but under the menu
+const RESULTS_Z: f32 = 850.0;
+
+const RESULTS_LINE_SPACING: f32 = 14.0;
+
+// The text depends on how the race went, so it's spawned each time the results come up,
+// and cleared away when they're dismissed
+struct Results {
+    parent_ent: Entity,
+    font_atlas: Handle<TextureAtlas>,
+    text_ents: Vec<Entity>,
+}
+
+fn startup_results(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut texture_loader: ResMut<TextureLoader>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+) {
+    let font_tex = texture_loader.load(&asset_server, "textures/small_font_atlas.png");
+    let font_atlas = texture_atlases.add(SMALL_FONT_SPRITE_DESC.make_atlas(font_tex));
+
+    let parent_ent = spawn_empty_parent(
+        &mut commands,
+        Vec3::new(
+            f32::conv(FIELD_WIDTH) * 0.5,
+            f32::conv(FIELD_HEIGHT) * 0.5,
+            RESULTS_Z,
+        ),
+    )
+    .id();
+
+    commands.insert_resource(Results {
+        parent_ent,
+        font_atlas,
+        text_ents: Vec::new(),
+    });
+}
+
+// The race is frozen behind the results. Any key or button returns to the menu, with the race reset so
+// the next one starts fresh
+fn update_results(
+    mut commands: Commands,
+    input: Res<JoyrideInput>,
+    mut screen: ResMut<State<GameScreen>>,
+    mut results: ResMut<Results>,
+    game: Res<JoyrideGame>,
+    score: Res<Score>,
+    position: Res<RacePosition>,
+    road_dyn: Res<RoadDynamic>,
+    mut restarted_events: EventWriter<RaceRestarted>,
+) {
+    let results: &mut Results = &mut results;
+    if *screen.current() != GameScreen::Results {
+        return;
+    }
+
+    if results.text_ents.is_empty() {
+        let race_secs = game
+            .completion_secs
+            .unwrap_or_else(|| game.remaining_time.elapsed_secs());
+        let remaining_secs =
+            game.remaining_time.duration().as_secs_f32() - game.remaining_time.elapsed_secs();
+        let meters = road_dyn.get_distance_traveled() * DISTANCE_TO_METERS;
+
+        let lines = [
+            "RESULTS".to_string(),
+            format!("DISTANCE {}M", u32::conv_floor(meters)),
+            format!("TIME {}", u32::conv_floor(race_secs)),
+            format!("LEFT {}", u32::conv_floor(remaining_secs.max(0.0))),
+            format!("PLACE {}", position.place),
+            format!("SCORE {}", score.points),
+            "PRESS ANY KEY".to_string(),
+        ];
+
+        let top_y = RESULTS_LINE_SPACING * f32::conv(lines.len() - 1) * 0.5;
+        for (i, line) in lines.iter().enumerate() {
+            let x = -SMALL_FONT_WIDTH * f32::conv(line.len() - 1) * 0.5;
+            let y = top_y - (RESULTS_LINE_SPACING * f32::conv(i));
+            let ents = spawn_text(
+                &mut commands,
+                &results.font_atlas,
+                line,
+                Vec3::new(x, y, 0.0),
+            );
+            results.text_ents.extend(ents);
+        }
+        commands
+            .entity(results.parent_ent)
+            .push_children(&results.text_ents);
+        return;
+    }
+
+    if input.any == JoyrideInputState::JustPressed {
+        for ent in results.text_ents.drain(..) {
+            commands.entity(ent).despawn_recursive();
+        }
+        restarted_events.send(RaceRestarted);
+        change_screen(&mut screen, GameScreen::Menu);
+    }
+}
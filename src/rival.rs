@@ -1,23 +1,99 @@
+use std::cmp::Ordering;
+
 use bevy::prelude::*;
 use easy_cast::*;
 
 use crate::{
     debug::{spawn_collision_debug_box, DebugAssets},
-    joyride::TIME_STEP,
+    joyride::{scaled_step, Difficulty, RaceRestarted, TimeScale},
     player::PLAYER_MAX_NORMAL_SPEED,
-    racer::{get_turning_sprite_desc, make_racer, Racer, RacerAssets, NUM_TURN_LEVELS},
-    road::{get_draw_params_on_road, RoadDynamic, RoadStatic},
+    racer::{
+        get_turning_sprite_desc, make_racer, Racer, RacerAssets, MAX_TURN_RATE, NUM_TURN_LEVELS,
+        RACER_MAX_SPEED,
+    },
+    road::{get_draw_params_on_road, RoadDynamic, RoadStatic, TrackConfig, PAVEMENT_WIDTH},
     road_object::{Collider, CollisionAction, RoadObject},
-    util::{LocalVisible, SpriteGridDesc},
+    time_of_day::TimeOfDay,
+    util::{LocalVisible, SpriteGridDesc, TextureLoader},
 };
 
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub enum RivalPalette {
     Green,
     Red,
 }
 
+// The rivals lined up ahead of the player at the start of a race. Any field left out of a
+// rivals file keeps its default
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct RivalConfig {
+    pub count: usize,
+
+    // Cycled through in spawn order
+    pub palettes: Vec<RivalPalette>,
+
+    // Z distance between one rival and the next, the first being this far ahead of the player
+    pub spacing_z: f32,
+
+    // Also used for rivals spawned along the road later, before difficulty is applied
+    pub initial_speed: f32,
+}
+
+impl Default for RivalConfig {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            palettes: vec![RivalPalette::Green, RivalPalette::Red],
+            spacing_z: 4.0,
+            initial_speed: 4.0,
+        }
+    }
+}
+
 pub struct Rival {
     palette: RivalPalette,
+
+    // Whether the player is currently in this rival's slipstream
+    is_drafted: bool,
+}
+
+impl Rival {
+    pub fn snapshot(&self, obj: &RoadObject, racer: &Racer) -> RivalState {
+        RivalState {
+            x_pos: obj.x_pos,
+            z_pos: obj.z_pos,
+            speed: racer.speed,
+            palette: self.palette,
+        }
+    }
+}
+
+// Enough to spawn a rival again where it was. Whether it's being drafted is worked out anew
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct RivalState {
+    pub x_pos: f32,
+    pub z_pos: f32,
+    pub speed: f32,
+    pub palette: RivalPalette,
+}
+
+// The player's place in the race, 1 being first. Rivals are cleared away once the player passes
+// them, so every rival still on the road is one place ahead
+pub struct RacePosition {
+    pub place: usize,
+}
+
+impl Default for RacePosition {
+    fn default() -> Self {
+        Self { place: 1 }
+    }
+}
+
+// Whether the player is in a rival's slipstream, which lets them pull past their normal top speed
+#[derive(Default)]
+pub struct Slipstream {
+    pub is_drafting: bool,
 }
 
 pub struct RivalAssets {
@@ -26,6 +102,7 @@ pub struct RivalAssets {
 
 pub struct Systems {
     pub startup_rivals: SystemSet,
+    pub reset_rivals: SystemSet,
     pub update_rivals: SystemSet,
     pub update_rival_visuals: SystemSet,
 }
@@ -34,7 +111,11 @@ impl Systems {
     pub fn new() -> Self {
         Self {
             startup_rivals: SystemSet::new().with_system(startup_rivals.system()),
-            update_rivals: SystemSet::new().with_system(update_rivals.system()),
+            reset_rivals: SystemSet::new().with_system(reset_rivals.system()),
+            update_rivals: SystemSet::new()
+                .with_system(update_rivals.system())
+                .with_system(update_drafted_rivals.system())
+                .with_system(update_race_position.system()),
             update_rival_visuals: SystemSet::new().with_system(update_rival_visuals.system()),
         }
     }
@@ -48,6 +129,46 @@ const RIVAL_SPRITE_DESC: SpriteGridDesc = SpriteGridDesc {
 
 const LOD_SCALE_MAPPING: [f32; 7] = [0.83, 0.67, 0.55, 0.42, 0.30, 0.22, 0.16];
 
+const RIVAL_COLLIDER_HEIGHT: f32 = 40.0;
+const RIVAL_HALF_WIDTH: f32 = 15.0;
+
+// Rivals steer around obstacles up to this far ahead of them (in Z), keeping this much room
+// on either side. They never leave the pavement to do so
+const RIVAL_AVOID_LOOKAHEAD_Z: f32 = 8.0;
+const RIVAL_AVOID_MARGIN_X: f32 = 5.0;
+const RIVAL_AVOID_SPEED: f32 = 80.0;
+const RIVAL_AVOID_TURN_RATE: f32 = MAX_TURN_RATE * 0.5;
+const RIVAL_MAX_X: f32 = PAVEMENT_WIDTH - RIVAL_HALF_WIDTH;
+
+// Rubber banding is at full strength once a rival is this far ahead of the player (in Z), and
+// eases off linearly as the player catches up, turning into a speed boost once they pass.
+// However strong it gets, it never slows a rival below the minimum speed
+const RIVAL_RUBBER_BAND_GAP_Z: f32 = 30.0;
+const RIVAL_MIN_SPEED: f32 = 1.0;
+
+// Rivals closer than this on both axes gently push each other apart laterally
+const RIVAL_MIN_SEPARATION_X: f32 = 30.0;
+const RIVAL_MIN_SEPARATION_Z: f32 = 1.0;
+const RIVAL_REPULSION_SPEED: f32 = 60.0;
+
+// How far ahead of the player (in Z) and how far to the side (in player X units) a rival can be
+// while the player is still in its slipstream
+const DRAFT_MAX_DISTANCE_Z: f32 = 3.0;
+const DRAFT_MAX_OFFSET_X: f32 = 20.0;
+
+// Lined-up rivals are cycled through these lanes, as fractions of how far they can be from the
+// center of the road, so that no two neighbors share one
+const RIVAL_LINEUP_LANES: [f32; 4] = [-0.6, 0.6, -0.2, 0.2];
+
+const RIVALS_NOT_LOADED: &str = "Failed to load rival lineup";
+
+// Tint applied to the rival the player is drafting
+const DRAFT_TINT: Color = Color::rgb(0.7, 0.85, 1.0);
+
+// At night, rivals fade out past this LOD level, down to this alpha at the farthest one
+const NIGHT_FADE_LOD_LEVEL: u8 = 3;
+const NIGHT_MIN_ALPHA: f32 = 0.1;
+
 pub fn spawn_rival(
     commands: &mut Commands,
     x_pos: f32,
@@ -66,56 +187,344 @@ pub fn spawn_rival(
         Vec3::default(),
     );
 
-    let coll_left = -15.0;
-    let coll_right = 15.0;
+    let coll_left = -RIVAL_HALF_WIDTH;
+    let coll_right = RIVAL_HALF_WIDTH;
+    let collision_action = CollisionAction::SlidePlayer;
     let debug_box = spawn_collision_debug_box(
         commands,
         &debug_assets,
+        Some(collision_action),
         Vec2::new(0.0, -f32::conv(RIVAL_SPRITE_DESC.tile_size) * 0.5),
         Vec2::new(coll_right - coll_left, 1.0),
     );
 
     commands
         .entity(racer_ent)
-        .insert(Rival { palette })
+        .insert(Rival {
+            palette,
+            is_drafted: false,
+        })
         .insert(RoadObject {
             x_pos,
             z_pos,
             collider1: Some(Collider {
                 left: coll_left,
                 right: coll_right,
+                height: RIVAL_COLLIDER_HEIGHT,
             }),
             collider2: None,
-            collision_action: CollisionAction::SlidePlayer,
+            collision_action,
+            ambient_tint: None,
         })
         .push_children(&[debug_box]);
 }
 
+pub fn spawn_rival_lineup(
+    commands: &mut Commands,
+    config: &RivalConfig,
+    rival_assets: &RivalAssets,
+    racer_assets: &RacerAssets,
+    debug_assets: &DebugAssets,
+) {
+    for idx in 0..config.count {
+        let x_pos = RIVAL_LINEUP_LANES[idx % RIVAL_LINEUP_LANES.len()] * RIVAL_MAX_X;
+        let z_pos = config.spacing_z * f32::conv(idx + 1);
+        let palette = config
+            .palettes
+            .get(idx % config.palettes.len().max(1))
+            .copied()
+            .unwrap_or(RivalPalette::Green);
+
+        spawn_rival(
+            commands,
+            x_pos,
+            z_pos,
+            config.initial_speed,
+            palette,
+            rival_assets,
+            racer_assets,
+            debug_assets,
+        );
+    }
+}
+
+fn load_rival_config(path: &str) -> Option<RivalConfig> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("{} from {}: {}", RIVALS_NOT_LOADED, path, e);
+            return None;
+        }
+    };
+
+    match ron::de::from_reader(file) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            warn!("{} from {}: {}", RIVALS_NOT_LOADED, path, e);
+            None
+        }
+    }
+}
+
 fn startup_rivals(
     mut commands: Commands,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     asset_server: Res<AssetServer>,
+    mut texture_loader: ResMut<TextureLoader>,
+    mut rival_config: ResMut<RivalConfig>,
+    track_config: Res<TrackConfig>,
+    racer_assets: Res<RacerAssets>,
+    debug_assets: Res<DebugAssets>,
 ) {
-    let bike_tex = asset_server.load("textures/rival_atlas.png");
+    let bike_tex = texture_loader.load(&asset_server, "textures/rival_atlas.png");
     let bike_atlas = RIVAL_SPRITE_DESC.make_atlas(bike_tex);
     let bike_atlas_handle = texture_atlases.add(bike_atlas);
 
     let rival_assets = RivalAssets {
         bike_atlas: bike_atlas_handle,
     };
+
+    // The track's own lineup replaces the configured one, so later spawns follow it too
+    if let Some(loaded_config) = track_config
+        .rivals_path
+        .as_deref()
+        .and_then(load_rival_config)
+    {
+        *rival_config = loaded_config;
+    }
+
+    spawn_rival_lineup(
+        &mut commands,
+        &rival_config,
+        &rival_assets,
+        &racer_assets,
+        &debug_assets,
+    );
+
     commands.insert_resource(rival_assets);
 }
 
+// Every rival is cleared away, including any spawned along the road, and the lineup starts over
+fn reset_rivals(
+    mut commands: Commands,
+    mut restarted_events: EventReader<RaceRestarted>,
+    rivals: Query<Entity, With<Rival>>,
+    rival_config: Res<RivalConfig>,
+    rival_assets: Res<RivalAssets>,
+    racer_assets: Res<RacerAssets>,
+    debug_assets: Res<DebugAssets>,
+) {
+    if restarted_events.iter().next().is_none() {
+        return;
+    }
+
+    for ent in rivals.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+
+    spawn_rival_lineup(
+        &mut commands,
+        &rival_config,
+        &rival_assets,
+        &racer_assets,
+        &debug_assets,
+    );
+}
+
+// The Z position and left and right X extents of an object's colliders
+fn get_obstacle_extents(obj: &RoadObject) -> Option<(f32, f32, f32)> {
+    let colliders = obj.collider1.iter().chain(obj.collider2.iter());
+    colliders.fold(None, |extents, coll| {
+        let (left, right) = (obj.x_pos + coll.left, obj.x_pos + coll.right);
+        Some(match extents {
+            Some((z_pos, l, r)) => (z_pos, f32::min(l, left), f32::max(r, right)),
+            None => (obj.z_pos, left, right),
+        })
+    })
+}
+
+// Which way (if at all) a rival at this position should steer to get around the nearest
+// obstacle ahead of it. Positive is to the right
+fn get_avoid_direction(x_pos: f32, z_pos: f32, obstacles: &[(f32, f32, f32)]) -> f32 {
+    let (left, right) = (
+        x_pos - RIVAL_HALF_WIDTH - RIVAL_AVOID_MARGIN_X,
+        x_pos + RIVAL_HALF_WIDTH + RIVAL_AVOID_MARGIN_X,
+    );
+
+    let nearest = obstacles
+        .iter()
+        .filter(|(obs_z, obs_left, obs_right)| {
+            let z_ahead = obs_z - z_pos;
+            z_ahead >= 0.0
+                && z_ahead <= RIVAL_AVOID_LOOKAHEAD_Z
+                && *obs_left < right
+                && *obs_right > left
+        })
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+    match nearest {
+        Some((_, obs_left, obs_right)) if x_pos >= (obs_left + obs_right) * 0.5 => 1.0,
+        Some(_) => -1.0,
+        None => 0.0,
+    }
+}
+
 fn update_rivals(
-    mut query: Query<(&mut RoadObject, &mut Racer, With<Rival>)>,
+    time_scale: Res<TimeScale>,
+    mut query: Query<(Entity, &mut RoadObject, &mut Racer, With<Rival>)>,
+    obstacle_query: Query<&RoadObject, Without<Rival>>,
     road_dyn: Res<RoadDynamic>,
+    difficulty: Res<Difficulty>,
 ) {
-    for (mut obj, mut racer, _) in query.iter_mut() {
-        obj.z_pos += racer.speed * TIME_STEP;
+    let step = scaled_step(&time_scale);
+
+    // Applied here rather than at spawn, so the lineup follows whatever was picked in the menu
+    let speed_scalar = difficulty.settings().rival_speed;
+    let rubber_band = difficulty.settings().rubber_band;
+
+    // Pickups are harmless, so there's no need to steer around them
+    let obstacles: Vec<(f32, f32, f32)> = obstacle_query
+        .iter()
+        .filter(|obj| obj.collision_action != CollisionAction::CollectPickup)
+        .filter_map(get_obstacle_extents)
+        .collect();
+
+    for (_, mut obj, mut racer, _) in query.iter_mut() {
+        obj.z_pos +=
+            get_rubber_band_speed(racer.speed * speed_scalar, obj.z_pos, rubber_band) * step;
 
         // Racers go significantly slower than the player, but we want their turn rates to be similar,
         // so we fudge their speed
-        racer.turn_rate = road_dyn.get_road_x_pull(obj.z_pos, PLAYER_MAX_NORMAL_SPEED);
+        let curve_turn_rate = road_dyn.get_road_x_pull(obj.z_pos, PLAYER_MAX_NORMAL_SPEED);
+
+        let avoid_dir = get_avoid_direction(obj.x_pos, obj.z_pos, &obstacles);
+        if avoid_dir != 0.0 {
+            obj.x_pos = f32::clamp(
+                obj.x_pos + avoid_dir * RIVAL_AVOID_SPEED * step,
+                -RIVAL_MAX_X,
+                RIVAL_MAX_X,
+            );
+        }
+
+        racer.turn_rate = f32::clamp(
+            curve_turn_rate + avoid_dir * RIVAL_AVOID_TURN_RATE,
+            -MAX_TURN_RATE,
+            MAX_TURN_RATE,
+        );
+    }
+
+    // The rival count is small, so a pairwise check is cheap enough
+    let positions: Vec<(Entity, f32, f32)> = query
+        .iter_mut()
+        .map(|(ent, obj, _, _)| (ent, obj.x_pos, obj.z_pos))
+        .collect();
+    if positions.len() < 2 {
+        return;
+    }
+
+    for &(ent, x_pos, z_pos) in positions.iter() {
+        let push_dir = get_separation_push_dir(ent, x_pos, z_pos, &positions);
+        if push_dir != 0.0 {
+            if let Ok((_, mut obj, _, _)) = query.get_mut(ent) {
+                obj.x_pos = f32::clamp(
+                    obj.x_pos + (push_dir * RIVAL_REPULSION_SPEED * step),
+                    -RIVAL_MAX_X,
+                    RIVAL_MAX_X,
+                );
+            }
+        }
+    }
+}
+
+// Z is relative to the player, so it's the gap between them. The speed follows it continuously,
+// so rivals never visibly jump
+fn get_rubber_band_speed(base_speed: f32, z_pos: f32, rubber_band: f32) -> f32 {
+    let rubber_band_scalar =
+        1.0 - (rubber_band * f32::clamp(z_pos / RIVAL_RUBBER_BAND_GAP_Z, -1.0, 1.0));
+    f32::clamp(
+        base_speed * rubber_band_scalar,
+        f32::min(base_speed, RIVAL_MIN_SPEED),
+        RACER_MAX_SPEED,
+    )
+}
+
+// Which way a rival is pushed by any others crowding it: -1, 1, or 0 when it has room. Only the
+// given neighbors are checked, so bucketing rivals by Z later only changes what's passed in
+fn get_separation_push_dir(
+    ent: Entity,
+    x_pos: f32,
+    z_pos: f32,
+    neighbors: &[(Entity, f32, f32)],
+) -> f32 {
+    let mut push_dir = 0.0;
+    for &(other_ent, other_x_pos, other_z_pos) in neighbors.iter() {
+        let x_diff = x_pos - other_x_pos;
+        if other_ent == ent
+            || x_diff.abs() >= RIVAL_MIN_SEPARATION_X
+            || (z_pos - other_z_pos).abs() >= RIVAL_MIN_SEPARATION_Z
+        {
+            continue;
+        }
+
+        // Break exact ties by entity order, so the two rivals don't push the same way
+        push_dir += if x_diff > 0.0 || (x_diff == 0.0 && ent > other_ent) {
+            1.0
+        } else {
+            -1.0
+        };
+    }
+
+    if push_dir == 0.0 {
+        0.0
+    } else {
+        f32::signum(push_dir)
+    }
+}
+
+fn update_drafted_rivals(
+    mut query: Query<(Entity, &RoadObject, &mut Rival)>,
+    road_static: Res<RoadStatic>,
+    road_dyn: Res<RoadDynamic>,
+    mut slipstream: ResMut<Slipstream>,
+) {
+    let screen_bottom_z = road_static.z_map()[0];
+    let screen_bottom_scale = road_static.scale_map()[0];
+    let player_x = -road_dyn.x_offset;
+
+    // Only the closest rival in the player's slipstream counts as being drafted
+    let mut drafted: Option<(Entity, f32)> = None;
+    for (ent, obj, _) in query.iter_mut() {
+        let distance_z = obj.z_pos - screen_bottom_z;
+        let offset_x = (obj.x_pos * screen_bottom_scale) - player_x;
+        if distance_z < 0.0
+            || distance_z > DRAFT_MAX_DISTANCE_Z
+            || offset_x.abs() > DRAFT_MAX_OFFSET_X
+        {
+            continue;
+        }
+
+        if drafted.map_or(true, |(_, closest_z)| distance_z < closest_z) {
+            drafted = Some((ent, distance_z));
+        }
+    }
+
+    let is_drafting = drafted.is_some();
+    if slipstream.is_drafting != is_drafting {
+        slipstream.is_drafting = is_drafting;
+    }
+
+    for (ent, _, mut rival) in query.iter_mut() {
+        let is_drafted = drafted.map_or(false, |(drafted_ent, _)| drafted_ent == ent);
+        if rival.is_drafted != is_drafted {
+            rival.is_drafted = is_drafted;
+        }
+    }
+}
+
+fn update_race_position(rivals: Query<&Rival>, mut position: ResMut<RacePosition>) {
+    let place = rivals.iter().count() + 1;
+    if position.place != place {
+        position.place = place;
     }
 }
 
@@ -130,20 +539,28 @@ fn update_rival_visuals(
     )>,
     road_static: Res<RoadStatic>,
     road_dyn: Res<RoadDynamic>,
+    time_of_day: Res<TimeOfDay>,
 ) {
+    let max_lod_level: u8 = (RIVAL_SPRITE_DESC.rows - 1).cast();
     for (rival, obj, mut racer, mut sprite, mut visible, mut xform) in query.iter_mut() {
         let draw_params = get_draw_params_on_road(&road_static, &road_dyn, obj.x_pos, obj.z_pos);
 
         let mut is_visible = false;
         if let Some(draw_params) = draw_params {
+            let lod_level = RIVAL_SPRITE_DESC.get_lod_level(&LOD_SCALE_MAPPING, draw_params.scale);
+            let draw_scale = RIVAL_SPRITE_DESC.get_lod_draw_scale(
+                &LOD_SCALE_MAPPING,
+                draw_params.scale,
+                lod_level,
+            );
+
+            // Scaled about its center, so the offset shrinks too to keep it standing on the road
             xform.translation.x = draw_params.draw_pos.x;
-            xform.translation.y =
-                draw_params.draw_pos.y + (f32::conv(RIVAL_SPRITE_DESC.tile_size) * 0.5);
+            xform.translation.y = draw_params.draw_pos.y
+                + (f32::conv(RIVAL_SPRITE_DESC.tile_size) * 0.5 * draw_scale);
+            xform.scale = Vec3::new(draw_scale, draw_scale, 1.0);
 
-            let lod_level: u8 = LOD_SCALE_MAPPING
-                .binary_search_by(|x| draw_params.scale.partial_cmp(&x).unwrap())
-                .unwrap_or_else(|x| x)
-                .cast();
+            let lod_level: u8 = lod_level.cast();
             racer.lod_level = lod_level;
 
             let sprite_params = get_turning_sprite_desc(racer.turn_rate);
@@ -153,6 +570,18 @@ fn update_rival_visuals(
             };
             sprite.flip_x = sprite_params.flip_x;
             sprite.index = RIVAL_SPRITE_DESC.get_sprite_index(sprite_x, lod_level.cast());
+            sprite.color = if rival.is_drafted {
+                DRAFT_TINT
+            } else {
+                Color::WHITE
+            };
+
+            if time_of_day.is_dark() && lod_level > NIGHT_FADE_LOD_LEVEL {
+                let fade = f32::conv(lod_level - NIGHT_FADE_LOD_LEVEL)
+                    / f32::conv(max_lod_level - NIGHT_FADE_LOD_LEVEL);
+                let alpha = 1.0 - (fade * time_of_day.get_darkness() * (1.0 - NIGHT_MIN_ALPHA));
+                sprite.color.set_a(alpha);
+            }
 
             is_visible = true;
         }
@@ -162,3 +591,157 @@ fn update_rival_visuals(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        headless::{build_headless_app, start_race_on_straight, step},
+        replay::RecordedInput,
+    };
+
+    fn get_rivals(app: &mut App) -> Vec<(Entity, f32, f32)> {
+        app.world
+            .query_filtered::<(Entity, &RoadObject), With<Rival>>()
+            .iter(&app.world)
+            .map(|(ent, obj)| (ent, obj.x_pos, obj.z_pos))
+            .collect()
+    }
+
+    #[test]
+    fn rivals_steer_away_from_obstacles_ahead() {
+        let mut app = build_headless_app();
+        app.world.insert_resource(RivalConfig {
+            count: 1,
+            spacing_z: 3.0,
+            ..Default::default()
+        });
+        start_race_on_straight(&mut app);
+
+        // Rivals spawned along the road start further out, so the nearest is the lined up one
+        let (rival_ent, rival_x, rival_z) = get_rivals(&mut app)
+            .into_iter()
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+            .expect("No rival was lined up");
+
+        // Just off to the rival's right, so the way around is to the left
+        app.world.spawn().insert(RoadObject {
+            x_pos: rival_x + 5.0,
+            z_pos: rival_z + RIVAL_AVOID_LOOKAHEAD_Z * 0.5,
+            collider1: Some(Collider {
+                left: -10.0,
+                right: 10.0,
+                height: 50.0,
+            }),
+            collider2: None,
+            collision_action: CollisionAction::CrashPlayer,
+            ambient_tint: None,
+        });
+
+        for _ in 0..5 {
+            step(&mut app, RecordedInput::default());
+        }
+
+        let new_x = app.world.get::<RoadObject>(rival_ent).unwrap().x_pos;
+        assert!(
+            new_x < rival_x,
+            "The rival went from {} to {}, not away from the obstacle",
+            rival_x,
+            new_x
+        );
+    }
+
+    #[test]
+    fn configured_lineup_spawns_that_many_rivals() {
+        let mut app = build_headless_app();
+        app.world.insert_resource(RivalConfig {
+            count: 5,
+            ..Default::default()
+        });
+
+        // Rivals stop being spawned along the road once there's a couple about, so a lineup
+        // this size is all there is
+        step(&mut app, RecordedInput::default());
+
+        let rivals = get_rivals(&mut app);
+        assert_eq!(rivals.len(), 5);
+
+        let first_x = rivals[0].1;
+        assert!(
+            rivals.iter().any(|&(_, x_pos, _)| x_pos != first_x),
+            "The rivals are all stacked at the same X"
+        );
+    }
+
+    // The two nearest rivals, placed almost on top of each other
+    fn overlap_lineup(app: &mut App) -> (Entity, Entity) {
+        let mut lineup = get_rivals(app);
+        lineup.sort_by(|a, b| a.2.total_cmp(&b.2));
+        let (left_ent, _, z_pos) = lineup[0];
+        let right_ent = lineup[1].0;
+
+        // A sliver apart, so which way each should go is clear
+        for &(ent, x_pos) in [(left_ent, -0.5), (right_ent, 0.5)].iter() {
+            let mut obj = app.world.get_mut::<RoadObject>(ent).unwrap();
+            obj.x_pos = x_pos;
+            obj.z_pos = z_pos;
+        }
+        (left_ent, right_ent)
+    }
+
+    #[test]
+    fn overlapping_rivals_push_apart() {
+        let mut app = build_headless_app();
+        app.world.insert_resource(RivalConfig {
+            count: 2,
+            ..Default::default()
+        });
+        start_race_on_straight(&mut app);
+        let (left_ent, right_ent) = overlap_lineup(&mut app);
+
+        for _ in 0..10 {
+            step(&mut app, RecordedInput::default());
+        }
+
+        let get_x = |app: &App, ent| app.world.get::<RoadObject>(ent).unwrap().x_pos;
+        let (left_x, right_x) = (get_x(&app, left_ent), get_x(&app, right_ent));
+        assert!(
+            left_x < -0.5 && right_x > 0.5,
+            "The rivals went to {} and {}, rather than apart",
+            left_x,
+            right_x
+        );
+        assert!(left_x >= -RIVAL_MAX_X && right_x <= RIVAL_MAX_X);
+    }
+
+    #[test]
+    fn rivals_left_behind_speed_up_within_bounds() {
+        let rubber_band = Difficulty::Hard.settings().rubber_band;
+        let base_speed = RivalConfig::default().initial_speed;
+
+        // Dropping further behind only ever helps, up to a point
+        let mut last_speed = get_rubber_band_speed(base_speed, 0.0, rubber_band);
+        assert_eq!(last_speed, base_speed);
+        for gap in 1..=4 {
+            let z_pos = -RIVAL_RUBBER_BAND_GAP_Z * 0.5 * f32::conv(gap);
+            let speed = get_rubber_band_speed(base_speed, z_pos, rubber_band);
+            assert!(
+                speed >= last_speed,
+                "Speed fell to {} at Z {}",
+                speed,
+                z_pos
+            );
+            assert!(speed <= RACER_MAX_SPEED);
+            last_speed = speed;
+        }
+        assert!(last_speed > base_speed);
+
+        // Even the fastest rival can't be pushed past the top speed
+        let fast_speed = get_rubber_band_speed(RACER_MAX_SPEED, -1000.0, rubber_band);
+        assert_eq!(fast_speed, RACER_MAX_SPEED);
+
+        // Pulling ahead slows them, but never to a stall
+        let ahead_speed = get_rubber_band_speed(base_speed, 1000.0, rubber_band);
+        assert!(ahead_speed < base_speed && ahead_speed >= RIVAL_MIN_SPEED);
+    }
+}
@@ -1,6 +1,11 @@
 use crate::debug::DebugConfig;
+use crate::joyride::{scaled_step, GameRng, JoyrideGame, RaceRestarted, RaceState, TimeScale};
 use crate::joyride::{FIELD_HEIGHT, FIELD_WIDTH};
-use crate::road_object::{RoadObjectType, RoadSide, RoadSignType};
+use crate::player::PlayerStartConfig;
+use crate::road_object::{RoadObjectType, RoadSide, RoadSignType, SpawnTable};
+use crate::road_shader::{write_line_params, RoadMaterial, RoadShader};
+use crate::time_of_day::TimeOfDay;
+use crate::track::{DifficultyCurve, TrackGenerator, TrackGeneratorConfig};
 use crate::{boxed_array, joyride};
 use bevy::{
     core::AsBytes,
@@ -10,9 +15,12 @@ use bevy::{
 use core::mem::size_of;
 use easy_cast::*;
 use lebe::Endian;
+use std::collections::VecDeque;
+use std::time::Duration;
 
 pub struct Systems {
     pub startup_road: SystemSet,
+    pub reset_road: SystemSet,
     pub update_road: SystemSet,
     pub draw_road: SystemSet,
     pub test_curve_road: SystemSet,
@@ -22,9 +30,14 @@ impl Systems {
     pub fn new() -> Self {
         Self {
             startup_road: SystemSet::new().with_system(startup_road.system()),
+            reset_road: SystemSet::new().with_system(reset_road.system()),
             update_road: SystemSet::new()
                 .with_system(update_road_curvature.system())
-                .with_system(update_road_hills.system()),
+                .with_system(update_road_hills.system())
+                .with_system(send_ambience_events.system())
+                .with_system(check_checkpoints.system())
+                .with_system(check_finish_line.system())
+                .with_system(update_skid_marks.system()),
             draw_road: SystemSet::new().with_system(render_road.system()),
             test_curve_road: SystemSet::new().with_system(test_curve_road.system()),
         }
@@ -32,17 +45,21 @@ impl Systems {
 }
 
 // Used for layering with other sprites
-const ROAD_SPRITE_Z: f32 = 50.0;
+pub const ROAD_SPRITE_Z: f32 = 50.0;
 
 // The number of pixel lines our coordinate maps stretch for, from the bottom of the screen
 pub const ROAD_DISTANCE: usize = 110;
 
 // Uphills move through the coordinate maps slower than one entry per pixel line.
 // This specifies the maximum on-screen height the drawn road can be
-const MAX_ROAD_DRAW_HEIGHT: usize = 170;
+pub const MAX_ROAD_DRAW_HEIGHT: usize = 170;
 
 const NUM_ROAD_PIXELS: usize = (FIELD_WIDTH as usize) * MAX_ROAD_DRAW_HEIGHT;
 
+// Flat road advances one map entry per line, and downhills a little more. Lines that skip more
+// entries than this are looking over a crest at road that can't be seen
+const MAX_VISIBLE_MAP_GAP: usize = 3;
+
 // The distance from the bottom of the screen at which the road fully converges. Typically, when
 // doing reverse projection, this is the center of the screen, but we fudge it for effect
 const CONVERGE_DISTANCE: f32 = 113.4;
@@ -56,6 +73,12 @@ const COLOR_SWITCH_Z_INTERVAL: f32 = 0.5;
 // The length (in Z) of a single road segment
 pub const SEGMENT_LENGTH: f32 = 15.0;
 
+// Well past anything a track or the generator produces, this only reins in runaway values, such
+// as from holding a debug curve key. The road center is also kept within this far off either
+// side of the screen, so objects converged onto it can't be flung arbitrarily far
+const MAX_SEGMENT_CURVE: f32 = 1.5;
+const MAX_ROAD_CENTER_OFFSCREEN_X: f32 = FIELD_WIDTH as f32 * 2.0;
+
 // The strength at which road curvature modifies the X positions of objects
 const ROAD_CURVE_PULL_SCALAR: f32 = 60.0;
 
@@ -63,6 +86,33 @@ pub const PAVEMENT_WIDTH: f32 = 125.0;
 const CENTER_LINE_WIDTH: f32 = 2.0;
 const RUMBLE_STRIP_WIDTH: f32 = 20.0;
 
+// Skid marks are laid where the rear tire meets the road, this far into the coordinate maps
+const SKID_MARK_LAY_MAP_IDX: usize = 6;
+
+// A mark laid within this Z of the last one continues its streak, rather than starting a new one
+const SKID_MARK_JOIN_Z: f32 = 1.0;
+
+const MAX_SKID_MARKS: usize = 64;
+const SKID_MARK_FADE_SECS: f32 = 1.5;
+const SKID_MARK_WIDTH: f32 = 6.0;
+const SKID_MARK_COLOR: u32 = 0x202020FF;
+const SKID_MARK_OPACITY: f32 = 0.6;
+
+// Marks past this many crossing the same line aren't drawn on it. The marks of a streak only meet
+// at their ends, so this is only reached where separate streaks happen to overlap
+pub const MAX_SKID_SPANS_PER_LINE: usize = 4;
+
+// Sharp curves fake a banked road by widening the outside half of the road and narrowing the
+// inside. Curvature past the minimum is scaled into the bank amount, which is then capped
+const ROAD_BANK_MIN_CURVE: f32 = 0.3;
+const ROAD_BANK_SCALAR: f32 = 0.25;
+const ROAD_MAX_BANK: f32 = 0.3;
+
+// At night, lines past this fraction of ROAD_DISTANCE fade out with distance, beyond the reach
+// of the headlight, down to this brightness at the far end
+const NIGHT_FADE_START: f32 = 0.15;
+const NIGHT_MIN_BRIGHTNESS: f32 = 0.2;
+
 const ROAD_NOT_INIT: &str = "Road was not initialized";
 
 #[derive(Clone, Copy)]
@@ -75,7 +125,7 @@ struct QuadraticCoefficients {
 const CURVE_COEFF: QuadraticCoefficients = QuadraticCoefficients { x2: 1.0, x: 0.0 };
 const HILL_COEFF: QuadraticCoefficients = QuadraticCoefficients { x2: 0.5, x: 0.5 };
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 struct ShiftableColor(u32, u32);
 
 struct RoadColors {
@@ -85,11 +135,296 @@ struct RoadColors {
     center_line: u32, // Shifts to match the pavement color
 }
 
-#[derive(Debug, Clone, serde::Deserialize)]
+impl RoadColors {
+    fn tinted(&self, time_of_day: &TimeOfDay) -> Self {
+        let tint = |color| time_of_day.tint_rgba_u32(color);
+        let tint_shiftable = |color: ShiftableColor| ShiftableColor(tint(color.0), tint(color.1));
+        Self {
+            offroad: tint_shiftable(self.offroad),
+            rumble_strip: tint_shiftable(self.rumble_strip),
+            pavement: tint_shiftable(self.pavement),
+            center_line: tint(self.center_line),
+        }
+    }
+
+    fn apply_to_material(&self, material: &mut RoadMaterial, skid: u32) {
+        let to_color = |color: u32| {
+            let [r, g, b, a] = color.to_be_bytes();
+            Color::rgba_u8(r, g, b, a)
+        };
+        material.pavement = to_color(self.pavement.0);
+        material.pavement_shift = to_color(self.pavement.1);
+        material.rumble_strip = to_color(self.rumble_strip.0);
+        material.rumble_strip_shift = to_color(self.rumble_strip.1);
+        material.offroad = to_color(self.offroad.0);
+        material.offroad_shift = to_color(self.offroad.1);
+        material.center_line = to_color(self.center_line);
+        material.skid = to_color(skid);
+    }
+
+    fn to_pixel_colors(&self, seg_boundary: u32) -> RoadPixelColors {
+        let to_px = |color: u32| color.from_current_into_big_endian();
+        let to_px_shiftable =
+            |color: ShiftableColor| ShiftableColor(to_px(color.0), to_px(color.1));
+        RoadPixelColors {
+            offroad: to_px_shiftable(self.offroad),
+            rumble_strip: to_px_shiftable(self.rumble_strip),
+            pavement: to_px_shiftable(self.pavement),
+            center_line: to_px(self.center_line),
+            seg_boundary: to_px(seg_boundary),
+        }
+    }
+}
+
+// RoadColors already in the render texture's byte order, so rasterizing can write them as-is
+#[derive(Clone, PartialEq)]
+struct RoadPixelColors {
+    offroad: ShiftableColor,
+    rumble_strip: ShiftableColor,
+    pavement: ShiftableColor,
+    center_line: u32,
+    seg_boundary: u32,
+}
+
+const DESERT_COLORS: RoadColors = RoadColors {
+    center_line: 0xFFFFFFFF,
+    offroad: ShiftableColor(0xFFFF91FF, 0xDADA91FF),
+    rumble_strip: ShiftableColor(0xFFFFFFFF, 0xFF0000FF),
+    pavement: ShiftableColor(0x303030FF, 0x333333FF),
+};
+
+const FOREST_COLORS: RoadColors = RoadColors {
+    center_line: 0xFFFFFFFF,
+    offroad: ShiftableColor(0x48A048FF, 0x3A8A3AFF),
+    rumble_strip: ShiftableColor(0xFFFFFFFF, 0x2060C0FF),
+    pavement: ShiftableColor(0x383838FF, 0x3C3C3CFF),
+};
+
+const CITY_COLORS: RoadColors = RoadColors {
+    center_line: 0xFFD800FF,
+    offroad: ShiftableColor(0x707070FF, 0x666666FF),
+    rumble_strip: ShiftableColor(0xFFD800FF, 0x202020FF),
+    pavement: ShiftableColor(0x28282EFF, 0x2C2C32FF),
+};
+
+// The look of the track. Chosen before startup, since the road and skybox both read it then
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+pub enum RoadTheme {
+    Desert,
+    Forest,
+    City,
+}
+
+impl Default for RoadTheme {
+    fn default() -> Self {
+        Self::Desert
+    }
+}
+
+impl RoadTheme {
+    fn colors(self) -> RoadColors {
+        match self {
+            Self::Desert => DESERT_COLORS,
+            Self::Forest => FOREST_COLORS,
+            Self::City => CITY_COLORS,
+        }
+    }
+
+    pub fn skybox_texture_path(self) -> &'static str {
+        match self {
+            Self::Desert => "textures/sky_bg.png",
+            Self::Forest => "textures/sky_forest.png",
+            Self::City => "textures/sky_city.png",
+        }
+    }
+}
+
+// How the road's pixels get colored. The shader needs a GPU backend that can run it, so the
+// web build keeps coloring on the CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoadRenderMode {
+    Cpu,
+    Shader,
+}
+
+impl Default for RoadRenderMode {
+    fn default() -> Self {
+        if cfg!(target_arch = "wasm32") {
+            Self::Cpu
+        } else {
+            Self::Shader
+        }
+    }
+}
+
+// Everything needed to color a single pixel line of the road
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RoadLineParams {
+    pub is_seg_boundary: bool,
+    pub shift_color: bool,
+    pub road_center: f32,
+    pub road_width: f32,
+    pub center_line_width: f32,
+    pub rumble_width: f32,
+
+    // Positive banks the left (outside) edge up for right-hand curves. See bank_distance
+    pub bank: f32,
+
+    // What every color on the line is scaled by. Always 1 unless it's dark. See NIGHT_FADE_START
+    pub brightness: f32,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct RoadSegment {
     pub curve: f32,
     pub hill: f32,
     pub spawn_object_type: Option<RoadObjectType>,
+
+    // Tints the road objects spawned in this segment, for theming a section of the track
+    #[serde(default)]
+    pub ambient_tint: Option<Color>,
+
+    // Identifies a sound cue (e.g. "crowd" or "tunnel-reverb") to fire when the player enters
+    #[serde(default)]
+    pub audio_cue: Option<String>,
+
+    // Entering a checkpoint segment extends the race timer
+    #[serde(default)]
+    pub checkpoint: bool,
+
+    // An index into TrackConfig::spawn_tables, rolled alongside any fixed spawn_object_type
+    #[serde(default)]
+    pub spawn_table: Option<usize>,
+}
+
+impl RoadSegment {
+    // What the road is actually drawn and steered with. See MAX_SEGMENT_CURVE
+    fn clamped_curve(&self) -> f32 {
+        f32::clamp(self.curve, -MAX_SEGMENT_CURVE, MAX_SEGMENT_CURVE)
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct CheckpointConfig {
+    // How much time is added to the race timer at each checkpoint
+    pub extend_secs: f32,
+
+    // How many times the track is driven before the race ends. Past one, the track loops back
+    // around to its first segment at the end of each lap
+    pub total_laps: u32,
+}
+
+impl Default for CheckpointConfig {
+    fn default() -> Self {
+        Self {
+            extend_secs: 30.0,
+            total_laps: 1,
+        }
+    }
+}
+
+// Sent when the player enters a checkpoint segment. The final one is the end of the race,
+// and doesn't extend the timer
+pub struct CheckpointReached {
+    pub is_final: bool,
+}
+
+// Sent when the player enters a segment with an audio cue
+pub struct AmbienceEvent {
+    pub cue: String,
+}
+
+// In endless mode, the track loops back around to its first segment instead of ending, and the
+// race only ends when time runs out. There's no finish line, and laps are never counted down
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct EndlessMode {
+    pub enabled: bool,
+}
+
+// The track file to read segments from, when they aren't being generated
+pub struct TrackConfig {
+    pub path: String,
+
+    // Applied to TimeOfDay when the track is set up. See TimeOfDay for the range
+    pub time_of_day: f32,
+
+    // A RON file describing the rivals lined up at the start. See RivalConfig
+    pub rivals_path: Option<String>,
+
+    // The segment the finish line stands at the start of, if the race ends at one
+    pub finish_seg_idx: Option<usize>,
+
+    // Guardrails along the road. Without them, the player can wander offroad freely
+    pub barriers: Option<RoadBounds>,
+
+    // The ground either side of the road. Without it, both sides are sand
+    pub terrain: Option<RoadsideTerrain>,
+
+    // Weighted object spawns that segments can refer to. See RoadSegment::spawn_table
+    pub spawn_tables: Vec<SpawnTable>,
+
+    // Seeds the spawn table rolls on their own, so a track rolls the same objects every run
+    // whatever the game seed. Without it, the rolls follow the game seed
+    pub spawn_seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+pub enum Terrain {
+    Sand,
+    Grass,
+    Gravel,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+pub struct RoadsideTerrain {
+    pub left: Terrain,
+    pub right: Terrain,
+}
+
+// Road X positions, in the same units as PAVEMENT_WIDTH, so left is negative
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+pub struct RoadBounds {
+    pub left: f32,
+    pub right: f32,
+}
+
+impl Default for TrackConfig {
+    fn default() -> Self {
+        Self {
+            path: "assets/road_segs.ron".to_string(),
+            time_of_day: 1.0,
+            rivals_path: None,
+            finish_seg_idx: None,
+            barriers: None,
+            terrain: None,
+            spawn_tables: Vec::new(),
+            spawn_seed: None,
+        }
+    }
+}
+
+pub struct ProjectionConfig {
+    // Warps how quickly Z increases up the screen, keeping the nearest line in place.
+    // Above 1 steepens the road for a more top-down feel, below 1 flattens it. Must be positive
+    pub road_pitch: f32,
+}
+
+impl Default for ProjectionConfig {
+    fn default() -> Self {
+        Self { road_pitch: 1.0 }
+    }
+}
+
+// Exactly the mutable simulation state of RoadDynamic. Everything else in it is either fixed,
+// or recomputed from this every frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RoadState {
+    pub x_offset: f32,
+    pub z_offset: f32,
+    pub seg_idx: usize,
+    pub seg_pos: f32,
 }
 
 pub struct RoadPoint {
@@ -99,20 +434,27 @@ pub struct RoadPoint {
 }
 
 pub struct RoadStatic {
-    render_tex: Handle<Texture>,
+    // The road is drawn to these in turn, so the one being displayed is never mid-update
+    render_texs: [Handle<Texture>; 2],
     z_map: Box<[f32; ROAD_DISTANCE]>,
     scale_map: Box<[f32; ROAD_DISTANCE]>,
+
+    // How much of the racer's X offset is left at each map index. See converge_x
+    converge_map: Box<[f32; ROAD_DISTANCE]>,
+
     colors: RoadColors,
     road_sprite: Entity,
 }
 
 impl RoadStatic {
-    pub fn z_map(&self) -> &[f32; ROAD_DISTANCE] {
-        &self.z_map
+    // The Z of each screen line, from the bottom of the screen up to the horizon
+    pub fn z_map(&self) -> &[f32] {
+        &self.z_map[..]
     }
 
-    pub fn scale_map(&self) -> &[f32; ROAD_DISTANCE] {
-        &self.scale_map
+    // The scale objects are drawn at on each screen line, matching z_map
+    pub fn scale_map(&self) -> &[f32] {
+        &self.scale_map[..]
     }
 }
 
@@ -124,6 +466,14 @@ pub struct RoadDynamic {
     // Table of road X offsets. Affected by curvature
     x_map: Box<[f32; ROAD_DISTANCE]>,
 
+    // Table of how banked the road is. Affected by curvature
+    bank_map: Box<[f32; ROAD_DISTANCE]>,
+
+    // The X offsets from curvature alone, before converging on x_offset. Kept between frames so
+    // it's only remapped when invalidated, see update_road_curvature
+    curve_x_map: Box<[f32; ROAD_DISTANCE]>,
+    curve_map_key: CurveMapKey,
+
     // Table that maps on-screen pixel lines to entries in the other tables
     // Affected by hills
     y_map: Box<[usize; MAX_ROAD_DRAW_HEIGHT]>,
@@ -131,6 +481,9 @@ pub struct RoadDynamic {
     // The racer's offset from the center of the road
     pub x_offset: f32,
 
+    // Slides the whole view of the road (and everything on it) horizontally on-screen
+    pub road_view_pan_x: f32,
+
     // Used to shift colors during road drawing
     z_offset: f32,
 
@@ -142,6 +495,12 @@ pub struct RoadDynamic {
 
     // TODO: Move to static once we read segs from file
     segs: Vec<RoadSegment>,
+
+    // Whether segment indices past the end of the track wrap around, so seg_idx can grow forever
+    loop_segs: bool,
+
+    // Whether the race goes on until time runs out. See EndlessMode
+    endless: bool,
 }
 
 impl RoadDynamic {
@@ -170,20 +529,23 @@ impl RoadDynamic {
         RoadPoint {
             seg_idx: idx,
             seg_pos: pos,
-            seg: get_bounded_seg(&self.segs, idx),
+            seg: get_bounded_seg(&self.segs, idx, self.loop_segs),
         }
     }
 
     pub fn get_bounded_seg(&self, idx: usize) -> RoadSegment {
-        get_bounded_seg(&self.segs, idx)
+        get_bounded_seg(&self.segs, idx, self.loop_segs)
     }
 
     pub fn get_seg_curvature(&self, pos_offset: f32) -> f32 {
         let seg_idx =
             self.seg_idx + usize::conv_floor((self.seg_pos + pos_offset) / SEGMENT_LENGTH);
-        get_bounded_seg(&self.segs, seg_idx).curve
+        get_bounded_seg(&self.segs, seg_idx, self.loop_segs).clamped_curve()
     }
 
+    // Positive when the road curves right. That's the turn rate needed to follow the curve,
+    // which is how rivals use it, while the player has it added to the road's X offset,
+    // pushing them toward the outside of the curve
     pub fn get_road_x_pull(&self, z_offset: f32, speed: f32) -> f32 {
         self.get_seg_curvature(z_offset) * speed * ROAD_CURVE_PULL_SCALAR
     }
@@ -191,12 +553,87 @@ impl RoadDynamic {
     pub fn get_draw_height_pixels(&self) -> usize {
         self.draw_height
     }
+
+    // The total Z distance travelled down the track
+    pub fn get_distance_traveled(&self) -> f32 {
+        (f32::conv(self.seg_idx) * SEGMENT_LENGTH) + self.seg_pos
+    }
+
+    pub fn get_num_segs(&self) -> usize {
+        self.segs.len()
+    }
+
+    // Endless runs never finish, so there's no finish line even where the track places one
+    pub fn get_finish_seg_idx(&self, track_config: &TrackConfig) -> Option<usize> {
+        if self.endless {
+            None
+        } else {
+            track_config.finish_seg_idx
+        }
+    }
+
+    pub fn push_seg(&mut self, seg: RoadSegment) {
+        self.segs.push(seg);
+    }
+
+    pub fn segs(&self) -> &[RoadSegment] {
+        &self.segs
+    }
+
+    // Swaps in a whole track, such as one saved with a snapshot of the race
+    pub fn replace_segs(&mut self, segs: Vec<RoadSegment>) {
+        self.segs = segs;
+    }
+
+    // Puts the racer back at the start of the track, keeping the segments as they are
+    pub fn restart(&mut self) {
+        self.restore(&RoadState::default());
+    }
+
+    pub fn snapshot(&self) -> RoadState {
+        RoadState {
+            x_offset: self.x_offset,
+            z_offset: self.z_offset,
+            seg_idx: self.seg_idx,
+            seg_pos: self.seg_pos,
+        }
+    }
+
+    pub fn restore(&mut self, state: &RoadState) {
+        self.x_offset = state.x_offset;
+        self.z_offset = state.z_offset;
+        self.seg_idx = state.seg_idx;
+        self.seg_pos = state.seg_pos;
+    }
 }
 
 pub fn is_offroad(road_static: &RoadStatic, road_dyn: &RoadDynamic) -> bool {
     road_dyn.x_offset.abs() > (PAVEMENT_WIDTH + RUMBLE_STRIP_WIDTH) * road_static.scale_map[0]
 }
 
+// The terrain on whichever side of the road the racer has gone off, if they're offroad at all
+pub fn get_offroad_terrain(
+    road_static: &RoadStatic,
+    road_dyn: &RoadDynamic,
+    track_config: &TrackConfig,
+) -> Option<Terrain> {
+    if !is_offroad(road_static, road_dyn) {
+        return None;
+    }
+
+    let terrain = match &track_config.terrain {
+        Some(terrain) => terrain,
+        None => return Some(Terrain::Sand),
+    };
+
+    // The racer is left of center when the road is offset to the right
+    if road_dyn.x_offset > 0.0 {
+        Some(terrain.left)
+    } else {
+        Some(terrain.right)
+    }
+}
+
 pub struct DrawParams {
     pub scale: f32,
     pub draw_pos: Vec2,
@@ -208,9 +645,14 @@ pub fn get_draw_params_on_road(
     x_pos: f32,
     z_pos: f32,
 ) -> Option<DrawParams> {
+    // An object whose Z has gone bad has nowhere on the road to be drawn
+    if z_pos.is_nan() {
+        return None;
+    }
+
     let search_result_idx = road_static
         .z_map
-        .binary_search_by(|z| z.partial_cmp(&z_pos).unwrap())
+        .binary_search_by(|z| z.total_cmp(&z_pos))
         .unwrap_or_else(|x| x);
 
     if search_result_idx == 0 || search_result_idx > ROAD_DISTANCE {
@@ -229,31 +671,229 @@ pub fn get_draw_params_on_road(
         }
     };
 
-    if y_map_idx > road_dyn.draw_height {
+    if y_map_idx > road_dyn.draw_height || is_behind_crest(road_dyn, map_idx) {
         return None;
     }
-    let x_offset = x_pos * scale;
+    let x_offset = bank_offset(x_pos * scale, road_dyn.bank_map[map_idx]);
 
     Some(DrawParams {
         scale,
-        draw_pos: Vec2::new(road_dyn.x_map[map_idx] + x_offset, f32::conv(y_map_idx)),
+        draw_pos: Vec2::new(
+            road_dyn.x_map[map_idx] + road_dyn.road_view_pan_x + x_offset,
+            f32::conv(y_map_idx),
+        ),
     })
 }
 
-fn converge_x(x_pos: f32, road_map_idx: usize) -> f32 {
-    let converge_scalar = f32::conv(road_map_idx) / f32::conv(ROAD_DISTANCE);
-    x_pos * (1.0 - converge_scalar)
+// Where the road drops away past a hill crest, consecutive lines skip over whole stretches of the
+// coordinate maps. Anything on a stretch skipped by more than MAX_VISIBLE_MAP_GAP is hidden
+// behind the crest, including the stretch cut off when the crest shortens the draw height
+fn is_behind_crest(road_dyn: &RoadDynamic, map_idx: usize) -> bool {
+    match road_dyn.y_map[..road_dyn.draw_height].binary_search(&map_idx) {
+        Ok(_) | Err(0) => false,
+        Err(line) => {
+            let next_map_idx = road_dyn.y_map.get(line).copied().unwrap_or(ROAD_DISTANCE);
+            next_map_idx - road_dyn.y_map[line - 1] > MAX_VISIBLE_MAP_GAP
+        }
+    }
+}
+
+// Scales an on-screen distance from the road's center by how banked the road is,
+// making the outside half of the road wider and the inside narrower
+fn bank_distance(x_from_center: f32, bank: f32) -> f32 {
+    (x_from_center * (1.0 + bank * x_from_center.signum())).abs()
+}
+
+// The inverse of bank_distance, placing an unbanked offset from center onto the banked road
+fn bank_offset(x_offset: f32, bank: f32) -> f32 {
+    x_offset / (1.0 + bank * x_offset.signum())
+}
+
+// Straights and gentle curves stay flat. The bank ramps up toward the horizon, so the road
+// under the player is never banked, and offroad checks there still match what's drawn
+fn calc_bank(curvature: f32, road_map_idx: usize) -> f32 {
+    let excess_curve = curvature.abs() - ROAD_BANK_MIN_CURVE;
+    if excess_curve <= 0.0 {
+        return 0.0;
+    }
+
+    let bank = f32::min(excess_curve * ROAD_BANK_SCALAR, ROAD_MAX_BANK) * curvature.signum();
+    bank * (f32::conv(road_map_idx) / f32::conv(ROAD_DISTANCE))
+}
+
+// What the curve and bank maps were last computed from. Only the curves of the segments within
+// draw distance matter, along with how far into the first one the racer is. Checked every frame,
+// so the road is compared in place and the curves buffer reused
+#[derive(Default)]
+struct CurveMapKey {
+    // None until the maps are first computed
+    seg_pos: Option<f32>,
+    curves: Vec<f32>,
+}
+
+impl CurveMapKey {
+    // Brings the key up to date with the road, and says whether it changed
+    fn update(&mut self, road_static: &RoadStatic, road_dyn: &RoadDynamic) -> bool {
+        // Bank lookups go by absolute Z, which reaches a little further than the curve mapping
+        let far_z = road_static.z_map[ROAD_DISTANCE - 1];
+        let num_segs = usize::conv_floor((road_dyn.seg_pos + far_z) / SEGMENT_LENGTH) + 1;
+        let get_curve = |i: usize| {
+            get_bounded_seg(&road_dyn.segs, road_dyn.seg_idx + i, road_dyn.loop_segs).curve
+        };
+
+        // A dead-straight stretch maps flat wherever the racer is within it
+        let seg_pos = if (0..=num_segs).all(|i| get_curve(i) == 0.0) {
+            0.0
+        } else {
+            road_dyn.seg_pos
+        };
+
+        let is_unchanged = self.seg_pos == Some(seg_pos)
+            && self.curves.len() == num_segs + 1
+            && (0..=num_segs).all(|i| self.curves[i] == get_curve(i));
+        if is_unchanged {
+            return false;
+        }
+
+        self.seg_pos = Some(seg_pos);
+        self.curves.clear();
+        self.curves.extend((0..=num_segs).map(get_curve));
+        true
+    }
+}
+
+fn calc_converge_scalar(road_map_idx: usize) -> f32 {
+    1.0 - (f32::conv(road_map_idx) / f32::conv(ROAD_DISTANCE))
+}
+
+// Shrinks an X offset toward nothing at the far end of the road, using the table precalculated
+// from calc_converge_scalar
+fn converge_x(road_static: &RoadStatic, x_pos: f32, road_map_idx: usize) -> f32 {
+    x_pos * road_static.converge_map[road_map_idx]
+}
+
+// A streak of rubber left on the road, interpolated between its two ends.
+// Positions are in world units, with Z as distance down the track so the streak scrolls with it
+#[derive(Clone, Copy)]
+struct SkidMark {
+    near_x: f32,
+    far_x: f32,
+    near_distance: f32,
+    far_distance: f32,
+    secs_left: f32,
+}
+
+// Drawn over the pavement by either renderer, fading out over time
+#[derive(Default)]
+pub struct SkidMarks {
+    marks: VecDeque<SkidMark>,
+}
+
+// Where a skid mark crosses a single pixel line, from left to right inclusive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkidSpan {
+    pub left: usize,
+    pub right: usize,
+    pub opacity: f32,
+}
+
+// In the order the marks were laid, which is also the order they're composited in
+pub type LineSkidSpans = [Option<SkidSpan>; MAX_SKID_SPANS_PER_LINE];
+
+impl SkidMarks {
+    pub fn lay(&mut self, road_static: &RoadStatic, road_dyn: &RoadDynamic, x_pos: f32) {
+        let distance = road_dyn.get_distance_traveled() + road_static.z_map[SKID_MARK_LAY_MAP_IDX];
+
+        let (near_x, near_distance) = match self.marks.back() {
+            Some(last) if (distance - last.far_distance).abs() <= SKID_MARK_JOIN_Z => {
+                (last.far_x, last.far_distance)
+            }
+            _ => (x_pos, distance),
+        };
+
+        self.marks.push_back(SkidMark {
+            near_x,
+            far_x: x_pos,
+            near_distance,
+            far_distance: distance,
+            secs_left: SKID_MARK_FADE_SECS,
+        });
+        while self.marks.len() > MAX_SKID_MARKS {
+            self.marks.pop_front();
+        }
+    }
 }
 
 struct RoadDrawing {
     // Colors are expected to be RGBA
     draw_buffer: Box<[u32; NUM_ROAD_PIXELS]>,
+
+    // None for lines that aren't drawn to
+    line_params: Box<[Option<RoadLineParams>; MAX_ROAD_DRAW_HEIGHT]>,
+
+    // Where skid marks cross each line this frame
+    skid_spans: Box<[LineSkidSpans; MAX_ROAD_DRAW_HEIGHT]>,
+
+    // Lines that had skid marks composited over them this frame
+    skidded_lines: Box<[bool; MAX_ROAD_DRAW_HEIGHT]>,
+
+    // Which of the render textures isn't currently displayed, and can be written to
+    back_tex_idx: usize,
+
+    // What each render texture was last drawn from, or None if it hasn't been yet
+    tex_contents: [Option<RoadTexContents>; 2],
 }
 
 impl Default for RoadDrawing {
     fn default() -> Self {
         Self {
             draw_buffer: boxed_array![0; NUM_ROAD_PIXELS],
+            line_params: boxed_array![None; MAX_ROAD_DRAW_HEIGHT],
+            skid_spans: boxed_array![[None; MAX_SKID_SPANS_PER_LINE]; MAX_ROAD_DRAW_HEIGHT],
+            skidded_lines: boxed_array![false; MAX_ROAD_DRAW_HEIGHT],
+            back_tex_idx: 1,
+            tex_contents: [None, None],
+        }
+    }
+}
+
+// A line only needs copying to a render texture if something it's drawn from has changed since
+// that texture last got it. Skid marks fade every frame, so lines under them always count
+struct RoadTexContents {
+    colors: RoadPixelColors,
+    line_params: Box<[Option<RoadLineParams>; MAX_ROAD_DRAW_HEIGHT]>,
+    skidded_lines: Box<[bool; MAX_ROAD_DRAW_HEIGHT]>,
+}
+
+// Copies the drawn lines to the back render texture. Only lines that differ from what it already
+// holds are copied, unless the colors have changed, which touches every line
+fn upload_road_lines(road_draw: &mut RoadDrawing, colors: &RoadPixelColors, dest: &mut [u8]) {
+    let src = road_draw.draw_buffer.as_bytes();
+    let line_bytes = src.len() / MAX_ROAD_DRAW_HEIGHT;
+
+    let contents = &mut road_draw.tex_contents[road_draw.back_tex_idx];
+    match contents {
+        Some(contents) if contents.colors == *colors => {
+            for line in 0..MAX_ROAD_DRAW_HEIGHT {
+                let is_dirty = contents.line_params[line] != road_draw.line_params[line]
+                    || contents.skidded_lines[line]
+                    || road_draw.skidded_lines[line];
+                if is_dirty {
+                    let range = (line * line_bytes)..((line + 1) * line_bytes);
+                    dest[range.clone()].copy_from_slice(&src[range]);
+                }
+            }
+
+            *contents.line_params = *road_draw.line_params;
+            *contents.skidded_lines = *road_draw.skidded_lines;
+        }
+        _ => {
+            dest.copy_from_slice(src);
+            *contents = Some(RoadTexContents {
+                colors: colors.clone(),
+                line_params: road_draw.line_params.clone(),
+                skidded_lines: road_draw.skidded_lines.clone(),
+            });
         }
     }
 }
@@ -263,49 +903,100 @@ fn startup_road(
     mut textures: ResMut<Assets<Texture>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     debug_config: Res<DebugConfig>,
+    projection_config: Res<ProjectionConfig>,
+    endless_mode: Res<EndlessMode>,
+    checkpoint_config: Res<CheckpointConfig>,
+    track_config: Res<TrackConfig>,
+    generator_config: Res<TrackGeneratorConfig>,
+    game_rng: Res<GameRng>,
+    player_start_config: Res<PlayerStartConfig>,
+    mut time_of_day: ResMut<TimeOfDay>,
+    render_mode: Res<RoadRenderMode>,
+    theme: Res<RoadTheme>,
 ) {
-    let road_static = build_road_static(&mut commands, &mut textures, &mut materials);
-    let road_dynamic = build_road_dynamic(&debug_config);
+    time_of_day.set(track_config.time_of_day);
+
+    let mut generator = if generator_config.enabled {
+        Some(TrackGenerator::new(&generator_config, &game_rng))
+    } else {
+        None
+    };
+
+    let road_static = build_road_static(
+        &mut commands,
+        &mut textures,
+        &mut materials,
+        &projection_config,
+        *render_mode,
+        *theme,
+    );
+    let road_dynamic = build_road_dynamic(
+        &debug_config,
+        &endless_mode,
+        &checkpoint_config,
+        &track_config,
+        &player_start_config,
+        generator.as_mut(),
+    );
 
     commands.insert_resource(road_static);
     commands.insert_resource(road_dynamic);
+    if let Some(generator) = generator {
+        commands.insert_resource(generator);
+    }
 }
 
 fn build_road_static(
     commands: &mut Commands,
     textures: &mut ResMut<Assets<Texture>>,
     materials: &mut ResMut<Assets<ColorMaterial>>,
+    projection_config: &ProjectionConfig,
+    render_mode: RoadRenderMode,
+    theme: RoadTheme,
 ) -> RoadStatic {
-    // Create a texture that will be overwritten every frame
-    let render_tex = Texture::new(
-        Extent3d::new(FIELD_WIDTH.cast(), MAX_ROAD_DRAW_HEIGHT.cast(), 1),
-        TextureDimension::D2,
-        vec![0; NUM_ROAD_PIXELS * size_of::<u32>()],
-        TextureFormat::Rgba8UnormSrgb,
-    );
-    let tex_handle = textures.add(render_tex);
+    // Create the textures that will be overwritten every other frame
+    let mut make_render_tex = || {
+        textures.add(Texture::new(
+            Extent3d::new(FIELD_WIDTH.cast(), MAX_ROAD_DRAW_HEIGHT.cast(), 1),
+            TextureDimension::D2,
+            vec![0; NUM_ROAD_PIXELS * size_of::<u32>()],
+            TextureFormat::Rgba8UnormSrgb,
+        ))
+    };
+    let render_texs = [make_render_tex(), make_render_tex()];
 
     let mut z_map = boxed_array![0.0; ROAD_DISTANCE];
     let mut scale_map = boxed_array![0.0; ROAD_DISTANCE];
+    let mut converge_map = boxed_array![0.0; ROAD_DISTANCE];
+    for (i, out_converge) in converge_map.iter_mut().enumerate() {
+        *out_converge = calc_converge_scalar(i);
+    }
+
+    let road_pitch = projection_config.road_pitch;
+    assert!(road_pitch > 0.0, "Road pitch must be positive");
 
     let converge_y = f32::conv(FIELD_HEIGHT) - CONVERGE_DISTANCE;
+    let nearest_y = f32::conv(FIELD_HEIGHT) - converge_y;
     for (i, (out_z, out_scale)) in z_map.iter_mut().zip(scale_map.iter_mut()).enumerate() {
         // Calculate the screen-space Y coordinate of this line, with the converge distance as zero
         let screen_y = f32::conv(FIELD_HEIGHT) - f32::conv(i);
 
+        // Apply the pitch relative to the nearest line, so that only the road's angle changes.
+        // powf isn't guaranteed to round the same on every platform, so it's skipped by default
+        let pitched_y = if road_pitch == 1.0 {
+            screen_y - converge_y
+        } else {
+            nearest_y * ((screen_y - converge_y) / nearest_y).powf(road_pitch)
+        };
+
         // Reverse-projection to world-space to get the Z value at this line
-        *out_z = CAMERA_HEIGHT / (screen_y - converge_y);
+        *out_z = CAMERA_HEIGHT / pitched_y;
 
         // Precalculate the scale of objects (including the road itself) at this Z coordinate
         *out_scale = 1.0 / *out_z;
     }
 
-    let colors = RoadColors {
-        center_line: 0xFFFFFFFFu32,
-        offroad: ShiftableColor(0xFFFF91FFu32, 0xDADA91FFu32),
-        rumble_strip: ShiftableColor(0xFFFFFFFF, 0xFF0000FF),
-        pavement: ShiftableColor(0x303030FF, 0x333333FF),
-    };
+    let colors = theme.colors();
 
     let mut xform = Transform::default();
     xform.translation = Vec3::new(
@@ -314,11 +1005,16 @@ fn build_road_static(
         ROAD_SPRITE_Z,
     );
 
-    // Create a sprite to draw the road using the render texture
+    // Create a sprite to draw the road using the render texture.
+    // When the shader colors the road instead, this is never shown
     let sprite = commands
         .spawn_bundle(SpriteBundle {
-            material: materials.add(tex_handle.clone().into()),
+            material: materials.add(render_texs[0].clone().into()),
             transform: xform,
+            visible: Visible {
+                is_visible: render_mode == RoadRenderMode::Cpu,
+                is_transparent: true,
+            },
             ..Default::default()
         })
         .id();
@@ -326,53 +1022,100 @@ fn build_road_static(
     RoadStatic {
         z_map,
         scale_map,
-        render_tex: tex_handle.clone(),
+        converge_map,
+        render_texs,
         colors,
         road_sprite: sprite,
     }
 }
 
-fn build_road_dynamic(debug_cfg: &DebugConfig) -> RoadDynamic {
+fn build_road_dynamic(
+    debug_cfg: &DebugConfig,
+    endless_mode: &EndlessMode,
+    checkpoint_config: &CheckpointConfig,
+    track_config: &TrackConfig,
+    player_start_config: &PlayerStartConfig,
+    generator: Option<&mut TrackGenerator>,
+) -> RoadDynamic {
     let default_x = f32::conv(FIELD_WIDTH) * 0.5;
 
     let x_map = boxed_array![default_x; ROAD_DISTANCE];
+    let curve_x_map = boxed_array![default_x; ROAD_DISTANCE];
+    let bank_map = boxed_array![0.0; ROAD_DISTANCE];
     let y_map = boxed_array![0; MAX_ROAD_DRAW_HEIGHT];
 
-    let road_segs: Vec<RoadSegment> = if debug_cfg.debug_gameplay {
-        vec![
-            RoadSegment {
-                curve: 0.0,
-                hill: 0.0,
-                spawn_object_type: None,
-            },
-            RoadSegment {
-                curve: 0.0,
-                hill: 0.0,
-                spawn_object_type: Some(RoadObjectType::RoadSigns(
-                    RoadSignType::Turn(false),
-                    RoadSide::Left,
-                )),
-            },
-        ]
+    let road_segs: Vec<RoadSegment> = if let Some(generator) = generator {
+        generator.generate_initial_segments()
+    } else if debug_cfg.debug_gameplay {
+        default_track()
     } else {
-        // TODO: Can we make this work with the AssetLoader? Async load would be a problem
-        let road_segs_file =
-            std::fs::File::open("assets/road_segs.ron").expect("Road segments file not found");
-        match ron::de::from_reader(road_segs_file) {
-            Ok(road_segs) => road_segs,
-            Err(e) => panic!("Failed to load road segments: {}", e),
-        }
+        load_track(&track_config.path)
     };
 
     RoadDynamic {
         x_map,
+        bank_map,
+        curve_x_map,
+        curve_map_key: CurveMapKey::default(),
         y_map,
         draw_height: ROAD_DISTANCE,
-        x_offset: 0.0,
+        x_offset: player_start_config.initial_x_offset,
+        road_view_pan_x: 0.0,
         z_offset: 0.0,
         seg_idx: 0,
         seg_pos: 0.0,
         segs: road_segs,
+        loop_segs: endless_mode.enabled || checkpoint_config.total_laps > 1,
+        endless: endless_mode.enabled,
+    }
+}
+
+// A minimal straight track with a single sign on it
+fn default_track() -> Vec<RoadSegment> {
+    vec![
+        RoadSegment {
+            curve: 0.0,
+            hill: 0.0,
+            spawn_object_type: None,
+            ambient_tint: None,
+            audio_cue: None,
+            checkpoint: false,
+            spawn_table: None,
+        },
+        RoadSegment {
+            curve: 0.0,
+            hill: 0.0,
+            spawn_object_type: Some(RoadObjectType::RoadSigns(
+                RoadSignType::Turn(false),
+                RoadSide::Left,
+            )),
+            ambient_tint: None,
+            audio_cue: None,
+            checkpoint: false,
+            spawn_table: None,
+        },
+    ]
+}
+
+// Reads a RON list of segments. A missing file falls back to the default track, but a malformed
+// one is a mistake in the track data and is treated as fatal
+// TODO: Can we make this work with the AssetLoader? Async load would be a problem
+pub fn load_track(path: &str) -> Vec<RoadSegment> {
+    let road_segs_file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!(
+                "Track file {} not found, using the default track: {}",
+                path, e
+            );
+            return default_track();
+        }
+    };
+
+    match ron::de::from_reader::<_, Vec<RoadSegment>>(road_segs_file) {
+        Ok(road_segs) if road_segs.is_empty() => panic!("Track file {} has no segments", path),
+        Ok(road_segs) => road_segs,
+        Err(e) => panic!("Failed to load road segments from {}: {}", path, e),
     }
 }
 
@@ -394,6 +1137,7 @@ fn test_curve_road(mut road_dyn: ResMut<RoadDynamic>, input: Res<Input<KeyCode>>
     if input.pressed(KeyCode::L) {
         road_dyn.segs[1].curve += curve_amt;
     }
+
     if input.pressed(KeyCode::I) {
         road_dyn.segs[0].hill -= hill_amt;
         road_dyn.segs[1].hill -= hill_amt;
@@ -405,17 +1149,26 @@ fn test_curve_road(mut road_dyn: ResMut<RoadDynamic>, input: Res<Input<KeyCode>>
 }
 
 // TODO: Return a ref instead
-pub fn get_bounded_seg(segs: &[RoadSegment], idx: usize) -> RoadSegment {
-    let actual_idx = usize::clamp(idx, 0, segs.len() - 1);
+// Past the end of the track the last segment repeats, unless looping back to the start
+pub fn get_bounded_seg(segs: &[RoadSegment], idx: usize, loop_segs: bool) -> RoadSegment {
+    let actual_idx = if loop_segs {
+        idx % segs.len()
+    } else {
+        usize::clamp(idx, 0, segs.len() - 1)
+    };
     return segs[actual_idx].clone();
 }
 
+// Replays and shared seeds rely on this giving identical results for identical inputs.
+// It sticks to plain f32 arithmetic (no fused multiply-adds or transcendental functions)
+// applied in a fixed order, which IEEE 754 guarantees across platforms. Keep it that way
 fn map_road_quadratic<F: Fn(&RoadSegment) -> f32>(
     coeff: QuadraticCoefficients,
     initial_value: f32,
     seg_value_func: F,
     road_static: &RoadStatic,
     segments: &[RoadSegment],
+    loop_segs: bool,
     mut seg_idx: usize,
     mut seg_pos: f32,
     out_map: &mut [f32; ROAD_DISTANCE],
@@ -423,7 +1176,7 @@ fn map_road_quadratic<F: Fn(&RoadSegment) -> f32>(
     let mut cur_value = initial_value;
     let mut delta_value = 0.0;
     let mut last_z = road_static.z_map[0];
-    let mut cur_seg = get_bounded_seg(&segments, seg_idx);
+    let mut cur_seg = get_bounded_seg(&segments, seg_idx, loop_segs);
 
     for (out_value, cur_z) in out_map.iter_mut().zip(road_static.z_map.iter()) {
         let delta_z = cur_z - last_z;
@@ -432,7 +1185,7 @@ fn map_road_quadratic<F: Fn(&RoadSegment) -> f32>(
         if seg_pos > SEGMENT_LENGTH {
             seg_idx += 1;
             seg_pos -= SEGMENT_LENGTH;
-            cur_seg = get_bounded_seg(&segments, seg_idx);
+            cur_seg = get_bounded_seg(&segments, seg_idx, loop_segs);
         }
 
         let parameter = seg_value_func(&cur_seg);
@@ -445,26 +1198,62 @@ fn map_road_quadratic<F: Fn(&RoadSegment) -> f32>(
     }
 }
 
+// The segments themselves are kept, so an endless track doesn't have to regenerate
+fn reset_road(
+    mut restarted_events: EventReader<RaceRestarted>,
+    mut road_dyn: ResMut<RoadDynamic>,
+    mut skid_marks: ResMut<SkidMarks>,
+) {
+    if restarted_events.iter().next().is_none() {
+        return;
+    }
+
+    road_dyn.restart();
+    skid_marks.marks.clear();
+}
+
+// The curve and bank maps are only remapped when the segment curves within draw distance change,
+// or when the racer moves along a stretch that isn't dead straight. Converging on x_offset is
+// cheap, so it's redone every frame regardless
 fn update_road_curvature(road_static: Res<RoadStatic>, mut road_dyn: ResMut<RoadDynamic>) {
     // Convert ResMut to a regular mutable reference - otherwise Rust can't properly split borrows
     // between individual struct fields, and complains about multiple-borrow
     let road_dyn: &mut RoadDynamic = &mut road_dyn;
 
-    map_road_quadratic(
-        CURVE_COEFF,
-        f32::conv(FIELD_WIDTH) * 0.5,
-        |seg| seg.curve,
-        &road_static,
-        &road_dyn.segs,
-        road_dyn.seg_idx,
-        road_dyn.seg_pos,
-        &mut road_dyn.x_map,
-    );
+    // Taken out for the check, as it needs the rest of the road alongside
+    let mut key = std::mem::take(&mut road_dyn.curve_map_key);
+    if key.update(&road_static, road_dyn) {
+        map_road_quadratic(
+            CURVE_COEFF,
+            f32::conv(FIELD_WIDTH) * 0.5,
+            |seg| seg.clamped_curve(),
+            &road_static,
+            &road_dyn.segs,
+            road_dyn.loop_segs,
+            road_dyn.seg_idx,
+            road_dyn.seg_pos,
+            &mut road_dyn.curve_x_map,
+        );
+
+        for i in 0..ROAD_DISTANCE {
+            let curvature = road_dyn.get_seg_curvature(road_static.z_map[i]);
+            road_dyn.bank_map[i] = calc_bank(curvature, i);
+        }
+    }
+    road_dyn.curve_map_key = key;
 
     // Assuming no curvature, focus the far end of the road to the center of the screen.
     // This ensures the player is "looking down the road" at all times.
-    for (i, x) in road_dyn.x_map.iter_mut().enumerate() {
-        *x += converge_x(road_dyn.x_offset, i);
+    let x_offset = road_dyn.x_offset;
+    let curve_x_map = road_dyn.curve_x_map.iter();
+    let min_x = -MAX_ROAD_CENTER_OFFSCREEN_X;
+    let max_x = f32::conv(FIELD_WIDTH) + MAX_ROAD_CENTER_OFFSCREEN_X;
+    for (i, (x, curve_x)) in road_dyn.x_map.iter_mut().zip(curve_x_map).enumerate() {
+        *x = f32::clamp(
+            curve_x + converge_x(&road_static, x_offset, i),
+            min_x,
+            max_x,
+        );
     }
 }
 
@@ -491,6 +1280,7 @@ fn update_road_hills(
         |seg| seg.hill,
         &road_static,
         &road_dyn.segs,
+        road_dyn.loop_segs,
         road_dyn.seg_idx,
         road_dyn.seg_pos,
         &mut scratch_pad.y_advancement_map,
@@ -514,91 +1304,1003 @@ fn update_road_hills(
     road_dyn.y_map[draw_height..MAX_ROAD_DRAW_HEIGHT].fill(ROAD_DISTANCE);
 }
 
+fn send_ambience_events(
+    road_dyn: Res<RoadDynamic>,
+    mut last_seg_idx: Local<Option<usize>>,
+    mut ambience_events: EventWriter<AmbienceEvent>,
+) {
+    let road_point = road_dyn.query_road_point(0.0);
+    if *last_seg_idx == Some(road_point.seg_idx) {
+        return;
+    }
+    *last_seg_idx = Some(road_point.seg_idx);
+
+    if let Some(cue) = road_point.seg.audio_cue {
+        ambience_events.send(AmbienceEvent { cue });
+    }
+}
+
+fn check_checkpoints(
+    road_dyn: Res<RoadDynamic>,
+    config: Res<CheckpointConfig>,
+    generator: Option<Res<TrackGenerator>>,
+    difficulty_curve: Res<DifficultyCurve>,
+    mut game: ResMut<JoyrideGame>,
+    mut last_seg_idx: Local<Option<usize>>,
+    mut checkpoint_events: EventWriter<CheckpointReached>,
+) {
+    let cur_seg_idx = road_dyn.seg_idx;
+    let first_new_idx = match *last_seg_idx {
+        Some(last) if last == cur_seg_idx => return,
+        Some(last) if last < cur_seg_idx => last + 1,
+        _ => cur_seg_idx, // First frame, or the road was restarted
+    };
+    *last_seg_idx = Some(cur_seg_idx);
+
+    let num_segs = road_dyn.segs.len();
+
+    // A fast enough player can skip over whole segments in one frame, so check each of them
+    for seg_idx in first_new_idx..=cur_seg_idx {
+        let (track_idx, lap) = if road_dyn.loop_segs {
+            (seg_idx % num_segs, u32::conv(seg_idx / num_segs))
+        } else if seg_idx < num_segs {
+            (seg_idx, 0)
+        } else {
+            break; // Past the end of the track, where the last segment just repeats
+        };
+
+        if !road_dyn.segs[track_idx].checkpoint {
+            continue;
+        }
+
+        // Endless runs and generated tracks go on forever, so they never finish
+        let is_last_on_track = road_dyn.segs[track_idx + 1..]
+            .iter()
+            .all(|seg| !seg.checkpoint);
+        let is_final_lap = !road_dyn.loop_segs || lap + 1 >= config.total_laps;
+        let is_final = !road_dyn.endless && generator.is_none() && is_last_on_track && is_final_lap;
+
+        // The bonus is keyed on where the checkpoint is, like the rest of the difficulty curve
+        if !is_final {
+            let seg_start_z = f32::conv(seg_idx) * SEGMENT_LENGTH;
+            let bonus = difficulty_curve.get_scalars(seg_start_z).checkpoint_bonus;
+            let timer = &mut game.remaining_time;
+            let extended = timer.duration() + Duration::from_secs_f32(config.extend_secs * bonus);
+            timer.set_duration(extended);
+        }
+
+        checkpoint_events.send(CheckpointReached { is_final });
+    }
+}
+
+// Ages the skid marks, and forgets any that have faded or are out of view
+fn update_skid_marks(
+    time_scale: Res<TimeScale>,
+    road_static: Res<RoadStatic>,
+    road_dyn: Res<RoadDynamic>,
+    mut skid_marks: ResMut<SkidMarks>,
+) {
+    let step = scaled_step(&time_scale);
+    let distance_traveled = road_dyn.get_distance_traveled();
+    let near_z = road_static.z_map[0];
+    let far_z = road_static.z_map[ROAD_DISTANCE - 1];
+
+    // Drop marks once faded or scrolled off-screen. Marks left far ahead, as after a restart, go too
+    skid_marks.marks.retain(|mark| {
+        mark.secs_left > 0.0
+            && mark.far_distance - distance_traveled >= near_z
+            && mark.near_distance - distance_traveled <= far_z
+    });
+    for mark in skid_marks.marks.iter_mut() {
+        mark.secs_left -= step;
+    }
+}
+
+// Only the player's own progress down the road counts, so rivals passing the line don't finish
+fn check_finish_line(
+    road_dyn: Res<RoadDynamic>,
+    track_config: Res<TrackConfig>,
+    mut race_state: ResMut<RaceState>,
+    mut game: ResMut<JoyrideGame>,
+) {
+    let finish_seg_idx = match road_dyn.get_finish_seg_idx(&track_config) {
+        Some(finish_seg_idx) => finish_seg_idx,
+        None => return,
+    };
+
+    if *race_state == RaceState::Racing && road_dyn.seg_idx >= finish_seg_idx {
+        *race_state = RaceState::Finished;
+        game.completion_secs = Some(game.remaining_time.elapsed_secs());
+    }
+}
+
+fn calc_road_line_params(
+    road_static: &RoadStatic,
+    road_dyn: &RoadDynamic,
+    show_seg_boundaries: bool,
+    time_of_day: &TimeOfDay,
+    cur_line: usize,
+) -> Option<RoadLineParams> {
+    let map_idx: usize = road_dyn.y_map[(MAX_ROAD_DRAW_HEIGHT - 1) - cur_line];
+    if map_idx >= ROAD_DISTANCE {
+        return None;
+    }
+
+    let road_z = road_static.z_map[map_idx];
+    let road_scale = road_static.scale_map[map_idx];
+
+    let is_seg_boundary = if show_seg_boundaries && map_idx > 0 {
+        let seg_num = usize::conv_trunc((road_z + road_dyn.seg_pos) / SEGMENT_LENGTH);
+        let last_seg_num =
+            usize::conv_trunc((road_static.z_map[map_idx - 1] + road_dyn.seg_pos) / SEGMENT_LENGTH);
+        seg_num != last_seg_num
+    } else {
+        false
+    };
+
+    // Switch the exact color used for each part of the road, based on Z
+    let num_color_switches =
+        i32::conv_trunc((road_z + road_dyn.z_offset) / COLOR_SWITCH_Z_INTERVAL);
+
+    let brightness = if time_of_day.is_dark() {
+        let distance = f32::conv(map_idx) / f32::conv(ROAD_DISTANCE);
+        let fade = f32::clamp(
+            (distance - NIGHT_FADE_START) / (1.0 - NIGHT_FADE_START),
+            0.0,
+            1.0,
+        );
+        1.0 - (fade * time_of_day.get_darkness() * (1.0 - NIGHT_MIN_BRIGHTNESS))
+    } else {
+        1.0
+    };
+
+    Some(RoadLineParams {
+        is_seg_boundary,
+        shift_color: num_color_switches % 2 != 0,
+        road_center: road_dyn.x_map[map_idx] + road_dyn.road_view_pan_x,
+        road_width: PAVEMENT_WIDTH * road_scale,
+        center_line_width: CENTER_LINE_WIDTH * road_scale,
+        rumble_width: RUMBLE_STRIP_WIDTH * road_scale,
+        bank: road_dyn.bank_map[map_idx],
+        brightness,
+    })
+}
+
+// Shared by both renderers, so the shader marks the same pixels the CPU does
+fn calc_line_skid_spans(
+    skid_marks: &SkidMarks,
+    road_static: &RoadStatic,
+    road_dyn: &RoadDynamic,
+    cur_line: usize,
+) -> LineSkidSpans {
+    let mut spans = [None; MAX_SKID_SPANS_PER_LINE];
+    if skid_marks.marks.is_empty() {
+        return spans;
+    }
+
+    let map_idx: usize = road_dyn.y_map[(MAX_ROAD_DRAW_HEIGHT - 1) - cur_line];
+    if map_idx >= ROAD_DISTANCE {
+        return spans;
+    }
+
+    let line_distance = road_dyn.get_distance_traveled() + road_static.z_map[map_idx];
+    let scale = road_static.scale_map[map_idx];
+    let road_center = road_dyn.x_map[map_idx] + road_dyn.road_view_pan_x;
+    let max_x = f32::conv(FIELD_WIDTH - 1);
+
+    let crossing_marks = skid_marks
+        .marks
+        .iter()
+        .filter(|mark| line_distance >= mark.near_distance && line_distance <= mark.far_distance);
+    for (span, mark) in spans.iter_mut().zip(crossing_marks) {
+        let length = mark.far_distance - mark.near_distance;
+        let t = if length > 0.0 {
+            (line_distance - mark.near_distance) / length
+        } else {
+            0.0
+        };
+        let mark_x = (mark.near_x + (mark.far_x - mark.near_x) * t) * scale;
+        let x = road_center + bank_offset(mark_x, road_dyn.bank_map[map_idx]);
+        let half_width = f32::max(SKID_MARK_WIDTH * scale * 0.5, 0.5);
+
+        *span = Some(SkidSpan {
+            left: usize::conv_floor(f32::clamp(x - half_width, 0.0, max_x)),
+            right: usize::conv_floor(f32::clamp(x + half_width, 0.0, max_x)),
+            opacity: SKID_MARK_OPACITY * (mark.secs_left / SKID_MARK_FADE_SECS),
+        });
+    }
+    spans
+}
+
+// Darkens pavement pixels under any skid marks. Only exact pavement colors are touched,
+// so marks never spill onto the center line, rumble strips, or offroad
+fn composite_skid_marks(
+    skid_spans: &[LineSkidSpans],
+    colors: &RoadColors,
+    pixel_colors: &RoadPixelColors,
+    skid_color: u32,
+    draw_buffer: &mut [u32],
+    skidded_lines: &mut [bool; MAX_ROAD_DRAW_HEIGHT],
+) {
+    let field_width: usize = FIELD_WIDTH.cast();
+    let pavement = [colors.pavement.0, colors.pavement.1];
+    let pavement_px = [pixel_colors.pavement.0, pixel_colors.pavement.1];
+
+    for ((px_line, spans), skidded) in draw_buffer
+        .chunks_mut(field_width)
+        .zip(skid_spans.iter())
+        .zip(skidded_lines.iter_mut())
+    {
+        *skidded = false;
+        for span in spans.iter().flatten() {
+            let blended_px = [
+                blend_rgba_u32(pavement[0], skid_color, span.opacity)
+                    .from_current_into_big_endian(),
+                blend_rgba_u32(pavement[1], skid_color, span.opacity)
+                    .from_current_into_big_endian(),
+            ];
+            for px in px_line[span.left..=span.right].iter_mut() {
+                if let Some(i) = pavement_px.iter().position(|pave_px| *px == *pave_px) {
+                    *px = blended_px[i];
+                }
+            }
+            *skidded = true;
+        }
+    }
+}
+
+// Pixels are in the render texture's byte order, which is RGBA in memory on any platform.
+// The segment boundary debug color is left as-is, so it stays easy to spot
+fn darken_road_line(params: &RoadLineParams, px_line: &mut [u32]) {
+    if params.brightness >= 1.0 || params.is_seg_boundary {
+        return;
+    }
+
+    for px in px_line.iter_mut() {
+        let mut bytes = px.to_ne_bytes();
+        for channel in bytes[..3].iter_mut() {
+            *channel = u8::conv_nearest(f32::conv(*channel) * params.brightness);
+        }
+        *px = u32::from_ne_bytes(bytes);
+    }
+}
+
+// Blends between two colors packed as 0xRRGGBBAA
+fn blend_rgba_u32(from: u32, to: u32, amount: f32) -> u32 {
+    let mut blended = 0;
+    for i in 0..4 {
+        let shift = 24 - (i * 8);
+        let from = f32::conv((from >> shift) & 0xFF);
+        let to = f32::conv((to >> shift) & 0xFF);
+        let channel = u32::conv_nearest(from + (to - from) * amount);
+        blended |= u32::min(channel, 0xFF) << shift;
+    }
+    blended
+}
+
+fn rasterize_road_line(
+    params: Option<&RoadLineParams>,
+    colors: &RoadPixelColors,
+    px_line: &mut [u32],
+) {
+    // Make any pixels we won't draw to transparent
+    let params = match params {
+        Some(params) => params,
+        None => {
+            for px in px_line {
+                *px = 0;
+            }
+            return;
+        }
+    };
+
+    // For every pixel in this line, from left to right
+    for (x, px) in px_line.iter_mut().enumerate() {
+        let x: f32 = x.cast();
+
+        // Calculate the distance from the center of the road, as it looks once banked
+        let distance_from_center = bank_distance(x - params.road_center, params.bank);
+
+        // Use that distance to determine the part of the road this pixel is on
+        let shiftable: ShiftableColor = if distance_from_center <= params.center_line_width {
+            ShiftableColor(colors.center_line, colors.pavement.1)
+        } else if distance_from_center <= params.road_width {
+            colors.pavement
+        } else if distance_from_center <= params.road_width + params.rumble_width {
+            colors.rumble_strip
+        } else {
+            colors.offroad
+        };
+
+        // Write the color
+        *px = if params.is_seg_boundary {
+            colors.seg_boundary
+        } else if params.shift_color {
+            shiftable.1
+        } else {
+            shiftable.0
+        };
+    }
+}
+
+// Serial on purpose. Timed on a standalone copy of this loop at the default 320x170, a frame took
+// about 0.3ms, and splitting the lines over a rayon pool cost roughly 10% more than that. Only a
+// single core was available to time on, and at 0.3ms there's little left for more cores to win
+fn rasterize_road_lines(
+    line_params: &[Option<RoadLineParams>],
+    colors: &RoadPixelColors,
+    draw_buffer: &mut [u32],
+) {
+    let field_width: usize = FIELD_WIDTH.cast();
+    let px_lines = draw_buffer.chunks_exact_mut(field_width);
+    for (px_line, params) in px_lines.zip(line_params.iter()) {
+        rasterize_road_line(params.as_ref(), colors, px_line);
+    }
+}
+
+fn darken_road_lines(line_params: &[Option<RoadLineParams>], draw_buffer: &mut [u32]) {
+    let field_width: usize = FIELD_WIDTH.cast();
+    let px_lines = draw_buffer.chunks_exact_mut(field_width);
+    for (px_line, params) in px_lines.zip(line_params.iter()) {
+        if let Some(params) = params {
+            darken_road_line(params, px_line);
+        }
+    }
+}
+
 fn render_road(
     road_static: Res<RoadStatic>,
     road_dyn: Res<RoadDynamic>,
     mut road_draw: Local<RoadDrawing>,
     mut textures: ResMut<Assets<Texture>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    sprite_query: Query<&Handle<ColorMaterial>>,
     debug_cfg: Res<DebugConfig>,
+    time_of_day: Res<TimeOfDay>,
+    render_mode: Res<RoadRenderMode>,
+    road_shader: Option<Res<RoadShader>>,
+    mut road_materials: ResMut<Assets<RoadMaterial>>,
+    skid_marks: Res<SkidMarks>,
 ) {
-    let field_width: usize = FIELD_WIDTH.cast();
-    let colors = &road_static.colors;
-
-    // Draw line-by-line, starting from the bottom
-    for cur_line in (0..MAX_ROAD_DRAW_HEIGHT).rev() {
-        let map_idx: usize = road_dyn.y_map[(MAX_ROAD_DRAW_HEIGHT - 1) - cur_line];
-        let px_line = road_draw
-            .draw_buffer
-            .get_mut((cur_line * field_width)..((cur_line + 1) * field_width))
-            .unwrap();
-
-        // Make any pixels we won't draw to transparent
-        let no_draw = map_idx >= ROAD_DISTANCE;
-        if no_draw {
-            for px in px_line {
-                *px = 0;
+    let road_draw = &mut *road_draw;
+    let colors = road_static.colors.tinted(&time_of_day);
+
+    // Read once for the whole road, rather than per line
+    let show_seg_boundaries = debug_cfg.debug_road_seg_boundaries;
+    for (cur_line, (params, spans)) in road_draw
+        .line_params
+        .iter_mut()
+        .zip(road_draw.skid_spans.iter_mut())
+        .enumerate()
+    {
+        *params = calc_road_line_params(
+            &road_static,
+            &road_dyn,
+            show_seg_boundaries,
+            &time_of_day,
+            cur_line,
+        );
+        *spans = calc_line_skid_spans(&skid_marks, &road_static, &road_dyn, cur_line);
+    }
+    let skid_color = time_of_day.tint_rgba_u32(SKID_MARK_COLOR);
+
+    if *render_mode == RoadRenderMode::Shader {
+        let road_shader = road_shader.expect(ROAD_NOT_INIT);
+
+        // Copy the line parameters to the back texture
+        let back_tex = &road_shader.line_params_texs[road_draw.back_tex_idx];
+        let dest_tex = textures.get_mut(back_tex).expect(ROAD_NOT_INIT);
+        let lines = road_draw
+            .line_params
+            .iter()
+            .zip(road_draw.skid_spans.iter());
+        for (cur_line, (params, spans)) in lines.enumerate() {
+            write_line_params(&mut dest_tex.data, cur_line, params.as_ref(), spans);
+        }
+
+        // Then display it, the same as the CPU path below
+        let material = road_materials
+            .get_mut(&road_shader.material)
+            .expect(ROAD_NOT_INIT);
+        material.line_params = back_tex.clone();
+        material.seg_boundary = debug_cfg.road_seg_boundary_color;
+        colors.apply_to_material(material, skid_color);
+        road_draw.back_tex_idx = 1 - road_draw.back_tex_idx;
+        return;
+    }
+
+    let seg_boundary_color = DebugConfig::color_to_rgba_u32(debug_cfg.road_seg_boundary_color);
+    let pixel_colors = colors.to_pixel_colors(seg_boundary_color);
+    rasterize_road_lines(
+        &road_draw.line_params[..],
+        &pixel_colors,
+        &mut road_draw.draw_buffer[..],
+    );
+
+    composite_skid_marks(
+        &road_draw.skid_spans[..],
+        &colors,
+        &pixel_colors,
+        skid_color,
+        &mut road_draw.draw_buffer[..],
+        &mut road_draw.skidded_lines,
+    );
+
+    // Done last, so skid marks can still find the exact pavement colors
+    darken_road_lines(&road_draw.line_params[..], &mut road_draw.draw_buffer[..]);
+
+    // Copy the pixel data to the back texture
+    let back_tex = &road_static.render_texs[road_draw.back_tex_idx];
+    let dest_tex = textures.get_mut(back_tex).expect(ROAD_NOT_INIT);
+    upload_road_lines(road_draw, &pixel_colors, &mut dest_tex.data);
+
+    // Now that it's fully written, display it, and draw to the other texture next time
+    let material_handle = sprite_query
+        .get(road_static.road_sprite)
+        .expect(ROAD_NOT_INIT);
+    let material = materials.get_mut(material_handle).expect(ROAD_NOT_INIT);
+    material.texture = Some(back_tex.clone());
+    road_draw.back_tex_idx = 1 - road_draw.back_tex_idx;
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::Events, ecs::schedule::Stage};
+
+    use super::*;
+    use crate::{
+        headless::{
+            build_headless_app, clear_rivals, get_player_mut, start_race_on_straight, step,
+        },
+        replay::RecordedInput,
+    };
+
+    fn test_seg(curve: f32) -> RoadSegment {
+        RoadSegment {
+            curve,
+            hill: 0.0,
+            spawn_object_type: None,
+            ambient_tint: None,
+            audio_cue: None,
+            checkpoint: false,
+            spawn_table: None,
+        }
+    }
+
+    fn test_track() -> Vec<RoadSegment> {
+        [0.0, 0.3, -0.5, 0.8, 0.0, -0.2, 0.4, 0.0]
+            .iter()
+            .map(|&curve| test_seg(curve))
+            .collect()
+    }
+
+    fn test_road_dynamic() -> RoadDynamic {
+        let debug_config = DebugConfig {
+            debug_gameplay: true,
+            ..Default::default()
+        };
+        let mut road_dyn = build_road_dynamic(
+            &debug_config,
+            &EndlessMode::default(),
+            &CheckpointConfig::default(),
+            &TrackConfig::default(),
+            &PlayerStartConfig::default(),
+            None,
+        );
+        road_dyn.replace_segs(test_track());
+        road_dyn
+    }
+
+    // Stands in for the player driving: a varying speed, pulled around by the curves
+    fn drive(road_dyn: &mut RoadDynamic, ticks: u32) {
+        for i in 0..ticks {
+            let speed = 10.0 + f32::conv(i % 7);
+            road_dyn.x_offset += road_dyn.get_road_x_pull(0.0, speed) * joyride::TIME_STEP;
+            road_dyn.advance_z(speed * joyride::TIME_STEP);
+        }
+    }
+
+    #[test]
+    fn restored_snapshot_advances_identically() {
+        let mut original = test_road_dynamic();
+        drive(&mut original, 40);
+        let state = original.snapshot();
+
+        // Through RON, the same as snapshots and sessions are saved
+        let serialized = ron::ser::to_string(&state).unwrap();
+        let deserialized: RoadState = ron::de::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, state);
+
+        let mut restored = test_road_dynamic();
+        restored.restore(&deserialized);
+
+        drive(&mut original, 40);
+        drive(&mut restored, 40);
+        assert_eq!(original.snapshot(), restored.snapshot());
+    }
+
+    // The exact results of a fixed drive down a fixed track. Any change to the road math that
+    // shifts them, on any platform, breaks replays and shared seeds, so this has to be updated
+    // knowingly rather than just to make it pass
+    #[test]
+    fn fixed_drive_gives_exact_road_state() {
+        let mut road_dyn = test_road_dynamic();
+        drive(&mut road_dyn, 120);
+
+        assert_eq!(
+            road_dyn.snapshot(),
+            RoadState {
+                x_offset: 125.20003,
+                z_offset: 0.9000033,
+                seg_idx: 3,
+                seg_pos: 6.9000034,
+            }
+        );
+    }
+
+    #[test]
+    fn track_round_trips_through_a_file() {
+        let segs = vec![
+            test_seg(0.25),
+            RoadSegment {
+                spawn_object_type: Some(RoadObjectType::RoadSigns(
+                    RoadSignType::Turn(true),
+                    RoadSide::Right,
+                )),
+                ..test_seg(-0.5)
+            },
+            test_seg(0.0),
+        ];
+
+        let path = std::env::temp_dir().join("joyride_test_track.ron");
+        let serialized = ron::ser::to_string(&segs).unwrap();
+        std::fs::write(&path, serialized).unwrap();
+        let loaded = load_track(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), 3);
+        let curves: Vec<f32> = loaded.iter().map(|seg| seg.curve).collect();
+        assert_eq!(curves, vec![0.25, -0.5, 0.0]);
+        assert!(loaded[1].spawn_object_type.is_some());
+    }
+
+    #[test]
+    fn missing_track_falls_back_to_default() {
+        let loaded = load_track("assets/no_such_track.ron");
+        assert_eq!(loaded.len(), default_track().len());
+    }
+
+    #[test]
+    fn themes_change_road_and_sky_together() {
+        let themes = [RoadTheme::Desert, RoadTheme::Forest, RoadTheme::City];
+        for (i, &a) in themes.iter().enumerate() {
+            for &b in &themes[i + 1..] {
+                assert!(
+                    a.colors().pavement != b.colors().pavement,
+                    "{:?} and {:?} share a pavement color",
+                    a,
+                    b
+                );
+                assert_ne!(a.skybox_texture_path(), b.skybox_texture_path());
             }
-            continue;
         }
+    }
 
-        let road_z = road_static.z_map[map_idx];
-        let road_scale = road_static.scale_map[map_idx];
+    #[test]
+    fn road_point_is_found_segments_ahead() {
+        let mut road_dyn = test_road_dynamic();
+        road_dyn.advance_z(SEGMENT_LENGTH * 0.5);
+        let here = road_dyn.query_road_point(0.0);
+
+        let ahead = road_dyn.query_road_point(SEGMENT_LENGTH * 2.0);
+        assert_eq!(ahead.seg_idx, here.seg_idx + 2);
+        assert_eq!(ahead.seg_pos, here.seg_pos);
+        assert_eq!(ahead.seg.curve, test_track()[ahead.seg_idx].curve);
+    }
 
-        let is_seg_boundary = if debug_cfg.debug_road_seg_boundaries && map_idx > 0 {
-            let seg_num = usize::conv_trunc((road_z + road_dyn.seg_pos) / SEGMENT_LENGTH);
-            let last_seg_num = usize::conv_trunc(
-                (road_static.z_map[map_idx - 1] + road_dyn.seg_pos) / SEGMENT_LENGTH,
+    #[test]
+    fn bounded_seg_loops_or_clamps() {
+        let segs: Vec<RoadSegment> = [0.1, 0.2, 0.3].iter().map(|&c| test_seg(c)).collect();
+        assert_eq!(get_bounded_seg(&segs, 4, true).curve, 0.2);
+        assert_eq!(get_bounded_seg(&segs, 4, false).curve, 0.3);
+        assert_eq!(get_bounded_seg(&segs, 1, false).curve, 0.2);
+    }
+
+    #[test]
+    fn road_pull_follows_curve_direction() {
+        let road_dyn = test_road_dynamic();
+        let pull_in_seg =
+            |seg_idx: f32| road_dyn.get_road_x_pull((seg_idx + 0.5) * SEGMENT_LENGTH, 10.0);
+
+        // The test track runs straight, then curves right, then left
+        assert_eq!(pull_in_seg(0.0), 0.0);
+        assert!(
+            pull_in_seg(1.0) > 0.0,
+            "A right-hand curve didn't pull right"
+        );
+        assert!(pull_in_seg(2.0) < 0.0, "A left-hand curve didn't pull left");
+    }
+
+    // converge_x used to work its scalar out on every call. The table it reads instead has to
+    // give exactly what that did, all the way to the far end of the road
+    #[test]
+    fn converge_table_matches_per_call_math() {
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+        let road_static = app.world.get_resource::<RoadStatic>().unwrap();
+
+        for &x_pos in [-400.0, -37.5, 0.0, 12.25, 250.0].iter() {
+            for i in 0..ROAD_DISTANCE {
+                let converge_scalar = f32::conv(i) / f32::conv(ROAD_DISTANCE);
+                assert_eq!(
+                    converge_x(road_static, x_pos, i),
+                    x_pos * (1.0 - converge_scalar),
+                    "Converging {} differs at map index {}",
+                    x_pos,
+                    i
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn nan_z_is_not_drawn() {
+        // Flat, so nothing is hidden behind a crest
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+
+        let road_static = app.world.get_resource::<RoadStatic>().unwrap();
+        let road_dyn = app.world.get_resource::<RoadDynamic>().unwrap();
+
+        let mid_z = road_static.z_map()[ROAD_DISTANCE / 2];
+        assert!(get_draw_params_on_road(road_static, road_dyn, 0.0, mid_z).is_some());
+        assert!(get_draw_params_on_road(road_static, road_dyn, 0.0, f32::NAN).is_none());
+    }
+
+    #[test]
+    fn offroad_terrain_follows_the_side() {
+        let mut app = build_headless_app();
+        app.world.insert_resource(TrackConfig {
+            terrain: Some(RoadsideTerrain {
+                left: Terrain::Grass,
+                right: Terrain::Gravel,
+            }),
+            ..Default::default()
+        });
+        start_race_on_straight(&mut app);
+
+        let scale = app.world.get_resource::<RoadStatic>().unwrap().scale_map()[0];
+        let offroad_x = (PAVEMENT_WIDTH + RUMBLE_STRIP_WIDTH) * scale * 1.5;
+
+        let mut terrain_at = |x_offset: f32| {
+            app.world
+                .get_resource_mut::<RoadDynamic>()
+                .unwrap()
+                .x_offset = x_offset;
+            let road_static = app.world.get_resource::<RoadStatic>().unwrap();
+            let road_dyn = app.world.get_resource::<RoadDynamic>().unwrap();
+            let track_config = app.world.get_resource::<TrackConfig>().unwrap();
+            get_offroad_terrain(road_static, road_dyn, track_config)
+        };
+
+        // The road is offset the opposite way to the player
+        assert_eq!(terrain_at(0.0), None);
+        assert_eq!(terrain_at(offroad_x), Some(Terrain::Grass));
+        assert_eq!(terrain_at(-offroad_x), Some(Terrain::Gravel));
+    }
+
+    fn curvature_maps(app: &App) -> (Vec<f32>, Vec<f32>) {
+        let road_dyn = app.world.get_resource::<RoadDynamic>().unwrap();
+        (road_dyn.x_map.to_vec(), road_dyn.bank_map.to_vec())
+    }
+
+    // Drives along the test track, sometimes only moving sideways, and checks that whatever the
+    // cache kept matches remapping everything from scratch
+    #[test]
+    fn cached_curvature_matches_a_full_remap() {
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+        app.world
+            .get_resource_mut::<RoadDynamic>()
+            .unwrap()
+            .replace_segs(test_track());
+
+        let mut stage = SystemStage::single(update_road_curvature.system());
+        for i in 0..60 {
+            {
+                let mut road_dyn = app.world.get_resource_mut::<RoadDynamic>().unwrap();
+                if i % 3 != 0 {
+                    road_dyn.advance_z(1.7);
+                }
+                road_dyn.x_offset = (f32::conv(i % 11) * 4.0) - 20.0;
+            }
+            stage.run(&mut app.world);
+            let cached = curvature_maps(&app);
+
+            app.world
+                .get_resource_mut::<RoadDynamic>()
+                .unwrap()
+                .curve_map_key = CurveMapKey::default();
+            stage.run(&mut app.world);
+            assert_eq!(cached, curvature_maps(&app), "Maps differ on tick {}", i);
+        }
+    }
+
+    // Pegs the whole draw distance far past the curve cap, with the racer far off to either side
+    // of the road, and checks the road center never runs away past the offscreen bound
+    #[test]
+    fn road_center_stays_near_the_screen_at_max_curve() {
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+
+        let min_x = -MAX_ROAD_CENTER_OFFSCREEN_X;
+        let max_x = f32::conv(FIELD_WIDTH) + MAX_ROAD_CENTER_OFFSCREEN_X;
+        let mut stage = SystemStage::single(update_road_curvature.system());
+        for &curve in [MAX_SEGMENT_CURVE * 10.0, -MAX_SEGMENT_CURVE * 10.0].iter() {
+            for &x_offset in [-500.0, 0.0, 500.0].iter() {
+                {
+                    let mut road_dyn = app.world.get_resource_mut::<RoadDynamic>().unwrap();
+                    road_dyn.replace_segs(vec![test_seg(curve); 16]);
+                    road_dyn.x_offset = x_offset;
+                }
+                stage.run(&mut app.world);
+
+                let road_dyn = app.world.get_resource::<RoadDynamic>().unwrap();
+                for (i, &x) in road_dyn.x_map.iter().enumerate() {
+                    assert!(
+                        (min_x..=max_x).contains(&x),
+                        "Road center {} ran away at map index {}, with curve {} and offset {}",
+                        x,
+                        i,
+                        curve,
+                        x_offset
+                    );
+                }
+            }
+        }
+    }
+
+    // Driving well off the side of an ordinary curve puts the road center off screen, which the
+    // clamp has to leave alone so the road and everything along it are drawn where they are
+    #[test]
+    fn normal_curves_are_not_clamped() {
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+
+        let mut stage = SystemStage::single(update_road_curvature.system());
+        for &x_offset in [-500.0, 500.0].iter() {
+            {
+                let mut road_dyn = app.world.get_resource_mut::<RoadDynamic>().unwrap();
+                road_dyn.replace_segs(vec![test_seg(0.5); 16]);
+                road_dyn.x_offset = x_offset;
+            }
+            stage.run(&mut app.world);
+
+            let road_static = app.world.get_resource::<RoadStatic>().unwrap();
+            let road_dyn = app.world.get_resource::<RoadDynamic>().unwrap();
+            let mut went_off_screen = false;
+            for (i, (&x, &curve_x)) in road_dyn
+                .x_map
+                .iter()
+                .zip(road_dyn.curve_x_map.iter())
+                .enumerate()
+            {
+                let unclamped = curve_x + converge_x(road_static, x_offset, i);
+                assert_eq!(x, unclamped, "Road center was clamped at map index {}", i);
+                went_off_screen |= !(0.0..=f32::conv(FIELD_WIDTH)).contains(&x);
+            }
+            assert!(
+                went_off_screen,
+                "Offset {} never took the road center off screen",
+                x_offset
             );
-            seg_num != last_seg_num
-        } else {
-            false
+        }
+    }
+
+    // Something different on each line and frame, with some lines left undrawn and others kept
+    // the same for a few frames, so there's a mix of dirty and clean lines
+    fn test_line_params(line: usize, frame: usize) -> Option<RoadLineParams> {
+        if (line + frame) % 9 == 0 {
+            return None;
+        }
+        Some(RoadLineParams {
+            is_seg_boundary: false,
+            shift_color: ((line / 4) + (frame / 3)) % 2 == 0,
+            road_center: f32::conv((line % 5) * (frame / 2)) * 3.0 + 80.0,
+            road_width: f32::conv(line) * 0.5 + 10.0,
+            center_line_width: 1.0,
+            rumble_width: 2.0,
+            bank: 0.0,
+            brightness: 1.0,
+        })
+    }
+
+    // Rasterizes with the colors left as 0xRRGGBBAA, converting each pixel afterwards the way
+    // rasterizing used to, and checks the preconverted colors lay out the same bytes
+    #[test]
+    fn preconverted_colors_match_per_pixel_conversion() {
+        const SEG_BOUNDARY: u32 = 0xFF00FFFF;
+        let line_len: usize = FIELD_WIDTH.cast();
+        let colors = DESERT_COLORS;
+        let unconverted_colors = RoadPixelColors {
+            offroad: colors.offroad,
+            rumble_strip: colors.rumble_strip,
+            pavement: colors.pavement,
+            center_line: colors.center_line,
+            seg_boundary: SEG_BOUNDARY,
         };
+        let pixel_colors = colors.to_pixel_colors(SEG_BOUNDARY);
+
+        let mut preconverted = vec![0; NUM_ROAD_PIXELS];
+        let mut per_pixel = vec![0; NUM_ROAD_PIXELS];
+        let lines = preconverted
+            .chunks_exact_mut(line_len)
+            .zip(per_pixel.chunks_exact_mut(line_len));
+        for (line, (preconverted_line, per_pixel_line)) in lines.enumerate() {
+            let params = test_line_params(line, 4).map(|params| RoadLineParams {
+                is_seg_boundary: line % 11 == 0,
+                ..params
+            });
+            rasterize_road_line(params.as_ref(), &pixel_colors, preconverted_line);
+            rasterize_road_line(params.as_ref(), &unconverted_colors, per_pixel_line);
+            for px in per_pixel_line.iter_mut() {
+                *px = (*px).from_current_into_big_endian();
+            }
+        }
 
-        // Switch the exact color used for each part of the road, based on Z
-        let num_color_switches =
-            i32::conv_trunc((road_z + road_dyn.z_offset) / COLOR_SWITCH_Z_INTERVAL);
-        let shift_color = num_color_switches % 2 != 0;
-
-        let road_center = road_dyn.x_map[map_idx];
-        let road_width = PAVEMENT_WIDTH * road_scale;
-        let center_line_width = CENTER_LINE_WIDTH * road_scale;
-        let rumble_width = RUMBLE_STRIP_WIDTH * road_scale;
-
-        // For every pixel in this line, from left to right
-        for (x, px) in px_line.iter_mut().enumerate() {
-            let x: f32 = x.cast();
-
-            // Calculate the distance from the center of the road
-            let distance_from_center = (x - road_center).abs();
-
-            // Use that distance to determine the part of the road this pixel is on
-            let shiftable: ShiftableColor = if distance_from_center <= center_line_width {
-                ShiftableColor(colors.center_line, colors.pavement.1)
-            } else if distance_from_center <= road_width {
-                colors.pavement
-            } else if distance_from_center <= road_width + rumble_width {
-                colors.rumble_strip
-            } else {
-                colors.offroad
-            };
+        assert!(preconverted.as_bytes() == per_pixel.as_bytes());
 
-            // Write the color
-            let color = if is_seg_boundary {
-                0x00FF00FF
-            } else if shift_color {
-                shiftable.1
+        // The texture is RGBA8, so whatever the target, each pixel's bytes go R, G, B, A
+        let pavement_px = pixel_colors.pavement.0;
+        assert_eq!(pavement_px.as_bytes(), &colors.pavement.0.to_be_bytes()[..]);
+    }
+
+    // Uploads frame after frame to the two render textures in turn, the way render_road does,
+    // and checks each texture always ends up the same as if the whole buffer had been copied
+    #[test]
+    fn dirty_line_upload_matches_a_full_copy() {
+        const NUM_FRAMES: usize = 12;
+        // The colors change partway through, which should force one full copy per texture
+        const COLOR_CHANGE_FRAME: usize = 7;
+        const SKID_COLOR: u32 = 0x202020FF;
+
+        let line_len = NUM_ROAD_PIXELS / MAX_ROAD_DRAW_HEIGHT;
+        let mut road_draw = RoadDrawing::default();
+        // Garbage to begin with, so a line that's never uploaded gets caught
+        let mut texs = [
+            vec![0xAB; NUM_ROAD_PIXELS * 4],
+            vec![0xAB; NUM_ROAD_PIXELS * 4],
+        ];
+
+        for frame in 0..NUM_FRAMES {
+            let colors = if frame < COLOR_CHANGE_FRAME {
+                DESERT_COLORS
             } else {
-                shiftable.0
+                FOREST_COLORS
             };
-            *px = color.from_current_into_big_endian();
+            let pixel_colors = colors.to_pixel_colors(0);
+
+            for line in 0..MAX_ROAD_DRAW_HEIGHT {
+                let params = test_line_params(line, frame);
+                let px_line =
+                    &mut road_draw.draw_buffer[(line * line_len)..((line + 1) * line_len)];
+                rasterize_road_line(params.as_ref(), &pixel_colors, px_line);
+                road_draw.line_params[line] = params;
+
+                // A mark over lines whose params don't change, which only the skid tracking
+                // can tell need copying again once it's gone
+                let skidded = (line + frame) % 13 == 0;
+                if skidded {
+                    px_line[line_len / 2] = SKID_COLOR;
+                }
+                road_draw.skidded_lines[line] = skidded;
+            }
+
+            let full_copy = road_draw.draw_buffer.as_bytes().to_vec();
+            let back_tex_idx = road_draw.back_tex_idx;
+            upload_road_lines(&mut road_draw, &pixel_colors, &mut texs[back_tex_idx]);
+            assert!(
+                texs[back_tex_idx] == full_copy,
+                "Texture {} differs from a full copy on frame {}",
+                back_tex_idx,
+                frame
+            );
+            road_draw.back_tex_idx = 1 - road_draw.back_tex_idx;
         }
     }
 
-    // Copy the pixel data to the texture
-    let dest_tex = textures
-        .get_mut(road_static.render_tex.clone())
-        .expect(ROAD_NOT_INIT);
-    dest_tex
-        .data
-        .copy_from_slice(road_draw.draw_buffer.as_bytes());
+    // Drives off down the road and over to one side, then restarts, which should put the player
+    // back at the start of the road, stopped, for another countdown
+    #[test]
+    fn restart_puts_the_road_back_at_the_start() {
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+
+        let accel_right = RecordedInput {
+            accel: true,
+            right: true,
+            ..Default::default()
+        };
+        for _ in 0..300 {
+            clear_rivals(&mut app);
+            step(&mut app, accel_right);
+        }
+        {
+            let road_dyn = app.world.get_resource::<RoadDynamic>().unwrap();
+            assert!(
+                road_dyn.seg_idx > 0,
+                "The player never left the first segment"
+            );
+            assert!(road_dyn.x_offset != 0.0, "The player never moved sideways");
+        }
+
+        app.world
+            .get_resource_mut::<Events<RaceRestarted>>()
+            .unwrap()
+            .send(RaceRestarted);
+        step(&mut app, RecordedInput::default());
+
+        let road_dyn = app.world.get_resource::<RoadDynamic>().unwrap();
+        assert_eq!(road_dyn.seg_idx, 0);
+        assert_eq!(road_dyn.x_offset, 0.0);
+        assert_eq!(
+            *app.world.get_resource::<RaceState>().unwrap(),
+            RaceState::Countdown
+        );
+        assert_eq!(get_player_mut(&mut app).1.speed, 0.0);
+    }
+
+    // Puts an object just over the top of a steep hill, and walks the camera up to the crest. The
+    // climb hides the far side, so the object shouldn't show until the crest is nearly reached,
+    // and shouldn't flicker back out once it has
+    #[test]
+    fn objects_behind_a_crest_stay_hidden_until_it_is_reached() {
+        const HILL: f32 = 0.05;
+        const CREST_AHEAD: f32 = 6.0;
+        const OBJECT_PAST_CREST: f32 = 2.0;
+        const STEP_Z: f32 = 0.1;
+
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+        let near_z = app.world.get_resource::<RoadStatic>().unwrap().z_map[0];
+        {
+            let mut road_dyn = app.world.get_resource_mut::<RoadDynamic>().unwrap();
+            let mut segs = vec![test_seg(0.0); 8];
+            segs[0].hill = -HILL;
+            segs[1].hill = HILL;
+            road_dyn.replace_segs(segs);
+            road_dyn.advance_z(SEGMENT_LENGTH - CREST_AHEAD);
+        }
+
+        let mut stage = SystemStage::single(update_road_hills.system());
+        let num_steps = usize::conv_trunc(CREST_AHEAD / STEP_Z);
+        let mut first_shown = None;
+        for i in 0..num_steps {
+            if i > 0 {
+                let mut road_dyn = app.world.get_resource_mut::<RoadDynamic>().unwrap();
+                road_dyn.advance_z(STEP_Z);
+            }
+            stage.run(&mut app.world);
+
+            let crest_distance = CREST_AHEAD - (f32::conv(i) * STEP_Z);
+            let object_z = near_z + crest_distance + OBJECT_PAST_CREST;
+            let road_static = app.world.get_resource::<RoadStatic>().unwrap();
+            let road_dyn = app.world.get_resource::<RoadDynamic>().unwrap();
+            let shown = get_draw_params_on_road(road_static, road_dyn, 0.0, object_z).is_some();
+
+            match first_shown {
+                None if shown => {
+                    assert!(
+                        crest_distance < CREST_AHEAD * 0.5,
+                        "Shown with the crest still {} away",
+                        crest_distance
+                    );
+                    first_shown = Some(i);
+                }
+                Some(_) => assert!(shown, "Hidden again with the crest {} away", crest_distance),
+                None => {}
+            }
+        }
+        assert!(first_shown.is_some(), "Still hidden on reaching the crest");
+    }
 }
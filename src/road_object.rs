@@ -1,25 +1,38 @@
-use bevy::prelude::*;
+use std::time::Duration;
+
+use bevy::{ecs::system::EntityCommands, prelude::*};
 use easy_cast::*;
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
 use crate::{
-    debug::{spawn_collision_debug_box, DebugAssets},
-    joyride::TIME_STEP,
-    player::{Player, PlayerSlideDirection},
+    debug::{set_collision_debug_box, spawn_collision_debug_box, DebugAssets},
+    joyride::{
+        scaled_step, Difficulty, GameRng, JoyrideGame, RaceRestarted, RngStream, Score, TimeScale,
+    },
+    player::{Player, PlayerCrashed, PlayerSlid, PlayerSlideDirection},
     racer::{Racer, RacerAssets},
-    rival::{spawn_rival, Rival, RivalAssets, RivalPalette},
-    road::{get_draw_params_on_road, RoadDynamic, RoadStatic, PAVEMENT_WIDTH, SEGMENT_LENGTH},
-    util::{LocalVisible, SpriteGridDesc},
+    rival::{spawn_rival, Rival, RivalAssets, RivalConfig, RivalPalette},
+    road::{
+        get_draw_params_on_road, RoadDynamic, RoadSegment, RoadStatic, TrackConfig, PAVEMENT_WIDTH,
+        SEGMENT_LENGTH,
+    },
+    track::DifficultyCurve,
+    util::{LocalVisible, SpriteGridDesc, TextureLoader},
 };
 
 pub const PLAYER_COLLISION_WIDTH: f32 = 30.0;
 
+// Passing an obstacle with no more than this gap, in pixels at the bottom of the screen,
+// counts as a near miss
+pub const NEAR_MISS_MAX_GAP: f32 = 12.0;
+const NEAR_MISS_POINTS: u32 = 200;
+
 pub const ROAD_OBJ_BASE_Z: f32 = 300.0;
 
 const ROAD_OBJ_SPRITE_DESC: SpriteGridDesc = SpriteGridDesc {
     tile_size: 128,
     rows: 10,
-    columns: 3,
+    columns: 9,
 };
 
 // TODO: Share this with Rival?
@@ -31,39 +44,186 @@ const ROAD_SIGN_Z_OFFSETS: [f32; 3] = [
     SEGMENT_LENGTH * 0.65,
 ];
 
+const ROAD_SIGN_COLLIDER_HEIGHT: f32 = 100.0;
+
+const VEHICLE_SPRITE_SET_IDX: u32 = 3;
+const VEHICLE_COLLIDER_HALF_WIDTH: f32 = 35.0;
+const VEHICLE_COLLIDER_HEIGHT: f32 = 50.0;
+const VEHICLE_Z_OFFSET: f32 = SEGMENT_LENGTH * 0.5;
+
+// Vehicles hold one of these lanes, as fractions of the pavement width, weaving gently about it
+const VEHICLE_LANES: [f32; 2] = [-0.5, 0.5];
+const VEHICLE_WEAVE_AMPLITUDE: f32 = 12.0;
+const VEHICLE_WEAVE_FREQUENCY: f32 = 1.5;
+
+// A gate is two posts with a gap between them wide enough for the player to pass through
+const GATE_SPRITE_SET_IDX: u32 = 8;
+const GATE_HALF_GAP: f32 = 36.0;
+const GATE_POST_WIDTH: f32 = 10.0;
+const GATE_COLLIDER_HEIGHT: f32 = 90.0;
+const GATE_MAX_X: f32 = PAVEMENT_WIDTH * 0.5;
+
+// Each pickup kind has this many animation frames, in consecutive sprite sets
+const PICKUP_ANIM_FRAMES: u32 = 2;
+const PICKUP_ANIM_FRAME_SECS: f32 = 0.15;
+const PICKUP_COLLIDER_HALF_WIDTH: f32 = 20.0;
+const PICKUP_COLLIDER_HEIGHT: f32 = 40.0;
+const PICKUP_MAX_X: f32 = PAVEMENT_WIDTH - PICKUP_COLLIDER_HALF_WIDTH;
+
+const PICKUP_POINTS: u32 = 100;
+const PICKUP_TIME_BONUS_SECS: f32 = 5.0;
+
+// Taken off the race timer every time the player crashes
+const CRASH_TIME_PENALTY_SECS: f32 = 2.0;
+
+// The finish banner's texture is one pixel per unit of road width, spanning the road and its
+// rumble strips with a post on either side
+const FINISH_BANNER_HEIGHT: f32 = 170.0;
+
+// The oil slick's texture is one pixel per unit of road width, like the finish banner.
+// Its collider is much wider than most, so it's hard to steer around
+const OIL_SLICK_HEIGHT: f32 = 24.0;
+const OIL_SLICK_COLLIDER_HALF_WIDTH: f32 = 90.0;
+const OIL_SLICK_MAX_X: f32 = PAVEMENT_WIDTH * 0.3;
+
+// Vehicles and rivals that get this far ahead of the player are despawned
+const FAR_OUT_DESPAWN_SCALAR: f32 = 2.5;
+
+const ROAD_OBJ_NOT_FOUND: &str = "Road object not found";
+
 const MAX_SPAWNED_RIVALS: usize = 2;
 const RIVAL_SPAWN_CHANCE: f64 = 0.6;
-const RIVAL_DESPAWN_SCALAR: f32 = 2.5;
-const SPAWNED_RIVAL_SPEED: f32 = 4.0;
 
 #[derive(Debug, Clone)]
 pub struct Collider {
     pub left: f32,
     pub right: f32,
+
+    // How tall the collider is off the ground. An airborne player at or above this clears it
+    pub height: f32,
 }
 
-#[derive(Debug, Clone, Copy)]
+// Ordered from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum CollisionAction {
+    // Collected by the player on contact, on top of any other collision that frame
+    CollectPickup,
+    BouncePlayer,
+    // Slides the player the way they were already turning, rather than away from the object
+    SlipPlayer,
     SlidePlayer,
     CrashPlayer,
 }
 
-#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub enum RoadSide {
     Left,
     Right,
 }
 
-#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub enum RoadSignType {
     Oxman,
     BeatDown,
     Turn(bool),
 }
 
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum RoadObjectType {
     RoadSigns(RoadSignType, RoadSide),
+
+    // Traffic driving down the road ahead of the player, at a speed in the same units as a racer's
+    Vehicle { speed: f32 },
+
+    // A short line of pickups somewhere across the pavement
+    Pickup(PickupKind),
+
+    // Two posts somewhere across the pavement, which the player has to thread between
+    Gate,
+
+    // A dark patch lying flat on the road, which sends the player into a slide
+    OilSlick,
+
+    // A banner across the road. Only a cosmetic marker, see TrackConfig for where the race ends
+    FinishLine,
+}
+
+impl RoadObjectType {
+    // Whether this sits on the pavement in the player's way, rather than being roadside scenery
+    // or something to collect
+    pub fn is_obstacle(&self) -> bool {
+        matches!(
+            self,
+            RoadObjectType::Vehicle { .. } | RoadObjectType::Gate | RoadObjectType::OilSlick
+        )
+    }
+}
+
+// Weighted choices for what a segment spawns, rolled once as the segment comes into range.
+// An entry without a type is a chance of spawning nothing
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SpawnTable {
+    pub entries: Vec<(f32, Option<RoadObjectType>)>,
+}
+
+impl SpawnTable {
+    // Obstacle entries have their weight scaled, so they come up more or less often than the rest
+    pub fn roll(&self, rng: &mut StdRng, obstacle_weight: f32) -> Option<&RoadObjectType> {
+        let get_weight = |(weight, obj_type): &(f32, Option<RoadObjectType>)| {
+            let scalar = match obj_type {
+                Some(obj_type) if obj_type.is_obstacle() => obstacle_weight,
+                _ => 1.0,
+            };
+            (weight * scalar).max(0.0)
+        };
+
+        let total_weight: f32 = self.entries.iter().map(get_weight).sum();
+        if total_weight <= 0.0 {
+            return None;
+        }
+
+        let mut pick = rng.gen_range(0.0..total_weight);
+        for entry in &self.entries {
+            let weight = get_weight(entry);
+            if pick < weight {
+                return entry.1.as_ref();
+            }
+            pick -= weight;
+        }
+
+        // Float error can carry the pick just past the last entry
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| get_weight(entry) > 0.0)
+            .and_then(|(_, obj_type)| obj_type.as_ref())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PickupKind {
+    Points,
+    TimeBonus,
+}
+
+impl PickupKind {
+    fn first_sprite_set_idx(self) -> u32 {
+        match self {
+            PickupKind::Points => 4,
+            PickupKind::TimeBonus => 6,
+        }
+    }
+}
+
+// Sent when the player drives over a pickup
+pub struct PickupCollected {
+    pub kind: PickupKind,
+}
+
+// Animated through its frames by a Timer alongside it
+pub struct Pickup {
+    kind: PickupKind,
+    anim_frame: u32,
 }
 
 pub struct RoadObject {
@@ -72,10 +232,65 @@ pub struct RoadObject {
     pub collider1: Option<Collider>,
     pub collider2: Option<Collider>,
     pub collision_action: CollisionAction,
+
+    // Taken from the segment the object was spawned in
+    pub ambient_tint: Option<Color>,
+}
+
+// A road object that drives forward on its own
+pub struct Vehicle {
+    speed: f32,
+    lane_x: f32,
+    weave_time: f32,
+}
+
+// Drawn scaled to the road, rather than from the LOD sprites other objects use.
+// The texture stands on the road from its bottom edge, this many units tall
+struct RoadScaledSprite {
+    height: f32,
 }
 
 struct RoadObjectAssets {
     sprite_atlas: Handle<TextureAtlas>,
+    finish_line_mat: Handle<ColorMaterial>,
+    oil_slick_mat: Handle<ColorMaterial>,
+}
+
+// The collision debug boxes parented to a pooled object, one per collider slot.
+// A slot without a collider keeps its box, sized to nothing
+struct RoadObjectDebugBoxes([Entity; 2]);
+
+// Objects drawn from the sprite atlas are hidden and kept once passed, rather than despawned,
+// since a fresh batch of them comes into range every segment
+#[derive(Default)]
+struct RoadObjectPool {
+    free: Vec<(Entity, [Entity; 2])>,
+
+    // Released objects only lose their components once commands are applied, so they can't be
+    // handed out again until the next frame
+    released: Vec<(Entity, [Entity; 2])>,
+}
+
+impl RoadObjectPool {
+    fn reclaim(&mut self) {
+        self.free.append(&mut self.released);
+    }
+
+    fn release(
+        &mut self,
+        commands: &mut Commands,
+        ent: Entity,
+        debug_boxes: &RoadObjectDebugBoxes,
+    ) {
+        commands
+            .entity(ent)
+            .remove::<RoadObject>()
+            .remove::<Vehicle>()
+            .remove::<Pickup>()
+            .remove::<Timer>()
+            .insert(LocalVisible { is_visible: false });
+        self.released.push((ent, debug_boxes.0));
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -87,10 +302,31 @@ struct RoadObjectSpriteSelector {
 struct Spawner {
     last_seg_idx: usize,
     segs_without_rival: usize,
+    rng: StdRng,
+
+    // Kept apart from the main stream so that rival spawns don't change what the tables roll
+    table_rng: StdRng,
+}
+
+impl Spawner {
+    fn new(track_config: &TrackConfig, game_rng: &GameRng) -> Self {
+        let table_rng = match track_config.spawn_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => game_rng.make_stream(RngStream::SpawnTables),
+        };
+
+        Self {
+            last_seg_idx: 0,
+            segs_without_rival: 0,
+            rng: game_rng.make_stream(RngStream::RoadObjects),
+            table_rng,
+        }
+    }
 }
 
 pub struct Systems {
     pub startup_road_objects: SystemSet,
+    pub reset_road_objects: SystemSet,
     pub manage_road_objects: SystemSet,
     pub update_road_object_visuals: SystemSet,
 }
@@ -99,13 +335,19 @@ impl Systems {
     pub fn new() -> Self {
         Self {
             startup_road_objects: SystemSet::new().with_system(startup_road_objects.system()),
+            reset_road_objects: SystemSet::new().with_system(reset_road_objects.system()),
             manage_road_objects: SystemSet::new()
                 .with_system(check_passed_objects.system().label("check_passed_objects"))
-                .with_system(check_far_out_rivals.system().after("check_passed_objects"))
+                .with_system(update_vehicles.system().before("check_passed_objects"))
+                .with_system(animate_pickups.system())
+                .with_system(apply_pickups.system().after("check_passed_objects"))
+                .with_system(apply_crash_penalty.system().after("check_passed_objects"))
+                .with_system(check_far_out_objects.system().after("check_passed_objects"))
                 .with_system(spawn_segment_objects.system().after("check_passed_objects"))
                 .with_system(update_road_object_z.system().after("check_passed_objects")),
             update_road_object_visuals: SystemSet::new()
-                .with_system(update_road_object_visuals.system()),
+                .with_system(update_road_object_visuals.system())
+                .with_system(update_road_scaled_visuals.system()),
         }
     }
 }
@@ -113,18 +355,61 @@ impl Systems {
 fn startup_road_objects(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
+    mut texture_loader: ResMut<TextureLoader>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
     debug_assets: Res<DebugAssets>,
     road_static: Res<RoadStatic>,
     road_dyn: Res<RoadDynamic>,
+    track_config: Res<TrackConfig>,
+    game_rng: Res<GameRng>,
+    difficulty: Res<Difficulty>,
+    difficulty_curve: Res<DifficultyCurve>,
 ) {
-    let tex = asset_server.load("textures/road_object_atlas.png");
+    let tex = texture_loader.load(&asset_server, "textures/road_object_atlas.png");
     let atlas = ROAD_OBJ_SPRITE_DESC.make_atlas(tex);
+    let finish_tex = texture_loader.load(&asset_server, "textures/finish_banner.png");
+    let oil_slick_tex = texture_loader.load(&asset_server, "textures/oil_slick.png");
 
     let assets = RoadObjectAssets {
         sprite_atlas: texture_atlases.add(atlas),
+        finish_line_mat: materials.add(finish_tex.into()),
+        oil_slick_mat: materials.add(oil_slick_tex.into()),
     };
 
+    let mut spawner = Spawner::new(&track_config, &game_rng);
+    let mut pool = RoadObjectPool::default();
+    spawn_initial_objects(
+        &mut commands,
+        &mut spawner,
+        &mut pool,
+        &assets,
+        &debug_assets,
+        &road_static,
+        &road_dyn,
+        &track_config,
+        *difficulty,
+        &difficulty_curve,
+    );
+
+    commands.insert_resource(assets);
+    commands.insert_resource(spawner);
+    commands.insert_resource(pool);
+}
+
+// Fills the road from the racer out to draw distance, as it is at the start of a race
+fn spawn_initial_objects(
+    commands: &mut Commands,
+    spawner: &mut Spawner,
+    pool: &mut RoadObjectPool,
+    assets: &RoadObjectAssets,
+    debug_assets: &DebugAssets,
+    road_static: &RoadStatic,
+    road_dyn: &RoadDynamic,
+    track_config: &TrackConfig,
+    difficulty: Difficulty,
+    difficulty_curve: &DifficultyCurve,
+) {
     let z_map = road_static.z_map();
     let far_z = z_map[z_map.len() - 1];
     let road_point = road_dyn.query_road_point(far_z);
@@ -132,22 +417,91 @@ fn startup_road_objects(
     for seg_idx in 0..=road_point.seg_idx {
         let seg = road_dyn.get_bounded_seg(seg_idx);
         let seg_start_z = SEGMENT_LENGTH * f32::conv(seg_idx);
-        if let Some(spawn_type) = &seg.spawn_object_type {
+        let obstacle_weight = get_obstacle_weight(difficulty, difficulty_curve, seg_idx);
+        if let Some(spawn_type) = roll_fixed_spawn(&seg, obstacle_weight, &mut spawner.table_rng) {
             spawn_objects(
                 spawn_type,
                 seg_start_z,
-                &assets,
-                &&debug_assets,
-                &mut commands,
+                seg.ambient_tint,
+                assets,
+                debug_assets,
+                &mut spawner.rng,
+                pool,
+                commands,
+            );
+        }
+
+        let table_spawn =
+            roll_spawn_table(&seg, track_config, obstacle_weight, &mut spawner.table_rng);
+        if let Some(spawn_type) = table_spawn {
+            spawn_objects(
+                &spawn_type,
+                seg_start_z,
+                seg.ambient_tint,
+                assets,
+                debug_assets,
+                &mut spawner.rng,
+                pool,
+                commands,
+            );
+        }
+
+        if road_dyn.get_finish_seg_idx(track_config) == Some(seg_idx) {
+            spawn_objects(
+                &RoadObjectType::FinishLine,
+                seg_start_z,
+                seg.ambient_tint,
+                assets,
+                debug_assets,
+                &mut spawner.rng,
+                pool,
+                commands,
             );
         }
     }
 
-    commands.insert_resource(assets);
-    commands.insert_resource(Spawner {
-        last_seg_idx: road_point.seg_idx,
-        segs_without_rival: 0,
-    });
+    spawner.last_seg_idx = road_point.seg_idx;
+}
+
+// Objects left on the road are cleared away before it's filled again. The random streams start
+// over too, so a restarted race spawns the same objects as the first
+fn reset_road_objects(
+    mut commands: Commands,
+    mut restarted_events: EventReader<RaceRestarted>,
+    objects: Query<Entity, (With<RoadObject>, Without<Rival>)>,
+    debug_boxes: Query<&RoadObjectDebugBoxes>,
+    mut spawner: ResMut<Spawner>,
+    mut pool: ResMut<RoadObjectPool>,
+    assets: Res<RoadObjectAssets>,
+    debug_assets: Res<DebugAssets>,
+    road_static: Res<RoadStatic>,
+    road_dyn: Res<RoadDynamic>,
+    track_config: Res<TrackConfig>,
+    game_rng: Res<GameRng>,
+    difficulty: Res<Difficulty>,
+    difficulty_curve: Res<DifficultyCurve>,
+) {
+    if restarted_events.iter().next().is_none() {
+        return;
+    }
+
+    for ent in objects.iter() {
+        release_or_despawn(&mut commands, &mut pool, &debug_boxes, ent);
+    }
+
+    *spawner = Spawner::new(&track_config, &game_rng);
+    spawn_initial_objects(
+        &mut commands,
+        &mut spawner,
+        &mut pool,
+        &assets,
+        &debug_assets,
+        &road_static,
+        &road_dyn,
+        &track_config,
+        *difficulty,
+        &difficulty_curve,
+    );
 }
 
 // TODO: Consolidate asset resources?
@@ -156,12 +510,20 @@ fn spawn_segment_objects(
     road_static: Res<RoadStatic>,
     road_dyn: Res<RoadDynamic>,
     mut spawner: ResMut<Spawner>,
+    mut pool: ResMut<RoadObjectPool>,
     obj_assets: Res<RoadObjectAssets>,
     racer_assets: Res<RacerAssets>,
     rival_assets: Res<RivalAssets>,
     debug_assets: Res<DebugAssets>,
+    difficulty_curve: Res<DifficultyCurve>,
+    rival_config: Res<RivalConfig>,
+    track_config: Res<TrackConfig>,
     rival_query: Query<&Rival>,
+    difficulty: Res<Difficulty>,
 ) {
+    // Convert ResMut to a regular mutable reference, so we can borrow the RNG alongside other fields
+    let spawner: &mut Spawner = &mut spawner;
+
     let z_map = road_static.z_map();
     let far_z = z_map[z_map.len() - 1];
     let road_point = road_dyn.query_road_point(far_z);
@@ -169,21 +531,65 @@ fn spawn_segment_objects(
     if road_point.seg_idx != spawner.last_seg_idx {
         let seg_start_z = far_z - road_point.seg_pos;
 
-        if let Some(spawn_type) = &road_point.seg.spawn_object_type {
+        let obstacle_weight =
+            get_obstacle_weight(*difficulty, &difficulty_curve, road_point.seg_idx);
+        let fixed_spawn =
+            roll_fixed_spawn(&road_point.seg, obstacle_weight, &mut spawner.table_rng);
+        if let Some(spawn_type) = fixed_spawn {
             spawn_objects(
                 spawn_type,
                 seg_start_z,
+                road_point.seg.ambient_tint,
+                &obj_assets,
+                &debug_assets,
+                &mut spawner.rng,
+                &mut pool,
+                &mut commands,
+            );
+        }
+
+        let table_spawn = roll_spawn_table(
+            &road_point.seg,
+            &track_config,
+            obstacle_weight,
+            &mut spawner.table_rng,
+        );
+        if let Some(spawn_type) = table_spawn {
+            spawn_objects(
+                &spawn_type,
+                seg_start_z,
+                road_point.seg.ambient_tint,
                 &obj_assets,
                 &debug_assets,
+                &mut spawner.rng,
+                &mut pool,
+                &mut commands,
+            );
+        }
+
+        if road_dyn.get_finish_seg_idx(&track_config) == Some(road_point.seg_idx) {
+            spawn_objects(
+                &RoadObjectType::FinishLine,
+                seg_start_z,
+                road_point.seg.ambient_tint,
+                &obj_assets,
+                &debug_assets,
+                &mut spawner.rng,
+                &mut pool,
                 &mut commands,
             );
         }
 
         let num_rivals = rival_query.iter().count();
         if num_rivals < MAX_SPAWNED_RIVALS {
-            let mut rng = rand::thread_rng();
+            let rng = &mut spawner.rng;
+            let difficulty = difficulty_curve.get_scalars(road_dyn.get_distance_traveled());
 
-            let should_spawn_rival = rng.gen_bool(RIVAL_SPAWN_CHANCE);
+            let spawn_chance = f64::min(
+                RIVAL_SPAWN_CHANCE * f64::from(difficulty.rival_spawn_chance),
+                1.0,
+            );
+            let should_spawn_rival = rng.gen_bool(spawn_chance);
             if should_spawn_rival || spawner.segs_without_rival > 1 {
                 let pavement_width = PAVEMENT_WIDTH as i32;
                 let x_pos = f32::conv(rng.gen_range(-pavement_width..pavement_width));
@@ -201,7 +607,7 @@ fn spawn_segment_objects(
                     &mut commands,
                     x_pos,
                     z_pos,
-                    SPAWNED_RIVAL_SPEED,
+                    rival_config.initial_speed * difficulty.rival_speed,
                     rival_palette,
                     &rival_assets,
                     &racer_assets,
@@ -218,11 +624,62 @@ fn spawn_segment_objects(
     }
 }
 
+// Both the difficulty setting and the difficulty curve weight obstacles. The curve is keyed on
+// where the segment starts, the same as it is for track generation
+fn get_obstacle_weight(
+    difficulty: Difficulty,
+    difficulty_curve: &DifficultyCurve,
+    seg_idx: usize,
+) -> f32 {
+    let seg_start_z = SEGMENT_LENGTH * f32::conv(seg_idx);
+    let scalars = difficulty_curve.get_scalars(seg_start_z);
+    difficulty.settings().obstacle_weight * scalars.obstacle_weight
+}
+
+// A fixed obstacle can't come up more than every time, but when obstacles are weighted down, it's
+// skipped as often as its weight falls short
+fn roll_fixed_spawn<'a>(
+    seg: &'a RoadSegment,
+    obstacle_weight: f32,
+    rng: &mut StdRng,
+) -> Option<&'a RoadObjectType> {
+    let obj_type = seg.spawn_object_type.as_ref()?;
+    if obj_type.is_obstacle()
+        && obstacle_weight < 1.0
+        && !rng.gen_bool(f64::from(obstacle_weight.max(0.0)))
+    {
+        return None;
+    }
+    Some(obj_type)
+}
+
+fn roll_spawn_table(
+    seg: &RoadSegment,
+    track_config: &TrackConfig,
+    obstacle_weight: f32,
+    rng: &mut StdRng,
+) -> Option<RoadObjectType> {
+    let table_idx = seg.spawn_table?;
+    match track_config.spawn_tables.get(table_idx) {
+        Some(table) => table.roll(rng, obstacle_weight).cloned(),
+        None => {
+            warn!(
+                "Segment refers to spawn table {}, which doesn't exist",
+                table_idx
+            );
+            None
+        }
+    }
+}
+
 fn spawn_objects(
     obj_type: &RoadObjectType,
     seg_start_z: f32,
+    ambient_tint: Option<Color>,
     assets: &RoadObjectAssets,
     debug_assets: &DebugAssets,
+    rng: &mut StdRng,
+    pool: &mut RoadObjectPool,
     commands: &mut Commands,
 ) {
     match obj_type {
@@ -248,98 +705,460 @@ fn spawn_objects(
             };
 
             for z_pos in ROAD_SIGN_Z_OFFSETS.iter() {
-                let coll_left = -43.0;
-                let coll_right = 43.0;
-                let debug_box = spawn_collision_debug_box(
+                let road_obj = RoadObject {
+                    x_pos,
+                    z_pos: *z_pos + seg_start_z,
+                    collider1: Some(Collider {
+                        left: -43.0,
+                        right: 43.0,
+                        height: ROAD_SIGN_COLLIDER_HEIGHT,
+                    }),
+                    collider2: None,
+                    collision_action: CollisionAction::CrashPlayer,
+                    ambient_tint,
+                };
+
+                spawn_atlas_object(
                     commands,
+                    pool,
+                    assets,
                     debug_assets,
-                    Vec2::new(0.0, -f32::conv(ROAD_OBJ_SPRITE_DESC.tile_size) * 0.5),
-                    Vec2::new(coll_right - coll_left, 1.0),
+                    road_obj,
+                    selector.clone(),
                 );
+            }
+        }
+        &RoadObjectType::Vehicle { speed } => {
+            let lane_x = VEHICLE_LANES[rng.gen_range(0..VEHICLE_LANES.len())] * PAVEMENT_WIDTH;
+
+            let road_obj = RoadObject {
+                x_pos: lane_x,
+                z_pos: seg_start_z + VEHICLE_Z_OFFSET,
+                collider1: Some(Collider {
+                    left: -VEHICLE_COLLIDER_HALF_WIDTH,
+                    right: VEHICLE_COLLIDER_HALF_WIDTH,
+                    height: VEHICLE_COLLIDER_HEIGHT,
+                }),
+                collider2: None,
+                collision_action: CollisionAction::CrashPlayer,
+                ambient_tint,
+            };
+            let selector = RoadObjectSpriteSelector {
+                sprite_set_idx: VEHICLE_SPRITE_SET_IDX,
+                flip: false,
+            };
 
+            spawn_atlas_object(commands, pool, assets, debug_assets, road_obj, selector).insert(
+                Vehicle {
+                    speed,
+                    lane_x,
+                    weave_time: 0.0,
+                },
+            );
+        }
+        &RoadObjectType::Pickup(kind) => {
+            let x_pos = rng.gen_range(-PICKUP_MAX_X..PICKUP_MAX_X);
+
+            for z_pos in ROAD_SIGN_Z_OFFSETS.iter() {
                 let road_obj = RoadObject {
                     x_pos,
                     z_pos: *z_pos + seg_start_z,
                     collider1: Some(Collider {
-                        left: coll_left,
-                        right: coll_right,
+                        left: -PICKUP_COLLIDER_HALF_WIDTH,
+                        right: PICKUP_COLLIDER_HALF_WIDTH,
+                        height: PICKUP_COLLIDER_HEIGHT,
                     }),
                     collider2: None,
-                    collision_action: CollisionAction::CrashPlayer,
+                    collision_action: CollisionAction::CollectPickup,
+                    ambient_tint,
+                };
+                let selector = RoadObjectSpriteSelector {
+                    sprite_set_idx: kind.first_sprite_set_idx(),
+                    flip: false,
                 };
 
-                commands
-                    .spawn_bundle(SpriteSheetBundle {
-                        texture_atlas: assets.sprite_atlas.clone(),
-                        ..Default::default()
+                spawn_atlas_object(commands, pool, assets, debug_assets, road_obj, selector)
+                    .insert(Pickup {
+                        kind,
+                        anim_frame: 0,
                     })
-                    .insert(road_obj)
-                    .insert(selector.clone())
-                    .insert(LocalVisible::default())
-                    .push_children(&[debug_box]);
+                    .insert(Timer::from_seconds(PICKUP_ANIM_FRAME_SECS, true));
             }
         }
+        &RoadObjectType::Gate => {
+            let x_pos = rng.gen_range(-GATE_MAX_X..GATE_MAX_X);
+            let road_obj = make_gate(x_pos, seg_start_z + (SEGMENT_LENGTH * 0.5), ambient_tint);
+            let selector = RoadObjectSpriteSelector {
+                sprite_set_idx: GATE_SPRITE_SET_IDX,
+                flip: false,
+            };
+
+            spawn_atlas_object(commands, pool, assets, debug_assets, road_obj, selector);
+        }
+        &RoadObjectType::OilSlick => {
+            let x_pos = rng.gen_range(-OIL_SLICK_MAX_X..OIL_SLICK_MAX_X);
+
+            // No height, so only a jump clears it
+            let road_obj = RoadObject {
+                x_pos,
+                z_pos: seg_start_z + (SEGMENT_LENGTH * 0.5),
+                collider1: Some(Collider {
+                    left: -OIL_SLICK_COLLIDER_HALF_WIDTH,
+                    right: OIL_SLICK_COLLIDER_HALF_WIDTH,
+                    height: 0.0,
+                }),
+                collider2: None,
+                collision_action: CollisionAction::SlipPlayer,
+                ambient_tint,
+            };
+
+            commands
+                .spawn_bundle(SpriteBundle {
+                    material: assets.oil_slick_mat.clone(),
+                    ..Default::default()
+                })
+                .insert(road_obj)
+                .insert(RoadScaledSprite {
+                    height: OIL_SLICK_HEIGHT,
+                })
+                .insert(LocalVisible::default());
+        }
+        &RoadObjectType::FinishLine => {
+            // No colliders, so the player drives straight through it
+            let road_obj = RoadObject {
+                x_pos: 0.0,
+                z_pos: seg_start_z,
+                collider1: None,
+                collider2: None,
+                collision_action: CollisionAction::BouncePlayer,
+                ambient_tint,
+            };
+
+            commands
+                .spawn_bundle(SpriteBundle {
+                    material: assets.finish_line_mat.clone(),
+                    ..Default::default()
+                })
+                .insert(road_obj)
+                .insert(RoadScaledSprite {
+                    height: FINISH_BANNER_HEIGHT,
+                })
+                .insert(LocalVisible::default());
+        }
+    }
+}
+
+// Reuses a pooled object if one is free, otherwise spawns a new one. The caller inserts whatever
+// else the object type needs
+fn spawn_atlas_object<'a, 'b>(
+    commands: &'b mut Commands<'a>,
+    pool: &mut RoadObjectPool,
+    assets: &RoadObjectAssets,
+    debug_assets: &DebugAssets,
+    road_obj: RoadObject,
+    selector: RoadObjectSpriteSelector,
+) -> EntityCommands<'a, 'b> {
+    let action = Some(road_obj.collision_action);
+    let colliders = [road_obj.collider1.as_ref(), road_obj.collider2.as_ref()];
+
+    let ent = match pool.free.pop() {
+        Some((ent, debug_boxes)) => {
+            for (debug_box, coll) in debug_boxes.iter().zip(colliders.iter()) {
+                let (offset, size) = get_debug_box_layout(*coll);
+                set_collision_debug_box(commands, debug_assets, *debug_box, action, offset, size);
+            }
+            ent
+        }
+        None => {
+            let mut spawn_debug_box = |coll: Option<&Collider>| {
+                let (offset, size) = get_debug_box_layout(coll);
+                spawn_collision_debug_box(commands, debug_assets, action, offset, size)
+            };
+            let debug_boxes = [spawn_debug_box(colliders[0]), spawn_debug_box(colliders[1])];
+
+            commands
+                .spawn_bundle(SpriteSheetBundle {
+                    texture_atlas: assets.sprite_atlas.clone(),
+                    ..Default::default()
+                })
+                .insert(RoadObjectDebugBoxes(debug_boxes))
+                .insert(LocalVisible::default())
+                .push_children(&debug_boxes)
+                .id()
+        }
+    };
+
+    let mut ent_commands = commands.entity(ent);
+    ent_commands.insert(road_obj).insert(selector);
+    ent_commands
+}
+
+// Debug boxes sit along the bottom edge of the sprite, under their collider
+fn get_debug_box_layout(coll: Option<&Collider>) -> (Vec2, Vec2) {
+    let bottom_y = -f32::conv(ROAD_OBJ_SPRITE_DESC.tile_size) * 0.5;
+    match coll {
+        Some(coll) => (
+            Vec2::new((coll.left + coll.right) * 0.5, bottom_y),
+            Vec2::new(coll.right - coll.left, 1.0),
+        ),
+        None => (Vec2::new(0.0, bottom_y), Vec2::ZERO),
+    }
+}
+
+// Pooled objects go back to the pool, anything else (such as rivals) is despawned
+fn release_or_despawn(
+    commands: &mut Commands,
+    pool: &mut RoadObjectPool,
+    debug_boxes: &Query<&RoadObjectDebugBoxes>,
+    ent: Entity,
+) {
+    match debug_boxes.get(ent) {
+        Ok(debug_boxes) => pool.release(commands, ent, debug_boxes),
+        Err(_) => commands.entity(ent).despawn_recursive(),
+    }
+}
+
+fn animate_pickups(
+    time_scale: Res<TimeScale>,
+    mut query: Query<(&mut Pickup, &mut Timer, &mut RoadObjectSpriteSelector)>,
+) {
+    let step = scaled_step(&time_scale);
+
+    for (mut pickup, mut timer, mut selector) in query.iter_mut() {
+        timer.tick(Duration::from_secs_f32(step));
+        if timer.just_finished() {
+            pickup.anim_frame = (pickup.anim_frame + 1) % PICKUP_ANIM_FRAMES;
+            selector.sprite_set_idx = pickup.kind.first_sprite_set_idx() + pickup.anim_frame;
+        }
+    }
+}
+
+fn apply_pickups(
+    mut pickup_events: EventReader<PickupCollected>,
+    mut game: ResMut<JoyrideGame>,
+    mut score: ResMut<Score>,
+) {
+    for event in pickup_events.iter() {
+        match event.kind {
+            PickupKind::Points => score.points += PICKUP_POINTS,
+            PickupKind::TimeBonus => {
+                let timer = &mut game.remaining_time;
+                let extended = timer.duration() + Duration::from_secs_f32(PICKUP_TIME_BONUS_SECS);
+                timer.set_duration(extended);
+            }
+        }
+    }
+}
+
+// A penalty bigger than the time left runs the timer out, ending the race
+fn apply_crash_penalty(
+    mut crashed_events: EventReader<PlayerCrashed>,
+    mut game: ResMut<JoyrideGame>,
+) {
+    for _ in crashed_events.iter() {
+        let timer = &mut game.remaining_time;
+        let shortened = timer
+            .duration()
+            .checked_sub(Duration::from_secs_f32(CRASH_TIME_PENALTY_SECS))
+            .unwrap_or_default();
+        timer.set_duration(shortened);
+    }
+}
+
+// Vehicles drive forward on top of the road moving past the player, so the player only gains
+// on them at the difference in speed
+fn update_vehicles(time_scale: Res<TimeScale>, mut query: Query<(&mut Vehicle, &mut RoadObject)>) {
+    let step = scaled_step(&time_scale);
+
+    for (mut vehicle, mut obj) in query.iter_mut() {
+        vehicle.weave_time += step;
+
+        obj.z_pos += vehicle.speed * step;
+        obj.x_pos = vehicle.lane_x
+            + f32::sin(vehicle.weave_time * VEHICLE_WEAVE_FREQUENCY) * VEHICLE_WEAVE_AMPLITUDE;
     }
 }
 
 fn check_passed_objects(
+    time_scale: Res<TimeScale>,
     mut commands: Commands,
     mut obj_query: Query<(&mut RoadObject, Entity)>,
     road_static: Res<RoadStatic>,
     road_dyn: Res<RoadDynamic>,
-    mut player: ResMut<Player>,
-    racer_query: Query<&Racer>,
+    mut players: Query<(&mut Player, &Racer)>,
+    pickups: Query<&Pickup>,
+    mut pickup_events: EventWriter<PickupCollected>,
+    mut crashed_events: EventWriter<PlayerCrashed>,
+    mut slid_events: EventWriter<PlayerSlid>,
+    mut score: ResMut<Score>,
+    mut pool: ResMut<RoadObjectPool>,
+    debug_boxes: Query<&RoadObjectDebugBoxes>,
 ) {
+    let step = scaled_step(&time_scale);
+
+    // Everything released last frame has been stripped by now
+    pool.reclaim();
+
     let screen_bottom_z = road_static.z_map()[0];
     let screen_bottom_scale = road_static.scale_map()[0];
 
-    let player_speed = racer_query
-        .get(player.get_racer_ent())
-        .map_or(0.0, |r| r.speed);
+    // TODO: Players share the one road for now, so objects move against their combined speed
+    let player_speed: f32 = players.iter_mut().map(|(_, racer)| racer.speed).sum();
     let player_x = -road_dyn.x_offset;
 
+    let mut passed_objs = Vec::new();
     for (mut obj, ent) in obj_query.iter_mut() {
-        obj.z_pos -= player_speed * TIME_STEP;
-        if obj.z_pos >= screen_bottom_z {
-            continue;
+        obj.z_pos -= player_speed * step;
+        if obj.z_pos < screen_bottom_z {
+            passed_objs.push(ent);
+        }
+    }
+
+    for (mut player, racer) in players.iter_mut() {
+        let player_y = player.get_airborne_y();
+
+        let mut collisions = Vec::new();
+        for ent in passed_objs.iter() {
+            let (obj, _) = obj_query.get_mut(*ent).expect(ROAD_OBJ_NOT_FOUND);
+            if !object_colliding_with_player(&obj, player_x, player_y, screen_bottom_scale) {
+                // Passed objects are despawned below, so each can only be a near miss once
+                if is_near_miss(&obj, player_x, screen_bottom_scale) && !player.is_crashing() {
+                    score.points += NEAR_MISS_POINTS;
+                }
+                continue;
+            }
+
+            // Just after recovering from a crash, whatever caused it is passed through
+            if obj.collision_action == CollisionAction::CrashPlayer && player.is_invulnerable() {
+                continue;
+            }
+
+            // Every pickup touched is collected, regardless of what else was hit
+            if obj.collision_action == CollisionAction::CollectPickup {
+                let pickup = pickups.get(*ent).expect(ROAD_OBJ_NOT_FOUND);
+                pickup_events.send(PickupCollected { kind: pickup.kind });
+                continue;
+            }
+
+            collisions.push((obj.collision_action, obj.x_pos));
         }
+        let worst_collision = get_worst_collision(&collisions, player_x);
 
-        if object_colliding_with_player(&obj, player_x, screen_bottom_scale) {
-            match obj.collision_action {
-                CollisionAction::CrashPlayer => {
-                    player.crash();
+        // Slides and bounces both push the player away from the object
+        let away_direction = |x_pos: f32| {
+            if x_pos > player_x {
+                PlayerSlideDirection::Left
+            } else {
+                PlayerSlideDirection::Right
+            }
+        };
+
+        match worst_collision {
+            Some((CollisionAction::CrashPlayer, _)) => {
+                if player.damage() {
+                    crashed_events.send(PlayerCrashed);
                 }
-                CollisionAction::SlidePlayer => {
-                    let direction = if obj.x_pos > player_x {
-                        PlayerSlideDirection::Left
-                    } else {
-                        PlayerSlideDirection::Right
-                    };
-                    player.slide(direction);
+            }
+            Some((CollisionAction::SlidePlayer, x_pos)) => {
+                let direction = away_direction(x_pos);
+                if player.slide(direction) {
+                    slid_events.send(PlayerSlid { direction });
                 }
             }
+            Some((CollisionAction::SlipPlayer, x_pos)) => {
+                // Going dead straight, the player slips off whichever side they're on
+                let direction = if racer.turn_rate < 0.0 {
+                    PlayerSlideDirection::Left
+                } else if racer.turn_rate > 0.0 {
+                    PlayerSlideDirection::Right
+                } else {
+                    away_direction(x_pos)
+                };
+                if player.slide(direction) {
+                    slid_events.send(PlayerSlid { direction });
+                }
+            }
+            Some((CollisionAction::BouncePlayer, x_pos)) => {
+                player.bounce(away_direction(x_pos));
+            }
+            Some((CollisionAction::CollectPickup, _)) | None => {}
         }
+    }
+
+    for ent in passed_objs {
+        release_or_despawn(&mut commands, &mut pool, &debug_boxes, ent);
+    }
+}
 
-        commands.entity(ent).despawn_recursive();
+// Only the most severe collision in a frame applies, so that the outcome doesn't depend on the
+// order objects are iterated in. Ties go to the object closest to the player
+fn get_worst_collision(
+    collisions: &[(CollisionAction, f32)],
+    player_x: f32,
+) -> Option<(CollisionAction, f32)> {
+    let mut worst_collision: Option<(CollisionAction, f32)> = None;
+    for &(action, x_pos) in collisions {
+        let is_worse = match worst_collision {
+            None => true,
+            Some((worst_action, worst_x_pos)) => {
+                action > worst_action
+                    || (action == worst_action
+                        && (x_pos - player_x).abs() < (worst_x_pos - player_x).abs())
+            }
+        };
+        if is_worse {
+            worst_collision = Some((action, x_pos));
+        }
     }
+    worst_collision
 }
 
-fn check_far_out_rivals(mut commands: Commands, obj_query: Query<(&RoadObject, Entity, &Rival)>) {
-    for (obj, ent, _) in obj_query.iter() {
-        if obj.z_pos > (SEGMENT_LENGTH * RIVAL_DESPAWN_SCALAR) {
-            commands.entity(ent).despawn_recursive();
+fn check_far_out_objects(
+    mut commands: Commands,
+    obj_query: Query<(&RoadObject, Entity), Or<(With<Rival>, With<Vehicle>)>>,
+    mut pool: ResMut<RoadObjectPool>,
+    debug_boxes: Query<&RoadObjectDebugBoxes>,
+) {
+    for (obj, ent) in obj_query.iter() {
+        if obj.z_pos > (SEGMENT_LENGTH * FAR_OUT_DESPAWN_SCALAR) {
+            release_or_despawn(&mut commands, &mut pool, &debug_boxes, ent);
         }
     }
 }
 
-fn object_colliding_with_player(obj: &RoadObject, player_x: f32, scale: f32) -> bool {
+// One collider per post, either side of the gap
+fn make_gate(x_pos: f32, z_pos: f32, ambient_tint: Option<Color>) -> RoadObject {
+    RoadObject {
+        x_pos,
+        z_pos,
+        collider1: Some(Collider {
+            left: -(GATE_HALF_GAP + GATE_POST_WIDTH),
+            right: -GATE_HALF_GAP,
+            height: GATE_COLLIDER_HEIGHT,
+        }),
+        collider2: Some(Collider {
+            left: GATE_HALF_GAP,
+            right: GATE_HALF_GAP + GATE_POST_WIDTH,
+            height: GATE_COLLIDER_HEIGHT,
+        }),
+        collision_action: CollisionAction::CrashPlayer,
+        ambient_tint,
+    }
+}
+
+fn object_colliding_with_player(
+    obj: &RoadObject,
+    player_x: f32,
+    player_y: f32,
+    scale: f32,
+) -> bool {
     if let Some(coll) = &obj.collider1 {
-        if collider_colliding_with_player(coll, obj.x_pos * scale, player_x) {
+        if collider_colliding_with_player(coll, obj.x_pos * scale, player_x, player_y) {
             return true;
         }
     }
     if let Some(coll) = &obj.collider2 {
-        if collider_colliding_with_player(coll, obj.x_pos * scale, player_x) {
+        if collider_colliding_with_player(coll, obj.x_pos * scale, player_x, player_y) {
             return true;
         }
     }
@@ -347,13 +1166,46 @@ fn object_colliding_with_player(obj: &RoadObject, player_x: f32, scale: f32) ->
     return false;
 }
 
-fn collider_colliding_with_player(collider: &Collider, x_pos: f32, player_x: f32) -> bool {
+// Only obstacles count, and jumping clean over one isn't a near miss
+fn is_near_miss(obj: &RoadObject, player_x: f32, scale: f32) -> bool {
+    let is_obstacle = matches!(
+        obj.collision_action,
+        CollisionAction::CrashPlayer | CollisionAction::SlidePlayer
+    );
+    if !is_obstacle {
+        return false;
+    }
+
+    let player_left = player_x - (PLAYER_COLLISION_WIDTH * 0.5);
+    let player_right = player_x + (PLAYER_COLLISION_WIDTH * 0.5);
+    let x_pos = obj.x_pos * scale;
+
+    let near_collider = |coll: &Collider| {
+        let gap = f32::max(
+            (coll.left + x_pos) - player_right,
+            player_left - (coll.right + x_pos),
+        );
+        gap > 0.0 && gap <= NEAR_MISS_MAX_GAP
+    };
+    obj.collider1.as_ref().map_or(false, near_collider)
+        || obj.collider2.as_ref().map_or(false, near_collider)
+}
+
+fn collider_colliding_with_player(
+    collider: &Collider,
+    x_pos: f32,
+    player_x: f32,
+    player_y: f32,
+) -> bool {
     let coll_left = collider.left + x_pos;
     let coll_right = collider.right + x_pos;
     let player_left = player_x - (PLAYER_COLLISION_WIDTH * 0.5);
     let player_right = player_x + (PLAYER_COLLISION_WIDTH * 0.5);
 
-    coll_left <= player_right && player_left <= coll_right
+    // A player on the ground always hits, even colliders with no height
+    let is_below_top = player_y <= 0.0 || player_y < collider.height;
+
+    coll_left <= player_right && player_left <= coll_right && is_below_top
 }
 
 fn update_road_object_z(mut query: Query<(&mut Transform, With<RoadObject>)>) {
@@ -379,20 +1231,29 @@ fn update_road_object_visuals(
         let mut is_visible = false;
 
         if let Some(draw_params) = draw_params {
-            xform.translation.x = draw_params.draw_pos.x;
-            xform.translation.y =
-                draw_params.draw_pos.y + (f32::conv(ROAD_OBJ_SPRITE_DESC.tile_size) * 0.5);
+            let lod_level =
+                ROAD_OBJ_SPRITE_DESC.get_lod_level(&LOD_SCALE_MAPPING, draw_params.scale);
+            let draw_scale = ROAD_OBJ_SPRITE_DESC.get_lod_draw_scale(
+                &LOD_SCALE_MAPPING,
+                draw_params.scale,
+                lod_level,
+            );
 
-            let lod_level: u32 = LOD_SCALE_MAPPING
-                .binary_search_by(|x| draw_params.scale.partial_cmp(&x).unwrap())
-                .unwrap_or_else(|x| x)
-                .cast();
+            xform.translation.x = draw_params.draw_pos.x;
+            xform.translation.y = draw_params.draw_pos.y
+                + (f32::conv(ROAD_OBJ_SPRITE_DESC.tile_size) * 0.5 * draw_scale);
+            xform.scale = Vec3::new(draw_scale, draw_scale, 1.0);
 
             let sprite_x: u32 = selector.sprite_set_idx;
             let sprite_y: u32 = lod_level;
             sprite.index = ROAD_OBJ_SPRITE_DESC.get_sprite_index(sprite_x, sprite_y);
             sprite.flip_x = selector.flip;
 
+            let color = object.ambient_tint.unwrap_or(Color::WHITE);
+            if sprite.color != color {
+                sprite.color = color;
+            }
+
             is_visible = true;
         }
 
@@ -401,3 +1262,380 @@ fn update_road_object_visuals(
         }
     });
 }
+
+fn update_road_scaled_visuals(
+    mut query: Query<(
+        &RoadObject,
+        &RoadScaledSprite,
+        &mut LocalVisible,
+        &mut Transform,
+    )>,
+    road_static: Res<RoadStatic>,
+    road_dyn: Res<RoadDynamic>,
+) {
+    for (object, scaled_sprite, mut visible, mut xform) in query.iter_mut() {
+        let draw_params =
+            get_draw_params_on_road(&road_static, &road_dyn, object.x_pos, object.z_pos);
+        let mut is_visible = false;
+
+        if let Some(draw_params) = draw_params {
+            xform.translation.x = draw_params.draw_pos.x;
+            xform.translation.y =
+                draw_params.draw_pos.y + (scaled_sprite.height * 0.5 * draw_params.scale);
+            xform.scale = Vec3::new(draw_params.scale, draw_params.scale, 1.0);
+
+            is_visible = true;
+        }
+
+        if visible.is_visible != is_visible {
+            visible.is_visible = is_visible;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::app::Events;
+
+    use super::*;
+    use crate::{
+        headless::{build_headless_app, clear_rivals, start_race_on_straight, step},
+        replay::RecordedInput,
+    };
+
+    #[test]
+    fn worst_collision_wins_regardless_of_order() {
+        let slide = (CollisionAction::SlidePlayer, 5.0);
+        let crash = (CollisionAction::CrashPlayer, 20.0);
+
+        assert_eq!(get_worst_collision(&[slide, crash], 0.0), Some(crash));
+        assert_eq!(get_worst_collision(&[crash, slide], 0.0), Some(crash));
+        assert_eq!(get_worst_collision(&[], 0.0), None);
+    }
+
+    #[test]
+    fn equal_collisions_go_to_the_closest() {
+        let near = (CollisionAction::SlidePlayer, -3.0);
+        let far = (CollisionAction::SlidePlayer, 8.0);
+
+        assert_eq!(get_worst_collision(&[far, near], 0.0), Some(near));
+        assert_eq!(get_worst_collision(&[near, far], 0.0), Some(near));
+    }
+
+    // Puts a pickup of each kind right under the stopped player, then checks collecting them
+    // pays out both
+    #[test]
+    fn collecting_pickups_awards_points_and_time() {
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+        clear_rivals(&mut app);
+
+        let points_before = app.world.get_resource::<Score>().unwrap().points;
+        let duration_before = app
+            .world
+            .get_resource::<JoyrideGame>()
+            .unwrap()
+            .remaining_time
+            .duration();
+
+        for &kind in &[PickupKind::Points, PickupKind::TimeBonus] {
+            app.world
+                .spawn()
+                .insert(RoadObject {
+                    x_pos: 0.0,
+                    z_pos: -1.0,
+                    collider1: Some(Collider {
+                        left: -PICKUP_COLLIDER_HALF_WIDTH,
+                        right: PICKUP_COLLIDER_HALF_WIDTH,
+                        height: PICKUP_COLLIDER_HEIGHT,
+                    }),
+                    collider2: None,
+                    collision_action: CollisionAction::CollectPickup,
+                    ambient_tint: None,
+                })
+                .insert(Pickup {
+                    kind,
+                    anim_frame: 0,
+                });
+        }
+
+        // One step to collect them, and one for the events to be applied
+        step(&mut app, RecordedInput::default());
+        step(&mut app, RecordedInput::default());
+
+        let points = app.world.get_resource::<Score>().unwrap().points;
+        assert!(
+            points >= points_before + PICKUP_POINTS,
+            "Score only went from {} to {}",
+            points_before,
+            points
+        );
+
+        let duration = app
+            .world
+            .get_resource::<JoyrideGame>()
+            .unwrap()
+            .remaining_time
+            .duration();
+        assert_eq!(
+            duration,
+            duration_before + Duration::from_secs_f32(PICKUP_TIME_BONUS_SECS)
+        );
+    }
+
+    // The types a segment rolls from its spawn table, over and over, for a track with its own
+    // spawn seed
+    fn rolled_types(game_seed: u64) -> Vec<String> {
+        let track_config = TrackConfig {
+            spawn_tables: vec![SpawnTable {
+                entries: vec![
+                    (1.0, None),
+                    (1.0, Some(RoadObjectType::Gate)),
+                    (1.0, Some(RoadObjectType::OilSlick)),
+                    (1.0, Some(RoadObjectType::Pickup(PickupKind::Points))),
+                ],
+            }],
+            spawn_seed: Some(1234),
+            ..Default::default()
+        };
+        let seg = RoadSegment {
+            curve: 0.0,
+            hill: 0.0,
+            spawn_object_type: None,
+            ambient_tint: None,
+            audio_cue: None,
+            checkpoint: false,
+            spawn_table: Some(0),
+        };
+
+        let mut spawner = Spawner::new(&track_config, &GameRng::new(game_seed));
+        (0..20)
+            .map(|_| {
+                let rolled = roll_spawn_table(
+                    &seg,
+                    &track_config,
+                    Difficulty::Normal.settings().obstacle_weight,
+                    &mut spawner.table_rng,
+                );
+                format!("{:?}", rolled)
+            })
+            .collect()
+    }
+
+    // How many times out of a hundred a segment's fixed gate spawns
+    fn fixed_gate_spawns(difficulty: Difficulty) -> usize {
+        let seg = RoadSegment {
+            curve: 0.0,
+            hill: 0.0,
+            spawn_object_type: Some(RoadObjectType::Gate),
+            ambient_tint: None,
+            audio_cue: None,
+            checkpoint: false,
+            spawn_table: None,
+        };
+
+        let mut rng = GameRng::new(1).make_stream(RngStream::SpawnTables);
+        let obstacle_weight = difficulty.settings().obstacle_weight;
+        (0..100)
+            .filter(|_| roll_fixed_spawn(&seg, obstacle_weight, &mut rng).is_some())
+            .count()
+    }
+
+    #[test]
+    fn fixed_obstacles_are_skipped_when_weighted_down() {
+        let easy_spawns = fixed_gate_spawns(Difficulty::Easy);
+        assert!(
+            easy_spawns > 0 && easy_spawns < 100,
+            "{} of 100",
+            easy_spawns
+        );
+        assert_eq!(fixed_gate_spawns(Difficulty::Normal), 100);
+        assert_eq!(fixed_gate_spawns(Difficulty::Hard), 100);
+    }
+
+    #[test]
+    fn spawn_seed_rolls_the_same_whatever_the_game_seed() {
+        let rolled = rolled_types(1);
+        assert_eq!(rolled, rolled_types(2));
+
+        // Twenty rolls of a four way table landing all the same would mean it isn't rolling
+        assert!(rolled.iter().any(|obj_type| *obj_type != rolled[0]));
+    }
+
+    #[test]
+    fn gate_gap_can_be_threaded_but_posts_hit() {
+        let gate_x = 20.0;
+        let gate = make_gate(gate_x, 0.0, None);
+        let post_x = GATE_HALF_GAP + (GATE_POST_WIDTH * 0.5);
+
+        assert!(!object_colliding_with_player(&gate, gate_x, 0.0, 1.0));
+        assert!(object_colliding_with_player(
+            &gate,
+            gate_x - post_x,
+            0.0,
+            1.0
+        ));
+        assert!(object_colliding_with_player(
+            &gate,
+            gate_x + post_x,
+            0.0,
+            1.0
+        ));
+    }
+
+    // Steers left onto an oil slick, which should slide the player the way they were turning
+    #[test]
+    fn oil_slick_slides_the_way_the_player_turns() {
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+
+        let steer_left = RecordedInput {
+            accel: true,
+            left: true,
+            ..Default::default()
+        };
+        for _ in 0..10 {
+            clear_rivals(&mut app);
+            step(&mut app, steer_left);
+        }
+
+        app.world.spawn().insert(RoadObject {
+            x_pos: 0.0,
+            z_pos: -1.0,
+            collider1: Some(Collider {
+                left: -OIL_SLICK_COLLIDER_HALF_WIDTH,
+                right: OIL_SLICK_COLLIDER_HALF_WIDTH,
+                height: 0.0,
+            }),
+            collider2: None,
+            collision_action: CollisionAction::SlipPlayer,
+            ambient_tint: None,
+        });
+        clear_rivals(&mut app);
+        step(&mut app, steer_left);
+
+        let slid_events = app.world.get_resource::<Events<PlayerSlid>>().unwrap();
+        let slides: Vec<PlayerSlideDirection> = slid_events
+            .get_reader()
+            .iter(slid_events)
+            .map(|event| event.direction)
+            .collect();
+        assert!(slides == vec![PlayerSlideDirection::Left]);
+
+        let crashed_events = app.world.get_resource::<Events<PlayerCrashed>>().unwrap();
+        assert_eq!(crashed_events.get_reader().iter(crashed_events).count(), 0);
+    }
+
+    // The score after a few steps, with an obstacle passing just clear of the player's right
+    // side if asked for
+    fn score_passing_obstacle(near_miss: bool) -> u32 {
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+        clear_rivals(&mut app);
+
+        if near_miss {
+            // Half the near miss gap clear of the player, at the screen bottom's scale
+            let scale = app.world.get_resource::<RoadStatic>().unwrap().scale_map()[0];
+            let gap_x = (PLAYER_COLLISION_WIDTH + NEAR_MISS_MAX_GAP) * 0.5;
+            app.world.spawn().insert(RoadObject {
+                x_pos: gap_x / scale,
+                z_pos: -1.0,
+                collider1: Some(Collider {
+                    left: 0.0,
+                    right: 10.0,
+                    height: 50.0,
+                }),
+                collider2: None,
+                collision_action: CollisionAction::CrashPlayer,
+                ambient_tint: None,
+            });
+        }
+
+        for _ in 0..5 {
+            clear_rivals(&mut app);
+            step(&mut app, RecordedInput::default());
+        }
+        app.world.get_resource::<Score>().unwrap().points
+    }
+
+    // The player creeps forward even without accel, so the score is compared against the same
+    // drive without the obstacle
+    #[test]
+    fn near_miss_is_scored_once() {
+        assert_eq!(
+            score_passing_obstacle(true),
+            score_passing_obstacle(false) + NEAR_MISS_POINTS
+        );
+    }
+
+    fn count_entities(app: &mut App) -> usize {
+        app.world.query::<Entity>().iter(&app.world).count()
+    }
+
+    // Lays a sign on every segment and drives down a long stretch of them. Passed signs go back
+    // to the pool for the ones coming into range, so once the first few segments have filled it,
+    // the number of entities shouldn't grow any further
+    #[test]
+    fn pooling_keeps_the_entity_count_flat() {
+        const WARM_UP_TICKS: u32 = 1000;
+        const RUN_TICKS: u32 = 2000;
+        const MIN_SEGS_DRIVEN: f32 = 5.0;
+
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+        let sign_seg = RoadSegment {
+            curve: 0.0,
+            hill: 0.0,
+            spawn_object_type: Some(RoadObjectType::RoadSigns(
+                RoadSignType::Oxman,
+                RoadSide::Left,
+            )),
+            ambient_tint: None,
+            audio_cue: None,
+            checkpoint: false,
+            spawn_table: None,
+        };
+        app.world
+            .get_resource_mut::<RoadDynamic>()
+            .unwrap()
+            .replace_segs(vec![sign_seg; 64]);
+
+        let accel = RecordedInput {
+            accel: true,
+            ..Default::default()
+        };
+        let mut warm_up_count = 0;
+        for _ in 0..WARM_UP_TICKS {
+            step(&mut app, accel);
+            clear_rivals(&mut app);
+            warm_up_count = usize::max(warm_up_count, count_entities(&mut app));
+        }
+
+        let get_distance = |app: &App| {
+            app.world
+                .get_resource::<RoadDynamic>()
+                .unwrap()
+                .get_distance_traveled()
+        };
+        let start_distance = get_distance(&app);
+        for tick in 0..RUN_TICKS {
+            step(&mut app, accel);
+            clear_rivals(&mut app);
+            let count = count_entities(&mut app);
+            assert!(
+                count <= warm_up_count,
+                "Entity count grew to {} from {} on tick {}",
+                count,
+                warm_up_count,
+                tick
+            );
+        }
+
+        let segs_driven = (get_distance(&app) - start_distance) / SEGMENT_LENGTH;
+        assert!(
+            segs_driven >= MIN_SEGS_DRIVEN,
+            "Only drove through {} segments",
+            segs_driven
+        );
+    }
+}
@@ -0,0 +1,376 @@
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::shape,
+        pipeline::{PipelineDescriptor, RenderPipeline},
+        render_graph::{base, AssetRenderResourcesNode, RenderGraph},
+        renderer::RenderResources,
+        shader::{ShaderStage, ShaderStages},
+        texture::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+use easy_cast::*;
+
+use crate::{
+    joyride::FIELD_WIDTH,
+    road::{
+        LineSkidSpans, RoadLineParams, RoadRenderMode, MAX_ROAD_DRAW_HEIGHT,
+        MAX_SKID_SPANS_PER_LINE, ROAD_SPRITE_Z,
+    },
+};
+
+pub struct Systems {
+    pub startup_road_shader: SystemSet,
+}
+
+impl Systems {
+    pub fn new() -> Self {
+        Self {
+            startup_road_shader: SystemSet::new().with_system(startup_road_shader.system()),
+        }
+    }
+}
+
+// Each line is described by one texel per float parameter, followed by one texel of flags, then
+// two texels per skid span: the left and right pixels packed as 16 bits each, then the opacity.
+// Unused spans are left at zero opacity
+const PARAM_TEXELS_PER_LINE: usize = 7;
+const TEXELS_PER_SKID_SPAN: usize = 2;
+const TEXELS_PER_LINE: usize =
+    PARAM_TEXELS_PER_LINE + TEXELS_PER_SKID_SPAN * MAX_SKID_SPANS_PER_LINE;
+const BYTES_PER_TEXEL: usize = 4;
+const BYTES_PER_LINE: usize = TEXELS_PER_LINE * BYTES_PER_TEXEL;
+
+const LINE_FLAG_NO_DRAW: u32 = 1;
+const LINE_FLAG_SHIFT_COLOR: u32 = 2;
+const LINE_FLAG_SEG_BOUNDARY: u32 = 4;
+
+const ROAD_MATERIAL_NODE: &str = "road_material";
+
+#[derive(RenderResources, TypeUuid)]
+#[uuid = "5f0e7c4a-2b4d-4c61-9a3e-8d2f1b6c7e90"]
+pub struct RoadMaterial {
+    pub line_params: Handle<Texture>,
+    pub pavement: Color,
+    pub pavement_shift: Color,
+    pub rumble_strip: Color,
+    pub rumble_strip_shift: Color,
+    pub offroad: Color,
+    pub offroad_shift: Color,
+    pub center_line: Color,
+    pub seg_boundary: Color,
+    pub skid: Color,
+}
+
+pub struct RoadShader {
+    // Written in turn like the CPU render textures, so the one being displayed is never mid-update
+    pub line_params_texs: [Handle<Texture>; 2],
+    pub material: Handle<RoadMaterial>,
+}
+
+const VERTEX_SHADER: &str = r#"
+#version 450
+
+layout(location = 0) in vec3 Vertex_Position;
+layout(location = 2) in vec2 Vertex_Uv;
+layout(location = 0) out vec2 v_Uv;
+
+layout(set = 0, binding = 0) uniform CameraViewProj {
+    mat4 ViewProj;
+};
+layout(set = 1, binding = 0) uniform Transform {
+    mat4 Model;
+};
+
+void main() {
+    v_Uv = Vertex_Uv;
+    gl_Position = ViewProj * Model * vec4(Vertex_Position, 1.0);
+}
+"#;
+
+// Mirrors the pixel loop of the CPU path exactly. The line parameters are stored as raw f32 bits,
+// so that the distance comparisons here round the same way they do on the CPU
+const FRAGMENT_SHADER_BODY: &str = r#"
+layout(location = 0) in vec2 v_Uv;
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 2, binding = 0) uniform texture2D RoadMaterial_line_params;
+layout(set = 2, binding = 1) uniform sampler RoadMaterial_line_params_sampler;
+layout(set = 2, binding = 2) uniform RoadMaterial_pavement { vec4 Pavement; };
+layout(set = 2, binding = 3) uniform RoadMaterial_pavement_shift { vec4 PavementShift; };
+layout(set = 2, binding = 4) uniform RoadMaterial_rumble_strip { vec4 RumbleStrip; };
+layout(set = 2, binding = 5) uniform RoadMaterial_rumble_strip_shift { vec4 RumbleStripShift; };
+layout(set = 2, binding = 6) uniform RoadMaterial_offroad { vec4 Offroad; };
+layout(set = 2, binding = 7) uniform RoadMaterial_offroad_shift { vec4 OffroadShift; };
+layout(set = 2, binding = 8) uniform RoadMaterial_center_line { vec4 CenterLine; };
+layout(set = 2, binding = 9) uniform RoadMaterial_seg_boundary { vec4 SegBoundary; };
+layout(set = 2, binding = 10) uniform RoadMaterial_skid { vec4 Skid; };
+
+uint fetch_bits(int texel, int line) {
+    vec4 unorm = texelFetch(
+        sampler2D(RoadMaterial_line_params, RoadMaterial_line_params_sampler),
+        ivec2(texel, line),
+        0);
+    uvec4 bytes = uvec4(round(unorm * 255.0));
+    return bytes.r | (bytes.g << 8) | (bytes.b << 16) | (bytes.a << 24);
+}
+
+float fetch_param(int texel, int line) {
+    return uintBitsToFloat(fetch_bits(texel, line));
+}
+
+void main() {
+    int line = int(floor(v_Uv.y * ROAD_HEIGHT));
+    uint flags = fetch_bits(6, line);
+    if ((flags & LINE_FLAG_NO_DRAW) != 0u) {
+        o_Target = vec4(0.0);
+        return;
+    }
+
+    float road_center = fetch_param(0, line);
+    float road_width = fetch_param(1, line);
+    float center_line_width = fetch_param(2, line);
+    float rumble_width = fetch_param(3, line);
+    float bank = fetch_param(4, line);
+    float brightness = fetch_param(5, line);
+    bool shift_color = (flags & LINE_FLAG_SHIFT_COLOR) != 0u;
+
+    float x = floor(v_Uv.x * FIELD_WIDTH);
+    float x_from_center = x - road_center;
+
+    // GLSL's sign is 0 at 0 where Rust's signum is 1, but either way the distance there is 0
+    float distance_from_center = abs(x_from_center * (1.0 + bank * sign(x_from_center)));
+
+    // Left undarkened, like on the CPU
+    if ((flags & LINE_FLAG_SEG_BOUNDARY) != 0u) {
+        o_Target = SegBoundary;
+        return;
+    }
+
+    if (distance_from_center <= center_line_width) {
+        o_Target = shift_color ? PavementShift : CenterLine;
+    } else if (distance_from_center <= road_width) {
+        o_Target = shift_color ? PavementShift : Pavement;
+    } else if (distance_from_center <= road_width + rumble_width) {
+        o_Target = shift_color ? RumbleStripShift : RumbleStrip;
+    } else {
+        o_Target = shift_color ? OffroadShift : Offroad;
+    }
+
+    // Like the CPU, only pavement colored pixels are marked, and the first span over one wins
+    bool is_pavement = distance_from_center <= road_width
+        && (shift_color || distance_from_center > center_line_width);
+    for (int span = 0; is_pavement && span < MAX_SKID_SPANS_PER_LINE; span++) {
+        int span_texel = PARAM_TEXELS_PER_LINE + span * TEXELS_PER_SKID_SPAN;
+        float opacity = fetch_param(span_texel + 1, line);
+        if (opacity <= 0.0) {
+            break;
+        }
+
+        uint bounds = fetch_bits(span_texel, line);
+        float left = float(bounds & 0xFFFFu);
+        float right = float(bounds >> 16);
+        if (x >= left && x <= right) {
+            o_Target = mix(o_Target, Skid, opacity);
+            break;
+        }
+    }
+    o_Target.rgb *= brightness;
+}
+"#;
+
+fn make_fragment_shader() -> String {
+    format!(
+        "#version 450\n\
+        #define FIELD_WIDTH {}.0\n\
+        #define ROAD_HEIGHT {}.0\n\
+        #define LINE_FLAG_NO_DRAW {}u\n\
+        #define LINE_FLAG_SHIFT_COLOR {}u\n\
+        #define LINE_FLAG_SEG_BOUNDARY {}u\n\
+        #define PARAM_TEXELS_PER_LINE {}\n\
+        #define TEXELS_PER_SKID_SPAN {}\n\
+        #define MAX_SKID_SPANS_PER_LINE {}\n\
+        {}",
+        FIELD_WIDTH,
+        MAX_ROAD_DRAW_HEIGHT,
+        LINE_FLAG_NO_DRAW,
+        LINE_FLAG_SHIFT_COLOR,
+        LINE_FLAG_SEG_BOUNDARY,
+        PARAM_TEXELS_PER_LINE,
+        TEXELS_PER_SKID_SPAN,
+        MAX_SKID_SPANS_PER_LINE,
+        FRAGMENT_SHADER_BODY
+    )
+}
+
+// Lines count down from the top of the texture, same as the CPU draw buffer
+pub fn write_line_params(
+    data: &mut [u8],
+    line: usize,
+    params: Option<&RoadLineParams>,
+    skid_spans: &LineSkidSpans,
+) {
+    let line_data = &mut data[(line * BYTES_PER_LINE)..((line + 1) * BYTES_PER_LINE)];
+
+    let (values, flags) = match params {
+        Some(params) => {
+            let mut flags = 0;
+            if params.shift_color {
+                flags |= LINE_FLAG_SHIFT_COLOR;
+            }
+            if params.is_seg_boundary {
+                flags |= LINE_FLAG_SEG_BOUNDARY;
+            }
+
+            let values = [
+                params.road_center,
+                params.road_width,
+                params.center_line_width,
+                params.rumble_width,
+                params.bank,
+                params.brightness,
+            ];
+            (values, flags)
+        }
+        None => ([0.0; 6], LINE_FLAG_NO_DRAW),
+    };
+
+    let span_texels = skid_spans.iter().flat_map(|span| match span {
+        Some(span) => {
+            let bounds = u32::conv(span.left) | (u32::conv(span.right) << 16);
+            [bounds, span.opacity.to_bits()]
+        }
+        None => [0, 0],
+    });
+    let texels = values
+        .iter()
+        .map(|value| value.to_bits())
+        .chain(std::iter::once(flags))
+        .chain(span_texels);
+    for (texel, bits) in line_data.chunks_exact_mut(BYTES_PER_TEXEL).zip(texels) {
+        texel.copy_from_slice(&bits.to_le_bytes());
+    }
+}
+
+fn startup_road_shader(
+    mut commands: Commands,
+    render_mode: Res<RoadRenderMode>,
+    mut pipelines: ResMut<Assets<PipelineDescriptor>>,
+    mut shaders: ResMut<Assets<Shader>>,
+    mut render_graph: ResMut<RenderGraph>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut textures: ResMut<Assets<Texture>>,
+    mut road_materials: ResMut<Assets<RoadMaterial>>,
+) {
+    if *render_mode != RoadRenderMode::Shader {
+        return;
+    }
+
+    let fragment_shader = make_fragment_shader();
+    let pipeline = pipelines.add(PipelineDescriptor::default_config(ShaderStages {
+        vertex: shaders.add(Shader::from_glsl(ShaderStage::Vertex, VERTEX_SHADER)),
+        fragment: Some(shaders.add(Shader::from_glsl(ShaderStage::Fragment, &fragment_shader))),
+    }));
+
+    render_graph.add_system_node(
+        ROAD_MATERIAL_NODE,
+        AssetRenderResourcesNode::<RoadMaterial>::new(true),
+    );
+    render_graph
+        .add_node_edge(ROAD_MATERIAL_NODE, base::node::MAIN_PASS)
+        .unwrap();
+
+    // Plain Unorm, so the raw bytes come back out of the sampler unchanged
+    let mut make_params_tex = || {
+        textures.add(Texture::new(
+            Extent3d::new(TEXELS_PER_LINE.cast(), MAX_ROAD_DRAW_HEIGHT.cast(), 1),
+            TextureDimension::D2,
+            vec![0; BYTES_PER_LINE * MAX_ROAD_DRAW_HEIGHT],
+            TextureFormat::Rgba8Unorm,
+        ))
+    };
+    let line_params_texs = [make_params_tex(), make_params_tex()];
+
+    // Colors are filled in by the road renderer every frame
+    let material = road_materials.add(RoadMaterial {
+        line_params: line_params_texs[0].clone(),
+        pavement: Color::NONE,
+        pavement_shift: Color::NONE,
+        rumble_strip: Color::NONE,
+        rumble_strip_shift: Color::NONE,
+        offroad: Color::NONE,
+        offroad_shift: Color::NONE,
+        center_line: Color::NONE,
+        seg_boundary: Color::NONE,
+        skid: Color::NONE,
+    });
+
+    let road_size = Vec2::new(f32::conv(FIELD_WIDTH), f32::conv(MAX_ROAD_DRAW_HEIGHT));
+    commands
+        .spawn_bundle(MeshBundle {
+            mesh: meshes.add(Mesh::from(shape::Quad::new(road_size))),
+            render_pipelines: RenderPipelines::from_pipelines(vec![RenderPipeline::new(pipeline)]),
+            visible: Visible {
+                is_visible: true,
+                is_transparent: true,
+            },
+            transform: Transform::from_translation((road_size * 0.5).extend(ROAD_SPRITE_Z)),
+            ..Default::default()
+        })
+        .insert(material.clone());
+
+    commands.insert_resource(RoadShader {
+        line_params_texs,
+        material,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::road::SkidSpan;
+
+    fn read_texel(data: &[u8], line: usize, texel: usize) -> u32 {
+        let start = (line * BYTES_PER_LINE) + (texel * BYTES_PER_TEXEL);
+        let bytes = &data[start..(start + BYTES_PER_TEXEL)];
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    // The shader stops at the first span with no opacity, so unused ones must be left at zero
+    #[test]
+    fn skid_spans_are_packed_after_the_line_params() {
+        let mut spans = [None; MAX_SKID_SPANS_PER_LINE];
+        spans[0] = Some(SkidSpan {
+            left: 3,
+            right: 300,
+            opacity: 0.25,
+        });
+        spans[1] = Some(SkidSpan {
+            left: 0,
+            right: 0,
+            opacity: 0.6,
+        });
+
+        let mut data = vec![0xAB; BYTES_PER_LINE * MAX_ROAD_DRAW_HEIGHT];
+        write_line_params(&mut data, 5, None, &spans);
+
+        for (i, span) in spans.iter().enumerate() {
+            let span_texel = PARAM_TEXELS_PER_LINE + (i * TEXELS_PER_SKID_SPAN);
+            let bounds = read_texel(&data, 5, span_texel);
+            let opacity = f32::from_bits(read_texel(&data, 5, span_texel + 1));
+            match span {
+                Some(span) => {
+                    assert_eq!(usize::conv(bounds & 0xFFFF), span.left);
+                    assert_eq!(usize::conv(bounds >> 16), span.right);
+                    assert_eq!(opacity, span.opacity);
+                }
+                None => assert_eq!(opacity, 0.0),
+            }
+        }
+
+        // Neighboring lines are left alone
+        assert_eq!(read_texel(&data, 4, TEXELS_PER_LINE - 1), 0xABAB_ABAB);
+        assert_eq!(read_texel(&data, 6, 0), 0xABAB_ABAB);
+    }
+}
@@ -0,0 +1,146 @@
+use bevy::{app::AppExit, prelude::*};
+
+use crate::{
+    joyride::{FrameCounter, GameRng, RebindState},
+    road::{RoadDynamic, RoadState},
+};
+
+pub struct Systems {
+    pub request_quit: SystemSet,
+    pub save_on_quit: SystemSet,
+}
+
+impl Systems {
+    pub fn new() -> Self {
+        Self {
+            request_quit: SystemSet::new().with_system(request_quit.system()),
+            save_on_quit: SystemSet::new().with_system(save_on_quit.system()),
+        }
+    }
+}
+
+pub struct SessionConfig {
+    // Whether the session is written out when the game quits
+    pub save_on_quit: bool,
+    pub path: String,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            save_on_quit: true,
+            path: "session.ron".to_string(),
+        }
+    }
+}
+
+impl SessionConfig {
+    // Saves on quit unless told not to with `--no-save-session`
+    pub fn from_args() -> Self {
+        Self {
+            save_on_quit: !std::env::args().any(|arg| arg == "--no-save-session"),
+            ..Default::default()
+        }
+    }
+}
+
+// Everything needed to pick a run back up: the seed regenerates the same track, and the road
+// state puts the player back where they were on it
+#[derive(Debug, serde::Serialize)]
+struct SessionSave {
+    seed: u64,
+    frame_index: u64,
+    road: RoadState,
+}
+
+// Escape quits, unless it's being used to back out of rebinding a key
+fn request_quit(
+    input: Res<Input<KeyCode>>,
+    rebind_state: Res<RebindState>,
+    mut app_exit: EventWriter<AppExit>,
+) {
+    if input.just_pressed(KeyCode::Escape) && !rebind_state.is_active() {
+        app_exit.send(AppExit);
+    }
+}
+
+// The Winit runner only acts on AppExit between app updates, so saving anywhere in the same
+// update that the event was sent is guaranteed to finish before the app actually exits.
+// This also catches the AppExit that Bevy sends when the window is closed
+fn save_on_quit(
+    config: Res<SessionConfig>,
+    mut exit_events: EventReader<AppExit>,
+    game_rng: Res<GameRng>,
+    frame_counter: Res<FrameCounter>,
+    road_dyn: Res<RoadDynamic>,
+) {
+    if exit_events.iter().next().is_none() || !config.save_on_quit {
+        return;
+    }
+
+    let save = SessionSave {
+        seed: game_rng.seed(),
+        frame_index: frame_counter.frame_index(),
+        road: road_dyn.snapshot(),
+    };
+
+    let result = ron::ser::to_string_pretty(&save, ron::ser::PrettyConfig::new())
+        .map_err(|e| e.to_string())
+        .and_then(|text| std::fs::write(&config.path, text).map_err(|e| e.to_string()));
+
+    match result {
+        Ok(_) => info!("Saved session to {}", config.path),
+        Err(e) => warn!("Failed to save session to {}: {}", config.path, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::app::Events;
+
+    use super::*;
+    use crate::{
+        headless::{build_headless_app, start_race_on_straight, step},
+        replay::RecordedInput,
+    };
+
+    fn quit_with_config(save_on_quit: bool, path: &str) {
+        let mut app = build_headless_app();
+        app.world.insert_resource(SessionConfig {
+            save_on_quit,
+            path: path.to_string(),
+        });
+        start_race_on_straight(&mut app);
+
+        app.world
+            .get_resource_mut::<Events<AppExit>>()
+            .unwrap()
+            .send(AppExit);
+        step(&mut app, RecordedInput::default());
+    }
+
+    // The save has to be written within the update that quits, as the app is gone after it
+    #[test]
+    fn quitting_saves_the_session() {
+        let path = std::env::temp_dir().join("joyride_test_session.ron");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        quit_with_config(true, path);
+
+        let text = std::fs::read_to_string(path).expect("The session wasn't saved on quit");
+        std::fs::remove_file(path).unwrap();
+        assert!(text.contains("seed"), "The saved session has no seed");
+    }
+
+    #[test]
+    fn quitting_without_save_on_quit_writes_nothing() {
+        let path = std::env::temp_dir().join("joyride_test_unsaved_session.ron");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        quit_with_config(false, path);
+
+        assert!(!std::path::Path::new(path).exists());
+    }
+}
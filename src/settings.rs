@@ -0,0 +1,61 @@
+use std::path::Path;
+
+const VIDEO_SETTINGS_PATH: &str = "video_settings.ron";
+
+// Read before the app is built, since the window descriptor can't be changed once inserted.
+// Any field missing from the file keeps its default
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct VideoSettings {
+    pub vsync: bool,
+
+    // Whether the fixed-framerate scheduler throws away time it couldn't catch up on,
+    // instead of running extra steps later to make up for it
+    pub drop_time_after_max_runs: bool,
+
+    // How many sim steps may run per app update. None lets the scheduler run as many as it
+    // needs, which can starve the Winit runner's event readers
+    pub max_runs_per_step: Option<u32>,
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        Self {
+            vsync: true,
+            drop_time_after_max_runs: true,
+            max_runs_per_step: Some(1),
+        }
+    }
+}
+
+impl VideoSettings {
+    // Logging isn't set up this early, so problems are reported straight to stderr
+    pub fn load() -> Self {
+        let path = Path::new(VIDEO_SETTINGS_PATH);
+        if !path.exists() {
+            return Self::default();
+        }
+
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!(
+                    "Failed to open {}, using defaults: {}",
+                    VIDEO_SETTINGS_PATH, e
+                );
+                return Self::default();
+            }
+        };
+
+        match ron::de::from_reader(file) {
+            Ok(settings) => settings,
+            Err(e) => {
+                eprintln!(
+                    "Failed to parse {}, using defaults: {}",
+                    VIDEO_SETTINGS_PATH, e
+                );
+                Self::default()
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use bevy::prelude::*;
+use bevy_kira_audio::{Audio, AudioChannel, AudioSource};
+
+use crate::{
+    player::{PlayerCrashed, PlayerSlid, PlayerSlideDirection},
+    road::AmbienceEvent,
+    road_object::PickupCollected,
+};
+
+pub struct Systems {
+    pub startup_sfx: SystemSet,
+    pub play_sfx: SystemSet,
+    pub play_ambience: SystemSet,
+}
+
+impl Systems {
+    pub fn new() -> Self {
+        Self {
+            startup_sfx: SystemSet::new().with_system(startup_sfx.system()),
+            play_sfx: SystemSet::new().with_system(play_sfx.system()),
+            play_ambience: SystemSet::new().with_system(play_ambience.system()),
+        }
+    }
+}
+
+// How far the slide sound is panned toward the side the bike slides to. 0.5 is centered
+const SLIDE_PANNING: f32 = 0.25;
+
+struct SfxAssets {
+    crash: Handle<AudioSource>,
+    slide: Handle<AudioSource>,
+    pickup: Handle<AudioSource>,
+
+    // Panning applies to a whole channel, so the slide gets one to itself
+    slide_channel: AudioChannel,
+}
+
+fn startup_sfx(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(SfxAssets {
+        crash: asset_server.load("sounds/crash.wav"),
+        slide: asset_server.load("sounds/slide.wav"),
+        pickup: asset_server.load("sounds/pickup.wav"),
+        slide_channel: AudioChannel::new("slide".to_string()),
+    });
+}
+
+// Each clip plays at most once a frame, however many events asked for it
+fn play_sfx(
+    audio: Res<Audio>,
+    assets: Res<SfxAssets>,
+    mut crashed_events: EventReader<PlayerCrashed>,
+    mut slid_events: EventReader<PlayerSlid>,
+    mut pickup_events: EventReader<PickupCollected>,
+) {
+    let clips = [
+        (&assets.crash, crashed_events.iter().count()),
+        (&assets.pickup, pickup_events.iter().count()),
+    ];
+
+    for (clip, num_events) in clips.iter() {
+        if *num_events > 0 {
+            audio.play((*clip).clone());
+        }
+    }
+
+    // A new slide interrupts the last, so the latest one decides the side
+    if let Some(slid) = slid_events.iter().last() {
+        let panning = match slid.direction {
+            PlayerSlideDirection::Left => SLIDE_PANNING,
+            PlayerSlideDirection::Right => 1.0 - SLIDE_PANNING,
+        };
+        audio.set_panning_in_channel(panning, &assets.slide_channel);
+        audio.play_in_channel(assets.slide.clone(), &assets.slide_channel);
+    }
+}
+
+// Segment audio cues name a clip under sounds/ambience. Tracks can cue anything, so clips are only
+// loaded once a cue fires, and a cue with no clip just fails to load
+fn play_ambience(
+    audio: Res<Audio>,
+    asset_server: Res<AssetServer>,
+    mut ambience_events: EventReader<AmbienceEvent>,
+) {
+    for event in ambience_events.iter() {
+        let clip: Handle<AudioSource> =
+            asset_server.load(format!("sounds/ambience/{}.wav", event.cue).as_str());
+        audio.play(clip);
+    }
+}
@@ -2,11 +2,12 @@ use bevy::prelude::*;
 use easy_cast::*;
 
 use crate::{
-    joyride::TIME_STEP,
+    joyride::{scaled_step, GameScreen, TimeScale},
     player::Player,
     racer::Racer,
-    road::{RoadDynamic, ROAD_DISTANCE},
-    util::spawn_empty_parent,
+    road::{RoadDynamic, RoadTheme, ROAD_DISTANCE},
+    time_of_day::TimeOfDay,
+    util::{spawn_empty_parent, TextureLoader},
 };
 
 // Used for layering with other sprites
@@ -17,7 +18,15 @@ const SKYBOX_UPHILL_SCROLL_SCALAR: f32 = 0.5;
 
 const SKYBOX_SIZE: (f32, f32) = (640.0, 240.0);
 
-struct Skybox {}
+// Where each tile sits relative to the scroll position, one either side of the middle one
+const SKYBOX_TILE_OFFSETS: [f32; 3] = [-SKYBOX_SIZE.0, 0.0, SKYBOX_SIZE.0];
+
+struct Skybox {
+    // How far the tiles are scrolled horizontally, always within one tile's width
+    scroll_x: f32,
+}
+
+struct SkyboxTile {}
 
 pub struct Systems {
     pub startup_skybox: SystemSet,
@@ -37,35 +46,58 @@ fn startup_skybox(
     mut commands: Commands,
     mut materials: ResMut<Assets<ColorMaterial>>,
     asset_server: Res<AssetServer>,
+    mut texture_loader: ResMut<TextureLoader>,
+    theme: Res<RoadTheme>,
 ) {
-    let tex = asset_server.load("textures/sky_bg.png");
+    let tex = texture_loader.load(&asset_server, theme.skybox_texture_path());
     spawn_empty_parent(&mut commands, Vec3::new(0.0, 0.0, SKYBOX_SPRITE_Z))
-        .insert(Skybox {})
+        .insert(Skybox { scroll_x: 0.0 })
         .with_children(|cmd| {
-            let x_positions: [f32; 3] = [-SKYBOX_SIZE.0, 0.0, SKYBOX_SIZE.0];
-            for x in x_positions.iter() {
+            for x in SKYBOX_TILE_OFFSETS.iter() {
                 cmd.spawn_bundle(SpriteBundle {
                     material: materials.add(tex.clone().into()),
                     transform: Transform::from_translation(Vec3::new(*x, 0.0, 0.0)),
                     ..Default::default()
-                });
+                })
+                .insert(SkyboxTile {});
             }
         });
 }
 
+// Kept within [0, tile width) no matter how far it scrolls in one step. With a tile either
+// side of this one, that always leaves the whole view covered, with a tile to spare
+fn scroll_skybox(scroll_x: f32, scroll_delta: f32) -> f32 {
+    (scroll_x + scroll_delta).rem_euclid(SKYBOX_SIZE.0)
+}
+
 fn reposition_skybox(
-    mut skyboxes: Query<&mut Transform, With<Skybox>>,
-    racers: Query<&Racer>,
-    player: Option<Res<Player>>,
+    time_scale: Res<TimeScale>,
+    screen: Res<State<GameScreen>>,
+    mut skyboxes: Query<(&mut Skybox, &mut Transform)>,
+    players: Query<&Racer, With<Player>>,
     road_dyn: Option<Res<RoadDynamic>>,
+    time_of_day: Res<TimeOfDay>,
+    tiles: Query<&Handle<ColorMaterial>, With<SkyboxTile>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
+    let step = scaled_step(&time_scale);
+
+    if time_of_day.is_changed() {
+        let tint = time_of_day.get_tint_color();
+        for material in tiles.iter() {
+            if let Some(material) = materials.get_mut(material) {
+                material.color = tint;
+            }
+        }
+    }
+
     let road_dyn = match road_dyn {
         Some(road_dyn) => road_dyn,
         None => return, // No-op if no road
     };
     let road_draw_height = road_dyn.get_draw_height_pixels();
 
-    for mut xform in skyboxes.iter_mut() {
+    for (mut skybox, mut xform) in skyboxes.iter_mut() {
         // Hide skybox over horizon if going uphill
         let y_offset = if road_draw_height < ROAD_DISTANCE {
             let uphill_height: f32 = -f32::conv(ROAD_DISTANCE - road_draw_height);
@@ -74,18 +106,61 @@ fn reposition_skybox(
             0.0
         };
 
-        let horizontal_scroll_speed = {
-            let player_speed = player
-                .as_ref()
-                .and_then(|p| racers.get(p.get_racer_ent()).ok())
-                .map_or(0.0, |r| r.speed);
-            -road_dyn.get_road_x_pull(0.0, player_speed) * TIME_STEP
+        // The sky holds still while the race is frozen behind another screen
+        let horizontal_scroll_speed = if !screen.current().is_race_running() {
+            0.0
+        } else {
+            let player_speed = players.iter().next().map_or(0.0, |r| r.speed);
+            -road_dyn.get_road_x_pull(0.0, player_speed) * step
         };
 
-        xform.translation.x =
-            (xform.translation.x + horizontal_scroll_speed) % f32::conv(SKYBOX_SIZE.0);
+        skybox.scroll_x = scroll_skybox(skybox.scroll_x, horizontal_scroll_speed);
+        xform.translation.x = skybox.scroll_x;
 
         // Fit the skybox to match the height of the road
         xform.translation.y = f32::conv(road_draw_height - 1) + (SKYBOX_SIZE.1 * 0.5) + y_offset;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joyride::FIELD_WIDTH;
+
+    #[test]
+    fn large_scrolls_leave_the_view_covered() {
+        let view_width = f32::conv(FIELD_WIDTH);
+
+        let mut scroll_x = 0.0;
+        for &delta in &[5000.0, -12345.6, 639.9, -640.0, 1.0e6] {
+            scroll_x = scroll_skybox(scroll_x, delta);
+
+            // Tiles are centered on their positions
+            let mut spans: Vec<(f32, f32)> = SKYBOX_TILE_OFFSETS
+                .iter()
+                .map(|offset| {
+                    let center = scroll_x + offset;
+                    (center - SKYBOX_SIZE.0 * 0.5, center + SKYBOX_SIZE.0 * 0.5)
+                })
+                .collect();
+            spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut covered_to = 0.0;
+            for (left, right) in spans {
+                assert!(
+                    left <= covered_to || covered_to >= view_width,
+                    "Scrolling by {} left a gap at {}",
+                    delta,
+                    covered_to
+                );
+                covered_to = f32::max(covered_to, right);
+            }
+            assert!(
+                covered_to >= view_width,
+                "Scrolling by {} left the view uncovered past {}",
+                delta,
+                covered_to
+            );
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use bevy::prelude::*;
+
+use crate::{
+    debug::DebugAssets,
+    joyride::{JoyrideGame, JoyrideInput, JoyrideInputState, RaceState, Score},
+    player::{Player, PlayerState},
+    racer::{Racer, RacerAssets},
+    rival::{spawn_rival, Rival, RivalAssets, RivalState},
+    road::{RoadDynamic, RoadSegment, RoadState},
+    road_object::RoadObject,
+};
+
+pub struct Systems {
+    pub update_snapshots: SystemSet,
+}
+
+impl Systems {
+    pub fn new() -> Self {
+        Self {
+            update_snapshots: SystemSet::new().with_system(update_snapshots.system()),
+        }
+    }
+}
+
+const SNAPSHOT_PATH: &str = "snapshot.ron";
+
+// The live state of a race partway through, for picking it back up while debugging.
+// Entities aren't saved, so anything holding one is rebuilt around them on restore
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct RaceSnapshot {
+    road: RoadState,
+    segs: Vec<RoadSegment>,
+    player: PlayerState,
+    player_speed: f32,
+    #[serde(default)]
+    player_turn_rate: f32,
+    rivals: Vec<RivalState>,
+    score: Score,
+    game: JoyrideGame,
+    race_state: RaceState,
+}
+
+pub fn snapshot(
+    road_dyn: &RoadDynamic,
+    player: &Player,
+    player_racer: &Racer,
+    rivals: Vec<RivalState>,
+    score: &Score,
+    game: &JoyrideGame,
+    race_state: RaceState,
+) -> RaceSnapshot {
+    RaceSnapshot {
+        road: road_dyn.snapshot(),
+        segs: road_dyn.segs().to_vec(),
+        player: player.snapshot(),
+        player_speed: player_racer.speed,
+        player_turn_rate: player_racer.turn_rate,
+        rivals,
+        score: score.clone(),
+        game: game.clone(),
+        race_state,
+    }
+}
+
+// Rivals are left to the caller, as they're respawned from the snapshot rather than restored
+pub fn restore(
+    snapshot: &RaceSnapshot,
+    road_dyn: &mut RoadDynamic,
+    player: &mut Player,
+    player_racer: &mut Racer,
+    score: &mut Score,
+    game: &mut JoyrideGame,
+    race_state: &mut RaceState,
+) {
+    road_dyn.replace_segs(snapshot.segs.clone());
+    road_dyn.restore(&snapshot.road);
+    player.restore(&snapshot.player);
+    player_racer.speed = snapshot.player_speed;
+    player_racer.turn_rate = snapshot.player_turn_rate;
+    *score = snapshot.score.clone();
+    *game = snapshot.game.clone();
+    *race_state = snapshot.race_state;
+}
+
+fn save_snapshot(snapshot: &RaceSnapshot, path: &str) {
+    let result = ron::ser::to_string_pretty(snapshot, ron::ser::PrettyConfig::new())
+        .map_err(|e| e.to_string())
+        .and_then(|text| std::fs::write(path, text).map_err(|e| e.to_string()));
+
+    match result {
+        Ok(_) => info!("Saved race snapshot to {}", path),
+        Err(e) => warn!("Failed to save race snapshot to {}: {}", path, e),
+    }
+}
+
+fn load_snapshot(path: &str) -> Option<RaceSnapshot> {
+    let result = std::fs::File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| ron::de::from_reader(file).map_err(|e| e.to_string()));
+
+    match result {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            warn!("Failed to load race snapshot from {}: {}", path, e);
+            None
+        }
+    }
+}
+
+// Saving and loading both go through SNAPSHOT_PATH. Road objects other than rivals aren't part of
+// a snapshot, so they're left where they are on the road
+fn update_snapshots(
+    mut commands: Commands,
+    input: Res<JoyrideInput>,
+    mut road_dyn: ResMut<RoadDynamic>,
+    mut players: Query<(&mut Player, &mut Racer)>,
+    rivals: Query<(Entity, &Rival, &RoadObject, &Racer), Without<Player>>,
+    mut score: ResMut<Score>,
+    mut game: ResMut<JoyrideGame>,
+    mut race_state: ResMut<RaceState>,
+    rival_assets: Res<RivalAssets>,
+    racer_assets: Res<RacerAssets>,
+    debug_assets: Res<DebugAssets>,
+) {
+    let (mut player, mut player_racer) = match players.iter_mut().next() {
+        Some(player) => player,
+        None => return,
+    };
+
+    if input.debug_save_snapshot == JoyrideInputState::JustPressed {
+        let rival_states = rivals
+            .iter()
+            .map(|(_, rival, obj, racer)| rival.snapshot(obj, racer))
+            .collect();
+        let snapshot = snapshot(
+            &road_dyn,
+            &player,
+            &player_racer,
+            rival_states,
+            &score,
+            &game,
+            *race_state,
+        );
+        save_snapshot(&snapshot, SNAPSHOT_PATH);
+    }
+
+    if input.debug_load_snapshot != JoyrideInputState::JustPressed {
+        return;
+    }
+    let snapshot = match load_snapshot(SNAPSHOT_PATH) {
+        Some(snapshot) => snapshot,
+        None => return,
+    };
+
+    restore(
+        &snapshot,
+        &mut road_dyn,
+        &mut player,
+        &mut player_racer,
+        &mut score,
+        &mut game,
+        &mut race_state,
+    );
+
+    for (ent, _, _, _) in rivals.iter() {
+        commands.entity(ent).despawn_recursive();
+    }
+    for rival in snapshot.rivals.iter() {
+        spawn_rival(
+            &mut commands,
+            rival.x_pos,
+            rival.z_pos,
+            rival.speed,
+            rival.palette,
+            &rival_assets,
+            &racer_assets,
+            &debug_assets,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        headless::{build_headless_app, clear_rivals, start_race_on_straight, step},
+        replay::RecordedInput,
+    };
+
+    fn drive(app: &mut App, ticks: u32) {
+        let accel_left = RecordedInput {
+            accel: true,
+            left: true,
+            ..Default::default()
+        };
+        for _ in 0..ticks {
+            clear_rivals(app);
+            step(app, accel_left);
+        }
+    }
+
+    fn take_snapshot(world: &mut World) -> RaceSnapshot {
+        let mut players = world.query::<(&Player, &Racer)>();
+        let (player, racer) = players.iter(world).next().unwrap();
+        snapshot(
+            world.get_resource::<RoadDynamic>().unwrap(),
+            player,
+            racer,
+            Vec::new(),
+            world.get_resource::<Score>().unwrap(),
+            world.get_resource::<JoyrideGame>().unwrap(),
+            *world.get_resource::<RaceState>().unwrap(),
+        )
+    }
+
+    // Snapshots partway down the road, drives on, then restores, which should put the player
+    // back exactly where the snapshot was taken
+    #[test]
+    fn restoring_returns_to_the_snapshot() {
+        let mut app = build_headless_app();
+        start_race_on_straight(&mut app);
+        drive(&mut app, 120);
+
+        // Through RON, the same as it's saved
+        let serialized = ron::ser::to_string(&take_snapshot(&mut app.world)).unwrap();
+        let saved: RaceSnapshot = ron::de::from_str(&serialized).unwrap();
+
+        drive(&mut app, 120);
+        assert_ne!(
+            app.world.get_resource::<RoadDynamic>().unwrap().snapshot(),
+            saved.road
+        );
+
+        let world = &mut app.world;
+        let mut road_dyn = world.remove_resource::<RoadDynamic>().unwrap();
+        let mut score = world.remove_resource::<Score>().unwrap();
+        let mut game = world.remove_resource::<JoyrideGame>().unwrap();
+        let mut race_state = world.remove_resource::<RaceState>().unwrap();
+        let mut players = world.query::<(&mut Player, &mut Racer)>();
+        let (mut player, mut racer) = players.iter_mut(world).next().unwrap();
+        restore(
+            &saved,
+            &mut road_dyn,
+            &mut player,
+            &mut racer,
+            &mut score,
+            &mut game,
+            &mut race_state,
+        );
+
+        assert_eq!(road_dyn.snapshot(), saved.road);
+        assert_eq!(road_dyn.segs().len(), saved.segs.len());
+        assert_eq!(racer.speed, saved.player_speed);
+
+        // Left was held the whole way, so the bike was mid-turn when the snapshot was taken
+        assert!(saved.player_turn_rate != 0.0);
+        assert_eq!(racer.turn_rate, saved.player_turn_rate);
+    }
+}
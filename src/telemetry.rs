@@ -0,0 +1,155 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use bevy::{app::AppExit, prelude::*};
+
+use crate::{
+    joyride::FrameComplete,
+    player::Player,
+    racer::Racer,
+    road::{is_offroad, RoadDynamic, RoadStatic},
+};
+
+pub struct Systems {
+    pub startup_telemetry: SystemSet,
+    pub record_telemetry: SystemSet,
+    pub flush_telemetry_on_quit: SystemSet,
+}
+
+impl Systems {
+    pub fn new() -> Self {
+        Self {
+            startup_telemetry: SystemSet::new().with_system(startup_telemetry.system()),
+            record_telemetry: SystemSet::new().with_system(record_telemetry.system()),
+            flush_telemetry_on_quit: SystemSet::new().with_system(flush_telemetry_on_quit.system()),
+        }
+    }
+}
+
+const TELEMETRY_CSV_HEADER: &str =
+    "frame,sim_time,speed,turn_rate,x_offset,curvature,surface,turbo,turbo_fuel,crash,slide";
+
+// Writes a CSV row of the player's state every sim frame, for plotting runs when tuning
+pub struct TelemetryConfig {
+    pub enabled: bool,
+    pub path: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "telemetry.csv".to_string(),
+        }
+    }
+}
+
+impl TelemetryConfig {
+    // Off unless turned on with `--telemetry`
+    pub fn from_args() -> Self {
+        Self {
+            enabled: std::env::args().any(|arg| arg == "--telemetry"),
+            ..Default::default()
+        }
+    }
+}
+
+struct TelemetryLog {
+    // None when telemetry is disabled, or if writing to the file failed
+    writer: Option<BufWriter<File>>,
+}
+
+fn startup_telemetry(mut commands: Commands, config: Res<TelemetryConfig>) {
+    let writer = if config.enabled {
+        open_telemetry_file(&config.path)
+    } else {
+        None
+    };
+
+    commands.insert_resource(TelemetryLog { writer });
+}
+
+fn open_telemetry_file(path: &str) -> Option<BufWriter<File>> {
+    let file = match File::create(path) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Failed to create telemetry file {}: {}", path, e);
+            return None;
+        }
+    };
+
+    let mut writer = BufWriter::new(file);
+    match writeln!(writer, "{}", TELEMETRY_CSV_HEADER) {
+        Ok(_) => Some(writer),
+        Err(e) => {
+            warn!("Failed to write telemetry header: {}", e);
+            None
+        }
+    }
+}
+
+fn record_telemetry(
+    mut log: ResMut<TelemetryLog>,
+    mut frame_events: EventReader<FrameComplete>,
+    players: Query<(&Player, &Racer)>,
+    road_static: Res<RoadStatic>,
+    road_dyn: Res<RoadDynamic>,
+) {
+    let writer = match log.writer.as_mut() {
+        Some(writer) => writer,
+        None => return, // Telemetry is off
+    };
+
+    let (player, racer) = match players.iter().next() {
+        Some(player) => player,
+        None => return,
+    };
+
+    let surface = if is_offroad(&road_static, &road_dyn) {
+        "offroad"
+    } else {
+        "road"
+    };
+
+    let mut result = Ok(());
+    for frame in frame_events.iter() {
+        result = result.and_then(|_| {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{},{}",
+                frame.frame_index,
+                frame.sim_time.as_secs_f64(),
+                racer.speed,
+                racer.turn_rate,
+                road_dyn.x_offset,
+                road_dyn.get_seg_curvature(0.0),
+                surface,
+                player.is_turboing(),
+                player.get_turbo_fuel(),
+                player.is_crashing(),
+                player.is_sliding(),
+            )
+        });
+    }
+
+    if let Err(e) = result {
+        warn!("Failed to write telemetry, stopping: {}", e);
+        log.writer = None;
+    }
+}
+
+// Rows are only recorded while racing, but the game can be quit from any screen
+fn flush_telemetry_on_quit(mut log: ResMut<TelemetryLog>, mut exit_events: EventReader<AppExit>) {
+    if exit_events.iter().next().is_none() {
+        return;
+    }
+
+    if let Some(writer) = log.writer.as_mut() {
+        if let Err(e) = writer.flush() {
+            warn!("Failed to flush telemetry: {}", e);
+            log.writer = None;
+        }
+    }
+}
@@ -4,20 +4,98 @@ use bevy::prelude::*;
 use easy_cast::*;
 
 use crate::{
-    joyride::{JoyrideGame, FIELD_HEIGHT, FIELD_WIDTH, TIME_STEP},
-    player::{Player, PLAYER_MAX_NORMAL_SPEED},
+    debug::{DebugConfig, LoopSectionTimer},
+    fixed_framerate::FixedFramerateStats,
+    high_score::HighScores,
+    joyride::{
+        scaled_step, JoyrideGame, RaceState, Score, TimeScale, COUNTDOWN_SECS, FIELD_HEIGHT,
+        FIELD_WIDTH,
+    },
+    player::{Player, PLAYER_MAX_HEALTH, PLAYER_MAX_NORMAL_SPEED},
     racer::Racer,
-    util::{spawn_empty_parent, SpriteGridDesc},
+    road::{CheckpointReached, RoadDynamic, SEGMENT_LENGTH},
+    util::{spawn_empty_parent, LocalVisible, SpriteGridDesc, TextureLoader},
 };
 
 struct SpeedText {
-    num_ents: [Entity; 3],
+    number: NumberWidget<3>,
     flash_timer: Timer,
     should_flash: bool,
+
+    // Follows the true display speed, so the readout doesn't jitter along with the bike's speed
+    displayed_speed: f32,
+}
+
+pub struct SpeedTextConfig {
+    // If false, the readout shows the true speed every frame
+    pub ease_speed: bool,
+
+    // The fraction of the remaining distance to the true speed covered per second
+    pub ease_rate: f32,
+}
+
+impl Default for SpeedTextConfig {
+    fn default() -> Self {
+        Self {
+            ease_speed: true,
+            ease_rate: 8.0,
+        }
+    }
 }
 
 struct TimeText {
-    number_ents: [Entity; 2],
+    number: NumberWidget<2>,
+
+    // The readout flashes for a moment whenever a checkpoint adds time
+    flash_timer: Timer,
+    flash_secs_left: f32,
+    should_flash: bool,
+}
+
+struct BestText {
+    number: NumberWidget<5>,
+}
+
+struct CountdownText {
+    digit_ent: Entity,
+    go_ent: Entity,
+}
+
+struct ScoreText {
+    number: NumberWidget<6>,
+}
+
+// One pip per point of health, left to right, with lost health shown as empty pips
+struct HealthPips {
+    pip_ents: [Entity; PLAYER_MAX_HEALTH as usize],
+    full_material: Handle<ColorMaterial>,
+    empty_material: Handle<ColorMaterial>,
+}
+
+// One arrow per upcoming segment, nearest on the left
+struct CurvePreview {
+    arrow_ents: [Entity; CURVE_PREVIEW_SLOTS],
+}
+
+pub struct CurvePreviewConfig {
+    pub enabled: bool,
+}
+
+impl Default for CurvePreviewConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+struct FramerateText {
+    step_rate: NumberWidget<3>,
+    update_rate: NumberWidget<3>,
+}
+
+struct PerfText {
+    frame_ms: NumberWidget<3>,
+    update_rate: NumberWidget<3>,
+    skipped_steps: NumberWidget<3>,
 }
 
 pub struct Systems {
@@ -31,7 +109,14 @@ impl Systems {
             startup_text: SystemSet::new().with_system(startup_text.system()),
             update_texts: SystemSet::new()
                 .with_system(update_speed_text.system())
-                .with_system(update_time_text.system()),
+                .with_system(update_time_text.system())
+                .with_system(update_best_text.system())
+                .with_system(update_score_text.system())
+                .with_system(update_health_pips.system())
+                .with_system(update_countdown_text.system())
+                .with_system(update_curve_preview.system())
+                .with_system(update_framerate_text.system())
+                .with_system(debug_perf_overlay.system()),
         }
     }
 }
@@ -59,45 +144,220 @@ const SMALL_TEXT_SPRITE_DESC: SpriteGridDesc = SpriteGridDesc {
     columns: 4,
 };
 
+// Glyphs for arbitrary strings, laid out A-Z then 0-9 along a single row
+pub const SMALL_FONT_WIDTH: f32 = 6.0;
+pub const SMALL_FONT_SPRITE_DESC: SpriteGridDesc = SpriteGridDesc {
+    tile_size: 32,
+    rows: 1,
+    columns: 36,
+};
+
+// Speeds are shown as km/h, so distances are shown in matching meters
+pub const DISTANCE_TO_METERS: f32 =
+    (MAX_NORMAL_DISPLAY_SPEED as f32 / PLAYER_MAX_NORMAL_SPEED) * (1000.0 / 3600.0);
+
+const TIME_EXTEND_FLASH_SECS: f32 = 1.0;
+const TIME_EXTEND_FLASH_INTERVAL: f32 = 0.1;
+
+// The arrows point left, and are flipped for right-hand curves
+const CURVE_ARROW_SPRITE_DESC: SpriteGridDesc = SpriteGridDesc {
+    tile_size: 16,
+    rows: 1,
+    columns: 3,
+};
+const CURVE_ARROW_STRAIGHT: u32 = 0;
+const CURVE_ARROW_GENTLE: u32 = 1;
+const CURVE_ARROW_SHARP: u32 = 2;
+
+const CURVE_PREVIEW_SLOTS: usize = 4;
+const CURVE_PREVIEW_SPACING: f32 = 18.0;
+
+// Curvature at or past these shows a gentle or sharp arrow
+const CURVE_PREVIEW_GENTLE: f32 = 0.05;
+const CURVE_PREVIEW_SHARP: f32 = 0.45;
+
+const HEALTH_PIP_SIZE: f32 = 4.0;
+const HEALTH_PIP_SPACING: f32 = 6.0;
+
+// How long "GO" stays up once the race starts
+const COUNTDOWN_GO_SECS: f32 = 1.0;
+
 const TEXT_NOT_INIT: &str = "Text not initialized";
 
+// Lowercase letters share the uppercase glyphs. Anything else unsupported has no glyph
+fn get_font_sprite_index(c: char) -> Option<u32> {
+    let c = c.to_ascii_uppercase();
+    match c {
+        'A'..='Z' => Some(u32::from(c) - u32::from('A')),
+        '0'..='9' => Some(26 + u32::from(c) - u32::from('0')),
+        _ => None,
+    }
+}
+
+// The sprite index and X offset of each glyph in a string. Spaces, and any characters
+// without a glyph, still take up room
+fn layout_text(string: &str) -> Vec<(u32, f32)> {
+    string
+        .chars()
+        .enumerate()
+        .filter_map(|(i, c)| {
+            let index = get_font_sprite_index(c)?;
+            Some((index, SMALL_FONT_WIDTH * f32::conv(i)))
+        })
+        .collect()
+}
+
+// Spawns a string of text left to right, with the first character centered on pos
+pub fn spawn_text(
+    commands: &mut Commands,
+    atlas: &Handle<TextureAtlas>,
+    string: &str,
+    pos: Vec3,
+) -> Vec<Entity> {
+    layout_text(string)
+        .into_iter()
+        .map(|(index, x)| {
+            commands
+                .spawn_bundle(SpriteSheetBundle {
+                    texture_atlas: atlas.clone(),
+                    sprite: TextureAtlasSprite {
+                        index,
+                        ..Default::default()
+                    },
+                    transform: Transform::from_translation(pos + Vec3::new(x, 0.0, 0.0)),
+                    ..Default::default()
+                })
+                .id()
+        })
+        .collect()
+}
+
+// What fills the digits in front of a number that doesn't need all of them
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum NumberPadding {
+    Zeros,
+    Blank,
+}
+
+// A number shown with a fixed count of digit sprites, laid out left to right. Values with too
+// many digits are clamped to all nines
+struct NumberWidget<const N: usize> {
+    digit_ents: [Entity; N],
+    padding: NumberPadding,
+    color: Color,
+}
+
+impl<const N: usize> NumberWidget<N> {
+    // The digits aren't parented to anything, so callers can put them under whichever entity
+    // controls their visibility
+    fn spawn(
+        commands: &mut Commands,
+        atlas: &Handle<TextureAtlas>,
+        pos: Vec3,
+        spacing: f32,
+        padding: NumberPadding,
+        color: Color,
+    ) -> Self {
+        // Placeholder value. Unfortunately, building by iterating over (0..N) loses the fixed size
+        let mut digit_ents = [Entity::new(0); N];
+        for (i, ent) in digit_ents.iter_mut().enumerate() {
+            let t = pos + Vec3::new(spacing * f32::conv(i), 0.0, 0.0);
+            *ent = commands
+                .spawn_bundle(SpriteSheetBundle {
+                    texture_atlas: atlas.clone(),
+                    sprite: TextureAtlasSprite {
+                        color,
+                        ..Default::default()
+                    },
+                    transform: Transform::from_translation(t),
+                    ..Default::default()
+                })
+                .id();
+        }
+
+        Self {
+            digit_ents,
+            padding,
+            color,
+        }
+    }
+
+    fn ents(&self) -> &[Entity; N] {
+        &self.digit_ents
+    }
+
+    fn max_value() -> u32 {
+        (0..N).fold(0u32, |max, _| max.saturating_mul(10).saturating_add(9))
+    }
+
+    // The sprite index of each digit, most significant first. None marks blank padding
+    fn get_digits(&self, value: u32) -> [Option<u32>; N] {
+        let mut value = u32::min(value, Self::max_value());
+        let mut digits = [None; N];
+        for (i, digit) in digits.iter_mut().enumerate().rev() {
+            let is_padding = value == 0 && i + 1 < N;
+            if !is_padding || self.padding == NumberPadding::Zeros {
+                *digit = Some(value % 10);
+            }
+            value /= 10;
+        }
+        digits
+    }
+
+    // Blank digits are drawn fully transparent, rather than hidden, so visibility is left
+    // entirely to whatever the digits are parented to
+    fn set_value(&self, value: u32, texts: &mut Query<&mut TextureAtlasSprite>) {
+        for (digit, ent) in self.get_digits(value).iter().zip(&self.digit_ents) {
+            let mut sprite = texts.get_mut(*ent).expect(TEXT_NOT_INIT);
+            match digit {
+                Some(digit) => {
+                    sprite.index = *digit;
+                    sprite.color = self.color;
+                }
+                None => sprite.color = Color::NONE,
+            }
+        }
+    }
+}
+
 fn startup_text(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
+    mut texture_loader: ResMut<TextureLoader>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
-    let small_nums_tex = asset_server.load("textures/small_num_atlas.png");
+    let small_nums_tex = texture_loader.load(&asset_server, "textures/small_num_atlas.png");
     let small_nums_atlas = texture_atlases.add(SMALL_NUM_SPRITE_DESC.make_atlas(small_nums_tex));
-    let large_nums_tex = asset_server.load("textures/large_num_atlas.png");
+    let large_nums_tex = texture_loader.load(&asset_server, "textures/large_num_atlas.png");
     let large_nums_atlas = texture_atlases.add(LARGE_NUM_SPRITE_DESC.make_atlas(large_nums_tex));
-    let small_texts_tex = asset_server.load("textures/small_text_atlas.png");
+    let small_texts_tex = texture_loader.load(&asset_server, "textures/small_text_atlas.png");
     let small_texts_atlas = texture_atlases.add(SMALL_TEXT_SPRITE_DESC.make_atlas(small_texts_tex));
+    let small_font_tex = texture_loader.load(&asset_server, "textures/small_font_atlas.png");
+    let small_font_atlas = texture_atlases.add(SMALL_FONT_SPRITE_DESC.make_atlas(small_font_tex));
+    let countdown_go_tex = texture_loader.load(&asset_server, "textures/countdown_go.png");
+    let curve_arrows_tex = texture_loader.load(&asset_server, "textures/curve_arrow_atlas.png");
+    let curve_arrows_atlas =
+        texture_atlases.add(CURVE_ARROW_SPRITE_DESC.make_atlas(curve_arrows_tex));
 
     let field_width = f32::conv(FIELD_WIDTH);
     let field_height = f32::conv(FIELD_HEIGHT);
 
     let base_pos = Vec2::new(field_width - 48.0, field_height - 10.0);
 
-    // Placeholder value. Unfortunately, building by iterating over (0..3) loses the fixed size
-    let mut speed_num_ents = [Entity::new(0); 3];
-    for (i, ent) in speed_num_ents.iter_mut().enumerate() {
-        let i: f32 = i.cast();
-        let start: f32 = (SMALL_NUM_WIDTH * 0.5).floor();
-
-        let t = Vec3::new(
-            base_pos.x + start + (SMALL_NUM_WIDTH * i),
+    let speed_number = NumberWidget::spawn(
+        &mut commands,
+        &small_nums_atlas,
+        Vec3::new(
+            base_pos.x + (SMALL_NUM_WIDTH * 0.5).floor(),
             base_pos.y,
             TEXT_Z,
-        );
-
-        *ent = commands
-            .spawn_bundle(SpriteSheetBundle {
-                texture_atlas: small_nums_atlas.clone(),
-                transform: Transform::from_translation(t),
-                ..Default::default()
-            })
-            .id()
-    }
+        ),
+        SMALL_NUM_WIDTH,
+        NumberPadding::Zeros,
+        Color::WHITE,
+    );
+    let speed_num_ents = *speed_number.ents();
 
     let km_text_ent = commands
         .spawn_bundle(SpriteSheetBundle {
@@ -135,16 +395,17 @@ fn startup_text(
 
     spawn_empty_parent(&mut commands, Vec3::ZERO)
         .insert(SpeedText {
-            num_ents: speed_num_ents,
+            number: speed_number,
             flash_timer: Timer::from_seconds(1.0, true),
             should_flash: false,
+            displayed_speed: 0.0,
         })
         .push_children(&[km_text_ent, speed_text_ent])
         .push_children(&speed_num_ents);
 
     let time_text_ent = commands
         .spawn_bundle(SpriteSheetBundle {
-            texture_atlas: small_texts_atlas,
+            texture_atlas: small_texts_atlas.clone(),
             sprite: TextureAtlasSprite {
                 color: Color::YELLOW,
                 index: 2,
@@ -159,57 +420,229 @@ fn startup_text(
         })
         .id();
 
-    let time_num_ents: [Entity; 2] = [
-        commands
-            .spawn_bundle(SpriteSheetBundle {
-                texture_atlas: large_nums_atlas.clone(),
-                transform: Transform::from_translation(Vec3::new(
-                    (field_width * 0.5) - LARGE_NUM_WIDTH * 0.5,
-                    field_height - 30.0,
-                    TEXT_Z,
-                )),
+    let time_number = NumberWidget::spawn(
+        &mut commands,
+        &large_nums_atlas,
+        Vec3::new(
+            (field_width * 0.5) - LARGE_NUM_WIDTH * 0.5,
+            field_height - 30.0,
+            TEXT_Z,
+        ),
+        LARGE_NUM_WIDTH,
+        NumberPadding::Zeros,
+        Color::WHITE,
+    );
+    let time_num_ents = *time_number.ents();
+
+    spawn_empty_parent(&mut commands, Vec3::ZERO)
+        .insert(TimeText {
+            number: time_number,
+            flash_timer: Timer::from_seconds(TIME_EXTEND_FLASH_INTERVAL, true),
+            flash_secs_left: 0.0,
+            should_flash: false,
+        })
+        .push_children(&[time_text_ent])
+        .push_children(&time_num_ents);
+
+    let best_text_ent = commands
+        .spawn_bundle(SpriteSheetBundle {
+            texture_atlas: small_texts_atlas,
+            sprite: TextureAtlasSprite {
+                color: Color::YELLOW,
+                index: 3,
+                ..Default::default()
+            },
+            transform: Transform::from_translation(Vec3::new(16.0, field_height - 34.0, TEXT_Z)),
+            ..Default::default()
+        })
+        .id();
+
+    let best_number = NumberWidget::spawn(
+        &mut commands,
+        &large_nums_atlas,
+        Vec3::new(8.0, field_height - 50.0, TEXT_Z),
+        LARGE_NUM_WIDTH,
+        NumberPadding::Zeros,
+        Color::WHITE,
+    );
+    let best_num_ents = *best_number.ents();
+
+    spawn_empty_parent(&mut commands, Vec3::ZERO)
+        .insert(BestText {
+            number: best_number,
+        })
+        .push_children(&[best_text_ent])
+        .push_children(&best_num_ents);
+
+    let score_text_ents = spawn_text(
+        &mut commands,
+        &small_font_atlas,
+        "SCORE",
+        Vec3::new(field_width - 36.0, field_height - 26.0, TEXT_Z),
+    );
+
+    let score_number = NumberWidget::spawn(
+        &mut commands,
+        &large_nums_atlas,
+        Vec3::new(field_width - 48.0, field_height - 42.0, TEXT_Z),
+        LARGE_NUM_WIDTH,
+        NumberPadding::Zeros,
+        Color::WHITE,
+    );
+    let score_num_ents = *score_number.ents();
+
+    spawn_empty_parent(&mut commands, Vec3::ZERO)
+        .insert(ScoreText {
+            number: score_number,
+        })
+        .push_children(&score_text_ents)
+        .push_children(&score_num_ents);
+
+    let full_pip_material = materials.add(Color::RED.into());
+    let empty_pip_material = materials.add(Color::rgb(0.3, 0.3, 0.3).into());
+    let mut pip_ents = [Entity::new(0); PLAYER_MAX_HEALTH as usize];
+    for (i, ent) in pip_ents.iter_mut().enumerate() {
+        let i: f32 = i.cast();
+        *ent = commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    size: Vec2::splat(HEALTH_PIP_SIZE),
+                    ..Default::default()
+                },
+                material: full_pip_material.clone(),
+                transform: Transform::from_translation(Vec3::new(HEALTH_PIP_SPACING * i, 0.0, 0.0)),
                 ..Default::default()
             })
-            .id(),
-        commands
+            .id();
+    }
+
+    spawn_empty_parent(
+        &mut commands,
+        Vec3::new(field_width - 44.0, field_height - 54.0, TEXT_Z),
+    )
+    .insert(HealthPips {
+        pip_ents,
+        full_material: full_pip_material,
+        empty_material: empty_pip_material,
+    })
+    .push_children(&pip_ents);
+
+    let countdown_digit_ent = commands
+        .spawn_bundle(SpriteSheetBundle {
+            texture_atlas: large_nums_atlas,
+            ..Default::default()
+        })
+        .insert(LocalVisible::default())
+        .id();
+
+    let countdown_go_ent = commands
+        .spawn_bundle(SpriteBundle {
+            material: materials.add(countdown_go_tex.into()),
+            ..Default::default()
+        })
+        .insert(LocalVisible::default())
+        .id();
+
+    spawn_empty_parent(
+        &mut commands,
+        Vec3::new(field_width * 0.5, field_height * 0.6, TEXT_Z),
+    )
+    .insert(CountdownText {
+        digit_ent: countdown_digit_ent,
+        go_ent: countdown_go_ent,
+    })
+    .push_children(&[countdown_digit_ent, countdown_go_ent]);
+
+    let mut curve_arrow_ents = [Entity::new(0); CURVE_PREVIEW_SLOTS];
+    for (i, ent) in curve_arrow_ents.iter_mut().enumerate() {
+        let i: f32 = i.cast();
+        let t = Vec3::new(CURVE_PREVIEW_SPACING * i, 0.0, 0.0);
+
+        *ent = commands
             .spawn_bundle(SpriteSheetBundle {
-                texture_atlas: large_nums_atlas.clone(),
-                transform: Transform::from_translation(Vec3::new(
-                    (field_width * 0.5) + LARGE_NUM_WIDTH * 0.5,
-                    field_height - 30.0,
-                    TEXT_Z,
-                )),
+                texture_atlas: curve_arrows_atlas.clone(),
+                transform: Transform::from_translation(t),
                 ..Default::default()
             })
-            .id(),
-    ];
+            .id();
+    }
+
+    spawn_empty_parent(&mut commands, Vec3::new(16.0, field_height - 70.0, TEXT_Z))
+        .insert(CurvePreview {
+            arrow_ents: curve_arrow_ents,
+        })
+        .insert(LocalVisible::default())
+        .push_children(&curve_arrow_ents);
+
+    // Simulation step rate on the first line, app update rate below it
+    let spawn_debug_nums = |commands: &mut Commands, y: f32, color: Color| -> NumberWidget<3> {
+        NumberWidget::spawn(
+            commands,
+            &small_nums_atlas,
+            Vec3::new(8.0, y, TEXT_Z),
+            SMALL_NUM_WIDTH,
+            NumberPadding::Blank,
+            color,
+        )
+    };
+    let step_rate = spawn_debug_nums(&mut commands, field_height - 10.0, Color::WHITE);
+    let update_rate = spawn_debug_nums(&mut commands, field_height - 20.0, Color::YELLOW);
+    let framerate_ents = [*step_rate.ents(), *update_rate.ents()].concat();
 
     spawn_empty_parent(&mut commands, Vec3::ZERO)
-        .insert(TimeText {
-            number_ents: time_num_ents,
+        .insert(FramerateText {
+            step_rate,
+            update_rate,
         })
-        .push_children(&[time_text_ent])
-        .push_children(&time_num_ents);
+        .push_children(&framerate_ents);
+
+    // Average frame time in milliseconds at the top, then app update rate, then skipped steps
+    let frame_ms = spawn_debug_nums(&mut commands, 30.0, Color::WHITE);
+    let perf_update_rate = spawn_debug_nums(&mut commands, 20.0, Color::YELLOW);
+    let skipped_steps = spawn_debug_nums(&mut commands, 10.0, Color::RED);
+    let perf_ents = [
+        *frame_ms.ents(),
+        *perf_update_rate.ents(),
+        *skipped_steps.ents(),
+    ]
+    .concat();
+
+    spawn_empty_parent(&mut commands, Vec3::ZERO)
+        .insert(PerfText {
+            frame_ms,
+            update_rate: perf_update_rate,
+            skipped_steps,
+        })
+        .push_children(&perf_ents);
 }
 
 fn update_speed_text(
-    player: Res<Player>,
-    racers: Query<&Racer>,
+    config: Res<SpeedTextConfig>,
+    time_scale: Res<TimeScale>,
+    players: Query<(&Player, &Racer)>,
     mut speed_texts: Query<&mut SpeedText>,
     mut texts: Query<&mut TextureAtlasSprite>,
 ) {
-    let speed = racers.get(player.get_racer_ent()).map_or(0.0, |r| r.speed);
-    let speed_mph =
-        u32::conv_nearest(speed * f32::conv(MAX_NORMAL_DISPLAY_SPEED) / PLAYER_MAX_NORMAL_SPEED);
-
-    let digits: [u32; 3] = if speed_mph <= 999 {
-        [speed_mph / 100, (speed_mph / 10) % 10, speed_mph % 10]
-    } else {
-        [9, 9, 9]
-    };
+    // TODO: Give each player their own speed display
+    let (speed, is_over_rev_warning) = players
+        .iter()
+        .next()
+        .map_or((0.0, false), |(p, r)| (r.speed, p.is_over_rev_warning()));
+    let target_speed = speed * f32::conv(MAX_NORMAL_DISPLAY_SPEED) / PLAYER_MAX_NORMAL_SPEED;
+    let step = scaled_step(&time_scale);
+    let ease_amount = f32::min(config.ease_rate * step, 1.0);
 
     for mut speed_text in speed_texts.iter_mut() {
-        if speed_mph >= MAX_NORMAL_DISPLAY_SPEED {
+        speed_text.displayed_speed = if config.ease_speed {
+            speed_text.displayed_speed + ((target_speed - speed_text.displayed_speed) * ease_amount)
+        } else {
+            target_speed
+        };
+
+        let speed_mph = u32::conv_nearest(speed_text.displayed_speed);
+
+        let is_over_speed = speed_mph >= MAX_NORMAL_DISPLAY_SPEED;
+        if is_over_speed || is_over_rev_warning {
             speed_text.flash_timer.unpause();
         } else {
             speed_text.should_flash = true;
@@ -219,41 +652,298 @@ fn update_speed_text(
 
         if speed_text
             .flash_timer
-            .tick(Duration::from_secs_f32(TIME_STEP))
+            .tick(Duration::from_secs_f32(step))
             .just_finished()
         {
             speed_text.should_flash = !speed_text.should_flash;
         }
 
-        let color = if speed_text.should_flash && speed_mph >= MAX_NORMAL_DISPLAY_SPEED {
+        // The over-rev warning takes priority, since the player can do something about it
+        speed_text.number.color = if speed_text.should_flash && is_over_rev_warning {
+            Color::YELLOW
+        } else if speed_text.should_flash && is_over_speed {
             Color::RED
         } else {
             Color::WHITE
         };
-
-        for (digit, ent) in digits.iter().zip(&speed_text.num_ents) {
-            let mut sprite = texts.get_mut(*ent).expect(TEXT_NOT_INIT);
-            sprite.index = *digit;
-            sprite.color = color;
-        }
+        speed_text.number.set_value(speed_mph, &mut texts);
     }
 }
 
 fn update_time_text(
     game: Res<JoyrideGame>,
-    time_texts: Query<&TimeText>,
+    time_scale: Res<TimeScale>,
+    mut checkpoint_events: EventReader<CheckpointReached>,
+    mut time_texts: Query<&mut TimeText>,
     mut texts: Query<&mut TextureAtlasSprite>,
 ) {
+    let time_extended = checkpoint_events.iter().any(|event| !event.is_final);
+
     let rem_seconds =
         game.remaining_time.duration().as_secs_f32() - game.remaining_time.elapsed_secs();
 
-    let rem_seconds: u32 = u32::clamp(rem_seconds.cast_floor(), 0, 99);
-    let digits: [u32; 2] = [(rem_seconds / 10), (rem_seconds % 10)];
+    // Crash penalties can briefly take the duration below what's already elapsed
+    let rem_seconds: u32 = f32::max(rem_seconds, 0.0).cast_floor();
+
+    let step = scaled_step(&time_scale);
+    for mut time_text in time_texts.iter_mut() {
+        if time_extended {
+            time_text.flash_secs_left = TIME_EXTEND_FLASH_SECS;
+            time_text.flash_timer.reset();
+            time_text.should_flash = true;
+        }
+
+        if time_text.flash_secs_left > 0.0 {
+            time_text.flash_secs_left -= step;
+            if time_text
+                .flash_timer
+                .tick(Duration::from_secs_f32(step))
+                .just_finished()
+            {
+                time_text.should_flash = !time_text.should_flash;
+            }
+        } else {
+            time_text.should_flash = false;
+        }
+
+        time_text.number.color = if time_text.should_flash {
+            Color::GREEN
+        } else {
+            Color::WHITE
+        };
+        time_text.number.set_value(rem_seconds, &mut texts);
+    }
+}
+
+fn update_best_text(
+    high_scores: Res<HighScores>,
+    best_texts: Query<&BestText>,
+    mut texts: Query<&mut TextureAtlasSprite>,
+) {
+    if !high_scores.is_changed() {
+        return;
+    }
+
+    let best_distance = high_scores.best_distance.map_or(0.0, |record| record.value);
+    let meters: u32 = (best_distance * DISTANCE_TO_METERS).cast_floor();
+
+    for best_text in best_texts.iter() {
+        best_text.number.set_value(meters, &mut texts);
+    }
+}
+
+fn update_score_text(
+    score: Res<Score>,
+    score_texts: Query<&ScoreText>,
+    mut texts: Query<&mut TextureAtlasSprite>,
+) {
+    if !score.is_changed() {
+        return;
+    }
+
+    for score_text in score_texts.iter() {
+        score_text.number.set_value(score.points, &mut texts);
+    }
+}
+
+fn update_health_pips(
+    players: Query<&Player>,
+    health_pips: Query<&HealthPips>,
+    mut pip_materials: Query<&mut Handle<ColorMaterial>>,
+) {
+    let health = match players.iter().next() {
+        Some(player) => player.get_health(),
+        None => return,
+    };
+
+    for pips in health_pips.iter() {
+        for (i, pip_ent) in pips.pip_ents.iter().enumerate() {
+            let material = if i < usize::from(health) {
+                &pips.full_material
+            } else {
+                &pips.empty_material
+            };
+
+            let mut pip_material = pip_materials.get_mut(*pip_ent).expect(TEXT_NOT_INIT);
+            if *pip_material != *material {
+                *pip_material = material.clone();
+            }
+        }
+    }
+}
+
+// Counts down the seconds before the race, then shows "GO" for a moment once it starts
+fn update_countdown_text(
+    game: Res<JoyrideGame>,
+    race_state: Res<RaceState>,
+    mut countdown_texts: Query<(&CountdownText, &mut LocalVisible)>,
+    mut child_visibles: Query<&mut LocalVisible, Without<CountdownText>>,
+    mut texts: Query<&mut TextureAtlasSprite>,
+) {
+    let is_counting = *race_state == RaceState::Countdown;
+    let is_go =
+        *race_state == RaceState::Racing && game.remaining_time.elapsed_secs() < COUNTDOWN_GO_SECS;
+
+    let set_visible = |visible: &mut Mut<LocalVisible>, is_visible: bool| {
+        if visible.is_visible != is_visible {
+            visible.is_visible = is_visible;
+        }
+    };
+
+    for (countdown_text, mut visible) in countdown_texts.iter_mut() {
+        set_visible(&mut visible, is_counting || is_go);
+
+        let mut digit_visible = child_visibles
+            .get_mut(countdown_text.digit_ent)
+            .expect(TEXT_NOT_INIT);
+        set_visible(&mut digit_visible, is_counting);
+
+        let mut go_visible = child_visibles
+            .get_mut(countdown_text.go_ent)
+            .expect(TEXT_NOT_INIT);
+        set_visible(&mut go_visible, is_go);
+
+        if is_counting {
+            let secs_left = game.countdown.duration().as_secs_f32() - game.countdown.elapsed_secs();
+            let digit = u32::clamp(secs_left.cast_ceil(), 1, COUNTDOWN_SECS);
+
+            let mut sprite = texts
+                .get_mut(countdown_text.digit_ent)
+                .expect(TEXT_NOT_INIT);
+            sprite.index = digit;
+        }
+    }
+}
+
+fn update_curve_preview(
+    config: Res<CurvePreviewConfig>,
+    road_dyn: Res<RoadDynamic>,
+    mut previews: Query<(&CurvePreview, &mut LocalVisible)>,
+    mut texts: Query<&mut TextureAtlasSprite>,
+) {
+    for (preview, mut visible) in previews.iter_mut() {
+        if visible.is_visible != config.enabled {
+            visible.is_visible = config.enabled;
+        }
+        if !config.enabled {
+            continue;
+        }
+
+        for (i, ent) in preview.arrow_ents.iter().enumerate() {
+            let offset = SEGMENT_LENGTH * f32::conv(i + 1);
+            let curve = road_dyn.get_seg_curvature(offset);
+
+            let (index, color) = if curve.abs() >= CURVE_PREVIEW_SHARP {
+                (CURVE_ARROW_SHARP, Color::RED)
+            } else if curve.abs() >= CURVE_PREVIEW_GENTLE {
+                (CURVE_ARROW_GENTLE, Color::YELLOW)
+            } else {
+                (CURVE_ARROW_STRAIGHT, Color::WHITE)
+            };
 
-    for time_text in time_texts.iter() {
-        for (digit, ent) in digits.iter().zip(&time_text.number_ents) {
             let mut sprite = texts.get_mut(*ent).expect(TEXT_NOT_INIT);
-            sprite.index = *digit;
+            sprite.index = index;
+            sprite.color = color;
+            sprite.flip_x = curve > 0.0;
         }
     }
 }
+
+fn update_framerate_text(
+    debug_cfg: Res<DebugConfig>,
+    stats: Res<FixedFramerateStats>,
+    mut framerate_texts: Query<(&FramerateText, &mut LocalVisible)>,
+    mut texts: Query<&mut TextureAtlasSprite>,
+) {
+    let step_rate: u32 = stats.steps_per_second.cast_nearest();
+    let update_rate: u32 = stats.updates_per_second.cast_nearest();
+
+    for (framerate_text, mut visible) in framerate_texts.iter_mut() {
+        if visible.is_visible != debug_cfg.debug_framerate {
+            visible.is_visible = debug_cfg.debug_framerate;
+        }
+        if !debug_cfg.debug_framerate {
+            continue;
+        }
+
+        framerate_text.step_rate.set_value(step_rate, &mut texts);
+        framerate_text
+            .update_rate
+            .set_value(update_rate, &mut texts);
+    }
+}
+
+fn debug_perf_overlay(
+    debug_cfg: Res<DebugConfig>,
+    loop_section_timer: Res<LoopSectionTimer>,
+    stats: Res<FixedFramerateStats>,
+    mut perf_texts: Query<(&PerfText, &mut LocalVisible)>,
+    mut texts: Query<&mut TextureAtlasSprite>,
+) {
+    let frame_ms: u32 = (loop_section_timer.get_average_frame_secs() * 1000.0).cast_nearest();
+    let update_rate: u32 = stats.updates_per_second.cast_nearest();
+    let skipped_steps: u32 = u64::min(stats.skipped_steps, 999).cast();
+
+    for (perf_text, mut visible) in perf_texts.iter_mut() {
+        if visible.is_visible != debug_cfg.debug_perf {
+            visible.is_visible = debug_cfg.debug_perf;
+        }
+        if !debug_cfg.debug_perf {
+            continue;
+        }
+
+        perf_text.frame_ms.set_value(frame_ms, &mut texts);
+        perf_text.update_rate.set_value(update_rate, &mut texts);
+        perf_text.skipped_steps.set_value(skipped_steps, &mut texts);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_lays_out_glyphs_with_gaps_for_spaces() {
+        let w = SMALL_FONT_WIDTH;
+        assert_eq!(layout_text("Go 2"), vec![(6, 0.0), (14, w), (28, w * 3.0)]);
+    }
+
+    #[test]
+    fn font_covers_letters_and_digits() {
+        assert_eq!(get_font_sprite_index('A'), Some(0));
+        assert_eq!(get_font_sprite_index('z'), Some(25));
+        assert_eq!(get_font_sprite_index('0'), Some(26));
+        assert_eq!(get_font_sprite_index('9'), Some(35));
+        assert_eq!(get_font_sprite_index(' '), None);
+        assert!(get_font_sprite_index('9').unwrap() < SMALL_FONT_SPRITE_DESC.columns);
+    }
+
+    fn test_widget<const N: usize>(padding: NumberPadding) -> NumberWidget<N> {
+        NumberWidget {
+            digit_ents: [Entity::new(0); N],
+            padding,
+            color: Color::WHITE,
+        }
+    }
+
+    #[test]
+    fn number_widget_pads_and_clamps_digits() {
+        let zeros = test_widget::<3>(NumberPadding::Zeros);
+        assert_eq!(zeros.get_digits(42), [Some(0), Some(4), Some(2)]);
+        assert_eq!(zeros.get_digits(0), [Some(0), Some(0), Some(0)]);
+
+        // The last digit is always drawn, so zero still shows
+        let blank = test_widget::<3>(NumberPadding::Blank);
+        assert_eq!(blank.get_digits(42), [None, Some(4), Some(2)]);
+        assert_eq!(blank.get_digits(0), [None, None, Some(0)]);
+        assert_eq!(blank.get_digits(305), [Some(3), Some(0), Some(5)]);
+
+        assert_eq!(NumberWidget::<3>::max_value(), 999);
+        assert_eq!(zeros.get_digits(999), [Some(9), Some(9), Some(9)]);
+        assert_eq!(zeros.get_digits(1000), [Some(9), Some(9), Some(9)]);
+        assert_eq!(blank.get_digits(u32::MAX), [Some(9), Some(9), Some(9)]);
+
+        // More digits than a u32 can fill still clamps to what it can hold
+        assert_eq!(NumberWidget::<12>::max_value(), u32::MAX);
+    }
+}
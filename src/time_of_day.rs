@@ -0,0 +1,86 @@
+use bevy::prelude::*;
+use easy_cast::*;
+
+// The tint at each point in the day, from the dead of night up to full daylight
+const TINT_GRADIENT: [(f32, [f32; 3]); 4] = [
+    (0.0, [0.2, 0.2, 0.4]),
+    (0.3, [0.45, 0.35, 0.6]),
+    (0.6, [0.95, 0.6, 0.45]),
+    (1.0, [1.0, 1.0, 1.0]),
+];
+
+// Below this, it's dark enough that headlights come on and the distance fades out
+const DARK_THRESHOLD: f32 = 0.35;
+
+// How far into the day the track is set. 0 is night, 1 is full daylight, which leaves
+// every color exactly as authored
+pub struct TimeOfDay {
+    value: f32,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self { value: 1.0 }
+    }
+}
+
+impl TimeOfDay {
+    pub fn set(&mut self, value: f32) {
+        self.value = f32::clamp(value, 0.0, 1.0);
+    }
+
+    pub fn is_full_daylight(&self) -> bool {
+        self.value >= 1.0
+    }
+
+    pub fn is_dark(&self) -> bool {
+        self.value < DARK_THRESHOLD
+    }
+
+    // 0 until it gets dark, rising to 1 at the dead of night
+    pub fn get_darkness(&self) -> f32 {
+        f32::clamp(1.0 - (self.value / DARK_THRESHOLD), 0.0, 1.0)
+    }
+
+    pub fn get_tint(&self) -> [f32; 3] {
+        let upper_idx = TINT_GRADIENT
+            .iter()
+            .position(|(stop, _)| *stop >= self.value)
+            .unwrap_or(TINT_GRADIENT.len() - 1);
+        if upper_idx == 0 {
+            return TINT_GRADIENT[0].1;
+        }
+
+        let (lower_stop, lower) = TINT_GRADIENT[upper_idx - 1];
+        let (upper_stop, upper) = TINT_GRADIENT[upper_idx];
+        let t = (self.value - lower_stop) / (upper_stop - lower_stop);
+
+        let mut tint = [0.0; 3];
+        for (i, channel) in tint.iter_mut().enumerate() {
+            *channel = lower[i] + ((upper[i] - lower[i]) * t);
+        }
+        tint
+    }
+
+    pub fn get_tint_color(&self) -> Color {
+        let [r, g, b] = self.get_tint();
+        Color::rgb(r, g, b)
+    }
+
+    // Tints a color packed as 0xRRGGBBAA, leaving alpha alone
+    pub fn tint_rgba_u32(&self, color: u32) -> u32 {
+        if self.is_full_daylight() {
+            return color;
+        }
+
+        let tint = self.get_tint();
+        let mut tinted = color & 0xFF;
+        for (i, channel_tint) in tint.iter().enumerate() {
+            let shift = 24 - (i * 8);
+            let channel = (color >> shift) & 0xFF;
+            let channel = u32::conv_nearest(f32::conv(channel) * channel_tint);
+            tinted |= u32::min(channel, 0xFF) << shift;
+        }
+        tinted
+    }
+}
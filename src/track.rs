@@ -0,0 +1,443 @@
+use bevy::prelude::*;
+use easy_cast::*;
+use rand::{rngs::StdRng, Rng};
+
+use crate::{
+    joyride::{GameRng, RngStream},
+    rival::RivalConfig,
+    road::{
+        CheckpointConfig, EndlessMode, RoadBounds, RoadDynamic, RoadSegment, RoadStatic, RoadTheme,
+        RoadsideTerrain, TrackConfig, SEGMENT_LENGTH,
+    },
+    road_object::SpawnTable,
+    weather::Weather,
+};
+
+// How many segments past the far end of the drawn road are kept generated
+const GENERATE_AHEAD_SEGS: usize = 4;
+
+// The drawn road is only ever a couple of segments deep, so this comfortably covers it
+const NUM_INITIAL_SEGS: usize = GENERATE_AHEAD_SEGS * 2;
+
+pub struct Systems {
+    pub extend_track: SystemSet,
+}
+
+impl Systems {
+    pub fn new() -> Self {
+        Self {
+            extend_track: SystemSet::new().with_system(extend_generated_track.system()),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct TrackGeneratorConfig {
+    // If set, the track is generated on the fly rather than loaded
+    pub enabled: bool,
+
+    // The sharpest curve a generated segment can have, in either direction
+    pub max_curve: f32,
+
+    // The largest change in curvature from one segment to the next
+    pub max_curve_change: f32,
+
+    // The chance of any given segment being on a hill, and how steep that hill can be
+    pub hill_chance: f64,
+    pub max_hill: f32,
+
+    // How many segments apart checkpoints are placed
+    pub checkpoint_interval: usize,
+}
+
+impl Default for TrackGeneratorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_curve: 0.6,
+            max_curve_change: 0.3,
+            hill_chance: 0.2,
+            max_hill: 0.01,
+            checkpoint_interval: 25,
+        }
+    }
+}
+
+// Multipliers applied on top of the base difficulty
+#[derive(Clone, Copy, serde::Deserialize)]
+#[serde(default)]
+pub struct DifficultyScalars {
+    pub curve: f32,
+    pub rival_speed: f32,
+    pub rival_spawn_chance: f32,
+
+    // On top of the difficulty setting's obstacle weight. See DifficultySettings::obstacle_weight
+    pub obstacle_weight: f32,
+
+    // How much time a checkpoint adds, against CheckpointConfig::extend_secs
+    pub checkpoint_bonus: f32,
+}
+
+impl Default for DifficultyScalars {
+    fn default() -> Self {
+        Self {
+            curve: 1.0,
+            rival_speed: 1.0,
+            rival_spawn_chance: 1.0,
+            obstacle_weight: 1.0,
+            checkpoint_bonus: 1.0,
+        }
+    }
+}
+
+// Maps distance travelled to difficulty. Holds at base difficulty for a grace distance,
+// then ramps linearly up to the maximum
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct DifficultyCurve {
+    pub enabled: bool,
+    pub grace_distance: f32,
+    pub ramp_distance: f32,
+    pub max_scalars: DifficultyScalars,
+}
+
+impl Default for DifficultyCurve {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            grace_distance: SEGMENT_LENGTH * 20.0,
+            ramp_distance: SEGMENT_LENGTH * 200.0,
+            max_scalars: DifficultyScalars {
+                curve: 1.6,
+                rival_speed: 1.5,
+                rival_spawn_chance: 1.5,
+                obstacle_weight: 1.5,
+                checkpoint_bonus: 0.7,
+            },
+        }
+    }
+}
+
+impl DifficultyCurve {
+    pub fn get_scalars(&self, distance: f32) -> DifficultyScalars {
+        if !self.enabled {
+            return DifficultyScalars::default();
+        }
+
+        let t = f32::clamp(
+            (distance - self.grace_distance) / self.ramp_distance,
+            0.0,
+            1.0,
+        );
+        let lerp = |max: f32| 1.0 + ((max - 1.0) * t);
+
+        DifficultyScalars {
+            curve: lerp(self.max_scalars.curve),
+            rival_speed: lerp(self.max_scalars.rival_speed),
+            rival_spawn_chance: lerp(self.max_scalars.rival_spawn_chance),
+            obstacle_weight: lerp(self.max_scalars.obstacle_weight),
+            checkpoint_bonus: lerp(self.max_scalars.checkpoint_bonus),
+        }
+    }
+}
+
+// Everything a track file can set, picked with `--track <path>`. Any section left out keeps its
+// defaults, so a file only needs to say what makes the track different
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+pub struct TrackFile {
+    pub endless: EndlessMode,
+    pub checkpoints: CheckpointConfig,
+    pub generator: TrackGeneratorConfig,
+    pub difficulty_curve: DifficultyCurve,
+    pub weather: Weather,
+    pub theme: RoadTheme,
+
+    // The lineup at the start. Left out, there are no rivals lined up, only those spawned along
+    // the road. TrackConfig::rivals_path still replaces it, if set
+    pub rivals: Option<RivalConfig>,
+
+    // See TrackConfig::finish_seg_idx, TrackConfig::barriers and TrackConfig::terrain
+    pub finish_seg_idx: Option<usize>,
+    pub barriers: Option<RoadBounds>,
+    pub terrain: Option<RoadsideTerrain>,
+
+    // See TrackConfig::time_of_day. Left out, the track is raced in full daylight
+    pub time_of_day: Option<f32>,
+
+    // The segments to race, see TrackConfig::path. Left out, the default track is raced
+    pub segs_path: Option<String>,
+
+    // See TrackConfig::spawn_tables and TrackConfig::spawn_seed
+    pub spawn_tables: Vec<SpawnTable>,
+    pub spawn_seed: Option<u64>,
+}
+
+impl TrackFile {
+    // Unlike a missing default track, a track that was asked for by name has to load, so a
+    // missing or malformed file is fatal
+    pub fn from_args() -> Self {
+        let args: Vec<String> = std::env::args().collect();
+        let path = args
+            .windows(2)
+            .find(|pair| pair[0] == "--track")
+            .map(|pair| pair[1].clone());
+
+        match path {
+            Some(path) => Self::load(&path),
+            None => Self::default(),
+        }
+    }
+
+    pub fn load(path: &str) -> Self {
+        let file = std::fs::File::open(path)
+            .unwrap_or_else(|e| panic!("Failed to open track file {}: {}", path, e));
+        ron::de::from_reader(file)
+            .unwrap_or_else(|e| panic!("Failed to load track file {}: {}", path, e))
+    }
+
+    // Must happen before the game's own resources are set up, which only fill in what's missing
+    pub fn insert_resources(self, app: &mut AppBuilder) {
+        let track_defaults = TrackConfig::default();
+        if let Some(rivals) = self.rivals {
+            app.insert_resource(rivals);
+        }
+        app.insert_resource(self.endless)
+            .insert_resource(self.checkpoints)
+            .insert_resource(self.generator)
+            .insert_resource(self.difficulty_curve)
+            .insert_resource(self.weather)
+            .insert_resource(self.theme)
+            .insert_resource(TrackConfig {
+                path: self.segs_path.unwrap_or(track_defaults.path),
+                finish_seg_idx: self.finish_seg_idx,
+                barriers: self.barriers,
+                terrain: self.terrain,
+                time_of_day: self.time_of_day.unwrap_or(track_defaults.time_of_day),
+                spawn_tables: self.spawn_tables,
+                spawn_seed: self.spawn_seed,
+                ..track_defaults
+            });
+    }
+}
+
+pub struct TrackGenerator {
+    max_curve: f32,
+    max_curve_change: f32,
+    hill_chance: f64,
+    max_hill: f32,
+    checkpoint_interval: usize,
+
+    rng: StdRng,
+    last_curve: f32,
+    segs_since_checkpoint: usize,
+}
+
+impl TrackGenerator {
+    pub fn new(config: &TrackGeneratorConfig, game_rng: &GameRng) -> Self {
+        Self {
+            max_curve: config.max_curve,
+            max_curve_change: config.max_curve_change,
+            hill_chance: config.hill_chance,
+            max_hill: config.max_hill,
+            checkpoint_interval: config.checkpoint_interval,
+            rng: game_rng.make_stream(RngStream::Track),
+            last_curve: 0.0,
+            segs_since_checkpoint: 0,
+        }
+    }
+
+    pub fn next_segment(&mut self, difficulty: &DifficultyScalars) -> RoadSegment {
+        let max_curve = self.max_curve * difficulty.curve;
+        let max_curve_change = self.max_curve_change * difficulty.curve;
+
+        let curve_change = self.rng.gen_range(-max_curve_change..=max_curve_change);
+        let mut curve = f32::clamp(self.last_curve + curve_change, -max_curve, max_curve);
+
+        // Don't whip straight from a hard turn into a turn the other way, give a straight between
+        if curve * self.last_curve < 0.0 && self.last_curve.abs() > max_curve_change {
+            curve = 0.0;
+        }
+        self.last_curve = curve;
+
+        let hill = if self.rng.gen_bool(self.hill_chance) {
+            self.rng.gen_range(-self.max_hill..=self.max_hill)
+        } else {
+            0.0
+        };
+
+        self.segs_since_checkpoint += 1;
+        let checkpoint = self.segs_since_checkpoint >= self.checkpoint_interval;
+        if checkpoint {
+            self.segs_since_checkpoint = 0;
+        }
+
+        RoadSegment {
+            curve,
+            hill,
+            spawn_object_type: None,
+            ambient_tint: None,
+            audio_cue: None,
+            checkpoint,
+            spawn_table: None,
+        }
+    }
+
+    // The track always begins with a straight, regardless of seed.
+    // These are generated at base difficulty, as they're well within any grace distance
+    pub fn generate_initial_segments(&mut self) -> Vec<RoadSegment> {
+        let mut segs = vec![RoadSegment {
+            curve: 0.0,
+            hill: 0.0,
+            spawn_object_type: None,
+            ambient_tint: None,
+            audio_cue: None,
+            checkpoint: false,
+            spawn_table: None,
+        }];
+        while segs.len() < NUM_INITIAL_SEGS {
+            segs.push(self.next_segment(&DifficultyScalars::default()));
+        }
+        segs
+    }
+}
+
+fn extend_generated_track(
+    generator: Option<ResMut<TrackGenerator>>,
+    difficulty_curve: Res<DifficultyCurve>,
+    road_static: Res<RoadStatic>,
+    mut road_dyn: ResMut<RoadDynamic>,
+) {
+    let mut generator = match generator {
+        Some(generator) => generator,
+        None => return, // No-op if the track isn't generated
+    };
+
+    let z_map = road_static.z_map();
+    let far_z = z_map[z_map.len() - 1];
+    let far_seg_idx = road_dyn.query_road_point(far_z).seg_idx;
+
+    while road_dyn.get_num_segs() <= far_seg_idx + GENERATE_AHEAD_SEGS {
+        // Difficulty is keyed on where the segment is, so generation stays deterministic
+        let seg_start_z = f32::conv(road_dyn.get_num_segs()) * SEGMENT_LENGTH;
+        let difficulty = difficulty_curve.get_scalars(seg_start_z);
+        road_dyn.push_seg(generator.next_segment(&difficulty));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::road::{load_track, Terrain};
+
+    #[test]
+    fn track_file_sections_left_out_keep_defaults() {
+        let track_file: TrackFile = ron::de::from_str("(endless: (enabled: true))").unwrap();
+        assert!(track_file.endless.enabled);
+        assert_eq!(
+            track_file.checkpoints.total_laps,
+            CheckpointConfig::default().total_laps
+        );
+    }
+
+    #[test]
+    fn endless_track_file_parses() {
+        let track_file = TrackFile::load("assets/tracks/endless.ron");
+        assert!(track_file.endless.enabled);
+    }
+
+    #[test]
+    fn generated_track_file_enables_generator() {
+        let track_file = TrackFile::load("assets/tracks/generated.ron");
+        assert!(track_file.generator.enabled);
+        assert!(!TrackFile::default().generator.enabled);
+    }
+
+    #[test]
+    fn difficulty_curve_ramps_once_enabled_by_track_file() {
+        let track_file = TrackFile::load("assets/tracks/generated.ron");
+        let curve = &track_file.difficulty_curve;
+        assert!(curve.enabled);
+
+        let start = curve.get_scalars(0.0);
+        let end = curve.get_scalars(curve.grace_distance + curve.ramp_distance);
+        assert_eq!(start.curve, 1.0);
+        assert_eq!(end.curve, curve.max_scalars.curve);
+        assert!(end.rival_speed > start.rival_speed);
+        assert!(end.obstacle_weight > start.obstacle_weight);
+        assert!(end.checkpoint_bonus < start.checkpoint_bonus);
+    }
+
+    #[test]
+    fn rain_track_file_makes_it_rain() {
+        let track_file = TrackFile::load("assets/tracks/rain.ron");
+        assert!(track_file.weather.is_raining());
+        assert!(!TrackFile::default().weather.is_raining());
+    }
+
+    #[test]
+    fn walled_track_file_sets_barriers() {
+        let track_file = TrackFile::load("assets/tracks/walled.ron");
+        let barriers = track_file.barriers.expect("The track has no barriers");
+        assert!(barriers.left < 0.0 && barriers.right > 0.0);
+        assert!(TrackFile::default().barriers.is_none());
+    }
+
+    #[test]
+    fn countryside_track_file_sets_terrain() {
+        let track_file = TrackFile::load("assets/tracks/countryside.ron");
+        let terrain = track_file.terrain.expect("The track has no terrain");
+        assert_eq!(terrain.left, Terrain::Grass);
+        assert_eq!(terrain.right, Terrain::Gravel);
+        assert!(TrackFile::default().terrain.is_none());
+    }
+
+    #[test]
+    fn sprint_track_file_sets_finish_line() {
+        let track_file = TrackFile::load("assets/tracks/sprint.ron");
+        assert_eq!(track_file.finish_seg_idx, Some(2));
+        assert!(TrackFile::default().finish_seg_idx.is_none());
+    }
+
+    #[test]
+    fn grand_prix_track_file_lines_up_rivals() {
+        let track_file = TrackFile::load("assets/tracks/grand_prix.ron");
+        let rivals = track_file.rivals.expect("The track has no rivals");
+        assert!(rivals.count > 0);
+        assert!(TrackFile::default().rivals.is_none());
+    }
+
+    // Every table a segment refers to has to be in the track file, or the segment spawns nothing
+    #[test]
+    fn traffic_track_file_rolls_spawn_tables() {
+        let track_file = TrackFile::load("assets/tracks/traffic.ron");
+        assert!(track_file.spawn_seed.is_some());
+
+        let segs = load_track(&track_file.segs_path.expect("The track has no segments"));
+        let table_idxs: Vec<usize> = segs.iter().filter_map(|seg| seg.spawn_table).collect();
+        assert!(!table_idxs.is_empty(), "No segment rolls a spawn table");
+        for table_idx in table_idxs {
+            assert!(table_idx < track_file.spawn_tables.len());
+        }
+    }
+
+    #[test]
+    fn themed_track_files_set_theme() {
+        let forest = TrackFile::load("assets/tracks/forest.ron");
+        assert_eq!(forest.theme, RoadTheme::Forest);
+        let city = TrackFile::load("assets/tracks/city.ron");
+        assert_eq!(city.theme, RoadTheme::City);
+        assert_eq!(TrackFile::default().theme, RoadTheme::Desert);
+    }
+
+    #[test]
+    fn dusk_track_file_sets_time_of_day() {
+        let track_file = TrackFile::load("assets/tracks/dusk.ron");
+        let time_of_day = track_file
+            .time_of_day
+            .expect("The track has no time of day");
+        assert!(time_of_day < TrackConfig::default().time_of_day);
+        assert!(TrackFile::default().time_of_day.is_none());
+    }
+}
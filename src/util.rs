@@ -1,7 +1,9 @@
+use bevy::asset::LoadState;
 use bevy::ecs::system::EntityCommands;
 use bevy::prelude::TextureAtlas;
 
 use bevy::prelude::*;
+use bevy::render::texture::{Extent3d, TextureDimension, TextureFormat};
 use easy_cast::*;
 
 // Create a heap-stored array without allocating the array on the stack first (which could overflow it)
@@ -35,12 +37,129 @@ impl SpriteGridDesc {
         return (y * self.columns) + x;
     }
 
+    // Picks the LOD level (and so the sprite row) for a scale, from a descending scale mapping.
+    // Scales past the end of the mapping are clamped to the last row this grid actually has.
+    // So is a NaN scale, which would otherwise sort above every threshold
+    pub fn get_lod_level(&self, lod_scale_mapping: &[f32], scale: f32) -> u32 {
+        if scale.is_nan() {
+            return self.rows - 1;
+        }
+
+        let lod_level: u32 = lod_scale_mapping
+            .binary_search_by(|x| scale.total_cmp(x))
+            .unwrap_or_else(|x| x)
+            .cast();
+        u32::min(lod_level, self.rows - 1)
+    }
+
+    // How much to scale a sprite drawn from a LOD level's row, so that its size follows the
+    // continuous scale instead of jumping each time the row changes. A row is drawn at the scale
+    // it starts being used from, which is the threshold before it (or full size for the first row),
+    // and the sprite is never blown up past that
+    pub fn get_lod_draw_scale(&self, lod_scale_mapping: &[f32], scale: f32, lod_level: u32) -> f32 {
+        if scale.is_nan() {
+            return 1.0;
+        }
+
+        let row_scale = match lod_level.checked_sub(1) {
+            Some(prev_level) => lod_scale_mapping
+                .get(usize::conv(prev_level))
+                .copied()
+                .unwrap_or(1.0),
+            None => 1.0,
+        };
+        f32::min(scale / row_scale, 1.0)
+    }
+
     pub fn make_atlas(&self, texture: Handle<Texture>) -> TextureAtlas {
         let tile_size = Vec2::new(self.tile_size.cast(), self.tile_size.cast());
         TextureAtlas::from_grid(texture, tile_size, self.columns.cast(), self.rows.cast())
     }
 }
 
+// Textures that haven't loaded after this long are treated as missing
+const TEXTURE_LOAD_TIMEOUT_SECS: f32 = 10.0;
+
+// Missing textures are swapped for a magenta checkerboard, so they stand out instead of vanishing
+const PLACEHOLDER_TEXTURE_SIZE: u32 = 64;
+const PLACEHOLDER_CHECK_SIZE: u32 = 8;
+
+struct PendingTexture {
+    handle: Handle<Texture>,
+    path: String,
+    requested_at_secs: f32,
+}
+
+// Loads textures through the asset server, keeping track of them until they've finished loading
+#[derive(Default)]
+pub struct TextureLoader {
+    pending: Vec<PendingTexture>,
+    elapsed_secs: f32,
+}
+
+impl TextureLoader {
+    pub fn load(&mut self, asset_server: &AssetServer, path: &str) -> Handle<Texture> {
+        let handle = asset_server.load(path);
+        self.pending.push(PendingTexture {
+            handle: handle.clone(),
+            path: path.to_string(),
+            requested_at_secs: self.elapsed_secs,
+        });
+        handle
+    }
+}
+
+pub fn substitute_missing_textures(
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    mut loader: ResMut<TextureLoader>,
+    mut textures: ResMut<Assets<Texture>>,
+) {
+    if loader.pending.is_empty() {
+        return;
+    }
+
+    loader.elapsed_secs += time.delta_seconds();
+    let elapsed_secs = loader.elapsed_secs;
+
+    loader.pending.retain(|pending| {
+        let is_missing = match asset_server.get_load_state(&pending.handle) {
+            LoadState::Loaded => return false,
+            LoadState::Failed => true,
+            _ => elapsed_secs - pending.requested_at_secs > TEXTURE_LOAD_TIMEOUT_SECS,
+        };
+
+        if is_missing {
+            warn!("Texture {} is missing, using a placeholder", pending.path);
+            textures.set_untracked(&pending.handle, make_placeholder_texture());
+        }
+        !is_missing
+    });
+}
+
+fn make_placeholder_texture() -> Texture {
+    let size = PLACEHOLDER_TEXTURE_SIZE;
+    let mut data = Vec::with_capacity(usize::conv(size * size * 4));
+    for y in 0..size {
+        for x in 0..size {
+            let is_magenta = ((x / PLACEHOLDER_CHECK_SIZE) + (y / PLACEHOLDER_CHECK_SIZE)) % 2 == 0;
+            let pixel: [u8; 4] = if is_magenta {
+                [255, 0, 255, 255]
+            } else {
+                [0, 0, 0, 255]
+            };
+            data.extend_from_slice(&pixel);
+        }
+    }
+
+    Texture::new(
+        Extent3d::new(size, size, 1),
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
 pub struct LocalVisible {
     pub is_visible: bool,
 }
@@ -65,24 +184,32 @@ pub fn spawn_empty_parent<'a, 'b>(
 
 pub fn propagate_visibility_system(
     mut root_query: Query<
-        (Entity, Option<&Children>, &LocalVisible, &mut Visible),
+        (
+            Entity,
+            Option<&Children>,
+            &LocalVisible,
+            Option<&mut Visible>,
+        ),
         Without<Parent>,
     >,
     changed_vis_query: Query<Entity, Changed<LocalVisible>>,
-    mut visible_query: Query<(&LocalVisible, &mut Visible), With<Parent>>,
-    children_query: Query<Option<&Children>, (With<Parent>, With<Visible>)>,
+    mut visible_query: Query<(Option<&LocalVisible>, Option<&mut Visible>), With<Parent>>,
+    children_query: Query<Option<&Children>, With<Parent>>,
 ) {
-    for (entity, children, local_vis, mut visible) in root_query.iter_mut() {
-        let mut changed = false;
-        if changed_vis_query.get(entity).is_ok() {
-            visible.is_visible = local_vis.is_visible;
-            changed = true;
+    for (entity, children, local_vis, visible) in root_query.iter_mut() {
+        let changed = changed_vis_query.get(entity).is_ok();
+
+        // Roots without a Visible component (such as empty parents) still control their subtree
+        if let Some(mut visible) = visible {
+            if changed {
+                visible.is_visible = local_vis.is_visible;
+            }
         }
 
         if let Some(children) = children {
             for child in children.iter() {
                 propagate_visibility_recursive(
-                    visible.is_visible,
+                    local_vis.is_visible,
                     &changed_vis_query,
                     &mut visible_query,
                     &children_query,
@@ -97,20 +224,26 @@ pub fn propagate_visibility_system(
 fn propagate_visibility_recursive(
     is_parent_visible: bool,
     changed_vis_query: &Query<Entity, Changed<LocalVisible>>,
-    visible_query: &mut Query<(&LocalVisible, &mut Visible), With<Parent>>,
-    children_query: &Query<Option<&Children>, (With<Parent>, With<Visible>)>,
+    visible_query: &mut Query<(Option<&LocalVisible>, Option<&mut Visible>), With<Parent>>,
+    children_query: &Query<Option<&Children>, With<Parent>>,
     entity: Entity,
     mut changed: bool,
 ) {
     changed |= changed_vis_query.get(entity).is_ok();
 
-    let visible = if let Ok((local_vis, mut visible)) = visible_query.get_mut(entity) {
-        if changed {
-            visible.is_visible = is_parent_visible && local_vis.is_visible;
+    // Entities missing either visibility component still pass visibility on to their children,
+    // so they don't cut off propagation to the rest of the subtree
+    let visible = match visible_query.get_mut(entity) {
+        Ok((local_vis, visible)) => {
+            let is_visible = is_parent_visible && local_vis.map_or(true, |v| v.is_visible);
+            if let Some(mut visible) = visible {
+                if changed {
+                    visible.is_visible = is_visible;
+                }
+            }
+            is_visible
         }
-        is_parent_visible && local_vis.is_visible
-    } else {
-        return;
+        Err(_) => is_parent_visible,
     };
 
     if let Ok(Some(children)) = children_query.get(entity) {
@@ -126,3 +259,157 @@ fn propagate_visibility_recursive(
         }
     }
 }
+
+// Timers don't implement serde, so they're saved as this instead. See timer_serde
+#[derive(serde::Serialize, serde::Deserialize)]
+struct TimerState {
+    duration_secs: f32,
+    elapsed_secs: f32,
+    repeating: bool,
+}
+
+impl TimerState {
+    fn new(timer: &Timer) -> Self {
+        Self {
+            duration_secs: timer.duration().as_secs_f32(),
+            elapsed_secs: timer.elapsed_secs(),
+            repeating: timer.repeating(),
+        }
+    }
+
+    // Whether the timer has finished is picked up again on its next tick
+    fn into_timer(self) -> Timer {
+        let mut timer = Timer::from_seconds(self.duration_secs, self.repeating);
+        timer.set_elapsed(std::time::Duration::from_secs_f32(self.elapsed_secs));
+        timer
+    }
+}
+
+// For use as #[serde(with = "timer_serde")] on Timer fields
+pub mod timer_serde {
+    use bevy::prelude::Timer;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::TimerState;
+
+    pub fn serialize<S: Serializer>(timer: &Timer, serializer: S) -> Result<S::Ok, S::Error> {
+        TimerState::new(timer).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Timer, D::Error> {
+        TimerState::deserialize(deserializer).map(TimerState::into_timer)
+    }
+}
+
+// As timer_serde, for Option<Timer> fields
+pub mod opt_timer_serde {
+    use bevy::prelude::Timer;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::TimerState;
+
+    pub fn serialize<S: Serializer>(
+        timer: &Option<Timer>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        timer.as_ref().map(TimerState::new).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Timer>, D::Error> {
+        Option::<TimerState>::deserialize(deserializer).map(|t| t.map(TimerState::into_timer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::schedule::Stage;
+
+    use super::*;
+
+    #[test]
+    fn visibility_propagates_past_entities_missing_visible() {
+        let mut world = World::default();
+        let leaf = world
+            .spawn()
+            .insert(LocalVisible::default())
+            .insert(Visible::default())
+            .id();
+        let middle = world.spawn().id();
+        world.entity_mut(middle).push_children(&[leaf]);
+        let root = world.spawn().insert(LocalVisible::default()).id();
+        world.entity_mut(root).push_children(&[middle]);
+
+        let mut stage = SystemStage::single(propagate_visibility_system.system());
+        stage.run(&mut world);
+        assert!(world.get::<Visible>(leaf).unwrap().is_visible);
+
+        world.get_mut::<LocalVisible>(root).unwrap().is_visible = false;
+        stage.run(&mut world);
+        assert!(!world.get::<Visible>(leaf).unwrap().is_visible);
+
+        world.get_mut::<LocalVisible>(root).unwrap().is_visible = true;
+        stage.run(&mut world);
+        assert!(world.get::<Visible>(leaf).unwrap().is_visible);
+    }
+
+    #[test]
+    fn lod_level_follows_descending_scale_mapping() {
+        let desc = SpriteGridDesc {
+            tile_size: 32,
+            rows: 3,
+            columns: 1,
+        };
+        let mapping = [0.5, 0.25, 0.1];
+
+        assert_eq!(desc.get_lod_level(&mapping, 1.0), 0);
+        assert_eq!(desc.get_lod_level(&mapping, 0.4), 1);
+        assert_eq!(desc.get_lod_level(&mapping, 0.2), 2);
+
+        // Past the end of the mapping, and NaN, both clamp to the last row the grid has
+        assert_eq!(desc.get_lod_level(&mapping, 0.01), 2);
+        assert_eq!(desc.get_lod_level(&mapping, f32::NAN), 2);
+    }
+
+    // Shrinks a sprite down through every LOD threshold, and past the end of the mapping. The size
+    // it ends up drawn at, the row's own size times the draw scale, should shrink steadily
+    // without popping as the row changes
+    #[test]
+    fn lod_draw_scale_shrinks_steadily_across_thresholds() {
+        let desc = SpriteGridDesc {
+            tile_size: 32,
+            rows: 3,
+            columns: 1,
+        };
+        let mapping = [0.5, 0.25, 0.1];
+        let drawn_size = |scale: f32| {
+            let lod_level = desc.get_lod_level(&mapping, scale);
+            let row_size = match lod_level {
+                0 => 1.0,
+                _ => mapping[usize::conv(lod_level - 1)],
+            };
+            row_size * desc.get_lod_draw_scale(&mapping, scale, lod_level)
+        };
+
+        let mut last_size = drawn_size(1.0);
+        for i in (1..1000u32).rev() {
+            let scale = f32::conv(i) * 0.001;
+            let size = drawn_size(scale);
+            assert!(
+                size <= last_size,
+                "Grew from {} to {} at scale {}",
+                last_size,
+                size,
+                scale
+            );
+            assert!(
+                (size - scale).abs() < 0.0001,
+                "Drawn at {} instead of {}",
+                size,
+                scale
+            );
+            last_size = size;
+        }
+    }
+}
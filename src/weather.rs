@@ -0,0 +1,18 @@
+// Clear weather leaves the driving exactly as tuned. See the RAIN_ constants in player.rs
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Deserialize)]
+pub enum Weather {
+    Clear,
+    Rain,
+}
+
+impl Default for Weather {
+    fn default() -> Self {
+        Self::Clear
+    }
+}
+
+impl Weather {
+    pub fn is_raining(&self) -> bool {
+        *self == Weather::Rain
+    }
+}